@@ -5,12 +5,17 @@ use mess;
 use teapot;
 use teapot_lean;
 
-fn main() {
-    let _ = setup_logger();
+fn main() -> anyhow::Result<()> {
+    if let Err(err) = setup_logger() {
+        eprintln!("failed to set up logging: {}", err);
+    }
 
     // mess::ash_test_main();
-    // teapot::main();
-    teapot_lean::main();
+    // teapot::main().map_err(|msg| anyhow::anyhow!(msg))?;
+    teapot_lean::main()
+        .map_err(|msg| anyhow::anyhow!(msg).context("failed to start the teapot"))?;
+
+    Ok(())
 }
 
 fn setup_logger() -> std::result::Result<(), SetLoggerError> {
@@ -11,11 +11,16 @@ use anyhow::Context;
 use ash::khr::{surface, swapchain};
 use ash::vk;
 
-use crossbeam_channel::{Receiver, Sender};
+use gpu_allocator::MemoryLocation;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator};
+use parking_lot::Mutex;
+use scopeguard::{ScopeGuard, guard};
 
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 use sdl2::video::Window;
 
+use crate::submit_worker::{SubmitTicket, SubmitWorker};
+
 const INSTANCE_EXTENSIONS: &[&CStr] = &[
     ash::ext::debug_utils::NAME,
     ash::khr::surface::NAME,
@@ -41,25 +46,27 @@ impl Deref for Core {
     }
 }
 
-pub struct DeferredSubmit {
-    pub cmd: vk::CommandBuffer,
-}
-
 pub struct CoreInner {
     pub entry: ash::Entry,
     pub instance: ash::Instance,
     pub pdevice: vk::PhysicalDevice,
     pub pdevice_properties: vk::PhysicalDeviceProperties,
     pub pdevice_mem_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Whether `descriptorBindingUpdateUnusedWhilePending` is supported and enabled.
+    pub update_unused_while_pending: bool,
     pub device: ash::Device,
     pub swapchain_device: swapchain::Device,
     pub surface_instance: surface::Instance,
 
     pub graphics_queue_family_index: u32,
-    pub graphics_queue: vk::Queue,
+    // only accessed with graphics_queue_lock held, see Core::graphics_queue_submit
+    graphics_queue: vk::Queue,
+    /// Held around every access to `graphics_queue`, including the submit worker's submits
+    /// when the graphics family has a single queue and the worker shares it.
+    graphics_queue_lock: Arc<Mutex<()>>,
 
     pub pipeline_depth: u32,
-    pub deferred_submits: (Sender<DeferredSubmit>, Receiver<DeferredSubmit>),
+    pub submit_worker: SubmitWorker,
 
     pub surface: vk::SurfaceKHR,
 }
@@ -131,12 +138,27 @@ impl Core {
             let pdevice_properties = instance.get_physical_device_properties(pdevice);
             let pdevice_mem_properties = instance.get_physical_device_memory_properties(pdevice);
 
+            // uploads get their own queue when there is one, so they don't contend with
+            // rendering for the graphics queue
+            let queue_count = instance.get_physical_device_queue_family_properties(pdevice)
+                [queue_family_index as usize]
+                .queue_count;
+            let queue_priorities: &[f32] = if queue_count > 1 { &[1.0, 0.5] } else { &[1.0] };
+
             let queue_info = vk::DeviceQueueCreateInfo::default()
                 .queue_family_index(queue_family_index)
-                .queue_priorities(&[1.0]);
+                .queue_priorities(queue_priorities);
 
             let extensions = Vec::from_iter(DEVICE_EXTENSIONS.iter().map(|c| c.as_ptr()));
 
+            // optional, see BindlessTextureTable
+            let mut supported_features_12 = vk::PhysicalDeviceVulkan12Features::default();
+            let mut supported_features =
+                vk::PhysicalDeviceFeatures2::default().push_next(&mut supported_features_12);
+            instance.get_physical_device_features2(pdevice, &mut supported_features);
+            let update_unused_while_pending =
+                supported_features_12.descriptor_binding_update_unused_while_pending == vk::TRUE;
+
             let mut features_13 = vk::PhysicalDeviceVulkan13Features::default()
                 .dynamic_rendering(true)
                 .synchronization2(true);
@@ -146,6 +168,7 @@ impl Core {
                 .descriptor_indexing(true)
                 .descriptor_binding_partially_bound(true)
                 .descriptor_binding_sampled_image_update_after_bind(true)
+                .descriptor_binding_update_unused_while_pending(update_unused_while_pending)
                 .shader_sampled_image_array_non_uniform_indexing(true)
                 .buffer_device_address(true);
 
@@ -161,10 +184,19 @@ impl Core {
             let device = instance.create_device(pdevice, &device_create_info, None)?;
 
             let graphics_queue = device.get_device_queue(queue_family_index, 0);
+            let graphics_queue_lock = Arc::new(Mutex::new(()));
 
             let swapchain_device = swapchain::Device::new(&instance, &device);
 
-            let deferred_submits = crossbeam_channel::bounded(16);
+            let (transfer_queue, transfer_queue_lock) = if queue_priorities.len() > 1 {
+                (device.get_device_queue(queue_family_index, 1), None)
+            } else {
+                log::warn!("graphics family has a single queue, uploads share it");
+                (graphics_queue, Some(graphics_queue_lock.clone()))
+            };
+
+            let submit_worker =
+                SubmitWorker::new(device.clone(), transfer_queue, transfer_queue_lock)?;
 
             Ok(Self {
                 inner: Arc::new(CoreInner {
@@ -173,15 +205,17 @@ impl Core {
                     device,
                     pdevice_properties,
                     pdevice_mem_properties,
+                    update_unused_while_pending,
                     pdevice,
                     swapchain_device,
                     surface_instance,
 
                     graphics_queue_family_index: queue_family_index,
                     graphics_queue,
+                    graphics_queue_lock,
 
                     pipeline_depth: 3,
-                    deferred_submits,
+                    submit_worker,
 
                     surface,
                 }),
@@ -197,12 +231,301 @@ impl Core {
         &self.inner.device
     }
 
-    pub fn graphics_queue(&self) -> vk::Queue {
-        self.inner.graphics_queue
+    /// Submits to the graphics queue, holding `graphics_queue_lock` since the submit worker may
+    /// share the queue.
+    pub fn graphics_queue_submit(
+        &self,
+        submits: &[vk::SubmitInfo2],
+        fence: vk::Fence,
+    ) -> ash::prelude::VkResult<()> {
+        let _guard = self.graphics_queue_lock.lock();
+
+        unsafe {
+            self.device
+                .queue_submit2(self.graphics_queue, submits, fence)
+        }
+    }
+
+    /// Presents on the graphics queue, holding `graphics_queue_lock` like
+    /// `graphics_queue_submit`. Returns whether the swapchain is suboptimal.
+    pub fn queue_present(&self, present_info: &vk::PresentInfoKHR) -> ash::prelude::VkResult<bool> {
+        let _guard = self.graphics_queue_lock.lock();
+
+        unsafe {
+            self.swapchain_device
+                .queue_present(self.graphics_queue, present_info)
+        }
+    }
+
+    /// Submits `cmd` to the transfer queue from the submit worker's thread, without waiting for
+    /// it. `cmd` and its pool must be kept until the returned ticket is done.
+    pub fn deferred_submit(&self, cmd: vk::CommandBuffer) -> SubmitTicket {
+        self.submit_worker.submit(cmd)
+    }
+
+    /// Copies `data` to a new gpu only buffer. The copy is submitted by the submit worker, poll
+    /// `BufferUpload::is_done` and `finish` it to get the buffer.
+    ///
+    /// The transfer queue is of the graphics family, so the buffer is created with `EXCLUSIVE`
+    /// sharing and needs no queue family ownership transfer before the graphics queue uses it.
+    /// A transfer queue of another family would need a release barrier here and an acquire
+    /// barrier on the graphics queue, or `CONCURRENT` sharing.
+    pub fn upload_buffer(
+        &self,
+        allocator: &Mutex<Allocator>,
+        data: &[u8],
+        usage: vk::BufferUsageFlags,
+        name: &str,
+    ) -> anyhow::Result<BufferUpload> {
+        anyhow::ensure!(!data.is_empty(), "{name}: no data to upload");
+
+        let size = data.len() as vk::DeviceSize;
+
+        let mut staging_sg = {
+            let staging = self.create_buffer(
+                allocator,
+                size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                MemoryLocation::CpuToGpu,
+                &format!("{name} staging"),
+            )?;
+            guard(staging, |(buffer, allocation)| {
+                self.destroy_buffer(allocator, buffer, allocation)
+            })
+        };
+
+        staging_sg
+            .1
+            .mapped_slice_mut()
+            .context("staging buffer is not host visible")?[..data.len()]
+            .copy_from_slice(data);
+
+        let buffer_sg = {
+            let buffer = self.create_buffer(
+                allocator,
+                size,
+                usage | vk::BufferUsageFlags::TRANSFER_DST,
+                MemoryLocation::GpuOnly,
+                name,
+            )?;
+            guard(buffer, |(buffer, allocation)| {
+                self.destroy_buffer(allocator, buffer, allocation)
+            })
+        };
+
+        let (staging_buffer, buffer) = (staging_sg.0, buffer_sg.0);
+
+        unsafe {
+            let command_pool_sg = {
+                let pool_info = vk::CommandPoolCreateInfo::default()
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                    .queue_family_index(self.graphics_queue_family_index);
+                let command_pool = self.device.create_command_pool(&pool_info, None)?;
+                guard(command_pool, |command_pool| {
+                    self.device.destroy_command_pool(command_pool, None)
+                })
+            };
+
+            let alloc_info = vk::CommandBufferAllocateInfo::default()
+                .command_pool(*command_pool_sg)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1);
+            let cmd = self.device.allocate_command_buffers(&alloc_info)?[0];
+
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.device.begin_command_buffer(cmd, &begin_info)?;
+
+            let region = vk::BufferCopy::default().size(size);
+            self.device
+                .cmd_copy_buffer(cmd, staging_buffer, buffer, slice::from_ref(&region));
+
+            // later submits only wait for the worker's fence on the host, the barrier makes the
+            // copy available to them
+            let buffer_barrier = vk::BufferMemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+                .buffer(buffer)
+                .size(vk::WHOLE_SIZE);
+
+            let dep_info = vk::DependencyInfo::default()
+                .buffer_memory_barriers(slice::from_ref(&buffer_barrier));
+            self.device.cmd_pipeline_barrier2(cmd, &dep_info);
+
+            self.device.end_command_buffer(cmd)?;
+
+            let (buffer, allocation) = ScopeGuard::into_inner(buffer_sg);
+            let (staging_buffer, staging_allocation) = ScopeGuard::into_inner(staging_sg);
+
+            Ok(BufferUpload {
+                buffer,
+                allocation,
+                staging_buffer,
+                staging_allocation,
+                command_pool: ScopeGuard::into_inner(command_pool_sg),
+                ticket: self.deferred_submit(cmd),
+            })
+        }
+    }
+
+    fn create_buffer(
+        &self,
+        allocator: &Mutex<Allocator>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+        name: &str,
+    ) -> anyhow::Result<(vk::Buffer, Allocation)> {
+        unsafe {
+            // EXCLUSIVE to the graphics family, which the transfer queue is part of
+            let buffer_info = vk::BufferCreateInfo::default()
+                .size(size)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let buffer_sg = guard(self.device.create_buffer(&buffer_info, None)?, |buffer| {
+                self.device.destroy_buffer(buffer, None)
+            });
+
+            let allocation = allocator.lock().allocate(&AllocationCreateDesc {
+                name,
+                requirements: self.device.get_buffer_memory_requirements(*buffer_sg),
+                location,
+                linear: true,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            })?;
+
+            if let Err(err) =
+                self.device
+                    .bind_buffer_memory(*buffer_sg, allocation.memory(), allocation.offset())
+            {
+                let _ = allocator.lock().free(allocation);
+                return Err(err.into());
+            }
+
+            Ok((ScopeGuard::into_inner(buffer_sg), allocation))
+        }
+    }
+
+    fn destroy_buffer(
+        &self,
+        allocator: &Mutex<Allocator>,
+        buffer: vk::Buffer,
+        allocation: Allocation,
+    ) {
+        unsafe {
+            self.device.destroy_buffer(buffer, None);
+        }
+        let _ = allocator.lock().free(allocation);
+    }
+
+    /// Records commands with `record` and waits for them to execute on the submit worker's
+    /// queue, e.g. for one-off setup.
+    pub fn one_time_submit(&self, record: impl FnOnce(vk::CommandBuffer)) -> anyhow::Result<()> {
+        unsafe {
+            let pool_info = vk::CommandPoolCreateInfo::default()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(self.graphics_queue_family_index);
+            let command_pool_sg = guard(
+                self.device.create_command_pool(&pool_info, None)?,
+                |command_pool| self.device.destroy_command_pool(command_pool, None),
+            );
+
+            let alloc_info = vk::CommandBufferAllocateInfo::default()
+                .command_pool(*command_pool_sg)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1);
+            let cmd = self.device.allocate_command_buffers(&alloc_info)?[0];
+
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.device.begin_command_buffer(cmd, &begin_info)?;
+            record(cmd);
+            self.device.end_command_buffer(cmd)?;
+
+            // the pool is destroyed once the command buffer has executed
+            self.deferred_submit(cmd).wait()
+        }
     }
 
-    pub fn deferred_submit(&self, cmd: vk::CommandBuffer) {
-        let _ = self.deferred_submits.0.send(DeferredSubmit { cmd });
+    /// A `width` x `height` RGBA8 image to sample, with its view and a linear sampler. Its
+    /// contents are undefined until written, e.g. by `SampledImage::cmd_clear`.
+    pub fn create_sampled_image(
+        &self,
+        allocator: &Mutex<Allocator>,
+        width: u32,
+        height: u32,
+        name: &str,
+    ) -> anyhow::Result<SampledImage> {
+        let format = vk::Format::R8G8B8A8_UNORM;
+
+        unsafe {
+            let image_info = vk::ImageCreateInfo::default()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED);
+            let image_sg = guard(self.device.create_image(&image_info, None)?, |image| {
+                self.device.destroy_image(image, None)
+            });
+
+            let allocation = allocator.lock().allocate(&AllocationCreateDesc {
+                name,
+                requirements: self.device.get_image_memory_requirements(*image_sg),
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            })?;
+            let allocation_sg = guard(allocation, |allocation| {
+                let _ = allocator.lock().free(allocation);
+            });
+
+            self.device.bind_image_memory(
+                *image_sg,
+                allocation_sg.memory(),
+                allocation_sg.offset(),
+            )?;
+
+            let view_info = vk::ImageViewCreateInfo::default()
+                .image(*image_sg)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .subresource_range(
+                    vk::ImageSubresourceRange::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .level_count(1)
+                        .layer_count(1),
+                );
+            let view_sg = guard(self.device.create_image_view(&view_info, None)?, |view| {
+                self.device.destroy_image_view(view, None)
+            });
+
+            let sampler_info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::REPEAT)
+                .address_mode_v(vk::SamplerAddressMode::REPEAT)
+                .address_mode_w(vk::SamplerAddressMode::REPEAT);
+            let sampler = self.device.create_sampler(&sampler_info, None)?;
+
+            Ok(SampledImage {
+                sampler,
+                view: ScopeGuard::into_inner(view_sg),
+                allocation: ScopeGuard::into_inner(allocation_sg),
+                image: ScopeGuard::into_inner(image_sg),
+            })
+        }
     }
 
     pub fn cmd_image_barrier(
@@ -237,12 +560,113 @@ impl Core {
     }
 }
 
+/// See `Core::create_sampled_image`.
+pub struct SampledImage {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub sampler: vk::Sampler,
+    allocation: Allocation,
+}
+
+impl SampledImage {
+    /// Fills the image with `color` and leaves it in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn cmd_clear(&self, core: &Core, cmd: vk::CommandBuffer, color: [f32; 4]) {
+        core.cmd_image_barrier(
+            cmd,
+            self.image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageAspectFlags::COLOR,
+        );
+
+        let range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+
+        unsafe {
+            core.device.cmd_clear_color_image(
+                cmd,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue { float32: color },
+                slice::from_ref(&range),
+            );
+        }
+
+        core.cmd_image_barrier(
+            cmd,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageAspectFlags::COLOR,
+        );
+    }
+
+    /// No pending command buffer may still use the image.
+    pub fn destroy(self, core: &Core, allocator: &Mutex<Allocator>) {
+        unsafe {
+            core.device.destroy_sampler(self.sampler, None);
+            core.device.destroy_image_view(self.view, None);
+            core.device.destroy_image(self.image, None);
+        }
+        let _ = allocator.lock().free(self.allocation);
+    }
+}
+
+/// A buffer being filled on the transfer queue, see `Core::upload_buffer`.
+pub struct BufferUpload {
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    staging_buffer: vk::Buffer,
+    staging_allocation: Allocation,
+    command_pool: vk::CommandPool,
+    ticket: SubmitTicket,
+}
+
+impl BufferUpload {
+    pub fn is_done(&mut self) -> bool {
+        self.ticket.is_done()
+    }
+
+    /// Waits for the copy and frees the staging buffer. Submits made after it returns can use
+    /// the buffer.
+    pub fn finish(
+        self,
+        core: &Core,
+        allocator: &Mutex<Allocator>,
+    ) -> anyhow::Result<(vk::Buffer, Allocation)> {
+        let result = self.ticket.wait();
+
+        let mut allocator = allocator.lock();
+
+        unsafe {
+            core.device.destroy_command_pool(self.command_pool, None);
+            core.device.destroy_buffer(self.staging_buffer, None);
+        }
+        allocator.free(self.staging_allocation)?;
+
+        if let Err(err) = result {
+            unsafe {
+                core.device.destroy_buffer(self.buffer, None);
+            }
+            allocator.free(self.allocation)?;
+            return Err(err);
+        }
+
+        Ok((self.buffer, self.allocation))
+    }
+}
+
 pub fn seconds(v: u64) -> u64 {
     Duration::from_secs(v).as_nanos() as u64
 }
 
 impl Drop for CoreInner {
     fn drop(&mut self) {
+        // the worker's fence and queue go with the device
+        self.submit_worker.stop();
+
         unsafe {
             if self.device.device_wait_idle().is_err() {
                 return;
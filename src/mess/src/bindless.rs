@@ -0,0 +1,235 @@
+use std::slice;
+
+use ash::vk;
+
+use crate::ash_test::Core;
+
+/// One descriptor set holding a large array of combined image samplers, indexed by shaders
+/// with an index pushed per draw instead of binding a set per texture:
+///
+/// ```glsl
+/// #extension GL_EXT_nonuniform_qualifier : require
+/// layout(set = 0, binding = 0) uniform sampler2D textures[];
+/// layout(push_constant) uniform PushConstants { uint texture_index; };
+/// // texture(textures[nonuniformEXT(texture_index)], uv)
+/// ```
+///
+/// Slots without a registered texture stay unwritten, which `PARTIALLY_BOUND` allows as long
+/// as shaders don't access them. With `UPDATE_AFTER_BIND` textures can be registered while the
+/// set is bound. Registering while earlier frames using the set are still executing also needs
+/// `UPDATE_UNUSED_WHILE_PENDING`, only set when `Core::update_unused_while_pending` is.
+pub struct BindlessTextureTable {
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set: vk::DescriptorSet,
+    capacity: u32,
+    // below next_index, freed by unregister
+    free_indices: Vec<u32>,
+    next_index: u32,
+}
+
+impl BindlessTextureTable {
+    pub const BINDING: u32 = 0;
+
+    /// `capacity` is clamped to the device's update-after-bind descriptor limits.
+    pub fn new(
+        core: &Core,
+        capacity: u32,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> anyhow::Result<Self> {
+        let capacity = capacity.min(get_max_capacity(core));
+        anyhow::ensure!(capacity > 0, "the device has no update-after-bind samplers");
+
+        log::info!("creating bindless texture table with {capacity} slots");
+
+        let device = core.device();
+
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(Self::BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(capacity)
+            .stage_flags(stage_flags);
+
+        let mut binding_flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND;
+        if core.update_unused_while_pending {
+            binding_flags |= vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+        }
+
+        let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
+            .binding_flags(slice::from_ref(&binding_flags));
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(slice::from_ref(&binding))
+            .push_next(&mut binding_flags_info);
+
+        let pool_size = vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(capacity);
+
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(slice::from_ref(&pool_size));
+
+        unsafe {
+            let descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)?;
+
+            let descriptor_pool = match device.create_descriptor_pool(&pool_info, None) {
+                Ok(descriptor_pool) => descriptor_pool,
+                Err(err) => {
+                    device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+                    return Err(err.into());
+                }
+            };
+
+            let alloc_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(slice::from_ref(&descriptor_set_layout));
+
+            let descriptor_set = match device.allocate_descriptor_sets(&alloc_info) {
+                Ok(descriptor_sets) => descriptor_sets[0],
+                Err(err) => {
+                    device.destroy_descriptor_pool(descriptor_pool, None);
+                    device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+                    return Err(err.into());
+                }
+            };
+
+            Ok(Self {
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_set,
+                capacity,
+                free_indices: Vec::new(),
+                next_index: 0,
+            })
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Writes the texture to a free slot and returns its index for the shaders. `image_view`
+    /// must be in `SHADER_READ_ONLY_OPTIMAL` layout when sampled.
+    pub fn register(
+        &mut self,
+        device: &ash::Device,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+    ) -> anyhow::Result<u32> {
+        let index = match self.free_indices.pop() {
+            Some(index) => index,
+            None if self.next_index < self.capacity => {
+                self.next_index += 1;
+                self.next_index - 1
+            }
+            None => anyhow::bail!("all {} bindless texture slots are used", self.capacity),
+        };
+
+        let image_info = vk::DescriptorImageInfo::default()
+            .sampler(sampler)
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_set)
+            .dst_binding(Self::BINDING)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(slice::from_ref(&image_info));
+
+        unsafe {
+            device.update_descriptor_sets(slice::from_ref(&write), &[]);
+        }
+
+        Ok(index)
+    }
+
+    /// Frees `index` for the next registered texture. No pending command buffer may still
+    /// access it, its descriptor is left as is until then.
+    pub fn unregister(&mut self, index: u32) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            index < self.next_index && !self.free_indices.contains(&index),
+            "bindless texture slot {index} is not registered"
+        );
+
+        self.free_indices.push(index);
+
+        Ok(())
+    }
+
+    /// The range of the `texture_index` pushed by `cmd_push_texture_index`, to create
+    /// pipeline layouts with.
+    pub fn push_constant_range(stage_flags: vk::ShaderStageFlags) -> vk::PushConstantRange {
+        vk::PushConstantRange::default()
+            .stage_flags(stage_flags)
+            .offset(0)
+            .size(size_of::<u32>() as u32)
+    }
+
+    pub fn cmd_bind(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+        set: u32,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                bind_point,
+                pipeline_layout,
+                set,
+                slice::from_ref(&self.descriptor_set),
+                &[],
+            );
+        }
+    }
+
+    pub fn cmd_push_texture_index(
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        stage_flags: vk::ShaderStageFlags,
+        index: u32,
+    ) {
+        unsafe {
+            device.cmd_push_constants(cmd, pipeline_layout, stage_flags, 0, &index.to_ne_bytes());
+        }
+    }
+
+    /// The registered textures are not destroyed, they are owned by the caller.
+    pub fn destroy(self, device: &ash::Device) {
+        unsafe {
+            // frees the set too
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+// the array counts against both the sampler and the sampled image limits
+fn get_max_capacity(core: &Core) -> u32 {
+    let mut properties_12 = vk::PhysicalDeviceVulkan12Properties::default();
+    let mut properties = vk::PhysicalDeviceProperties2::default().push_next(&mut properties_12);
+
+    unsafe {
+        core.instance
+            .get_physical_device_properties2(core.pdevice, &mut properties);
+    }
+
+    [
+        properties_12.max_per_stage_descriptor_update_after_bind_samplers,
+        properties_12.max_per_stage_descriptor_update_after_bind_sampled_images,
+        properties_12.max_descriptor_set_update_after_bind_samplers,
+        properties_12.max_descriptor_set_update_after_bind_sampled_images,
+        properties_12.max_per_stage_update_after_bind_resources,
+    ]
+    .into_iter()
+    .min()
+    .unwrap_or(0)
+}
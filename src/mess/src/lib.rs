@@ -6,7 +6,10 @@ use sdl2::{event::Event, keyboard::Keycode};
 // https://docs.rs/sdl2/latest/sdl2/
 
 mod ash_test;
+mod bindless;
+mod submit_worker;
 
+use ash::vk;
 use gpu_allocator::vulkan::*;
 use parking_lot::Mutex;
 use std::sync::Arc;
@@ -23,16 +26,64 @@ pub fn ash_test_main() {
 
     let x = ash_test::Core::new(&window).unwrap();
 
-    let mut allocator = Allocator::new(&AllocatorCreateDesc {
+    let allocator = Allocator::new(&AllocatorCreateDesc {
         instance: x.instance.clone(),
         device: x.device.clone(),
         physical_device: x.pdevice,
         debug_settings: Default::default(),
         buffer_device_address: true,
         allocation_sizes: Default::default(),
-    });
+    })
+    .unwrap();
     let allocator = Arc::new(Mutex::new(allocator));
 
+    // uploaded by the submit worker while the loop keeps running
+    let vertices: [[f32; 2]; 3] = [[0.0, -0.5], [0.5, 0.5], [-0.5, 0.5]];
+    let mut vertex_upload = Some(
+        x.upload_buffer(
+            &allocator,
+            bytemuck::cast_slice(&vertices),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            "triangle vertices",
+        )
+        .unwrap(),
+    );
+    let mut vertex_buffer = None;
+
+    // one texture indexed through the bindless table, bound with its index pushed the way a
+    // draw sampling textures[texture_index] does
+    let mut bindless_table =
+        bindless::BindlessTextureTable::new(&x, 1024, vk::ShaderStageFlags::FRAGMENT).unwrap();
+    let texture = x
+        .create_sampled_image(&allocator, 1, 1, "bindless test texture")
+        .unwrap();
+    let texture_index = bindless_table
+        .register(&x.device, texture.view, texture.sampler)
+        .unwrap();
+    let pipeline_layout = create_bindless_pipeline_layout(&x, &bindless_table).unwrap();
+    x.one_time_submit(|cmd| {
+        texture.cmd_clear(&x, cmd, [1.0, 0.0, 1.0, 1.0]);
+        bindless_table.cmd_bind(
+            &x.device,
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+        );
+        bindless::BindlessTextureTable::cmd_push_texture_index(
+            &x.device,
+            cmd,
+            pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            texture_index,
+        );
+    })
+    .unwrap();
+    log::info!(
+        "bound texture {texture_index} of {} bindless slots",
+        bindless_table.capacity()
+    );
+
     let mut event_pump = sdl_context.event_pump().unwrap();
 
     let mut i = 0;
@@ -59,13 +110,52 @@ pub fn ash_test_main() {
             }
         }
 
+        if vertex_upload
+            .as_mut()
+            .is_some_and(|upload| upload.is_done())
+        {
+            let upload = vertex_upload.take().unwrap();
+            vertex_buffer = Some(upload.finish(&x, &allocator).unwrap());
+        }
+
         // x.
         ::std::thread::sleep(Duration::new(0, (1_000_000u32 as f64 / 0.1) as u32));
     }
 
+    if let Some(upload) = vertex_upload {
+        vertex_buffer = upload.finish(&x, &allocator).ok();
+    }
+    bindless_table.unregister(texture_index).unwrap();
+    bindless_table.destroy(&x.device);
+    unsafe {
+        x.device.destroy_pipeline_layout(pipeline_layout, None);
+    }
+    texture.destroy(&x, &allocator);
+
+    if let Some((buffer, allocation)) = vertex_buffer {
+        unsafe {
+            x.device.destroy_buffer(buffer, None);
+        }
+        let _ = allocator.lock().free(allocation);
+    }
+
     // sdl_test();
 }
 
+fn create_bindless_pipeline_layout(
+    core: &ash_test::Core,
+    bindless_table: &bindless::BindlessTextureTable,
+) -> anyhow::Result<vk::PipelineLayout> {
+    let push_constant_range =
+        bindless::BindlessTextureTable::push_constant_range(vk::ShaderStageFlags::FRAGMENT);
+
+    let layout_info = vk::PipelineLayoutCreateInfo::default()
+        .set_layouts(std::slice::from_ref(&bindless_table.descriptor_set_layout))
+        .push_constant_ranges(std::slice::from_ref(&push_constant_range));
+
+    Ok(unsafe { core.device.create_pipeline_layout(&layout_info, None)? })
+}
+
 mod sdl2_test {
     use sdl2::event::Event;
     use sdl2::keyboard::Keycode;
@@ -0,0 +1,164 @@
+use std::slice;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use ash::vk;
+
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+
+pub struct DeferredSubmit {
+    pub cmd: vk::CommandBuffer,
+    done: Sender<Result<(), vk::Result>>,
+}
+
+/// Completion of a deferred submit, done once its command buffer has finished executing.
+pub struct SubmitTicket {
+    done: Receiver<Result<(), vk::Result>>,
+    result: Option<Result<(), vk::Result>>,
+}
+
+impl SubmitTicket {
+    pub fn is_done(&mut self) -> bool {
+        if self.result.is_none() {
+            self.result = self.done.try_recv().ok();
+        }
+
+        self.result.is_some()
+    }
+
+    /// Blocks until the command buffer has finished executing.
+    pub fn wait(mut self) -> anyhow::Result<()> {
+        let result = match self.result.take() {
+            Some(result) => result,
+            None => self
+                .done
+                .recv()
+                .map_err(|_| anyhow::anyhow!("submit worker stopped before the submit"))?,
+        };
+
+        Ok(result?)
+    }
+}
+
+/// Owns a thread that drains the deferred submits and submits them to its queue, so the queue
+/// is only ever accessed from that thread. Every submit available at once goes in a single
+/// batch with one fence, the thread waits for it instead of the caller.
+pub struct SubmitWorker {
+    sender: Option<Sender<DeferredSubmit>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SubmitWorker {
+    /// `queue_lock` is held around each submit, for a queue also submitted to from other
+    /// threads.
+    pub fn new(
+        device: ash::Device,
+        queue: vk::Queue,
+        queue_lock: Option<Arc<Mutex<()>>>,
+    ) -> anyhow::Result<Self> {
+        let fence = unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None)? };
+
+        let (sender, receiver) = crossbeam_channel::bounded(16);
+
+        let thread = std::thread::Builder::new()
+            .name(String::from("submit worker"))
+            .spawn(move || run(device, queue, queue_lock, fence, receiver))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues `cmd`, which must be fully recorded, for submission. Blocks while the queue of
+    /// pending submits is full.
+    pub fn submit(&self, cmd: vk::CommandBuffer) -> SubmitTicket {
+        let (done_sender, done_receiver) = crossbeam_channel::bounded(1);
+
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(DeferredSubmit {
+                cmd,
+                done: done_sender,
+            });
+        }
+
+        SubmitTicket {
+            done: done_receiver,
+            result: None,
+        }
+    }
+
+    /// Waits for the pending submits to finish executing and ends the thread, later submits
+    /// fail. Must be called before the device is destroyed, dropping the worker calls it too.
+    pub fn stop(&mut self) {
+        // disconnecting the channel ends the thread once it's drained
+        self.sender = None;
+
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            log::error!("submit worker panicked");
+        }
+    }
+}
+
+impl Drop for SubmitWorker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run(
+    device: ash::Device,
+    queue: vk::Queue,
+    queue_lock: Option<Arc<Mutex<()>>>,
+    fence: vk::Fence,
+    receiver: Receiver<DeferredSubmit>,
+) {
+    while let Ok(first) = receiver.recv() {
+        let batch = Vec::from_iter(std::iter::once(first).chain(receiver.try_iter()));
+
+        let result = submit_batch(&device, queue, queue_lock.as_deref(), fence, &batch);
+        if let Err(err) = result {
+            log::error!(
+                "failed to submit {} deferred command buffers: {err}",
+                batch.len()
+            );
+        }
+
+        for submit in batch {
+            let _ = submit.done.send(result);
+        }
+    }
+
+    unsafe {
+        device.destroy_fence(fence, None);
+    }
+}
+
+fn submit_batch(
+    device: &ash::Device,
+    queue: vk::Queue,
+    queue_lock: Option<&Mutex<()>>,
+    fence: vk::Fence,
+    batch: &[DeferredSubmit],
+) -> Result<(), vk::Result> {
+    let cmd_infos = Vec::from_iter(
+        batch
+            .iter()
+            .map(|submit| vk::CommandBufferSubmitInfo::default().command_buffer(submit.cmd)),
+    );
+
+    let submit_info = vk::SubmitInfo2::default().command_buffer_infos(&cmd_infos);
+
+    unsafe {
+        {
+            let _guard = queue_lock.map(|lock| lock.lock());
+            device.queue_submit2(queue, slice::from_ref(&submit_info), fence)?;
+        }
+
+        device.wait_for_fences(slice::from_ref(&fence), true, u64::MAX)?;
+        device.reset_fences(slice::from_ref(&fence))
+    }
+}
@@ -3,7 +3,7 @@ use std::path::Path;
 
 fn visit_dirs(
     dir: &Path,
-    cb: &dyn Fn(&std::path::PathBuf, shaderc::ShaderKind),
+    shaders: &mut Vec<(std::path::PathBuf, shaderc::ShaderKind)>,
 ) -> std::io::Result<()> {
     if dir.is_dir() {
         for entry in fs::read_dir(dir)? {
@@ -11,11 +11,11 @@ fn visit_dirs(
             let path = entry.path();
 
             if path.is_dir() {
-                visit_dirs(&path, cb)?;
+                visit_dirs(&path, shaders)?;
             } else {
                 let path_buf = entry.path();
                 if let Some(shader_kind) = get_shader_kind(&path_buf) {
-                    cb(&path_buf, shader_kind);
+                    shaders.push((path_buf, shader_kind));
                 }
             }
         }
@@ -35,35 +35,152 @@ fn get_shader_kind(path_buf: &std::path::PathBuf) -> Option<shaderc::ShaderKind>
         "frag" => Some(shaderc::ShaderKind::Fragment),
         "tese" => Some(shaderc::ShaderKind::TessEvaluation),
         "tesc" => Some(shaderc::ShaderKind::TessControl),
+        "geom" => Some(shaderc::ShaderKind::Geometry),
+        "comp" => Some(shaderc::ShaderKind::Compute),
         _ => None,
     }
 }
 
-fn compile_shader(path_buf: &std::path::PathBuf, shader_kind: shaderc::ShaderKind) {
-    let shader_str = fs::read_to_string(path_buf)
-        .expect(&format!("failed to read shader {:?} to string", path_buf));
+const FORCE_REBUILD_ENV_VAR: &str = "LYNX_FORCE_SHADER_REBUILD";
 
-    let compiler = shaderc::Compiler::new().expect("failed to create shader compilier");
+fn is_force_rebuild() -> bool {
+    std::env::var(FORCE_REBUILD_ENV_VAR).map_or(false, |value| value == "1")
+}
 
-    println!("compiling shader {:?}", path_buf);
+// the output is up to date when it is newer than every source it was built from
+fn is_up_to_date(sources: &[std::path::PathBuf], spv_path: &Path) -> bool {
+    if is_force_rebuild() {
+        return false;
+    }
+
+    let spv_modified = match fs::metadata(spv_path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return false,
+    };
+
+    sources.iter().all(|source| {
+        match fs::metadata(source).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified <= spv_modified,
+            Err(_) => false,
+        }
+    })
+}
+
+const SHADER_DEBUG_ENV_VAR: &str = "LYNX_SHADER_DEBUG";
+
+// debug info for RenderDoc and friends, on for the debug profile unless overridden
+fn is_shader_debug() -> bool {
+    match std::env::var(SHADER_DEBUG_ENV_VAR).as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        Ok(value) => panic!(
+            "unknown {} value {:?}, expected 0 or 1",
+            SHADER_DEBUG_ENV_VAR, value
+        ),
+        Err(_) => std::env::var("PROFILE").map_or(false, |profile| profile == "debug"),
+    }
+}
+
+// the spv files don't record the settings they were built with, so they are written next to
+// them and e.g. switching between the debug and release profile rebuilds the shader
+fn is_settings_unchanged(settings_path: &Path, settings: &str) -> bool {
+    fs::read_to_string(settings_path).map_or(false, |previous| previous == settings)
+}
 
-    let spv = compiler
-        .compile_into_spirv(
-            &shader_str,
-            shader_kind,
-            &path_buf.to_str().unwrap(),
-            "main",
-            None,
-        )
-        .expect(&format!("failed to compile shader {:?}", path_buf));
+const INCLUDE_DIR_ENV_VAR: &str = "LYNX_SHADER_INCLUDE_DIR";
+const DEFAULT_INCLUDE_DIR: &str = "shaders/include";
+
+fn get_include_dir() -> std::path::PathBuf {
+    std::env::var(INCLUDE_DIR_ENV_VAR).map_or(
+        std::path::PathBuf::from(DEFAULT_INCLUDE_DIR),
+        std::path::PathBuf::from,
+    )
+}
+
+// relative includes are looked up next to the including file first, then in the include dir
+fn resolve_include(
+    requested_source: &str,
+    include_type: shaderc::IncludeType,
+    requesting_source: &str,
+) -> Option<std::path::PathBuf> {
+    if matches!(include_type, shaderc::IncludeType::Relative) {
+        let include_path = Path::new(requesting_source)
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(requested_source);
+
+        if include_path.is_file() {
+            return Some(include_path);
+        }
+    }
+
+    let include_path = get_include_dir().join(requested_source);
+    if include_path.is_file() {
+        Some(include_path)
+    } else {
+        None
+    }
+}
+
+// all files reachable through #include, so edits to shared headers are noticed
+fn get_glsl_includes(path_buf: &std::path::PathBuf) -> Vec<std::path::PathBuf> {
+    let mut includes: Vec<std::path::PathBuf> = Vec::new();
+    let mut pending = vec![path_buf.clone()];
+
+    while let Some(source_path) = pending.pop() {
+        let source = match fs::read_to_string(&source_path) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+
+        for line in source.lines() {
+            let requested = match line.trim().strip_prefix("#include") {
+                Some(requested) => requested.trim(),
+                None => continue,
+            };
+
+            let (requested_source, include_type) =
+                if let Some(requested) = requested.strip_prefix('"') {
+                    (
+                        requested.trim_end_matches('"'),
+                        shaderc::IncludeType::Relative,
+                    )
+                } else if let Some(requested) = requested.strip_prefix('<') {
+                    (
+                        requested.trim_end_matches('>'),
+                        shaderc::IncludeType::Standard,
+                    )
+                } else {
+                    continue;
+                };
+
+            let requesting_source = source_path.to_str().unwrap();
+            if let Some(include_path) =
+                resolve_include(requested_source, include_type, requesting_source)
+            {
+                if !includes.contains(&include_path) {
+                    includes.push(include_path.clone());
+                    pending.push(include_path);
+                }
+            }
+        }
+    }
+
+    includes
+}
+
+fn compile_shader(
+    path_buf: &std::path::PathBuf,
+    shader_kind: shaderc::ShaderKind,
+    debug_info: bool,
+) {
+    println!("cargo:rerun-if-changed={}", path_buf.display());
 
     let mut file_name = path_buf
         .file_name()
         .expect("shader file should have a name")
         .to_os_string();
 
-    println!("cargo:rerun-if-changed={}", path_buf.display());
-
     file_name.push(".spv");
 
     let mut spv_path = path_buf
@@ -81,15 +198,87 @@ fn compile_shader(path_buf: &std::path::PathBuf, shader_kind: shaderc::ShaderKin
 
     spv_path.push(file_name);
 
+    let mut sources = vec![path_buf.clone()];
+    for include_path in get_glsl_includes(path_buf) {
+        println!("cargo:rerun-if-changed={}", include_path.display());
+        sources.push(include_path);
+    }
+
+    let settings_path = spv_path.with_extension("settings");
+    let settings = format!("debug_info: {}", debug_info);
+
+    if is_up_to_date(&sources, &spv_path) && is_settings_unchanged(&settings_path, &settings) {
+        println!("skipping up-to-date shader {:?}", path_buf);
+        return;
+    }
+
+    let shader_str = fs::read_to_string(path_buf)
+        .expect(&format!("failed to read shader {:?} to string", path_buf));
+
+    let compiler = shaderc::Compiler::new().expect("failed to create shader compilier");
+
+    let mut options =
+        shaderc::CompileOptions::new().expect("failed to create shader compile options");
+
+    if debug_info {
+        options.set_generate_debug_info();
+    }
+
+    options.set_include_callback(|requested_source, include_type, requesting_source, _| {
+        let include_path = resolve_include(requested_source, include_type, requesting_source)
+            .ok_or(format!(
+                "failed to resolve include {:?} from {:?}",
+                requested_source, requesting_source
+            ))?;
+
+        let content = fs::read_to_string(&include_path)
+            .map_err(|_| format!("failed to read include {:?}", include_path))?;
+
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: include_path.to_str().unwrap().to_string(),
+            content,
+        })
+    });
+
+    println!("compiling shader {:?}", path_buf);
+
+    // shaderc errors already carry file:line: message for every diagnostic
+    let spv = match compiler.compile_into_spirv(
+        &shader_str,
+        shader_kind,
+        &path_buf.to_str().unwrap(),
+        "main",
+        Some(&options),
+    ) {
+        Ok(spv) => spv,
+        Err(err) => panic!("failed to compile shader {:?}:\n{}", path_buf, err),
+    };
+
     fs::write(spv_path, spv.as_binary_u8()).expect("failed to write shader binary");
+    fs::write(settings_path, settings).expect("failed to write shader settings");
 }
 
 fn main() -> Result<(), i32> {
+    println!("cargo:rerun-if-env-changed={}", FORCE_REBUILD_ENV_VAR);
+    println!("cargo:rerun-if-env-changed={}", INCLUDE_DIR_ENV_VAR);
+    println!("cargo:rerun-if-env-changed={}", SHADER_DEBUG_ENV_VAR);
+
+    let debug_info = is_shader_debug();
+
     let shaders_dir = Path::new("shaders");
 
-    if let Err(_) = visit_dirs(shaders_dir, &compile_shader) {
+    let mut shaders = Vec::new();
+    if let Err(_) = visit_dirs(shaders_dir, &mut shaders) {
         return Err(1);
     }
 
+    // every thread creates its own compiler, a failing shader panics with its path
+    // and the scope propagates the panic once all threads are done
+    std::thread::scope(|scope| {
+        for (path_buf, shader_kind) in &shaders {
+            scope.spawn(move || compile_shader(path_buf, *shader_kind, debug_info));
+        }
+    });
+
     Ok(())
 }
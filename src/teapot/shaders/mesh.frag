@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+const vec3 lightDir = normalize(vec3(0.5, -1.0, -0.5));
+
+void main()
+{
+    float diffuse = max(dot(normalize(fragNormal), -lightDir), 0.0);
+
+    outColor = vec4(vec3(0.8) * (0.2 + 0.8 * diffuse), 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+#version 450
+
+layout(push_constant) uniform PushConst
+{
+    mat4 mvpMatrix;
+};
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUv;
+
+layout(location = 0) out vec3 outNormal;
+layout(location = 1) out vec2 outUv;
+
+void main()
+{
+    gl_Position = mvpMatrix * vec4(inPosition, 1.0);
+
+    outNormal = inNormal;
+    outUv = inUv;
+}
\ No newline at end of file
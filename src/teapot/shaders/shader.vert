@@ -17,9 +17,13 @@ out gl_PerVertex
     vec4 gl_Position;
 };
 
+layout(location = 0) out uint outInstanceIndex;
+
 void main()
 {
     ControlPoint cp = controlPointBuffer.data[gl_VertexIndex];
 
     gl_Position = vec4(cp.x, cp.y, cp.z, 1.0);
+
+    outInstanceIndex = gl_InstanceIndex;
 }
\ No newline at end of file
@@ -0,0 +1,115 @@
+use cgmath::{perspective, Deg, Matrix4, Point3, Vector3};
+
+/// Projection * view matrix of a camera at `position` looking at `target` with a vertical
+/// field of view of `fov_y` degrees.
+pub fn view_projection(
+    position: Point3<f32>,
+    target: Point3<f32>,
+    fov_y: f32,
+    aspect: f32,
+) -> Matrix4<f32> {
+    let view = Matrix4::look_at_rh(position, target, Vector3::new(0.0, 1.0, 0.0));
+    let projection = perspective(Deg(fov_y), aspect, 0.1, 100.0);
+
+    projection * view
+}
+
+// degrees per pixel of cursor movement
+const ORBIT_SENSITIVITY: f32 = 0.3;
+// distance factor per scrolled line
+const ZOOM_STEP: f32 = 0.9;
+// pixels of a touchpad scroll counted as one line
+const PIXELS_PER_LINE: f32 = 50.0;
+const MIN_DISTANCE: f32 = 2.0;
+const MAX_DISTANCE: f32 = 50.0;
+// the view would flip over the poles of look_at_rh
+const MAX_PITCH: f32 = 89.0;
+
+/// Camera circling `target`, rotated by dragging with the left mouse button and zoomed with
+/// the scroll wheel. With zero yaw and pitch it looks down the positive z axis.
+pub struct OrbitCamera {
+    pub target: Point3<f32>,
+    pub distance: f32,
+    /// Degrees around the y axis.
+    pub yaw: f32,
+    /// Degrees above the xz plane, within `MAX_PITCH`.
+    pub pitch: f32,
+    is_dragging: bool,
+    // None until the first cursor event of a drag, so the drag starts with a zero delta
+    last_cursor_position: Option<(f64, f64)>,
+}
+
+impl OrbitCamera {
+    pub fn new(target: Point3<f32>, distance: f32) -> Self {
+        OrbitCamera {
+            target,
+            distance: distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+            yaw: 0.0,
+            pitch: 0.0,
+            is_dragging: false,
+            last_cursor_position: None,
+        }
+    }
+
+    pub fn position(&self) -> Point3<f32> {
+        let (yaw_sin, yaw_cos) = self.yaw.to_radians().sin_cos();
+        let (pitch_sin, pitch_cos) = self.pitch.to_radians().sin_cos();
+
+        self.target
+            + self.distance * Vector3::new(pitch_cos * yaw_sin, pitch_sin, -pitch_cos * yaw_cos)
+    }
+
+    pub fn view_projection(&self, fov_y: f32, aspect: f32) -> Matrix4<f32> {
+        view_projection(self.position(), self.target, fov_y, aspect)
+    }
+
+    /// Returns whether a drag started or ended, the caller grabs or releases the cursor.
+    pub fn on_mouse_button(&mut self, is_pressed: bool) -> bool {
+        if self.is_dragging == is_pressed {
+            return false;
+        }
+
+        self.is_dragging = is_pressed;
+        self.last_cursor_position = None;
+
+        true
+    }
+
+    /// `position` in physical pixels, as reported by `WindowEvent::CursorMoved`.
+    pub fn on_cursor_moved(&mut self, position: (f64, f64)) {
+        if !self.is_dragging {
+            return;
+        }
+
+        if let Some((last_x, last_y)) = self.last_cursor_position {
+            let delta_x = (position.0 - last_x) as f32;
+            let delta_y = (position.1 - last_y) as f32;
+
+            self.yaw = (self.yaw - delta_x * ORBIT_SENSITIVITY) % 360.0;
+            self.pitch = (self.pitch + delta_y * ORBIT_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        self.last_cursor_position = Some(position);
+    }
+
+    /// Re-entering the window elsewhere must not count as movement.
+    pub fn on_cursor_left(&mut self) {
+        self.last_cursor_position = None;
+    }
+
+    /// Ends a drag whose button release the window won't see, e.g. after losing focus.
+    /// Returns whether a drag was ended.
+    pub fn cancel_drag(&mut self) -> bool {
+        self.on_mouse_button(false)
+    }
+
+    /// `lines` is positive when scrolling up, which zooms in. Touchpads report pixels, see
+    /// `lines_from_pixels`.
+    pub fn on_scroll(&mut self, lines: f32) {
+        self.distance = (self.distance * ZOOM_STEP.powf(lines)).clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+
+    pub fn lines_from_pixels(pixels: f64) -> f32 {
+        pixels as f32 / PIXELS_PER_LINE
+    }
+}
@@ -0,0 +1,193 @@
+use crate::overlay::OverlayRenderer;
+use ash::vk;
+use vulkan_base::VulkanBase;
+
+/// `OverlayRenderer` drawing an egui UI with `egui-ash-renderer`.
+///
+/// Only `RenderingMode::RenderPass` is supported, the renderer's pipeline is created for the
+/// teapot render pass. Font and image uploads use their own transient command pool on the
+/// graphics queue.
+pub struct EguiOverlay {
+    pub context: egui::Context,
+    renderer: egui_ash_renderer::Renderer,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    events: Vec<egui::Event>,
+    pointer_pos: egui::Pos2,
+    textures_delta: egui::TexturesDelta,
+    primitives: Vec<egui::ClippedPrimitive>,
+}
+
+impl EguiOverlay {
+    pub fn new(
+        vulkan_base: &VulkanBase,
+        render_pass: vk::RenderPass,
+        frames_in_flight: usize,
+    ) -> Result<Self, String> {
+        log::info!("creating egui overlay");
+
+        let create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(vulkan_base.queue_family)
+            .build();
+
+        let command_pool = unsafe {
+            vulkan_base
+                .device
+                .create_command_pool(&create_info, None)
+                .map_err(|_| String::from("failed to create egui command pool"))?
+        };
+
+        vulkan_utils::set_debug_utils_object_name(
+            vulkan_base.debug_utils_loader.as_ref(),
+            vulkan_base.device.handle(),
+            command_pool,
+            "egui command pool",
+        );
+
+        let renderer = match egui_ash_renderer::Renderer::with_default_allocator(
+            &vulkan_base.instance,
+            vulkan_base.physical_device,
+            (*vulkan_base.device).clone(),
+            render_pass,
+            egui_ash_renderer::Options {
+                in_flight_frames: frames_in_flight,
+                srgb_framebuffer: true,
+                ..Default::default()
+            },
+        ) {
+            Ok(renderer) => renderer,
+            Err(err) => {
+                unsafe {
+                    vulkan_base.device.destroy_command_pool(command_pool, None);
+                }
+                return Err(format!("failed to create egui renderer: {}", err));
+            }
+        };
+
+        log::info!("egui overlay created");
+
+        Ok(EguiOverlay {
+            context: egui::Context::default(),
+            renderer,
+            queue: vulkan_base.queue,
+            command_pool,
+            events: Vec::new(),
+            pointer_pos: egui::Pos2::ZERO,
+            textures_delta: egui::TexturesDelta::default(),
+            primitives: Vec::new(),
+        })
+    }
+
+    /// Forwards the pointer input egui needs. Positions are in physical pixels, one pixel per
+    /// point.
+    pub fn on_window_event(&mut self, event: &winit::event::WindowEvent) {
+        use winit::event::{ElementState, MouseButton, WindowEvent};
+
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer_pos = egui::pos2(position.x as f32, position.y as f32);
+                self.events
+                    .push(egui::Event::PointerMoved(self.pointer_pos));
+            }
+            WindowEvent::CursorLeft { .. } => self.events.push(egui::Event::PointerGone),
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    _ => return,
+                };
+
+                self.events.push(egui::Event::PointerButton {
+                    pos: self.pointer_pos,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: egui::Modifiers::default(),
+                });
+            }
+            _ => (),
+        }
+    }
+
+    /// Whether the last frame's UI used the pointer, so the app can ignore the click.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.context.wants_pointer_input()
+    }
+
+    /// Builds the UI for the next frame. Must be called before `draw`.
+    pub fn run(
+        &mut self,
+        device: &ash::Device,
+        extent: vk::Extent2D,
+        build_ui: impl FnOnce(&egui::Context),
+    ) -> Result<(), String> {
+        // textures freed by egui may still be read by frames in flight
+        if !self.textures_delta.free.is_empty() {
+            unsafe {
+                device
+                    .device_wait_idle()
+                    .map_err(|_| String::from("failed to wait device idle"))?;
+            }
+            self.renderer
+                .free_textures(&self.textures_delta.free)
+                .map_err(|err| format!("failed to free egui textures: {}", err))?;
+        }
+
+        let raw_input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(extent.width as f32, extent.height as f32),
+            )),
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+
+        let output = self.context.run(raw_input, build_ui);
+
+        self.primitives = self
+            .context
+            .tessellate(output.shapes, output.pixels_per_point);
+        self.textures_delta = output.textures_delta;
+
+        if !self.textures_delta.set.is_empty() {
+            self.renderer
+                .set_textures(self.queue, self.command_pool, &self.textures_delta.set)
+                .map_err(|err| format!("failed to upload egui textures: {}", err))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        log::info!("cleaning egui overlay");
+
+        unsafe {
+            let _ = device.device_wait_idle();
+        }
+
+        drop(self.renderer);
+
+        unsafe {
+            device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
+
+impl OverlayRenderer for EguiOverlay {
+    fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        _image_index: usize,
+        extent: vk::Extent2D,
+    ) -> Result<(), String> {
+        self.renderer
+            .cmd_draw(
+                command_buffer,
+                extent,
+                self.context.pixels_per_point(),
+                &self.primitives,
+            )
+            .map_err(|err| format!("failed to record egui draw: {}", err))
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub average_fps: f32,
+    /// Fps of the slowest 1% of frames in the window.
+    pub low_1_percent_fps: f32,
+    pub average_frame_time_ms: f32,
+}
+
+/// Measures frame times over the last `window` frames and logs their stats every
+/// `log_interval`. The ring buffer is allocated up front, `tick` doesn't allocate.
+pub struct FrameTimer {
+    frame_times: Vec<f32>,
+    sorted_frame_times: Vec<f32>,
+    next_index: usize,
+    frame_count: usize,
+    last_frame: Option<Instant>,
+    log_interval: Duration,
+    last_log: Instant,
+    last_stats: Option<FrameStats>,
+}
+
+impl FrameTimer {
+    pub fn new(window: usize, log_interval: Duration) -> Self {
+        let window = window.max(1);
+
+        FrameTimer {
+            frame_times: vec![0.0; window],
+            sorted_frame_times: Vec::with_capacity(window),
+            next_index: 0,
+            frame_count: 0,
+            last_frame: None,
+            log_interval,
+            last_log: Instant::now(),
+            last_stats: None,
+        }
+    }
+
+    /// Call once per presented frame.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+
+        if let Some(last_frame) = self.last_frame {
+            self.frame_times[self.next_index] = (now - last_frame).as_secs_f32();
+            self.next_index = (self.next_index + 1) % self.frame_times.len();
+            self.frame_count = (self.frame_count + 1).min(self.frame_times.len());
+        }
+
+        self.last_frame = Some(now);
+
+        if now - self.last_log >= self.log_interval {
+            self.last_log = now;
+            self.last_stats = self.stats();
+
+            if let Some(stats) = self.last_stats {
+                log::info!(
+                    "{:.1} fps ({:.2} ms), 1% low {:.1} fps",
+                    stats.average_fps,
+                    stats.average_frame_time_ms,
+                    stats.low_1_percent_fps
+                );
+            }
+        }
+    }
+
+    /// Computes the stats of the current window, `None` before the second frame.
+    pub fn stats(&mut self) -> Option<FrameStats> {
+        if self.frame_count == 0 {
+            return None;
+        }
+
+        let frame_times = &self.frame_times[..self.frame_count];
+        let average_frame_time = frame_times.iter().sum::<f32>() / self.frame_count as f32;
+
+        self.sorted_frame_times.clear();
+        self.sorted_frame_times.extend_from_slice(frame_times);
+        self.sorted_frame_times
+            .sort_unstable_by(|a, b| b.total_cmp(a));
+
+        let slowest_count = (self.frame_count / 100).max(1);
+        let slowest_average_frame_time =
+            self.sorted_frame_times[..slowest_count].iter().sum::<f32>() / slowest_count as f32;
+
+        Some(FrameStats {
+            average_fps: 1.0 / average_frame_time.max(f32::EPSILON),
+            low_1_percent_fps: 1.0 / slowest_average_frame_time.max(f32::EPSILON),
+            average_frame_time_ms: average_frame_time * 1000.0,
+        })
+    }
+
+    /// Stats computed by the last log, updated every `log_interval`.
+    #[cfg(feature = "egui")]
+    pub fn last_stats(&self) -> Option<FrameStats> {
+        self.last_stats
+    }
+}
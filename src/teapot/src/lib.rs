@@ -1,13 +1,48 @@
+mod camera;
+#[cfg(feature = "egui")]
+mod egui_overlay;
+mod frame_timer;
+mod mesh;
+mod overlay;
+#[cfg(feature = "hot-reload")]
+mod shader_watcher;
 mod teapot_data;
 mod vulkan;
 // mod vulkan_data;
 
-use vulkan::VulkanData;
-use vulkan_base::VulkanBase;
+use vulkan::{CommandBufferReset, FrameSyncMode, RenderingMode, VulkanData};
+use vulkan_base::VulkanBaseBuilder;
 
+// frames in flight, see VulkanData::new
 const CONCURRENT_RESOURCE_COUNT: u32 = 2;
+const RENDERING_MODE: RenderingMode = RenderingMode::RenderPass;
+// see CommandBufferReset for which is faster
+const COMMAND_BUFFER_RESET: CommandBufferReset = CommandBufferReset::Pool;
+// Timeline paces frames with one timeline semaphore instead of a fence per frame, see
+// vulkan_base::TimelineSync
+const FRAME_SYNC_MODE: FrameSyncMode = FrameSyncMode::Fence;
+// inserts shader.geom, which tints every triangle by its normal
+const SHOW_NORMALS: bool = false;
+// per-sample shading, requests the sample_rate_shading feature, see
+// PipelineConfig::min_sample_shading
+const MIN_SAMPLE_SHADING: Option<f32> = None;
+// skins loaded meshes to two swaying joints, see MeshData::add_sway_skin
+const SKIN_DEMO: bool = false;
+// teapots drawn on a grid, at most VulkanData::max_instance_count
+const INSTANCE_COUNT: u32 = 1;
+// resize events closer together than this, e.g. while dragging the window border, are
+// coalesced into one swapchain recreation
+const RESIZE_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(100);
+// LINE, or POINT to show the tessellated vertices as dots in wireframe mode, see
+// PipelineConfig::wireframe_polygon_mode
+const WIREFRAME_POLYGON_MODE: ash::vk::PolygonMode = ash::vk::PolygonMode::LINE;
+// drag with the left mouse button to orbit the camera around the teapot, scroll to zoom, see
+// camera::OrbitCamera
+const ORBIT_CAMERA: bool = false;
 
-pub fn main() {
+/// Returns an error when the window or Vulkan can't be initialized, e.g. without a suitable
+/// GPU. Once the event loop runs it never returns.
+pub fn main() -> Result<(), String> {
     // Window
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new()
@@ -15,45 +50,138 @@ pub fn main() {
         .with_inner_size(winit::dpi::LogicalSize::new(800.0, 600.0))
         .with_min_inner_size(winit::dpi::PhysicalSize::new(100.0, 100.0))
         .build(&event_loop)
-        .unwrap();
+        .map_err(|err| format!("failed to create window: {}", err))?;
 
     // vulkan base
     let device_extensions = vec![ash::extensions::khr::Swapchain::name()];
-    let instance_extensions = vulkan::get_required_instance_extensions(&window).unwrap();
+    let instance_extensions =
+        vulkan_base::get_required_instance_extensions(&window).map_err(|err| err.to_string())?;
 
-    let mut vk_base = match VulkanBase::new(&window, &instance_extensions, &device_extensions) {
+    let create_vk_base = |tessellation_shader: bool| {
+        VulkanBaseBuilder::new()
+            .instance_extensions(&instance_extensions)
+            .device_extensions(&device_extensions)
+            .required_features(
+                ash::vk::PhysicalDeviceFeatures::builder()
+                    .tessellation_shader(tessellation_shader)
+                    .fill_mode_non_solid(true)
+                    .geometry_shader(SHOW_NORMALS)
+                    .sample_rate_shading(MIN_SAMPLE_SHADING.is_some())
+                    .build(),
+            )
+            .optional_features(
+                ash::vk::PhysicalDeviceFeatures::builder()
+                    .wide_lines(true)
+                    .build(),
+            )
+            .enable_capture(true)
+            .application("teapot", ash::vk::make_api_version(0, 0, 1, 0))
+            .enable_dynamic_rendering(RENDERING_MODE == RenderingMode::Dynamic)
+            .enable_synchronization2(RENDERING_MODE == RenderingMode::Dynamic)
+            .enable_timeline_semaphore(FRAME_SYNC_MODE == FrameSyncMode::Timeline)
+            .build(&window)
+    };
+
+    // devices without tessellation shaders draw a CPU-built teapot, see VulkanData::new
+    let vk_base = match create_vk_base(true) {
+        Err(vulkan_base::VulkanError::NoSuitableDevice) => {
+            log::warn!("no suitable device with tessellation shaders, retrying without them");
+            create_vk_base(false)
+        }
+        result => result,
+    };
+
+    let mut vk_base = match vk_base {
         Ok(vk_base) => Some(vk_base),
-        Err(msg) => {
-            log::error!("{}", msg);
-            panic!("{}", msg);
+        Err(err) => {
+            log::error!("{}", err);
+            return Err(err.to_string());
         }
     };
 
     // vulkan data
-    let mut vk_data = match VulkanData::new(vk_base.as_mut().unwrap()) {
+    let mut vk_data = match VulkanData::new(vk_base.as_mut().unwrap(), CONCURRENT_RESOURCE_COUNT) {
         Ok(vk_data) => Some(vk_data),
         Err(msg) => {
             log::error!("{}", msg);
             let vk_base = vk_base.unwrap();
             vk_base.clean();
-            panic!("{}", msg);
+            return Err(msg);
+        }
+    };
+
+    if let Err(msg) = vk_data.as_mut().unwrap().set_instance_count(INSTANCE_COUNT) {
+        log::error!("{}", msg);
+        vulkan::vulkan_clean(&mut vk_base, &mut vk_data);
+        return Err(msg);
+    }
+
+    // a mesh file given as the first argument is drawn instead of the teapot
+    if let Some(mesh_path) = std::env::args_os().nth(1) {
+        if let Err(msg) = vk_data
+            .as_mut()
+            .unwrap()
+            .load_mesh(vk_base.as_mut().unwrap(), std::path::Path::new(&mesh_path))
+        {
+            log::error!("{}", msg);
+            vulkan::vulkan_clean(&mut vk_base, &mut vk_data);
+            return Err(msg);
+        }
+    }
+
+    #[cfg(feature = "egui")]
+    let mut egui_overlay = match RENDERING_MODE {
+        RenderingMode::RenderPass => match egui_overlay::EguiOverlay::new(
+            vk_base.as_ref().unwrap(),
+            vk_data.as_ref().unwrap().render_pass,
+            CONCURRENT_RESOURCE_COUNT as usize,
+        ) {
+            Ok(egui_overlay) => Some(egui_overlay),
+            Err(msg) => {
+                log::warn!("{}", msg);
+                None
+            }
+        },
+        RenderingMode::Dynamic => None,
+    };
+
+    // the directory build.rs writes the SPIR-V to
+    #[cfg(feature = "hot-reload")]
+    let shader_watcher = match shader_watcher::ShaderWatcher::new(std::path::Path::new("shaders")) {
+        Ok(shader_watcher) => Some(shader_watcher),
+        Err(msg) => {
+            log::warn!("{}, shaders are not reloaded", msg);
+            None
         }
     };
 
     // loop
     let mut app_exit = false;
     let start_time = std::time::Instant::now();
+    let mut frame_timer = frame_timer::FrameTimer::new(1000, std::time::Duration::from_secs(1));
+    // latest requested size and when it was requested
+    let mut pending_resize: Option<(winit::dpi::PhysicalSize<u32>, std::time::Instant)> = None;
+    let mut camera = camera::OrbitCamera::new(cgmath::Point3::new(0.0, 0.0, 0.0), 10.0);
 
     event_loop.run(move |event, _, control_flow| {
         use winit::event::ElementState;
         use winit::event::Event;
         use winit::event::KeyboardInput;
+        use winit::event::MouseButton;
+        use winit::event::MouseScrollDelta;
         use winit::event::VirtualKeyCode;
         use winit::event::WindowEvent;
         use winit::event_loop::ControlFlow;
 
         *control_flow = ControlFlow::Poll;
 
+        #[cfg(feature = "egui")]
+        if let (Event::WindowEvent { event, .. }, Some(egui_overlay)) =
+            (&event, egui_overlay.as_mut())
+        {
+            egui_overlay.on_window_event(event);
+        }
+
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -63,6 +191,8 @@ pub fn main() {
 
                 log::info!("exit requested");
 
+                #[cfg(feature = "egui")]
+                clean_egui_overlay(&mut egui_overlay, &vk_base);
                 vulkan::vulkan_clean(&mut vk_base, &mut vk_data);
 
                 app_exit = true;
@@ -81,6 +211,15 @@ pub fn main() {
                 let vk_base_ref = vk_base.as_mut().unwrap();
                 let vk_data_ref = vk_data.as_mut().unwrap();
 
+                // an out of date swapchain can't wait for the debounce
+                if let Some((size, requested_at)) = pending_resize {
+                    if vk_data_ref.should_resize || requested_at.elapsed() >= RESIZE_DEBOUNCE {
+                        log::info!("resizing to {:?}", size);
+                        pending_resize = None;
+                        vk_data_ref.should_resize = true;
+                    }
+                }
+
                 if vk_data_ref.should_resize {
                     vk_data_ref.should_resize = false;
 
@@ -88,14 +227,24 @@ pub fn main() {
 
                     if let Err(msg) = vk_base_ref.resize(&window) {
                         log::error!("{}", msg);
+                        #[cfg(feature = "egui")]
+                        clean_egui_overlay(&mut egui_overlay, &vk_base);
                         vulkan::vulkan_clean(&mut vk_base, &mut vk_data);
                         app_exit = true;
                         *control_flow = ControlFlow::Exit;
                         return;
                     }
 
-                    if let Err(msg) = vk_data_ref.resize(&vk_base_ref) {
+                    // retried every frame until the window has a non-zero size again
+                    if vk_base_ref.target.paused {
+                        vk_data_ref.should_resize = true;
+                        return;
+                    }
+
+                    if let Err(msg) = vk_data_ref.resize(vk_base_ref) {
                         log::error!("{}", msg);
+                        #[cfg(feature = "egui")]
+                        clean_egui_overlay(&mut egui_overlay, &vk_base);
                         vulkan::vulkan_clean(&mut vk_base, &mut vk_data);
                         app_exit = true;
                         *control_flow = ControlFlow::Exit;
@@ -103,20 +252,52 @@ pub fn main() {
                     }
                 }
 
+                // a broken shader keeps the previous pipelines, so it's only logged
+                #[cfg(feature = "hot-reload")]
+                if shader_watcher
+                    .as_ref()
+                    .is_some_and(shader_watcher::ShaderWatcher::take_changed)
+                {
+                    if let Err(msg) = vk_data_ref.reload_shaders(vk_base_ref) {
+                        log::error!("{}", msg);
+                    }
+                }
+
+                #[cfg(feature = "egui")]
+                let overlay = match egui_overlay.as_mut() {
+                    Some(egui_overlay) => {
+                        if let Err(msg) = run_egui_overlay(
+                            egui_overlay,
+                            vk_base_ref,
+                            vk_data_ref,
+                            frame_timer.last_stats(),
+                        ) {
+                            log::warn!("{}", msg);
+                        }
+                        Some(egui_overlay as &mut dyn overlay::OverlayRenderer)
+                    }
+                    None => None,
+                };
+                #[cfg(not(feature = "egui"))]
+                let overlay = None;
+
                 if let Err(msg) = vulkan::draw(
                     vk_data_ref,
                     vk_base_ref,
                     (std::time::Instant::now() - start_time).as_secs_f32(),
+                    &camera,
+                    overlay,
                 ) {
                     log::error!("{}", msg);
+                    #[cfg(feature = "egui")]
+                    clean_egui_overlay(&mut egui_overlay, &vk_base);
                     vulkan::vulkan_clean(&mut vk_base, &mut vk_data);
                     app_exit = true;
                     *control_flow = ControlFlow::Exit;
                     return;
                 }
 
-                vk_data_ref.curr_resource_index =
-                    (vk_data_ref.curr_resource_index + 1) % CONCURRENT_RESOURCE_COUNT;
+                frame_timer.tick();
             }
 
             Event::WindowEvent {
@@ -125,10 +306,50 @@ pub fn main() {
             } => {
                 log::info!("resize requested {:?}", physical_size);
 
-                let vk_data = vk_data.as_mut().unwrap();
-                vk_data.should_resize = true;
+                pending_resize = Some((physical_size, std::time::Instant::now()));
             }
 
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state,
+                        button: MouseButton::Left,
+                        ..
+                    },
+                ..
+            } if ORBIT_CAMERA => {
+                let is_pressed = state == ElementState::Pressed;
+                if camera.on_mouse_button(is_pressed) {
+                    set_cursor_grab(&window, is_pressed);
+                }
+            }
+
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } if ORBIT_CAMERA => camera.on_cursor_moved((position.x, position.y)),
+
+            Event::WindowEvent {
+                event: WindowEvent::CursorLeft { .. },
+                ..
+            } if ORBIT_CAMERA => camera.on_cursor_left(),
+
+            // the release of a drag that ends outside of the focused window is never reported
+            Event::WindowEvent {
+                event: WindowEvent::Focused(false),
+                ..
+            } if ORBIT_CAMERA && camera.cancel_drag() => set_cursor_grab(&window, false),
+
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel { delta, .. },
+                ..
+            } if ORBIT_CAMERA => camera.on_scroll(match delta {
+                MouseScrollDelta::LineDelta(_, lines) => lines,
+                MouseScrollDelta::PixelDelta(position) => {
+                    camera::OrbitCamera::lines_from_pixels(position.y)
+                }
+            }),
+
             Event::WindowEvent {
                 event:
                     WindowEvent::KeyboardInput {
@@ -144,17 +365,73 @@ pub fn main() {
             } => match virtual_code {
                 VirtualKeyCode::Space => {
                     let vk_data = vk_data.as_mut().unwrap();
-                    vk_data.is_wireframe_mode = !vk_data.is_wireframe_mode;
+                    vk_data.set_wireframe(!vk_data.is_wireframe_mode);
+                }
+                VirtualKeyCode::F12 => {
+                    let vk_data = vk_data.as_mut().unwrap();
+                    vk_data.capture_path = Some(std::path::PathBuf::from("screenshot.png"));
+                }
+                VirtualKeyCode::M => {
+                    let vk_base = vk_base.as_ref().unwrap();
+                    log::info!("{}", vk_base.memory_report());
+                }
+                VirtualKeyCode::V => {
+                    let vk_base = vk_base.as_mut().unwrap();
+                    let vk_data = vk_data.as_mut().unwrap();
+
+                    let supported_modes = match vk_base.supported_present_modes() {
+                        Ok(modes) => modes,
+                        Err(msg) => {
+                            log::warn!("{}", msg);
+                            return;
+                        }
+                    };
+
+                    // cycles through the supported modes, vsynced ones first
+                    let cycle = [
+                        ash::vk::PresentModeKHR::FIFO,
+                        ash::vk::PresentModeKHR::FIFO_RELAXED,
+                        ash::vk::PresentModeKHR::MAILBOX,
+                        ash::vk::PresentModeKHR::IMMEDIATE,
+                    ];
+                    let current = cycle
+                        .iter()
+                        .position(|&mode| mode == vk_base.target.present_mode)
+                        .unwrap_or(0);
+                    let present_mode = (1..=cycle.len())
+                        .map(|offset| cycle[(current + offset) % cycle.len()])
+                        .find(|mode| supported_modes.contains(mode))
+                        .unwrap_or(ash::vk::PresentModeKHR::FIFO);
+
+                    match vk_base.set_present_mode(present_mode, &window) {
+                        Ok(_) => {
+                            if let Err(msg) = vk_data.resize(vk_base) {
+                                log::error!("{}", msg);
+                                vk_data.should_resize = true;
+                            }
+                        }
+                        Err(msg) => log::warn!("{}", msg),
+                    }
                 }
                 VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => {
+                    let vk_base = vk_base.as_ref().unwrap();
                     let vk_data = vk_data.as_mut().unwrap();
-                    vk_data.tesselation_level += 0.1f32;
-                    vk_data.tesselation_level = vk_data.tesselation_level.min(64.0);
+                    vk_data.set_tessellation_level(vk_data.tesselation_level + 0.1f32, vk_base);
                 }
                 VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                    let vk_base = vk_base.as_ref().unwrap();
+                    let vk_data = vk_data.as_mut().unwrap();
+                    vk_data.set_tessellation_level(vk_data.tesselation_level - 0.1f32, vk_base);
+                }
+                VirtualKeyCode::RBracket => {
+                    let vk_base = vk_base.as_ref().unwrap();
+                    let vk_data = vk_data.as_mut().unwrap();
+                    vk_data.set_line_width(vk_data.line_width + 1.0f32, vk_base);
+                }
+                VirtualKeyCode::LBracket => {
+                    let vk_base = vk_base.as_ref().unwrap();
                     let vk_data = vk_data.as_mut().unwrap();
-                    vk_data.tesselation_level -= 0.1f32;
-                    vk_data.tesselation_level = vk_data.tesselation_level.max(1.0);
+                    vk_data.set_line_width(vk_data.line_width - 1.0f32, vk_base);
                 }
                 _ => (),
             },
@@ -163,3 +440,72 @@ pub fn main() {
         }
     });
 }
+
+// keeps the cursor in the window while dragging, not every platform supports confining it
+fn set_cursor_grab(window: &winit::window::Window, grab: bool) {
+    let mode = match grab {
+        true => winit::window::CursorGrabMode::Confined,
+        false => winit::window::CursorGrabMode::None,
+    };
+
+    if let Err(err) = window.set_cursor_grab(mode) {
+        log::info!("failed to set cursor grab {:?}: {}", mode, err);
+    }
+}
+
+#[cfg(feature = "egui")]
+fn run_egui_overlay(
+    egui_overlay: &mut egui_overlay::EguiOverlay,
+    vk_base: &vulkan_base::VulkanBase,
+    vk_data: &mut VulkanData,
+    frame_stats: Option<frame_timer::FrameStats>,
+) -> Result<(), String> {
+    let mut is_wireframe = vk_data.is_wireframe_mode;
+    let mut tesselation_level = vk_data.tesselation_level;
+    let mut line_width = vk_data.line_width;
+    let gpu_time = vk_data
+        .gpu_timer
+        .as_ref()
+        .and_then(|gpu_timer| gpu_timer.last_duration_ms());
+
+    egui_overlay.run(&vk_base.device, vk_base.target.surface_extent, |context| {
+        egui::Window::new("Teapot").show(context, |ui| {
+            if let Some(stats) = frame_stats {
+                ui.label(format!(
+                    "{:.1} fps ({:.2} ms), 1% low {:.1} fps",
+                    stats.average_fps, stats.average_frame_time_ms, stats.low_1_percent_fps
+                ));
+            }
+            if let Some(gpu_time) = gpu_time {
+                ui.label(format!("gpu {:.2} ms", gpu_time));
+            }
+            ui.checkbox(&mut is_wireframe, "wireframe");
+            ui.add(egui::Slider::new(&mut tesselation_level, 1.0..=64.0).text("tessellation"));
+            ui.add(egui::Slider::new(&mut line_width, 1.0..=8.0).text("line width"));
+        });
+    })?;
+
+    if is_wireframe != vk_data.is_wireframe_mode {
+        vk_data.set_wireframe(is_wireframe);
+    }
+
+    if tesselation_level != vk_data.tesselation_level {
+        vk_data.set_tessellation_level(tesselation_level, vk_base);
+    }
+
+    if line_width != vk_data.line_width {
+        vk_data.set_line_width(line_width, vk_base);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "egui")]
+fn clean_egui_overlay(
+    egui_overlay: &mut Option<egui_overlay::EguiOverlay>,
+    vk_base: &Option<vulkan_base::VulkanBase>,
+) {
+    if let (Some(egui_overlay), Some(vk_base)) = (egui_overlay.take(), vk_base.as_ref()) {
+        egui_overlay.clean(&vk_base.device);
+    }
+}
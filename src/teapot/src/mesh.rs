@@ -0,0 +1,324 @@
+use crate::teapot_data::Vertex;
+use cgmath::{Deg, InnerSpace, Matrix4, Vector3};
+use std::path::Path;
+
+// position (vec3), normal (vec3), uv (vec2), interleaved
+pub const MESH_VERTEX_FLOAT_COUNT: usize = 8;
+
+// joints influencing a vertex, unused ones have a 0 weight
+pub const SKIN_INFLUENCE_COUNT: usize = 4;
+
+/// Per vertex joint indices and weights, `SKIN_INFLUENCE_COUNT` of each. Vertices are skinned
+/// on the GPU by `VulkanSkin`.
+pub struct SkinData {
+    pub joints: Vec<u32>,
+    pub weights: Vec<f32>,
+    pub joint_count: u32,
+}
+
+/// Indexed triangle list, see `load_mesh`.
+pub struct MeshData {
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+    pub skin: Option<SkinData>,
+}
+
+impl MeshData {
+    /// Triangles of `vertices`, e.g. from `teapot_data::tessellate_bezier_patches`, fitted like
+    /// `load_mesh` does. UVs are 0.
+    pub fn from_vertices(vertices: &[Vertex], indices: Vec<u32>) -> Self {
+        let mut mesh_vertices = Vec::with_capacity(vertices.len() * MESH_VERTEX_FLOAT_COUNT);
+        for vertex in vertices {
+            mesh_vertices.extend_from_slice(&vertex.position);
+            mesh_vertices.extend_from_slice(&vertex.normal);
+            mesh_vertices.extend_from_slice(&[0.0, 0.0]);
+        }
+
+        let mut mesh_data = MeshData {
+            vertices: mesh_vertices,
+            indices,
+            skin: None,
+        };
+        mesh_data.fit_to_unit_box();
+
+        mesh_data
+    }
+
+    pub fn get_vertices_slice(&self) -> &[u8] {
+        bytemuck::cast_slice(&self.vertices)
+    }
+
+    pub fn get_indices_slice(&self) -> &[u8] {
+        bytemuck::cast_slice(&self.indices)
+    }
+
+    pub fn get_vertex_count(&self) -> u32 {
+        (self.vertices.len() / MESH_VERTEX_FLOAT_COUNT) as u32
+    }
+
+    pub fn get_index_count(&self) -> u32 {
+        self.indices.len() as u32
+    }
+
+    // centers the bounding box at the origin and scales its largest side to 2
+    fn fit_to_unit_box(&mut self) {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+
+        for vertex in self.vertices.chunks(MESH_VERTEX_FLOAT_COUNT) {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex[axis]);
+                max[axis] = max[axis].max(vertex[axis]);
+            }
+        }
+
+        let half_size = (0..3)
+            .map(|axis| (max[axis] - min[axis]) * 0.5)
+            .fold(0.0f32, f32::max);
+        let scale = if half_size > 0.0 {
+            1.0 / half_size
+        } else {
+            1.0
+        };
+
+        for vertex in self.vertices.chunks_mut(MESH_VERTEX_FLOAT_COUNT) {
+            for axis in 0..3 {
+                vertex[axis] = (vertex[axis] - (min[axis] + max[axis]) * 0.5) * scale;
+            }
+        }
+    }
+
+    // smooth normals for files that come without them, face normals are weighted by area
+    fn compute_normals(&mut self) {
+        let position = |vertices: &[f32], index: u32| {
+            let start = index as usize * MESH_VERTEX_FLOAT_COUNT;
+            Vector3::new(vertices[start], vertices[start + 1], vertices[start + 2])
+        };
+
+        let mut normals = vec![Vector3::new(0.0f32, 0.0, 0.0); self.get_vertex_count() as usize];
+
+        for triangle in self.indices.chunks_exact(3) {
+            let p0 = position(&self.vertices, triangle[0]);
+            let p1 = position(&self.vertices, triangle[1]);
+            let p2 = position(&self.vertices, triangle[2]);
+
+            let face_normal = (p1 - p0).cross(p2 - p0);
+
+            for &index in triangle {
+                normals[index as usize] += face_normal;
+            }
+        }
+
+        for (vertex, normal) in self
+            .vertices
+            .chunks_mut(MESH_VERTEX_FLOAT_COUNT)
+            .zip(normals)
+        {
+            let normal = if normal.magnitude2() > 0.0 {
+                normal.normalize()
+            } else {
+                normal
+            };
+
+            vertex[3..6].copy_from_slice(&[normal.x, normal.y, normal.z]);
+        }
+    }
+
+    /// Skins the mesh to two joints, the bottom follows joint 0 and the top joint 1, blended by
+    /// height. For trying out skinning without an animated file, see `get_sway_joint_matrices`.
+    pub fn add_sway_skin(&mut self) {
+        let vertex_count = self.get_vertex_count() as usize;
+
+        let mut joints = Vec::with_capacity(vertex_count * SKIN_INFLUENCE_COUNT);
+        let mut weights = Vec::with_capacity(vertex_count * SKIN_INFLUENCE_COUNT);
+
+        for vertex in self.vertices.chunks(MESH_VERTEX_FLOAT_COUNT) {
+            // fit_to_unit_box puts y in [-1, 1]
+            let top_weight = ((vertex[1] + 1.0) * 0.5).clamp(0.0, 1.0);
+
+            joints.extend_from_slice(&[0, 1, 0, 0]);
+            weights.extend_from_slice(&[1.0 - top_weight, top_weight, 0.0, 0.0]);
+        }
+
+        self.skin = Some(SkinData {
+            joints,
+            weights,
+            joint_count: 2,
+        });
+    }
+}
+
+/// Joint matrices for `MeshData::add_sway_skin`, joint 1 rocks around the z axis.
+pub fn get_sway_joint_matrices(time_sec: f32) -> Vec<Matrix4<f32>> {
+    vec![
+        Matrix4::from_scale(1.0),
+        Matrix4::from_angle_z(Deg((time_sec * 2.0).sin() * 30.0)),
+    ]
+}
+
+/// Loads `.obj` files with the `obj` feature and `.gltf`/`.glb` files with the `gltf` feature.
+/// All meshes of the file are merged into one, node transforms and materials are ignored. The
+/// result is centered at the origin and scaled to fit `[-1, 1]` on every axis.
+pub fn load_mesh(path: &Path) -> Result<MeshData, String> {
+    log::info!("{}: loading mesh", path.display());
+
+    let (mut mesh_data, has_normals) = load_file(path)?;
+
+    if mesh_data.indices.is_empty() {
+        return Err(format!("{}: no triangles found", path.display()));
+    }
+
+    let vertex_count = mesh_data.get_vertex_count();
+    if mesh_data.indices.iter().any(|&index| index >= vertex_count) {
+        return Err(format!("{}: index out of range", path.display()));
+    }
+
+    if !has_normals {
+        log::info!("{}: computing normals", path.display());
+        mesh_data.compute_normals();
+    }
+
+    mesh_data.fit_to_unit_box();
+
+    log::info!(
+        "{}: mesh loaded, {} vertices, {} triangles",
+        path.display(),
+        vertex_count,
+        mesh_data.indices.len() / 3
+    );
+
+    Ok(mesh_data)
+}
+
+// picks the loader by file extension, also returns whether every vertex came with a normal
+fn load_file(path: &Path) -> Result<(MeshData, bool), String> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        #[cfg(feature = "obj")]
+        Some("obj") => load_obj(path),
+        #[cfg(not(feature = "obj"))]
+        Some("obj") => Err(format!(
+            "{}: loading obj files needs the obj feature",
+            path.display()
+        )),
+        #[cfg(feature = "gltf")]
+        Some("gltf" | "glb") => load_gltf(path),
+        #[cfg(not(feature = "gltf"))]
+        Some("gltf" | "glb") => Err(format!(
+            "{}: loading gltf files needs the gltf feature",
+            path.display()
+        )),
+        _ => Err(format!("{}: unsupported mesh format", path.display())),
+    }
+}
+
+#[cfg(feature = "obj")]
+fn load_obj(path: &Path) -> Result<(MeshData, bool), String> {
+    let load_options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+
+    let (models, _) = tobj::load_obj(path, &load_options)
+        .map_err(|err| format!("{}: failed to load: {}", path.display(), err))?;
+
+    let mut mesh_data = MeshData {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        skin: None,
+    };
+    let mut has_normals = true;
+
+    for model in models {
+        let mesh = model.mesh;
+        let base_vertex = mesh_data.get_vertex_count();
+
+        has_normals &= mesh.normals.len() == mesh.positions.len();
+
+        for i in 0..mesh.positions.len() / 3 {
+            mesh_data
+                .vertices
+                .extend_from_slice(&mesh.positions[i * 3..i * 3 + 3]);
+            mesh_data
+                .vertices
+                .extend_from_slice(mesh.normals.get(i * 3..i * 3 + 3).unwrap_or(&[0.0; 3]));
+            mesh_data
+                .vertices
+                .extend_from_slice(mesh.texcoords.get(i * 2..i * 2 + 2).unwrap_or(&[0.0; 2]));
+        }
+
+        mesh_data
+            .indices
+            .extend(mesh.indices.iter().map(|index| base_vertex + index));
+    }
+
+    Ok((mesh_data, has_normals))
+}
+
+#[cfg(feature = "gltf")]
+fn load_gltf(path: &Path) -> Result<(MeshData, bool), String> {
+    let (document, buffers, _) =
+        gltf::import(path).map_err(|err| format!("{}: failed to load: {}", path.display(), err))?;
+
+    let mut mesh_data = MeshData {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        skin: None,
+    };
+    let mut has_normals = true;
+
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!(
+                    "{}: skipping {:?} primitive",
+                    path.display(),
+                    primitive.mode()
+                );
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+            let normals: Option<Vec<[f32; 3]>> =
+                reader.read_normals().map(|normals| normals.collect());
+            let uvs: Option<Vec<[f32; 2]>> = reader
+                .read_tex_coords(0)
+                .map(|uvs| uvs.into_f32().collect());
+
+            let base_vertex = mesh_data.get_vertex_count();
+
+            has_normals &= normals.is_some();
+
+            for (i, position) in positions.iter().enumerate() {
+                mesh_data.vertices.extend_from_slice(position);
+                mesh_data
+                    .vertices
+                    .extend_from_slice(normals.as_ref().map_or(&[0.0; 3], |normals| &normals[i]));
+                mesh_data
+                    .vertices
+                    .extend_from_slice(uvs.as_ref().map_or(&[0.0; 2], |uvs| &uvs[i]));
+            }
+
+            match reader.read_indices() {
+                Some(indices) => mesh_data
+                    .indices
+                    .extend(indices.into_u32().map(|index| base_vertex + index)),
+                None => mesh_data
+                    .indices
+                    .extend(base_vertex..base_vertex + positions.len() as u32),
+            }
+        }
+    }
+
+    Ok((mesh_data, has_normals))
+}
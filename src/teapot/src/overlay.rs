@@ -0,0 +1,15 @@
+use ash::vk;
+
+/// Extra draws recorded into the frame command buffer after the teapot, before the render pass
+/// or dynamic rendering ends, e.g. a debug UI.
+///
+/// The overlay renders into the same color and depth attachments as the teapot and is submitted
+/// with the frame, so it shares the frame's command pool, fence and queue.
+pub trait OverlayRenderer {
+    fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        extent: vk::Extent2D,
+    ) -> Result<(), String>;
+}
@@ -0,0 +1,58 @@
+use notify::Watcher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Notices SPIR-V files written to a directory, e.g. by build.rs or glslc, so the shaders can
+/// be reloaded with `VulkanData::reload_shaders`.
+pub struct ShaderWatcher {
+    // watching stops when it's dropped
+    _watcher: notify::RecommendedWatcher,
+    is_changed: Arc<AtomicBool>,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: &std::path::Path) -> Result<Self, String> {
+        let is_changed = Arc::new(AtomicBool::new(false));
+
+        let mut watcher = {
+            let is_changed = is_changed.clone();
+            notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+                Ok(event) if is_spirv_write(&event) => is_changed.store(true, Ordering::Relaxed),
+                Ok(_) => {}
+                Err(err) => log::warn!("shader watcher: {}", err),
+            })
+            .map_err(|err| format!("failed to create shader watcher: {}", err))?
+        };
+
+        watcher
+            .watch(dir, notify::RecursiveMode::NonRecursive)
+            .map_err(|err| format!("failed to watch {:?}: {}", dir, err))?;
+
+        log::info!("watching {:?} for shader changes", dir);
+
+        Ok(ShaderWatcher {
+            _watcher: watcher,
+            is_changed,
+        })
+    }
+
+    /// Returns whether a shader was written since the last call. Every write of a file is
+    /// reported, so a reload may see a partially written one and simply fails, the next write
+    /// reports it again.
+    pub fn take_changed(&self) -> bool {
+        self.is_changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+fn is_spirv_write(event: &notify::Event) -> bool {
+    let is_write = matches!(
+        event.kind,
+        notify::EventKind::Create(_) | notify::EventKind::Modify(_)
+    );
+
+    is_write
+        && event
+            .paths
+            .iter()
+            .any(|path| path.extension().is_some_and(|extension| extension == "spv"))
+}
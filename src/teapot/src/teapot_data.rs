@@ -1,3 +1,5 @@
+use cgmath::{InnerSpace, Vector3};
+
 pub struct TeapotData {
     control_points: Vec<f32>,
     patches: Vec<u16>,
@@ -196,15 +198,214 @@ impl TeapotData {
         bytemuck::cast_slice(&self.patches)
     }
     
-    pub fn get_instances_slice(&self) -> &[u8] {
-        bytemuck::cast_slice(&self.instances)
-    }
-    
     pub fn get_patch_point_count(&self) -> u32 {
         self.patches.len() as u32
     }
+
+    pub fn get_patch_count(&self) -> u32 {
+        (self.instances.len() / PATCH_DATA_FLOAT_COUNT) as u32
+    }
+
+    /// Patch data of `instance_count` teapots on a square grid in the xy plane, `spacing`
+    /// apart. Instance `i` uses entries `i * get_patch_count()..`.
+    pub fn get_grid_instances(&self, instance_count: u32, spacing: f32) -> Vec<f32> {
+        let side = (instance_count as f32).sqrt().ceil().max(1.0) as u32;
+        let center = (side - 1) as f32 * 0.5;
+
+        let mut grid_instances = Vec::with_capacity(self.instances.len() * instance_count as usize);
+
+        for i in 0..instance_count {
+            let offset_x = ((i % side) as f32 - center) * spacing;
+            let offset_y = ((i / side) as f32 - center) * spacing;
+
+            for patch_data in self.instances.chunks(PATCH_DATA_FLOAT_COUNT) {
+                let start = grid_instances.len();
+                grid_instances.extend_from_slice(patch_data);
+
+                // translation column of the column-major transform
+                grid_instances[start + 12] += offset_x;
+                grid_instances[start + 13] += offset_y;
+            }
+        }
+
+        grid_instances
+    }
+
+    /// The teapot as a triangle mesh with the patch transforms applied, see
+    /// `tessellate_bezier_patches`. Drawn on devices without tessellation shaders.
+    pub fn tessellate(&self, subdivisions: u32) -> (Vec<Vertex>, Vec<u32>) {
+        let (mut vertices, indices) =
+            tessellate_bezier_patches(&self.control_points, &self.patches, subdivisions);
+
+        let patch_vertex_count = (subdivisions.max(1) + 1).pow(2) as usize;
+
+        for (patch_vertices, patch_data) in vertices
+            .chunks_mut(patch_vertex_count)
+            .zip(self.instances.chunks(PATCH_DATA_FLOAT_COUNT))
+        {
+            let transform = &patch_data[..16];
+
+            // the transforms are rotations and mirrors, a mirror also flips the winding
+            let normal_sign = get_determinant_3x3(transform).signum();
+
+            for vertex in patch_vertices {
+                vertex.position = transform_point(transform, &vertex.position, 1.0);
+                vertex.normal =
+                    transform_point(transform, &vertex.normal, 0.0).map(|c| c * normal_sign);
+            }
+        }
+
+        (vertices, indices)
+    }
+}
+
+/// A point of a tessellated surface, see `tessellate_bezier_patches`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+/// Evaluates bicubic Bezier patches on the CPU, like shader.tese does on the GPU.
+/// `control_points` holds xyz triples and `patches` 16 point indices per patch, row by row.
+/// Every patch becomes a grid of `(subdivisions + 1)^2` vertices, patch after patch, with
+/// normals from the surface derivatives. The normals face the side the triangles wind
+/// clockwise towards.
+pub fn tessellate_bezier_patches(
+    control_points: &[f32],
+    patches: &[u16],
+    subdivisions: u32,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let subdivisions = subdivisions.max(1);
+    let side = subdivisions + 1;
+    let patch_count = patches.len() / 16;
+
+    let mut vertices = Vec::with_capacity(patch_count * (side * side) as usize);
+    let mut indices = Vec::with_capacity(patch_count * (subdivisions * subdivisions * 6) as usize);
+
+    for patch in patches.chunks_exact(16) {
+        let points: Vec<Vector3<f32>> = patch
+            .iter()
+            .map(|&index| {
+                let start = index as usize * 3;
+                Vector3::new(
+                    control_points[start],
+                    control_points[start + 1],
+                    control_points[start + 2],
+                )
+            })
+            .collect();
+
+        let first = vertices.len() as u32;
+
+        for row in 0..side {
+            let v = row as f32 / subdivisions as f32;
+            for col in 0..side {
+                let u = col as f32 / subdivisions as f32;
+                vertices.push(evaluate_bezier_patch(&points, u, v));
+            }
+        }
+
+        for row in 0..subdivisions {
+            for col in 0..subdivisions {
+                let i = first + row * side + col;
+                indices.extend_from_slice(&[i, i + 1, i + side, i + 1, i + side + 1, i + side]);
+            }
+        }
+    }
+
+    (vertices, indices)
+}
+
+fn evaluate_bezier_patch(points: &[Vector3<f32>], u: f32, v: f32) -> Vertex {
+    let (position, mut normal) = evaluate_bezier_surface(points, u, v);
+
+    // collapsed edges, like the apex of the lid, have a zero derivative along them. A point
+    // slightly inside the patch gives the limit normal
+    if normal.magnitude2() < 1e-12 {
+        normal = evaluate_bezier_surface(points, u + (0.5 - u) * 1e-3, v + (0.5 - v) * 1e-3).1;
+    }
+
+    if normal.magnitude2() > 0.0 {
+        normal = normal.normalize();
+    }
+
+    Vertex {
+        position: position.into(),
+        normal: normal.into(),
+    }
+}
+
+// position and the cross product of the derivatives in u and v
+fn evaluate_bezier_surface(
+    points: &[Vector3<f32>],
+    u: f32,
+    v: f32,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let (basis_u, derivative_u) = get_bernstein_basis(u);
+    let (basis_v, derivative_v) = get_bernstein_basis(v);
+
+    let combine = |weights_u: &[f32; 4], weights_v: &[f32; 4]| {
+        points
+            .iter()
+            .enumerate()
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, (i, &point)| {
+                sum + point * (weights_u[i % 4] * weights_v[i / 4])
+            })
+    };
+
+    let position = combine(&basis_u, &basis_v);
+    let tangent_u = combine(&derivative_u, &basis_v);
+    let tangent_v = combine(&basis_u, &derivative_v);
+
+    (position, tangent_u.cross(tangent_v))
+}
+
+// cubic Bernstein polynomials at t and their derivatives
+fn get_bernstein_basis(t: f32) -> ([f32; 4], [f32; 4]) {
+    let inv_t = 1.0 - t;
+
+    (
+        [
+            inv_t * inv_t * inv_t,
+            3.0 * t * inv_t * inv_t,
+            3.0 * t * t * inv_t,
+            t * t * t,
+        ],
+        [
+            -3.0 * inv_t * inv_t,
+            3.0 * inv_t * (1.0 - 3.0 * t),
+            3.0 * t * (2.0 - 3.0 * t),
+            3.0 * t * t,
+        ],
+    )
+}
+
+// column-major like PatchData::transform in shader.tese, `w` is 0 for directions
+fn transform_point(transform: &[f32], point: &[f32; 3], w: f32) -> [f32; 3] {
+    let mut result = [0.0f32; 3];
+
+    for (row, value) in result.iter_mut().enumerate() {
+        *value = transform[row] * point[0]
+            + transform[4 + row] * point[1]
+            + transform[8 + row] * point[2]
+            + transform[12 + row] * w;
+    }
+
+    result
 }
 
+fn get_determinant_3x3(transform: &[f32]) -> f32 {
+    let m = |col: usize, row: usize| transform[col * 4 + row];
+
+    m(0, 0) * (m(1, 1) * m(2, 2) - m(2, 1) * m(1, 2))
+        - m(1, 0) * (m(0, 1) * m(2, 2) - m(2, 1) * m(0, 2))
+        + m(2, 0) * (m(0, 1) * m(1, 2) - m(1, 1) * m(0, 2))
+}
+
+// mat4 transform and vec4 color, see PatchData in shader.tese
+const PATCH_DATA_FLOAT_COUNT: usize = 20;
+
 fn push_rotation_z(v: &mut Vec<f32>, ang_rad: f32) {
     v.push(ang_rad.cos());
     v.push(-ang_rad.sin());
@@ -277,3 +478,67 @@ fn push_color(v: &mut Vec<f32>, r: f32, g: f32, b: f32, a: f32) {
     v.push(b);
     v.push(a);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 4x4 grid of control points 1 apart in the z = 0 plane, as one patch
+    fn flat_patch() -> (Vec<f32>, Vec<u16>) {
+        let mut control_points = Vec::new();
+        for row in 0..4 {
+            for col in 0..4 {
+                control_points.extend_from_slice(&[col as f32, row as f32, 0.0]);
+            }
+        }
+
+        (control_points, (0..16).collect())
+    }
+
+    #[test]
+    fn flat_patch_tessellates_to_a_planar_grid() {
+        let (control_points, patches) = flat_patch();
+
+        for subdivisions in [1, 4, 7] {
+            let (vertices, indices) =
+                tessellate_bezier_patches(&control_points, &patches, subdivisions);
+
+            let side = subdivisions as usize + 1;
+            assert_eq!(vertices.len(), side * side);
+            assert_eq!(indices.len(), (subdivisions * subdivisions * 6) as usize);
+            assert!(indices
+                .iter()
+                .all(|&index| (index as usize) < vertices.len()));
+
+            // evenly spaced control points give an evenly spaced grid
+            let step = 3.0 / subdivisions as f32;
+            for (i, vertex) in vertices.iter().enumerate() {
+                let expected = [(i % side) as f32 * step, (i / side) as f32 * step, 0.0];
+                for (coordinate, expected) in vertex.position.iter().zip(expected) {
+                    assert!((coordinate - expected).abs() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flat_patch_normals_point_consistently() {
+        let (control_points, patches) = flat_patch();
+
+        let (vertices, indices) = tessellate_bezier_patches(&control_points, &patches, 5);
+
+        let sign = vertices[0].normal[2].signum();
+        for vertex in &vertices {
+            assert!(vertex.normal[0].abs() < 1e-5 && vertex.normal[1].abs() < 1e-5);
+            assert!((vertex.normal[2] - sign).abs() < 1e-5);
+        }
+
+        // and agree with the winding of every triangle
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] =
+                [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+            let face_normal = (b - a).cross(c - a);
+            assert_eq!(face_normal.z.signum(), sign);
+        }
+    }
+}
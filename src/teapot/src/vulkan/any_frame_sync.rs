@@ -0,0 +1,92 @@
+use ash::vk;
+use vulkan_base::{FrameSync, TimelineSync, VulkanBase, VulkanError};
+
+/// Which frame synchronization `VulkanData` uses, see `FRAME_SYNC_MODE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameSyncMode {
+    /// `FrameSync`, a fence per frame in flight.
+    Fence,
+    /// `TimelineSync`, a single timeline semaphore. Needs the device created with
+    /// `enable_timeline_semaphore`.
+    // only picked by editing FRAME_SYNC_MODE
+    #[allow(dead_code)]
+    Timeline,
+}
+
+/// The `FrameSync` or `TimelineSync` picked by `FrameSyncMode`, both pace frames the same way.
+pub enum AnyFrameSync {
+    Fence(FrameSync),
+    Timeline(TimelineSync),
+}
+
+impl AnyFrameSync {
+    pub fn new(
+        vulkan_base: &VulkanBase,
+        frame_count: u32,
+        mode: FrameSyncMode,
+    ) -> Result<Self, VulkanError> {
+        Ok(match mode {
+            FrameSyncMode::Fence => AnyFrameSync::Fence(FrameSync::new(vulkan_base, frame_count)?),
+            FrameSyncMode::Timeline => {
+                AnyFrameSync::Timeline(TimelineSync::new(vulkan_base, frame_count)?)
+            }
+        })
+    }
+
+    pub fn frame_index(&self) -> u32 {
+        match self {
+            AnyFrameSync::Fence(frame_sync) => frame_sync.frame_index,
+            AnyFrameSync::Timeline(timeline_sync) => timeline_sync.frame_index,
+        }
+    }
+
+    pub fn resize(&mut self, vulkan_base: &VulkanBase) -> Result<(), VulkanError> {
+        match self {
+            AnyFrameSync::Fence(frame_sync) => frame_sync.resize(vulkan_base),
+            AnyFrameSync::Timeline(timeline_sync) => timeline_sync.resize(vulkan_base),
+        }
+    }
+
+    pub fn begin_frame(&mut self, vulkan_base: &VulkanBase) -> Result<Option<u32>, VulkanError> {
+        match self {
+            AnyFrameSync::Fence(frame_sync) => frame_sync.begin_frame(vulkan_base),
+            AnyFrameSync::Timeline(timeline_sync) => timeline_sync.begin_frame(vulkan_base),
+        }
+    }
+
+    pub fn submit(
+        &mut self,
+        vulkan_base: &VulkanBase,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+    ) -> Result<(), VulkanError> {
+        match self {
+            AnyFrameSync::Fence(frame_sync) => {
+                frame_sync.submit(vulkan_base, command_buffer, image_index)
+            }
+            AnyFrameSync::Timeline(timeline_sync) => {
+                timeline_sync.submit(vulkan_base, command_buffer, image_index)
+            }
+        }
+    }
+
+    pub fn present(
+        &mut self,
+        vulkan_base: &VulkanBase,
+        image_index: u32,
+    ) -> Result<bool, VulkanError> {
+        match self {
+            AnyFrameSync::Fence(frame_sync) => frame_sync.present(vulkan_base, image_index),
+            AnyFrameSync::Timeline(timeline_sync) => {
+                timeline_sync.present(vulkan_base, image_index)
+            }
+        }
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        match self {
+            AnyFrameSync::Fence(frame_sync) => frame_sync.clean(device),
+            AnyFrameSync::Timeline(timeline_sync) => timeline_sync.clean(device),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use ash::vk;
+use scopeguard::{guard, ScopeGuard};
+
+/// A compute pipeline with its storage buffers bound to set 0 in the order they were given,
+/// recorded before the frame's rendering, see `VulkanSkin`.
+pub struct ComputePass {
+    pub shader_module: vk::ShaderModule,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set: vk::DescriptorSet,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    /// `local_size_x` of the shader.
+    pub local_size: u32,
+}
+
+impl ComputePass {
+    /// Takes ownership of `shader_module`, also when creation fails. Push constants are
+    /// disabled when `push_constant_size` is 0.
+    pub fn new(
+        device: &ash::Device,
+        debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+        shader_module: vk::ShaderModule,
+        storage_buffers: &[vk::Buffer],
+        push_constant_size: u32,
+        local_size: u32,
+        object_name: &str,
+    ) -> Result<Self, String> {
+        log::info!("{}: creating compute pass", object_name);
+
+        let shader_module_sg = guard(shader_module, |sm| {
+            log::warn!("{} shader module scopeguard", object_name);
+            unsafe {
+                device.destroy_shader_module(sm, None);
+            }
+        });
+
+        let descriptor_set_layout_sg = {
+            let bindings = (0..storage_buffers.len())
+                .map(|binding| {
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(binding as u32)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build()
+                })
+                .collect::<Vec<_>>();
+
+            let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&bindings)
+                .build();
+
+            let descriptor_set_layout = unsafe {
+                device
+                    .create_descriptor_set_layout(&create_info, None)
+                    .map_err(|_| {
+                        format!("{}: failed to create descriptor set layout", object_name)
+                    })?
+            };
+
+            vulkan_utils::set_debug_utils_object_name(
+                debug_utils_loader,
+                device.handle(),
+                descriptor_set_layout,
+                &format!("{} descriptor set layout", object_name),
+            );
+
+            guard(descriptor_set_layout, |layout| {
+                log::warn!("{} descriptor set layout scopeguard", object_name);
+                unsafe {
+                    device.destroy_descriptor_set_layout(layout, None);
+                }
+            })
+        };
+
+        let descriptor_pool_sg = {
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: storage_buffers.len() as u32,
+            }];
+
+            let create_info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes)
+                .build();
+
+            let descriptor_pool = unsafe {
+                device
+                    .create_descriptor_pool(&create_info, None)
+                    .map_err(|_| format!("{}: failed to create descriptor pool", object_name))?
+            };
+
+            vulkan_utils::set_debug_utils_object_name(
+                debug_utils_loader,
+                device.handle(),
+                descriptor_pool,
+                &format!("{} descriptor pool", object_name),
+            );
+
+            guard(descriptor_pool, |pool| {
+                log::warn!("{} descriptor pool scopeguard", object_name);
+                unsafe {
+                    device.destroy_descriptor_pool(pool, None);
+                }
+            })
+        };
+
+        // freed with the pool
+        let descriptor_set = {
+            let layouts = [*descriptor_set_layout_sg];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(*descriptor_pool_sg)
+                .set_layouts(&layouts)
+                .build();
+
+            unsafe {
+                device
+                    .allocate_descriptor_sets(&alloc_info)
+                    .map_err(|_| format!("{}: failed to allocate descriptor set", object_name))?[0]
+            }
+        };
+
+        let buffer_infos = storage_buffers
+            .iter()
+            .map(|&buffer| {
+                [vk::DescriptorBufferInfo {
+                    buffer,
+                    offset: 0,
+                    range: vk::WHOLE_SIZE,
+                }]
+            })
+            .collect::<Vec<_>>();
+
+        let writes = buffer_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
+            device.update_descriptor_sets(&writes, &[]);
+        }
+
+        let pipeline_layout_sg = {
+            let layouts = [*descriptor_set_layout_sg];
+            let ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: push_constant_size,
+            }];
+
+            let mut create_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&layouts);
+            if push_constant_size > 0 {
+                create_info = create_info.push_constant_ranges(&ranges);
+            }
+
+            let pipeline_layout = unsafe {
+                device
+                    .create_pipeline_layout(&create_info, None)
+                    .map_err(|_| format!("{}: failed to create pipeline layout", object_name))?
+            };
+
+            vulkan_utils::set_debug_utils_object_name(
+                debug_utils_loader,
+                device.handle(),
+                pipeline_layout,
+                &format!("{} pipeline layout", object_name),
+            );
+
+            guard(pipeline_layout, |pipeline_layout| {
+                log::warn!("{} pipeline layout scopeguard", object_name);
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout, None);
+                }
+            })
+        };
+
+        let pipeline = vulkan_utils::create_compute_pipeline(
+            device,
+            *shader_module_sg,
+            *pipeline_layout_sg,
+            debug_utils_loader,
+            &format!("{} pipeline", object_name),
+        )?;
+
+        log::info!("{}: compute pass created", object_name);
+
+        Ok(ComputePass {
+            shader_module: ScopeGuard::into_inner(shader_module_sg),
+            descriptor_set_layout: ScopeGuard::into_inner(descriptor_set_layout_sg),
+            descriptor_pool: ScopeGuard::into_inner(descriptor_pool_sg),
+            descriptor_set,
+            pipeline_layout: ScopeGuard::into_inner(pipeline_layout_sg),
+            pipeline,
+            local_size,
+        })
+    }
+
+    /// Dispatches enough groups for `element_count` invocations. Barriers are up to the caller.
+    pub fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        element_count: u32,
+        push_constants: &[u8],
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+
+            if !push_constants.is_empty() {
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_constants,
+                );
+            }
+
+            device.cmd_dispatch(
+                command_buffer,
+                vulkan_utils::get_dispatch_group_count(element_count, self.local_size),
+                1,
+                1,
+            );
+        }
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_shader_module(self.shader_module, None);
+        }
+    }
+}
@@ -1,7 +1,16 @@
+#[macro_use]
 mod vulkan_data;
+mod any_frame_sync;
+mod compute_pass;
 mod vulkan_data_fns;
 mod vulkan_draw;
+mod vulkan_mesh;
+mod vulkan_skin;
 
+pub use any_frame_sync::*;
+pub use compute_pass::*;
 pub use vulkan_data::*;
 pub use vulkan_data_fns::*;
 pub use vulkan_draw::draw;
+pub use vulkan_mesh::*;
+pub use vulkan_skin::*;
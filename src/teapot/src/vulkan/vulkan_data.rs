@@ -1,51 +1,180 @@
 use crate::teapot_data;
 use crate::vulkan;
+use crate::vulkan::{AnyFrameSync, VulkanMesh, VulkanSkin};
 use ash::vk;
 use scopeguard::{guard, ScopeGuard};
-use std::cell::RefCell;
-use vulkan_base::VulkanBase;
+use vulkan_base::{GpuTimer, PipelineCache, VulkanBase};
+
+// capacity of the instances buffer, see set_instance_count
+const MAX_INSTANCE_COUNT: u32 = 16;
+const INSTANCE_SPACING: f32 = 8.0;
+// patch subdivisions of the CPU-tessellated teapot, see load_teapot_fallback
+const FALLBACK_SUBDIVISIONS: u32 = 8;
+
+// with the embedded-shaders feature the SPIR-V written by build.rs is baked into the binary
+macro_rules! create_shader_module {
+    ($vulkan_base:expr, $file_name:literal, $object_name:expr) => {{
+        #[cfg(feature = "embedded-shaders")]
+        let shader_module = vulkan_utils::create_shader_module_from_bytes(
+            &$vulkan_base.device,
+            include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../../shaders/",
+                $file_name
+            )),
+            $vulkan_base.debug_utils_loader.as_ref(),
+            $object_name,
+        );
+
+        #[cfg(not(feature = "embedded-shaders"))]
+        let shader_module = vulkan_utils::create_shader_module(
+            &$vulkan_base.device,
+            std::path::Path::new(concat!("shaders/", $file_name)),
+            $vulkan_base.debug_utils_loader.as_ref(),
+            $object_name,
+        );
+
+        shader_module
+    }};
+}
+
+/// How frames are rendered. `Dynamic` needs `VK_KHR_dynamic_rendering` and creates no render
+/// pass or framebuffers, so resizing only recreates the swapchain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderingMode {
+    RenderPass,
+    Dynamic,
+}
+
+/// How a frame's command buffers are recycled by `reset_command_buffers`. Either way they may
+/// only be reset once the frame's submission has finished, `AnyFrameSync::begin_frame` waits for
+/// it.
+///
+/// `Pool` is faster for the teapot's single buffer per frame, it is one call and drivers can
+/// skip per buffer bookkeeping for pools created without `RESET_COMMAND_BUFFER`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandBufferReset {
+    /// Resets the frame's whole `TRANSIENT` pool.
+    Pool,
+    /// Creates the pools with `RESET_COMMAND_BUFFER` and resets every used buffer on its own.
+    // only picked by editing COMMAND_BUFFER_RESET
+    #[allow(dead_code)]
+    Buffer,
+}
 
 pub struct VulkanData {
     pub vertex_shader_module: vk::ShaderModule,
+    /// Null, like the pipelines, when the device has no tessellation shaders. The teapot is
+    /// then drawn as a `mesh` built on the CPU, see `load_teapot_fallback`.
     pub tese_shader_module: vk::ShaderModule,
     pub tesc_shader_module: vk::ShaderModule,
+    /// Set when `SHOW_NORMALS` is.
+    pub geometry_shader_module: Option<vk::ShaderModule>,
     pub fragment_shader_module: vk::ShaderModule,
-    pub control_points_mem_buffer: vulkan_utils::MemBuffer,
-    pub patches_mem_buffer: vulkan_utils::MemBuffer,
+    // only referenced by the descriptor sets, kept alive until the data is dropped
+    #[allow(dead_code)]
+    pub control_points_mem_buffer: vulkan_utils::OwnedMemBuffer,
+    pub patches_mem_buffer: vulkan_utils::OwnedMemBuffer,
     pub patch_point_count: u32,
-    pub instances_mem_buffer: vulkan_utils::MemBuffer,
-    pub uniform_mem_buffers: Vec<vulkan_utils::MemBuffer>,
+    pub patch_count: u32,
+    // see control_points_mem_buffer
+    #[allow(dead_code)]
+    pub instances_mem_buffer: vulkan_utils::OwnedMemBuffer,
+    /// Number of teapots the instances buffer holds patch data for.
+    pub max_instance_count: u32,
+    pub instance_count: u32,
+    /// Drawn instead of the teapot, see `load_mesh`.
+    pub mesh: Option<VulkanMesh>,
+    pub uniform_mem_buffers: Vec<vulkan_utils::OwnedMemBuffer>,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
     pub pipeline_layout: vk::PipelineLayout,
+    pub rendering_mode: RenderingMode,
+    /// Null in `RenderingMode::Dynamic`.
     pub render_pass: vk::RenderPass,
     pub solid_pipeline: vk::Pipeline,
     pub wireframe_pipeline: vk::Pipeline,
     pub framebuffers: Vec<vk::Framebuffer>,
     pub should_resize: bool,
-    pub image_available_semaphore: vk::Semaphore,
-    pub rendering_finished_semaphore: vk::Semaphore,
-    pub fences: Vec<vk::Fence>,
+    pub frame_sync: AnyFrameSync,
+    /// `None` when the queue family doesn't support timestamps.
+    pub gpu_timer: Option<GpuTimer>,
+    pub pipeline_cache: PipelineCache,
     pub command_pools: Vec<vk::CommandPool>,
+    pub command_buffer_reset: CommandBufferReset,
     pub descriptor_pools: Vec<vk::DescriptorPool>,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
     pub available_command_buffers: Vec<Vec<vk::CommandBuffer>>,
     pub used_command_buffers: Vec<Vec<vk::CommandBuffer>>,
-    pub curr_resource_index: u32,
     pub is_wireframe_mode: bool,
     pub tesselation_level: f32,
+    /// Wireframe edge width, see `set_line_width`.
+    pub line_width: f32,
+    line_width_clamp_logged: bool,
+    pub clear_color: [f32; 4],
+    pub depth_clear: f32,
+    pub capture_path: Option<std::path::PathBuf>,
 }
 
 impl VulkanData {
-    pub fn new(vulkan_base: &mut VulkanBase) -> Result<Self, String> {
+    /// `frames_in_flight` is how many frames the CPU may record while the GPU still works on
+    /// earlier ones, each with its own command pool, descriptor pool and uniform buffer. More
+    /// frames keep the GPU busier at the cost of input latency, 2 is the usual choice and 3
+    /// helps when CPU frame times vary.
+    pub fn new(vulkan_base: &mut VulkanBase, frames_in_flight: u32) -> Result<Self, String> {
+        debug_assert!(
+            frames_in_flight >= 1,
+            "at least one frame has to be in flight"
+        );
+
+        if frames_in_flight as usize > vulkan_base.target.swapchain_images.len() {
+            log::warn!(
+                "{} frames in flight but only {} swapchain images, extra frames wait on image acquisition",
+                frames_in_flight,
+                vulkan_base.target.swapchain_images.len()
+            );
+        }
+
+        let (transfer_queue_family, transfer_queue) = vulkan_base.get_transfer_queue();
         let device = &vulkan_base.device;
-        let allocator_rc = RefCell::new(&mut vulkan_base.allocator);
+
+        let frame_sync_sg = {
+            let frame_sync =
+                AnyFrameSync::new(vulkan_base, frames_in_flight, crate::FRAME_SYNC_MODE)
+                    .map_err(|err| err.to_string())?;
+
+            guard(frame_sync, |frame_sync| {
+                log::warn!("frame sync scopeguard");
+                frame_sync.clean(device);
+            })
+        };
+
+        let gpu_timer_sg = {
+            let gpu_timer = match GpuTimer::new(vulkan_base, frames_in_flight) {
+                Ok(gpu_timer) => Some(gpu_timer),
+                Err(err) => {
+                    log::warn!("{}, gpu timing is disabled", err);
+                    None
+                }
+            };
+
+            guard(gpu_timer, |gpu_timer| {
+                log::warn!("gpu timer scopeguard");
+                if let Some(gpu_timer) = gpu_timer {
+                    gpu_timer.clean(device);
+                }
+            })
+        };
+
+        let tessellation_supported = unsafe {
+            vulkan_base
+                .instance
+                .get_physical_device_features(vulkan_base.physical_device)
+                .tessellation_shader
+                == vk::TRUE
+        };
 
         let vertex_sm_sg = {
-            let vertex_sm = vulkan_utils::create_shader_module(
-                &vulkan_base.device,
-                std::path::Path::new("shaders/shader.vert.spv"),
-                &vulkan_base.debug_utils_loader,
-                "vertex shader",
-            )?;
+            let vertex_sm = create_shader_module!(vulkan_base, "shader.vert.spv", "vertex shader")?;
 
             scopeguard::guard(vertex_sm, |sm| {
                 log::warn!("vertex shader scopeguard");
@@ -56,12 +185,14 @@ impl VulkanData {
         };
 
         let tese_sm_sg = {
-            let tese_sm = vulkan_utils::create_shader_module(
-                &vulkan_base.device,
-                std::path::Path::new("shaders/shader.tese.spv"),
-                &vulkan_base.debug_utils_loader,
-                "tessellation evaluation shader",
-            )?;
+            let tese_sm = match tessellation_supported {
+                true => create_shader_module!(
+                    vulkan_base,
+                    "shader.tese.spv",
+                    "tessellation evaluation shader"
+                )?,
+                false => vk::ShaderModule::null(),
+            };
 
             scopeguard::guard(tese_sm, |sm| {
                 log::warn!("tessellation evaluation shader scopeguard");
@@ -72,12 +203,14 @@ impl VulkanData {
         };
 
         let tesc_sm_sg = {
-            let tesc_sm = vulkan_utils::create_shader_module(
-                &vulkan_base.device,
-                std::path::Path::new("shaders/shader.tesc.spv"),
-                &vulkan_base.debug_utils_loader,
-                "tessellation control shader",
-            )?;
+            let tesc_sm = match tessellation_supported {
+                true => create_shader_module!(
+                    vulkan_base,
+                    "shader.tesc.spv",
+                    "tessellation control shader"
+                )?,
+                false => vk::ShaderModule::null(),
+            };
 
             scopeguard::guard(tesc_sm, |sm| {
                 log::warn!("tessellation control shader scopeguard");
@@ -87,127 +220,129 @@ impl VulkanData {
             })
         };
 
-        let fragment_sm_sg = {
-            let fragment_sm = vulkan_utils::create_shader_module(
-                &vulkan_base.device,
-                std::path::Path::new("shaders/shader.frag.spv"),
-                &vulkan_base.debug_utils_loader,
-                "fragment shader",
-            )?;
+        let geometry_sm_sg = {
+            let geometry_sm = if crate::SHOW_NORMALS {
+                let features = unsafe {
+                    vulkan_base
+                        .instance
+                        .get_physical_device_features(vulkan_base.physical_device)
+                };
+                if features.geometry_shader == vk::FALSE {
+                    return Err(String::from("the device does not support geometry shaders"));
+                }
 
-            scopeguard::guard(fragment_sm, |sm| {
-                log::warn!("fragment shader scopeguard");
-                unsafe {
-                    vulkan_base.device.destroy_shader_module(sm, None);
+                Some(create_shader_module!(
+                    vulkan_base,
+                    "shader.geom.spv",
+                    "geometry shader"
+                )?)
+            } else {
+                None
+            };
+
+            scopeguard::guard(geometry_sm, |sm| {
+                if let Some(sm) = sm {
+                    log::warn!("geometry shader scopeguard");
+                    unsafe {
+                        vulkan_base.device.destroy_shader_module(sm, None);
+                    }
                 }
             })
         };
 
-        let teapot_data = teapot_data::TeapotData::new();
-
-        let control_points_mem_buffer_sg = {
-            let control_points_mem_buffer = vulkan_utils::create_gpu_buffer_init(
-                &vulkan_base.device,
-                *allocator_rc.borrow_mut(),
-                &vulkan_base.debug_utils_loader,
-                vulkan_base.queue_family,
-                vulkan_base.queue,
-                teapot_data.get_control_points_slice(),
-                vk::BufferUsageFlags::STORAGE_BUFFER,
-                vk::AccessFlags::SHADER_READ,
-                vk::PipelineStageFlags::VERTEX_SHADER,
-                "control points buffer",
-            )?;
+        let fragment_sm_sg = {
+            let fragment_sm =
+                create_shader_module!(vulkan_base, "shader.frag.spv", "fragment shader")?;
 
-            guard(control_points_mem_buffer, |mem_buffer| {
-                log::warn!("control points buffer scopeguard");
+            scopeguard::guard(fragment_sm, |sm| {
+                log::warn!("fragment shader scopeguard");
                 unsafe {
-                    device.destroy_buffer(mem_buffer.buffer, None);
+                    vulkan_base.device.destroy_shader_module(sm, None);
                 }
-                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
             })
         };
 
-        let patches_mem_buffer_sg = {
-            let patches_mem_buffer = vulkan_utils::create_gpu_buffer_init(
-                &vulkan_base.device,
-                *allocator_rc.borrow_mut(),
-                &vulkan_base.debug_utils_loader,
-                vulkan_base.queue_family,
-                vulkan_base.queue,
-                teapot_data.get_patches_slice(),
-                vk::BufferUsageFlags::INDEX_BUFFER,
-                vk::AccessFlags::INDEX_READ,
-                vk::PipelineStageFlags::VERTEX_INPUT,
-                "patches buffer",
-            )?;
+        let teapot_data = teapot_data::TeapotData::new();
+        let grid_instances = teapot_data.get_grid_instances(MAX_INSTANCE_COUNT, INSTANCE_SPACING);
 
-            guard(patches_mem_buffer, |mem_buffer| {
-                log::warn!("patches buffer scopeguard");
-                unsafe {
-                    device.destroy_buffer(mem_buffer.buffer, None);
-                }
-                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
-            })
-        };
+        let mut mem_buffers = vulkan_utils::upload_buffers(
+            &vulkan_base.device,
+            &mut vulkan_base.allocator.lock().unwrap(),
+            vulkan_base.debug_utils_loader.as_ref(),
+            transfer_queue_family,
+            transfer_queue,
+            vulkan_base.queue_family,
+            vulkan_base.queue,
+            &[
+                vulkan_utils::UploadRequest {
+                    data: teapot_data.get_control_points_slice(),
+                    usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                    access_mask: vk::AccessFlags::SHADER_READ,
+                    stage_flags: vk::PipelineStageFlags::VERTEX_SHADER,
+                    object_name: "control points buffer",
+                },
+                vulkan_utils::UploadRequest {
+                    data: teapot_data.get_patches_slice(),
+                    usage: vk::BufferUsageFlags::INDEX_BUFFER,
+                    access_mask: vk::AccessFlags::INDEX_READ,
+                    stage_flags: vk::PipelineStageFlags::VERTEX_INPUT,
+                    object_name: "patches buffer",
+                },
+                vulkan_utils::UploadRequest {
+                    data: bytemuck::cast_slice(&grid_instances),
+                    usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                    access_mask: vk::AccessFlags::SHADER_READ,
+                    // the stage is only valid with the tessellation_shader feature
+                    stage_flags: match tessellation_supported {
+                        true => vk::PipelineStageFlags::TESSELLATION_EVALUATION_SHADER,
+                        false => vk::PipelineStageFlags::VERTEX_SHADER,
+                    },
+                    object_name: "instances buffer",
+                },
+            ],
+        )?
+        .into_iter()
+        .map(|mem_buffer| {
+            vulkan_utils::OwnedMemBuffer::new(
+                vulkan_base.device.clone(),
+                vulkan_base.allocator.clone(),
+                mem_buffer,
+            )
+        });
+
+        let control_points_mem_buffer = mem_buffers.next().unwrap();
+        let patches_mem_buffer = mem_buffers.next().unwrap();
+        let instances_mem_buffer = mem_buffers.next().unwrap();
 
         let patch_point_count = teapot_data.get_patch_point_count();
+        let patch_count = teapot_data.get_patch_count();
 
-        let instances_mem_buffer_sg = {
-            let instances_mem_buffer = vulkan_utils::create_gpu_buffer_init(
+        let mut uniform_mem_buffers = Vec::with_capacity(frames_in_flight as usize);
+        for i in 0..frames_in_flight {
+            // CpuToGpu picks any host visible memory without resizable BAR
+            let mem_buffer = vulkan_utils::create_buffer_with_memory_types(
                 &vulkan_base.device,
-                *allocator_rc.borrow_mut(),
-                &vulkan_base.debug_utils_loader,
-                vulkan_base.queue_family,
-                vulkan_base.queue,
-                teapot_data.get_instances_slice(),
-                vk::BufferUsageFlags::STORAGE_BUFFER,
-                vk::AccessFlags::SHADER_READ,
-                vk::PipelineStageFlags::TESSELLATION_EVALUATION_SHADER,
-                "instances buffer",
+                &mut vulkan_base.allocator.lock().unwrap(),
+                vulkan_base.debug_utils_loader.as_ref(),
+                (16 * std::mem::size_of::<f32>()) as vk::DeviceSize,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                gpu_allocator::MemoryLocation::CpuToGpu,
+                vulkan_base.rebar_memory_type_bits,
+                &[vulkan_base.queue_family],
+                &format!("uniform buffer {}", i),
             )?;
 
-            guard(instances_mem_buffer, |mem_buffer| {
-                log::warn!("instances buffer scopeguard");
-                unsafe {
-                    device.destroy_buffer(mem_buffer.buffer, None);
-                }
-                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
-            })
-        };
-
-        let uniform_mem_buffers_sg = {
-            let mut mem_buffers = Vec::with_capacity(crate::CONCURRENT_RESOURCE_COUNT as usize);
-            for i in 0..crate::CONCURRENT_RESOURCE_COUNT {
-                let mem_buffer = vulkan_utils::create_buffer(
-                    &vulkan_base.device,
-                    *allocator_rc.borrow_mut(),
-                    &vulkan_base.debug_utils_loader,
-                    (16 * std::mem::size_of::<f32>()) as vk::DeviceSize,
-                    vk::BufferUsageFlags::UNIFORM_BUFFER,
-                    gpu_allocator::MemoryLocation::CpuToGpu,
-                    &format!("uniform buffer {}", i),
-                )?;
-
-                mem_buffers.push(mem_buffer);
-            }
-
-            guard(mem_buffers, |mem_buffers| {
-                log::warn!("uniform buffers scopeguard");
-
-                for mem_buffer in mem_buffers {
-                    unsafe {
-                        device.destroy_buffer(mem_buffer.buffer, None);
-                    }
-                    let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
-                }
-            })
-        };
+            uniform_mem_buffers.push(vulkan_utils::OwnedMemBuffer::new(
+                vulkan_base.device.clone(),
+                vulkan_base.allocator.clone(),
+                mem_buffer,
+            ));
+        }
 
         let descriptor_set_layout_sg = {
             let descriptor_set_layout = vulkan::create_descriptor_set_layout(
                 &vulkan_base.device,
-                &vulkan_base.debug_utils_loader,
+                vulkan_base.debug_utils_loader.as_ref(),
             )?;
 
             guard(descriptor_set_layout, |layout| {
@@ -222,7 +357,7 @@ impl VulkanData {
             let pipeline_layout = vulkan::create_pipeline_layout(
                 &vulkan_base.device,
                 *descriptor_set_layout_sg,
-                &vulkan_base.debug_utils_loader,
+                vulkan_base.debug_utils_loader.as_ref(),
             )?;
 
             guard(pipeline_layout, |layout| {
@@ -233,13 +368,18 @@ impl VulkanData {
             })
         };
 
+        let rendering_mode = crate::RENDERING_MODE;
+
         let render_pass_sg = {
-            let render_pass = vulkan::create_render_pass(
-                &vulkan_base.device,
-                vulkan_base.surface_format.format,
-                vulkan_base.depth_format,
-                &vulkan_base.debug_utils_loader,
-            )?;
+            let render_pass = match rendering_mode {
+                RenderingMode::RenderPass => vulkan::create_render_pass(
+                    &vulkan_base.device,
+                    vulkan_base.target.surface_format.format,
+                    vulkan_base.depth_format,
+                    vulkan_base.debug_utils_loader.as_ref(),
+                )?,
+                RenderingMode::Dynamic => vk::RenderPass::null(),
+            };
 
             guard(render_pass, |render_pass| {
                 log::warn!("render pass scopeguard");
@@ -249,17 +389,41 @@ impl VulkanData {
             })
         };
 
-        let (solid_pipeline_sg, wireframe_pipeline_sg) = {
-            let (solid_pipeline, wireframe_pipeline) = vulkan::create_pipelines(
+        let pipeline_cache_sg = {
+            let pipeline_cache = PipelineCache::new(
                 &vulkan_base.device,
-                *vertex_sm_sg,
-                *tesc_sm_sg,
-                *tese_sm_sg,
-                *fragment_sm_sg,
-                *pipeline_layout_sg,
-                *render_pass_sg,
-                &vulkan_base.debug_utils_loader,
-            )?;
+                &vulkan_base.physical_device_properties,
+                vulkan_base.debug_utils_loader.as_ref(),
+                std::path::Path::new("."),
+            )
+            .map_err(|err| err.to_string())?;
+
+            guard(pipeline_cache, |pipeline_cache| {
+                log::warn!("pipeline cache scopeguard");
+                unsafe {
+                    device.destroy_pipeline_cache(pipeline_cache.pipeline_cache, None);
+                }
+            })
+        };
+
+        let (solid_pipeline_sg, wireframe_pipeline_sg) = {
+            let (solid_pipeline, wireframe_pipeline) = match tessellation_supported {
+                true => vulkan::create_pipelines(
+                    &vulkan_base.device,
+                    *vertex_sm_sg,
+                    *tesc_sm_sg,
+                    *tese_sm_sg,
+                    *geometry_sm_sg,
+                    *fragment_sm_sg,
+                    *pipeline_layout_sg,
+                    *render_pass_sg,
+                    vulkan_base.target.surface_format.format,
+                    vulkan_base.depth_format,
+                    pipeline_cache_sg.pipeline_cache,
+                    vulkan_base.debug_utils_loader.as_ref(),
+                )?,
+                false => (vk::Pipeline::null(), vk::Pipeline::null()),
+            };
 
             let sg_1 = guard(solid_pipeline, |pipeline| {
                 log::warn!("solid pipeline scopeguard");
@@ -279,14 +443,17 @@ impl VulkanData {
         };
 
         let framebuffers_sg = {
-            let framebuffers = vulkan::create_framebuffers(
-                &vulkan_base.device,
-                &vulkan_base.swapchain_image_views,
-                *render_pass_sg,
-                vulkan_base.surface_extent,
-                vulkan_base.depth_buffer_mem_image.view,
-                &vulkan_base.debug_utils_loader,
-            )?;
+            let framebuffers = match rendering_mode {
+                RenderingMode::RenderPass => vulkan::create_framebuffers(
+                    &vulkan_base.device,
+                    &vulkan_base.target.swapchain_image_views,
+                    *render_pass_sg,
+                    vulkan_base.target.surface_extent,
+                    vulkan_base.target.depth_buffer_mem_image.view,
+                    vulkan_base.debug_utils_loader.as_ref(),
+                )?,
+                RenderingMode::Dynamic => Vec::new(),
+            };
 
             guard(framebuffers, move |framebuffers| {
                 log::warn!("framebuffers scopeguard");
@@ -298,55 +465,15 @@ impl VulkanData {
             })
         };
 
-        let image_available_semaphore_sg = {
-            let semaphore = vulkan_utils::create_semaphore(
-                &vulkan_base.device,
-                &vulkan_base.debug_utils_loader,
-                "image available semaphore",
-            )?;
-
-            guard(semaphore, |semaphore| {
-                log::warn!("image available semaphore scopeguard");
-                unsafe {
-                    device.destroy_semaphore(semaphore, None);
-                }
-            })
-        };
-
-        let rendering_finished_semaphore_sg = {
-            let semaphore = vulkan_utils::create_semaphore(
-                &vulkan_base.device,
-                &vulkan_base.debug_utils_loader,
-                "rendering finished semaphore",
-            )?;
-
-            guard(semaphore, |semaphore| {
-                log::warn!("rendering finished semaphore scopeguard");
-                unsafe {
-                    device.destroy_semaphore(semaphore, None);
-                }
-            })
-        };
-
-        let fences_sg = {
-            let fences =
-                vulkan::create_fences(&vulkan_base.device, &vulkan_base.debug_utils_loader)?;
-
-            guard(fences, |fences| {
-                log::warn!("fences scopeguard");
-                unsafe {
-                    for f in fences {
-                        device.destroy_fence(f, None);
-                    }
-                }
-            })
-        };
+        let command_buffer_reset = crate::COMMAND_BUFFER_RESET;
 
         let command_pools_sg = {
             let command_pools = vulkan::create_command_pools(
                 &vulkan_base.device,
                 vulkan_base.queue_family,
-                &vulkan_base.debug_utils_loader,
+                frames_in_flight,
+                command_buffer_reset,
+                vulkan_base.debug_utils_loader.as_ref(),
             )?;
 
             guard(command_pools, |command_pools| {
@@ -362,7 +489,8 @@ impl VulkanData {
         let descriptor_pools_sg = {
             let descriptor_pools = vulkan::create_descriptor_pools(
                 &vulkan_base.device,
-                &vulkan_base.debug_utils_loader,
+                frames_in_flight,
+                vulkan_base.debug_utils_loader.as_ref(),
             )?;
 
             guard(descriptor_pools, |descriptor_pools| {
@@ -375,37 +503,76 @@ impl VulkanData {
             })
         };
 
-        Ok(VulkanData {
+        let descriptor_sets = vulkan::allocate_and_update_descriptor_sets(
+            &vulkan_base.device,
+            vulkan_base.debug_utils_loader.as_ref(),
+            &descriptor_pools_sg,
+            *descriptor_set_layout_sg,
+            &control_points_mem_buffer,
+            &instances_mem_buffer,
+            &uniform_mem_buffers,
+        )?;
+
+        let mut vulkan_data = VulkanData {
             vertex_shader_module: ScopeGuard::into_inner(vertex_sm_sg),
             tese_shader_module: ScopeGuard::into_inner(tese_sm_sg),
             tesc_shader_module: ScopeGuard::into_inner(tesc_sm_sg),
+            geometry_shader_module: ScopeGuard::into_inner(geometry_sm_sg),
             fragment_shader_module: ScopeGuard::into_inner(fragment_sm_sg),
-            control_points_mem_buffer: ScopeGuard::into_inner(control_points_mem_buffer_sg),
-            patches_mem_buffer: ScopeGuard::into_inner(patches_mem_buffer_sg),
+            control_points_mem_buffer,
+            patches_mem_buffer,
             patch_point_count,
-            instances_mem_buffer: ScopeGuard::into_inner(instances_mem_buffer_sg),
-            uniform_mem_buffers: ScopeGuard::into_inner(uniform_mem_buffers_sg),
+            patch_count,
+            instances_mem_buffer,
+            max_instance_count: MAX_INSTANCE_COUNT,
+            instance_count: 1,
+            mesh: None,
+            uniform_mem_buffers,
             descriptor_set_layout: ScopeGuard::into_inner(descriptor_set_layout_sg),
             pipeline_layout: ScopeGuard::into_inner(pipeline_layout_sg),
+            rendering_mode,
             render_pass: ScopeGuard::into_inner(render_pass_sg),
             solid_pipeline: ScopeGuard::into_inner(solid_pipeline_sg),
             wireframe_pipeline: ScopeGuard::into_inner(wireframe_pipeline_sg),
             framebuffers: ScopeGuard::into_inner(framebuffers_sg),
             should_resize: false,
-            image_available_semaphore: ScopeGuard::into_inner(image_available_semaphore_sg),
-            rendering_finished_semaphore: ScopeGuard::into_inner(rendering_finished_semaphore_sg),
-            fences: ScopeGuard::into_inner(fences_sg),
+            frame_sync: ScopeGuard::into_inner(frame_sync_sg),
+            gpu_timer: ScopeGuard::into_inner(gpu_timer_sg),
+            pipeline_cache: ScopeGuard::into_inner(pipeline_cache_sg),
             command_pools: ScopeGuard::into_inner(command_pools_sg),
+            command_buffer_reset,
             descriptor_pools: ScopeGuard::into_inner(descriptor_pools_sg),
-            available_command_buffers: vec![vec![]; crate::CONCURRENT_RESOURCE_COUNT as usize],
-            used_command_buffers: vec![vec![]; crate::CONCURRENT_RESOURCE_COUNT as usize],
-            curr_resource_index: 0,
+            descriptor_sets,
+            available_command_buffers: vec![vec![]; frames_in_flight as usize],
+            used_command_buffers: vec![vec![]; frames_in_flight as usize],
             is_wireframe_mode: false,
             tesselation_level: 1.0,
-        })
+            line_width: 1.0,
+            line_width_clamp_logged: false,
+            clear_color: [0.5f32, 0.5f32, 0.5f32, 1.0f32],
+            depth_clear: 1.0,
+            capture_path: None,
+        };
+
+        if !tessellation_supported {
+            if let Err(msg) = vulkan_data.load_teapot_fallback(vulkan_base) {
+                vulkan_data.clean(vulkan_base);
+                return Err(msg);
+            }
+        }
+
+        Ok(vulkan_data)
     }
 
     pub fn resize(&mut self, vulkan_base: &VulkanBase) -> Result<(), String> {
+        self.frame_sync
+            .resize(vulkan_base)
+            .map_err(|err| err.to_string())?;
+
+        if self.rendering_mode == RenderingMode::Dynamic {
+            return Ok(());
+        }
+
         unsafe {
             for &framebuffer in &self.framebuffers {
                 vulkan_base.device.destroy_framebuffer(framebuffer, None);
@@ -414,41 +581,280 @@ impl VulkanData {
 
         self.framebuffers = vulkan::create_framebuffers(
             &vulkan_base.device,
-            &vulkan_base.swapchain_image_views,
+            &vulkan_base.target.swapchain_image_views,
             self.render_pass,
-            vulkan_base.surface_extent,
-            vulkan_base.depth_buffer_mem_image.view,
-            &vulkan_base.debug_utils_loader,
+            vulkan_base.target.surface_extent,
+            vulkan_base.target.depth_buffer_mem_image.view,
+            vulkan_base.debug_utils_loader.as_ref(),
         )?;
 
         Ok(())
     }
 
-    pub fn clean(self, vulkan_base: &mut VulkanBase) {
-        log::info!("cleaning vulkan data");
+    /// Recreates the teapot's shader modules and pipelines from the SPIR-V in `shaders/`, also
+    /// with the embedded-shaders feature. The new ones replace the old ones after a
+    /// `device_wait_idle` once all were created, on failure the old ones are kept and the
+    /// error is returned. Edited shaders must keep the interface of the pipeline layout.
+    /// Meshes keep their own pipeline.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shaders(&mut self, vulkan_base: &VulkanBase) -> Result<(), String> {
+        // drawn as a mesh, see load_teapot_fallback
+        if self.solid_pipeline == vk::Pipeline::null() {
+            log::info!("the teapot pipelines are not used, skipping the shader reload");
+            return Ok(());
+        }
+
+        log::info!("reloading shaders");
+
+        let device = &vulkan_base.device;
+
+        let mut shader_modules_sg = guard(Vec::new(), |shader_modules| {
+            log::warn!("reloaded shaders scopeguard");
+            for shader_module in shader_modules {
+                unsafe {
+                    device.destroy_shader_module(shader_module, None);
+                }
+            }
+        });
+
+        let mut create_shader_module = |file_name: &str, object_name: &str| {
+            let shader_module = vulkan_utils::create_shader_module(
+                device,
+                &std::path::Path::new("shaders").join(file_name),
+                vulkan_base.debug_utils_loader.as_ref(),
+                object_name,
+            )?;
+            shader_modules_sg.push(shader_module);
+
+            Ok::<_, String>(shader_module)
+        };
+
+        let vertex_sm = create_shader_module("shader.vert.spv", "vertex shader")?;
+        let tese_sm = create_shader_module("shader.tese.spv", "tessellation evaluation shader")?;
+        let tesc_sm = create_shader_module("shader.tesc.spv", "tessellation control shader")?;
+        let geometry_sm = match self.geometry_shader_module {
+            Some(_) => Some(create_shader_module("shader.geom.spv", "geometry shader")?),
+            None => None,
+        };
+        let fragment_sm = create_shader_module("shader.frag.spv", "fragment shader")?;
+
+        let (solid_pipeline, wireframe_pipeline) = vulkan::create_pipelines(
+            device,
+            vertex_sm,
+            tesc_sm,
+            tese_sm,
+            geometry_sm,
+            fragment_sm,
+            self.pipeline_layout,
+            self.render_pass,
+            vulkan_base.target.surface_format.format,
+            vulkan_base.depth_format,
+            self.pipeline_cache.pipeline_cache,
+            vulkan_base.debug_utils_loader.as_ref(),
+        )?;
+
+        ScopeGuard::into_inner(shader_modules_sg);
 
+        // the old pipelines may still be used by frames in flight
         unsafe {
-            let device = &vulkan_base.device;
-            let allocator = &mut vulkan_base.allocator;
+            let _ = device.device_wait_idle();
 
+            device.destroy_pipeline(self.solid_pipeline, None);
+            device.destroy_pipeline(self.wireframe_pipeline, None);
             device.destroy_shader_module(self.vertex_shader_module, None);
             device.destroy_shader_module(self.tese_shader_module, None);
             device.destroy_shader_module(self.tesc_shader_module, None);
+            if let Some(geometry_shader_module) = self.geometry_shader_module {
+                device.destroy_shader_module(geometry_shader_module, None);
+            }
             device.destroy_shader_module(self.fragment_shader_module, None);
+        }
+
+        self.vertex_shader_module = vertex_sm;
+        self.tese_shader_module = tese_sm;
+        self.tesc_shader_module = tesc_sm;
+        self.geometry_shader_module = geometry_sm;
+        self.fragment_shader_module = fragment_sm;
+        self.solid_pipeline = solid_pipeline;
+        self.wireframe_pipeline = wireframe_pipeline;
+
+        log::info!("shaders reloaded");
+
+        Ok(())
+    }
+
+    /// Selects the pipeline bound by the next recorded frame. The wireframe pipeline uses
+    /// `WIREFRAME_POLYGON_MODE`, `LINE` or `POINT`, which rely on the `fill_mode_non_solid`
+    /// feature teapot already requires when creating `VulkanBase`, so no extra capability check
+    /// is needed here.
+    pub fn set_wireframe(&mut self, is_wireframe: bool) {
+        self.is_wireframe_mode = is_wireframe;
+    }
+
+    /// Sets the level pushed to the tessellation control shader with every frame. It is
+    /// clamped to `[1, max_tessellation_generation_level]` of the physical device.
+    ///
+    /// The level is an `f32` because the shader's push constant is a float that the teapot
+    /// steps by 0.1. It is bounded by `max_tessellation_generation_level`, the limit for
+    /// tessellation levels, not by `max_tessellation_patch_size`, which bounds the control
+    /// points per patch (16 for the teapot).
+    pub fn set_tessellation_level(&mut self, level: f32, vulkan_base: &VulkanBase) {
+        self.tesselation_level = clamp_tessellation_level(
+            level,
+            vulkan_base
+                .physical_device_properties
+                .limits
+                .max_tessellation_generation_level,
+        );
+    }
+
+    /// Sets the line width of the wireframe pipeline, clamped to `line_width_range` of the
+    /// physical device. Without the `wide_lines` feature the width stays 1.0.
+    pub fn set_line_width(&mut self, line_width: f32, vulkan_base: &VulkanBase) {
+        if vulkan_base.enabled_features.wide_lines == vk::FALSE {
+            if line_width != 1.0 && !self.line_width_clamp_logged {
+                log::info!("wide_lines is not enabled, the line width stays 1.0");
+                self.line_width_clamp_logged = true;
+            }
+
+            self.line_width = 1.0;
+            return;
+        }
+
+        let [min_width, max_width] = vulkan_base
+            .physical_device_properties
+            .limits
+            .line_width_range;
+        self.line_width = line_width.clamp(min_width, max_width);
+    }
+
+    /// Sets the number of teapots drawn with every frame, laid out on a grid. Fails when
+    /// `instance_count` is 0 or exceeds the capacity of the instances buffer.
+    pub fn set_instance_count(&mut self, instance_count: u32) -> Result<(), String> {
+        if instance_count == 0 || instance_count > self.max_instance_count {
+            return Err(format!(
+                "instance count {} out of range, the instances buffer holds 1 to {}",
+                instance_count, self.max_instance_count
+            ));
+        }
+
+        self.instance_count = instance_count;
+
+        Ok(())
+    }
+
+    /// Loads the model at `path` with `mesh::load_mesh` and draws it instead of the teapot,
+    /// replacing a previously loaded one.
+    pub fn load_mesh(
+        &mut self,
+        vulkan_base: &mut VulkanBase,
+        path: &std::path::Path,
+    ) -> Result<(), String> {
+        let mut mesh_data = crate::mesh::load_mesh(path)?;
+
+        if crate::SKIN_DEMO {
+            mesh_data.add_sway_skin();
+        }
+
+        let mut mesh = VulkanMesh::new(
+            vulkan_base,
+            &mesh_data,
+            self.render_pass,
+            self.pipeline_cache.pipeline_cache,
+        )?;
+
+        if let Some(skin_data) = &mesh_data.skin {
+            match VulkanSkin::new(
+                vulkan_base,
+                mesh.vertex_mem_buffer.buffer,
+                mesh_data.get_vertex_count(),
+                skin_data,
+            ) {
+                Ok(skin) => mesh.skin = Some(skin),
+                Err(msg) => {
+                    mesh.clean(vulkan_base);
+                    return Err(msg);
+                }
+            }
+        }
+
+        if let Some(old_mesh) = self.mesh.replace(mesh) {
+            unsafe {
+                let _ = vulkan_base.device.device_wait_idle();
+            }
+            old_mesh.clean(vulkan_base);
+        }
+
+        Ok(())
+    }
+
+    // the teapot tessellated on the CPU, drawn with the mesh pipeline on devices without
+    // tessellation shaders
+    fn load_teapot_fallback(&mut self, vulkan_base: &mut VulkanBase) -> Result<(), String> {
+        log::warn!("the device has no tessellation shaders, tessellating the teapot on the CPU");
+
+        let (mut vertices, indices) =
+            teapot_data::TeapotData::new().tessellate(FALLBACK_SUBDIVISIONS);
+
+        // the teapot is z-up, meshes are y-up
+        for vertex in &mut vertices {
+            for v in [&mut vertex.position, &mut vertex.normal] {
+                let (y, z) = (v[1], v[2]);
+                v[1] = z;
+                v[2] = -y;
+            }
+        }
+
+        let mesh_data = crate::mesh::MeshData::from_vertices(&vertices, indices);
+
+        let mesh = VulkanMesh::new(
+            vulkan_base,
+            &mesh_data,
+            self.render_pass,
+            self.pipeline_cache.pipeline_cache,
+        )?;
+
+        self.mesh = Some(mesh);
+
+        Ok(())
+    }
+
+    /// Writes `mvp` to the uniform buffer of `frame_index`. The buffer is `CpuToGpu` memory,
+    /// which gpu-allocator always picks host coherent, so no flush is needed.
+    pub fn update_uniforms(
+        &mut self,
+        frame_index: usize,
+        mvp: [[f32; 4]; 4],
+    ) -> Result<(), String> {
+        let mvp_bytes: &[u8] = bytemuck::cast_slice(&mvp);
+
+        let mapped = self.uniform_mem_buffers[frame_index]
+            .allocation
+            .mapped_slice_mut()
+            .ok_or_else(|| format!("uniform buffer {} is not mapped", frame_index))?;
+
+        mapped[..mvp_bytes.len()].copy_from_slice(mvp_bytes);
+
+        Ok(())
+    }
 
-            device.destroy_buffer(self.control_points_mem_buffer.buffer, None);
-            let _ = allocator.free(self.control_points_mem_buffer.allocation);
+    pub fn clean(self, vulkan_base: &mut VulkanBase) {
+        log::info!("cleaning vulkan data");
 
-            device.destroy_buffer(self.patches_mem_buffer.buffer, None);
-            let _ = allocator.free(self.patches_mem_buffer.allocation);
+        if let Some(mesh) = self.mesh {
+            mesh.clean(vulkan_base);
+        }
 
-            device.destroy_buffer(self.instances_mem_buffer.buffer, None);
-            let _ = allocator.free(self.instances_mem_buffer.allocation);
+        unsafe {
+            let device = &vulkan_base.device;
 
-            for mem_buffer in self.uniform_mem_buffers {
-                device.destroy_buffer(mem_buffer.buffer, None);
-                let _ = allocator.free(mem_buffer.allocation);
+            device.destroy_shader_module(self.vertex_shader_module, None);
+            device.destroy_shader_module(self.tese_shader_module, None);
+            device.destroy_shader_module(self.tesc_shader_module, None);
+            if let Some(geometry_shader_module) = self.geometry_shader_module {
+                device.destroy_shader_module(geometry_shader_module, None);
             }
+            device.destroy_shader_module(self.fragment_shader_module, None);
 
             vulkan_base
                 .device
@@ -474,18 +880,6 @@ impl VulkanData {
                 vulkan_base.device.destroy_framebuffer(framebuffer, None);
             }
 
-            vulkan_base
-                .device
-                .destroy_semaphore(self.image_available_semaphore, None);
-
-            vulkan_base
-                .device
-                .destroy_semaphore(self.rendering_finished_semaphore, None);
-
-            for &fence in &self.fences {
-                vulkan_base.device.destroy_fence(fence, None);
-            }
-
             for &command_pool in &self.command_pools {
                 vulkan_base.device.destroy_command_pool(command_pool, None);
             }
@@ -496,5 +890,36 @@ impl VulkanData {
                     .destroy_descriptor_pool(descriptor_pool, None);
             }
         }
+
+        self.frame_sync.clean(&vulkan_base.device);
+        if let Some(gpu_timer) = self.gpu_timer {
+            gpu_timer.clean(&vulkan_base.device);
+        }
+        self.pipeline_cache.clean(&vulkan_base.device);
+    }
+}
+
+fn clamp_tessellation_level(level: f32, max_tessellation_generation_level: u32) -> f32 {
+    // the limit is 0 on devices without tessellation shaders
+    let max_level = (max_tessellation_generation_level as f32).max(1.0);
+
+    level.clamp(1.0, max_level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tessellation_level_is_clamped_to_the_generation_level_limit() {
+        assert_eq!(clamp_tessellation_level(16.5, 64), 16.5);
+        assert_eq!(clamp_tessellation_level(100.0, 64), 64.0);
+        assert_eq!(clamp_tessellation_level(0.5, 64), 1.0);
+        assert_eq!(clamp_tessellation_level(-3.0, 64), 1.0);
+    }
+
+    #[test]
+    fn tessellation_level_is_1_without_tessellation_support() {
+        assert_eq!(clamp_tessellation_level(8.0, 0), 1.0);
     }
 }
@@ -1,137 +1,197 @@
-use crate::VulkanData;
-use ash::vk;
-use cgmath::{num_traits::ToPrimitive, perspective, Deg, Matrix4, Point3, Vector3};
+use crate::camera::OrbitCamera;
+use crate::overlay::OverlayRenderer;
+use crate::{RenderingMode, VulkanData};
+use cgmath::{num_traits::ToPrimitive, Deg, Matrix4, Vector3};
 use vulkan_base::VulkanBase;
 
+// meshes are loaded to fit [-1, 1], see mesh::load_mesh
+const MESH_SCALE: f32 = 3.0;
+
+// debug label colors, shown by RenderDoc and Nsight
+const DRAW_LABEL_COLOR: [f32; 4] = [1.0, 0.6, 0.2, 1.0];
+const OVERLAY_LABEL_COLOR: [f32; 4] = [0.4, 0.8, 0.4, 1.0];
+
 pub fn draw(
     vulkan_data: &mut VulkanData,
-    vulkan_base: &VulkanBase,
+    vulkan_base: &mut VulkanBase,
     time_since_beginning_sec: f32,
+    camera: &OrbitCamera,
+    overlay: Option<&mut dyn OverlayRenderer>,
 ) -> Result<(), String> {
-    let get_image_index_result = super::get_image_index(vulkan_data, vulkan_base)?;
-
-    let image_index = match get_image_index_result {
-        super::GetImageIndexResult::Index(index) => index,
-        super::GetImageIndexResult::ShouldRebuildSwapchain => {
+    let image_index = match vulkan_data
+        .frame_sync
+        .begin_frame(vulkan_base)
+        .map_err(|err| err.to_string())?
+    {
+        Some(index) => index,
+        None => {
             println!("swapchain is suboptimal or out of date");
             vulkan_data.should_resize = true;
             return Ok(());
         }
     };
 
-    super::wait_resource_available(vulkan_data, vulkan_base)?;
-    super::reset_command_pool(vulkan_data, vulkan_base)?;
+    super::reset_command_buffers(vulkan_data, vulkan_base)?;
     let command_buffer = super::get_command_buffer(vulkan_data, vulkan_base)?;
     super::begin_command_buffer(vulkan_base, command_buffer)?;
 
-    super::begin_render_pass(
-        vulkan_data,
-        vulkan_base,
-        image_index as usize,
+    if let Some(gpu_timer) = vulkan_data.gpu_timer.as_mut() {
+        gpu_timer.begin(
+            &vulkan_base.device,
+            command_buffer,
+            vulkan_data.frame_sync.frame_index(),
+        );
+    }
+
+    // skinning writes the vertices the render pass reads, it has to be recorded before it.
+    // MeshData::add_sway_skin is the only source of skins so far
+    if let Some(skin) = vulkan_data
+        .mesh
+        .as_ref()
+        .and_then(|mesh| mesh.skin.as_ref())
+    {
+        skin.record(
+            &vulkan_base.device,
+            command_buffer,
+            &crate::mesh::get_sway_joint_matrices(time_since_beginning_sec),
+        )?;
+    }
+
+    vulkan_utils::cmd_begin_debug_label(
+        vulkan_base.debug_utils_loader.as_ref(),
         command_buffer,
+        match vulkan_data.mesh {
+            Some(_) => "Mesh Draw",
+            None => "Teapot Draw",
+        },
+        DRAW_LABEL_COLOR,
     );
 
+    match vulkan_data.rendering_mode {
+        RenderingMode::RenderPass => super::begin_render_pass(
+            vulkan_data,
+            vulkan_base,
+            image_index as usize,
+            command_buffer,
+        ),
+        RenderingMode::Dynamic => super::begin_rendering(
+            vulkan_data,
+            vulkan_base,
+            image_index as usize,
+            command_buffer,
+        )?,
+    }
+
     super::set_viewport(vulkan_base, command_buffer);
     super::set_scissor(vulkan_base, command_buffer);
-    super::reset_descriptor_pool(vulkan_data, vulkan_base)?;
-    let descriptor_set = super::allocate_descriptor_set(vulkan_data, vulkan_base)?;
-    super::update_descriptor_set(vulkan_data, vulkan_base, descriptor_set);
-
-    let model: Matrix4<f32> = Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0))
-        * Matrix4::from_angle_x(Deg::<f32>(120.0))
-        * Matrix4::from_angle_z(Deg::<f32>(time_since_beginning_sec * 20.0));
-
-    let view = Matrix4::look_at_rh(
-        Point3::<f32>::new(0.0, 0.0, -10.0),
-        Point3::<f32>::new(0.0, 0.0, 0.0),
-        Vector3::<f32>::new(0.0, 1.0, 0.0),
-    );
 
-    let projection = perspective(
-        Deg::<f32>(45.0),
-        vulkan_base
+    let mut aspect = vulkan_base
+        .target
+        .surface_extent
+        .width
+        .to_f32()
+        .expect("failed to convert surface width to f32")
+        / vulkan_base
+            .target
             .surface_extent
-            .width
+            .height
             .to_f32()
-            .expect("failed to convert surface width to f32")
-            / vulkan_base
-                .surface_extent
-                .height
-                .to_f32()
-                .expect("failed to convert surface width to f32"),
-        0.1,
-        100.0,
-    );
+            .expect("failed to convert surface width to f32");
 
-    let mvp = projection * view * model;
+    // the surface extent is in the display's orientation, the projection uses the window's
+    if vulkan_base.is_pre_rotated() {
+        aspect = 1.0 / aspect;
+    }
 
-    let curr_uniform_buffer =
-        &mut vulkan_data.uniform_mem_buffers[vulkan_data.curr_resource_index as usize];
+    let view_projection = camera.view_projection(45.0, aspect);
 
-    let mvp_data = cgmath::conv::array4(mvp);
-    let mvp_data_bytes = bytemuck::cast_slice(&mvp_data);
+    let pre_rotation = Matrix4::from_angle_z(Deg(vulkan_base.pre_rotation_degrees()));
 
-    curr_uniform_buffer.allocation.mapped_slice_mut().unwrap()[..16 * 4]
-        .copy_from_slice(mvp_data_bytes);
+    match vulkan_data.mesh.as_ref() {
+        Some(mesh) => {
+            let model: Matrix4<f32> = Matrix4::from_scale(MESH_SCALE)
+                * Matrix4::from_angle_y(Deg::<f32>(time_since_beginning_sec * 20.0));
 
-    unsafe {
-        vulkan_base.device.cmd_push_constants(
+            super::draw_mesh(
+                vulkan_base,
+                mesh,
+                command_buffer,
+                pre_rotation * view_projection * model,
+            );
+        }
+        None => {
+            let model: Matrix4<f32> = Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0))
+                * Matrix4::from_angle_x(Deg::<f32>(120.0))
+                * Matrix4::from_angle_z(Deg::<f32>(time_since_beginning_sec * 20.0));
+
+            super::draw_teapot(
+                vulkan_data,
+                vulkan_base,
+                command_buffer,
+                pre_rotation * view_projection * model,
+            )?;
+        }
+    }
+
+    if let Some(overlay) = overlay {
+        vulkan_utils::cmd_begin_debug_label(
+            vulkan_base.debug_utils_loader.as_ref(),
             command_buffer,
-            vulkan_data.pipeline_layout,
-            vk::ShaderStageFlags::TESSELLATION_CONTROL,
-            0,
-            bytemuck::cast_slice(&[vulkan_data.tesselation_level]),
+            "Overlay",
+            OVERLAY_LABEL_COLOR,
         );
 
-        vulkan_base.device.cmd_bind_descriptor_sets(
+        overlay.record(
             command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            vulkan_data.pipeline_layout,
-            0,
-            &[descriptor_set],
-            &[],
-        );
+            image_index as usize,
+            vulkan_base.target.surface_extent,
+        )?;
 
-        let curr_pipeline = match vulkan_data.is_wireframe_mode {
-            true => vulkan_data.wireframe_pipeline,
-            false => vulkan_data.solid_pipeline,
-        };
+        vulkan_utils::cmd_end_debug_label(vulkan_base.debug_utils_loader.as_ref(), command_buffer);
+    }
 
-        vulkan_base.device.cmd_bind_pipeline(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            curr_pipeline,
-        );
+    match vulkan_data.rendering_mode {
+        RenderingMode::RenderPass => unsafe {
+            vulkan_base.device.cmd_end_render_pass(command_buffer);
+        },
+        RenderingMode::Dynamic => {
+            super::end_rendering(vulkan_base, image_index as usize, command_buffer)?
+        }
+    }
 
-        vulkan_base.device.cmd_bind_index_buffer(
-            command_buffer,
-            vulkan_data.patches_mem_buffer.buffer,
-            0,
-            vk::IndexType::UINT16,
-        );
+    vulkan_utils::cmd_end_debug_label(vulkan_base.debug_utils_loader.as_ref(), command_buffer);
 
-        vulkan_base.device.cmd_draw_indexed(
+    if let Some(gpu_timer) = vulkan_data.gpu_timer.as_mut() {
+        gpu_timer.end(
+            &vulkan_base.device,
             command_buffer,
-            vulkan_data.patch_point_count,
-            1,
-            0,
-            0,
-            0,
+            vulkan_data.frame_sync.frame_index(),
         );
     }
 
     unsafe {
-        vulkan_base.device.cmd_end_render_pass(command_buffer);
-
         vulkan_base
             .device
             .end_command_buffer(command_buffer)
             .map_err(|_| String::from("failed to end command buffer"))?
     }
 
-    super::submit(vulkan_data, vulkan_base, command_buffer)?;
+    vulkan_data
+        .frame_sync
+        .submit(vulkan_base, command_buffer, image_index)
+        .map_err(|err| err.to_string())?;
+
+    if let Some(path) = vulkan_data.capture_path.take() {
+        if let Err(msg) = vulkan_base.capture_frame(image_index, &path) {
+            log::warn!("{}", msg);
+        }
+    }
 
-    if !super::present(vulkan_data, vulkan_base, image_index)? {
+    if !vulkan_data
+        .frame_sync
+        .present(vulkan_base, image_index)
+        .map_err(|err| err.to_string())?
+    {
         println!("swapchain is suboptimal or out of date");
         vulkan_data.should_resize = true;
         return Ok(());
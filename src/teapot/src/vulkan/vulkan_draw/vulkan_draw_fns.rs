@@ -1,89 +1,49 @@
-use crate::vulkan::VulkanData;
-use vulkan_base::VulkanBase;
+use crate::vulkan::{CommandBufferReset, VulkanData, VulkanMesh};
+use vulkan_base::{ImageBarrier, VulkanBase};
 
 use ash::vk;
+use cgmath::Matrix4;
 
-pub enum GetImageIndexResult {
-    Index(u32),
-    ShouldRebuildSwapchain,
-}
-
-pub fn get_image_index(
-    vulkan_data: &VulkanData,
-    vulkan_base: &VulkanBase,
-) -> Result<GetImageIndexResult, String> {
-    let (index, is_suboptimal) = match unsafe {
-        vulkan_base.swapchain_loader.acquire_next_image(
-            vulkan_base.swapchain,
-            u64::MAX,
-            vulkan_data.image_available_semaphore,
-            vk::Fence::null(),
-        )
-    } {
-        Ok((index, is_suboptimal)) => (index, is_suboptimal),
-        Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-            return Ok(GetImageIndexResult::ShouldRebuildSwapchain)
-        }
-        Err(_) => return Err(String::from("failed to acquire next image")),
-    };
-
-    if is_suboptimal {
-        return Ok(GetImageIndexResult::ShouldRebuildSwapchain);
-    }
-
-    Ok(GetImageIndexResult::Index(index))
-}
-
-pub fn wait_resource_available(
-    vulkan_data: &VulkanData,
-    vulkan_base: &VulkanBase,
-) -> Result<(), String> {
-    let fence = vulkan_data.fences[vulkan_data.curr_resource_index as usize];
-
-    unsafe {
-        vulkan_base
-            .device
-            .wait_for_fences(&[fence], true, u64::MAX)
-            .map_err(|_| {
-                format!(
-                    "failed to wait for resource fence {}",
-                    vulkan_data.curr_resource_index
-                )
-            })?;
-
-        vulkan_base.device.reset_fences(&[fence]).map_err(|_| {
-            format!(
-                "failed to reset resource fence {}",
-                vulkan_data.curr_resource_index
-            )
-        })?;
-    }
-
-    Ok(())
-}
-
-pub fn reset_command_pool(
+/// Resets the command buffers of the frame once its fence has signaled, per pool or per
+/// buffer as set by `VulkanData::command_buffer_reset`, and makes them available to
+/// `get_command_buffer` again.
+pub fn reset_command_buffers(
     vulkan_data: &mut VulkanData,
     vulkan_base: &VulkanBase,
 ) -> Result<(), String> {
-    let command_pool = vulkan_data.command_pools[vulkan_data.curr_resource_index as usize];
+    let command_pool = vulkan_data.command_pools[vulkan_data.frame_sync.frame_index() as usize];
     let available_command_buffers =
-        &mut vulkan_data.available_command_buffers[vulkan_data.curr_resource_index as usize];
+        &mut vulkan_data.available_command_buffers[vulkan_data.frame_sync.frame_index() as usize];
     let used_command_buffers =
-        &mut vulkan_data.used_command_buffers[vulkan_data.curr_resource_index as usize];
+        &mut vulkan_data.used_command_buffers[vulkan_data.frame_sync.frame_index() as usize];
 
     unsafe {
-        let curr_resource_index = vulkan_data.curr_resource_index;
+        let curr_resource_index = vulkan_data.frame_sync.frame_index();
 
-        vulkan_base
-            .device
-            .reset_command_pool(command_pool, vk::CommandPoolResetFlags::RELEASE_RESOURCES)
-            .map_err(|_| {
-                format!(
-                    "failed to reset command pool for frame index {}",
-                    curr_resource_index
-                )
-            })?;
+        match vulkan_data.command_buffer_reset {
+            CommandBufferReset::Pool => vulkan_base
+                .device
+                .reset_command_pool(command_pool, vk::CommandPoolResetFlags::RELEASE_RESOURCES)
+                .map_err(|_| {
+                    format!(
+                        "failed to reset command pool for frame index {}",
+                        curr_resource_index
+                    )
+                })?,
+            CommandBufferReset::Buffer => {
+                for &command_buffer in used_command_buffers.iter() {
+                    vulkan_base
+                        .device
+                        .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                        .map_err(|_| {
+                            format!(
+                                "failed to reset command buffer for frame index {}",
+                                curr_resource_index
+                            )
+                        })?;
+                }
+            }
+        }
 
         available_command_buffers.append(used_command_buffers);
     }
@@ -91,13 +51,14 @@ pub fn reset_command_pool(
     Ok(())
 }
 
+/// A primary command buffer of the frame's pool, allocated in batches when none is left.
 pub fn get_command_buffer(
     vulkan_data: &mut VulkanData,
     vulkan_base: &VulkanBase,
 ) -> Result<vk::CommandBuffer, String> {
-    let command_pool = vulkan_data.command_pools[vulkan_data.curr_resource_index as usize];
+    let command_pool = vulkan_data.command_pools[vulkan_data.frame_sync.frame_index() as usize];
     let available_command_buffers =
-        &mut vulkan_data.available_command_buffers[vulkan_data.curr_resource_index as usize];
+        &mut vulkan_data.available_command_buffers[vulkan_data.frame_sync.frame_index() as usize];
 
     if available_command_buffers.is_empty() {
         unsafe {
@@ -107,7 +68,7 @@ pub fn get_command_buffer(
                 .command_buffer_count(10)
                 .build();
 
-            let curr_resource_index = vulkan_data.curr_resource_index;
+            let curr_resource_index = vulkan_data.frame_sync.frame_index();
 
             let mut command_buffers = vulkan_base
                 .device
@@ -126,7 +87,7 @@ pub fn get_command_buffer(
     let command_buffer = available_command_buffers.pop().unwrap();
 
     let used_command_buffers =
-        &mut vulkan_data.used_command_buffers[vulkan_data.curr_resource_index as usize];
+        &mut vulkan_data.used_command_buffers[vulkan_data.frame_sync.frame_index() as usize];
 
     used_command_buffers.push(command_buffer);
 
@@ -158,11 +119,11 @@ pub fn begin_render_pass(
     command_buffer: vk::CommandBuffer,
 ) {
     let clear_color = vk::ClearColorValue {
-        float32: [0.5f32, 0.5f32, 0.5f32, 1.0f32],
+        float32: vulkan_data.clear_color,
     };
 
     let clear_depth = vk::ClearDepthStencilValue {
-        depth: 1.0,
+        depth: vulkan_data.depth_clear,
         stencil: 0,
     };
 
@@ -178,7 +139,7 @@ pub fn begin_render_pass(
         .framebuffer(vulkan_data.framebuffers[image_index])
         .render_area(vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
-            extent: vulkan_base.surface_extent,
+            extent: vulkan_base.target.surface_extent,
         })
         .clear_values(&clear_values)
         .build();
@@ -192,12 +153,116 @@ pub fn begin_render_pass(
     }
 }
 
+/// `RenderingMode::Dynamic` counterpart of `begin_render_pass`. Without a render pass the
+/// attachment layout transitions are recorded explicitly.
+pub fn begin_rendering(
+    vulkan_data: &VulkanData,
+    vulkan_base: &VulkanBase,
+    image_index: usize,
+    command_buffer: vk::CommandBuffer,
+) -> Result<(), String> {
+    let dynamic_rendering_loader = vulkan_base
+        .dynamic_rendering_loader
+        .as_ref()
+        .ok_or_else(|| String::from("dynamic rendering is not enabled"))?;
+    let synchronization2_loader = vulkan_base
+        .synchronization2_loader
+        .as_ref()
+        .ok_or_else(|| String::from("synchronization2 is not enabled"))?;
+
+    let color_attachment = vk::RenderingAttachmentInfo::builder()
+        .image_view(vulkan_base.target.swapchain_image_views[image_index])
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: vulkan_data.clear_color,
+            },
+        })
+        .build();
+
+    let depth_attachment = vk::RenderingAttachmentInfo::builder()
+        .image_view(vulkan_base.target.depth_buffer_mem_image.view)
+        .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .clear_value(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: vulkan_data.depth_clear,
+                stencil: 0,
+            },
+        })
+        .build();
+
+    let color_attachments = [color_attachment];
+    let rendering_info = vk::RenderingInfo::builder()
+        .render_area(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vulkan_base.target.surface_extent,
+        })
+        .layer_count(1)
+        .color_attachments(&color_attachments)
+        .depth_attachment(&depth_attachment)
+        .build();
+
+    vulkan_base::cmd_image_barrier(
+        synchronization2_loader,
+        command_buffer,
+        vulkan_base.target.swapchain_images[image_index],
+        &ImageBarrier::undefined_to_color_attachment(),
+    );
+
+    // the previous frame may still be testing against the depth buffer
+    vulkan_base::cmd_image_barrier(
+        synchronization2_loader,
+        command_buffer,
+        vulkan_base.target.depth_buffer_mem_image.image,
+        &ImageBarrier::undefined_to_depth_attachment(vulkan_base.depth_format),
+    );
+
+    unsafe {
+        dynamic_rendering_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+    }
+
+    Ok(())
+}
+
+/// Ends dynamic rendering and moves the swapchain image to `PRESENT_SRC_KHR`.
+pub fn end_rendering(
+    vulkan_base: &VulkanBase,
+    image_index: usize,
+    command_buffer: vk::CommandBuffer,
+) -> Result<(), String> {
+    let dynamic_rendering_loader = vulkan_base
+        .dynamic_rendering_loader
+        .as_ref()
+        .ok_or_else(|| String::from("dynamic rendering is not enabled"))?;
+    let synchronization2_loader = vulkan_base
+        .synchronization2_loader
+        .as_ref()
+        .ok_or_else(|| String::from("synchronization2 is not enabled"))?;
+
+    unsafe {
+        dynamic_rendering_loader.cmd_end_rendering(command_buffer);
+    }
+
+    vulkan_base::cmd_image_barrier(
+        synchronization2_loader,
+        command_buffer,
+        vulkan_base.target.swapchain_images[image_index],
+        &ImageBarrier::color_attachment_to_present(),
+    );
+
+    Ok(())
+}
+
 pub fn set_viewport(vulkan_base: &VulkanBase, command_buffer: vk::CommandBuffer) {
     let viewport = vk::Viewport {
         x: 0.0,
         y: 0.0,
-        width: vulkan_base.surface_extent.width as f32,
-        height: vulkan_base.surface_extent.height as f32,
+        width: vulkan_base.target.surface_extent.width as f32,
+        height: vulkan_base.target.surface_extent.height as f32,
         min_depth: 0.0f32,
         max_depth: 1.0f32,
     };
@@ -213,8 +278,8 @@ pub fn set_scissor(vulkan_base: &VulkanBase, command_buffer: vk::CommandBuffer)
     let scissor = vk::Rect2D {
         offset: vk::Offset2D { x: 0, y: 0 },
         extent: vk::Extent2D {
-            width: vulkan_base.surface_extent.width,
-            height: vulkan_base.surface_extent.height,
+            width: vulkan_base.target.surface_extent.width,
+            height: vulkan_base.target.surface_extent.height,
         },
     };
 
@@ -225,171 +290,119 @@ pub fn set_scissor(vulkan_base: &VulkanBase, command_buffer: vk::CommandBuffer)
     }
 }
 
-pub fn reset_descriptor_pool(
+/// Records the tessellated teapot, `mvp` is written to the uniform buffer of the current frame.
+pub fn draw_teapot(
     vulkan_data: &mut VulkanData,
     vulkan_base: &VulkanBase,
+    command_buffer: vk::CommandBuffer,
+    mvp: Matrix4<f32>,
 ) -> Result<(), String> {
-    let descriptor_pool = vulkan_data.descriptor_pools[vulkan_data.curr_resource_index as usize];
+    let frame_index = vulkan_data.frame_sync.frame_index() as usize;
+    vulkan_data.update_uniforms(frame_index, cgmath::conv::array4(mvp))?;
 
     unsafe {
-        let curr_resource_index = vulkan_data.curr_resource_index;
+        vulkan_base.device.cmd_push_constants(
+            command_buffer,
+            vulkan_data.pipeline_layout,
+            vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            0,
+            bytemuck::cast_slice(&[vulkan_data.tesselation_level]),
+        );
 
-        vulkan_base
-            .device
-            .reset_descriptor_pool(descriptor_pool, vk::DescriptorPoolResetFlags::empty())
-            .map_err(|_| {
-                format!(
-                    "failed to reset descriptor pool for frame index {}",
-                    curr_resource_index
-                )
-            })?;
-    }
+        vulkan_base.device.cmd_push_constants(
+            command_buffer,
+            vulkan_data.pipeline_layout,
+            vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            4,
+            bytemuck::cast_slice(&[vulkan_data.patch_count]),
+        );
 
-    Ok(())
-}
+        vulkan_base.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            vulkan_data.pipeline_layout,
+            0,
+            &[vulkan_data.descriptor_sets[frame_index]],
+            &[],
+        );
 
-pub fn allocate_descriptor_set(
-    vulkan_data: &mut VulkanData,
-    vulkan_base: &VulkanBase,
-) -> Result<vk::DescriptorSet, String> {
-    let layouts = [vulkan_data.descriptor_set_layout; 1];
+        let curr_pipeline = match vulkan_data.is_wireframe_mode {
+            true => vulkan_data.wireframe_pipeline,
+            false => vulkan_data.solid_pipeline,
+        };
 
-    let alloc_info = vk::DescriptorSetAllocateInfo::builder()
-        .descriptor_pool(vulkan_data.descriptor_pools[vulkan_data.curr_resource_index as usize])
-        .set_layouts(&layouts)
-        .build();
+        vulkan_base.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            curr_pipeline,
+        );
 
-    let descriptor_sets = match unsafe { vulkan_base.device.allocate_descriptor_sets(&alloc_info) }
-    {
-        Ok(sets) => sets,
-        Err(_) => return Err(String::from("failed to allocate descriptor sets")),
-    };
+        // both teapot pipelines have dynamic line width, the solid one ignores it
+        vulkan_base
+            .device
+            .cmd_set_line_width(command_buffer, vulkan_data.line_width);
 
-    let set = descriptor_sets[0];
+        vulkan_base.device.cmd_bind_index_buffer(
+            command_buffer,
+            vulkan_data.patches_mem_buffer.buffer,
+            0,
+            vk::IndexType::UINT16,
+        );
 
-    vulkan_utils::set_debug_utils_object_name2(
-        &vulkan_base.debug_utils_loader,
-        vulkan_base.device.handle(),
-        set,
-        "descriptor set",
-    );
+        vulkan_base.device.cmd_draw_indexed(
+            command_buffer,
+            vulkan_data.patch_point_count,
+            vulkan_data.instance_count,
+            0,
+            0,
+            0,
+        );
+    }
 
-    Ok(set)
+    Ok(())
 }
 
-pub fn update_descriptor_set(
-    vulkan_data: &VulkanData,
+pub fn draw_mesh(
     vulkan_base: &VulkanBase,
-    set: vk::DescriptorSet,
+    mesh: &VulkanMesh,
+    command_buffer: vk::CommandBuffer,
+    mvp: Matrix4<f32>,
 ) {
-    let control_point_buffer_info = vk::DescriptorBufferInfo {
-        buffer: vulkan_data.control_points_mem_buffer.buffer,
-        offset: 0,
-        range: vk::WHOLE_SIZE,
-    };
-
-    let instance_buffer_info = vk::DescriptorBufferInfo {
-        buffer: vulkan_data.instances_mem_buffer.buffer,
-        offset: 0,
-        range: vk::WHOLE_SIZE,
-    };
-
-    let uniform_buffer_info = vk::DescriptorBufferInfo {
-        buffer: vulkan_data.uniform_mem_buffers[vulkan_data.curr_resource_index as usize].buffer,
-        offset: 0,
-        range: vk::WHOLE_SIZE,
-    };
-
-    let infos_1 = [control_point_buffer_info];
-    let write_descriptor_set_1 = vk::WriteDescriptorSet::builder()
-        .dst_set(set)
-        .dst_binding(0)
-        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-        .buffer_info(&infos_1)
-        .build();
-
-    let infos_2 = [instance_buffer_info];
-    let write_descriptor_set_2 = vk::WriteDescriptorSet::builder()
-        .dst_set(set)
-        .dst_binding(1)
-        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-        .buffer_info(&infos_2)
-        .build();
-
-    let infos_3 = [uniform_buffer_info];
-    let write_descriptor_set_3 = vk::WriteDescriptorSet::builder()
-        .dst_set(set)
-        .dst_binding(2)
-        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-        .buffer_info(&infos_3)
-        .build();
+    let mvp = cgmath::conv::array4(mvp);
 
     unsafe {
-        vulkan_base.device.update_descriptor_sets(
-            &[
-                write_descriptor_set_1,
-                write_descriptor_set_2,
-                write_descriptor_set_3,
-            ],
-            &[],
+        vulkan_base.device.cmd_push_constants(
+            command_buffer,
+            mesh.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            bytemuck::cast_slice(&mvp),
         );
-    }
-}
 
-pub fn submit(
-    vulkan_data: &VulkanData,
-    vulkan_base: &VulkanBase,
-    command_buffer: vk::CommandBuffer,
-) -> Result<(), String> {
-    let fence = vulkan_data.fences[vulkan_data.curr_resource_index as usize];
-
-    let wait_semaphores = [vulkan_data.image_available_semaphore];
-    let masks = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-    let cmd_buffers = [command_buffer];
-    let signal_semaphores = [vulkan_data.rendering_finished_semaphore];
-    let submit_info = vk::SubmitInfo::builder()
-        .wait_semaphores(&wait_semaphores)
-        .wait_dst_stage_mask(&masks)
-        .command_buffers(&cmd_buffers)
-        .signal_semaphores(&signal_semaphores)
-        .build();
+        vulkan_base.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            mesh.pipeline,
+        );
+
+        let vertex_buffer = match &mesh.skin {
+            Some(skin) => skin.output_mem_buffer.buffer,
+            None => mesh.vertex_mem_buffer.buffer,
+        };
 
-    unsafe {
         vulkan_base
             .device
-            .queue_submit(vulkan_base.queue, &[submit_info], fence)
-            .map_err(|_| String::from("failed to submit graphics command buffer"))?
-    }
+            .cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
 
-    Ok(())
-}
-
-pub fn present(
-    vulkan_data: &VulkanData,
-    vulkan_base: &VulkanBase,
-    image_index: u32,
-) -> Result<bool, String> {
-    let semaphores = [vulkan_data.rendering_finished_semaphore];
-    let swapchains = [vulkan_base.swapchain];
-    let indices = [image_index];
-    let present_info = vk::PresentInfoKHR::builder()
-        .wait_semaphores(&semaphores)
-        .swapchains(&swapchains)
-        .image_indices(&indices)
-        .build();
+        vulkan_base.device.cmd_bind_index_buffer(
+            command_buffer,
+            mesh.index_mem_buffer.buffer,
+            0,
+            vk::IndexType::UINT32,
+        );
 
-    unsafe {
-        if let Err(err) = vulkan_base
-            .swapchain_loader
-            .queue_present(vulkan_base.queue, &present_info)
-        {
-            if err == vk::Result::SUBOPTIMAL_KHR || err == vk::Result::ERROR_OUT_OF_DATE_KHR {
-                return Ok(false);
-            } else {
-                return Err(String::from("failed to present"));
-            }
-        }
+        vulkan_base
+            .device
+            .cmd_draw_indexed(command_buffer, mesh.index_count, 1, 0, 0, 0);
     }
-
-    Ok(true)
 }
@@ -0,0 +1,180 @@
+use super::VulkanSkin;
+use crate::mesh::MeshData;
+use ash::vk;
+use scopeguard::{guard, ScopeGuard};
+use std::cell::RefCell;
+use vulkan_base::VulkanBase;
+
+/// A loaded mesh and the triangle list pipeline it is drawn with instead of the teapot.
+pub struct VulkanMesh {
+    pub vertex_shader_module: vk::ShaderModule,
+    pub fragment_shader_module: vk::ShaderModule,
+    pub vertex_mem_buffer: vulkan_utils::MemBuffer,
+    pub index_mem_buffer: vulkan_utils::MemBuffer,
+    pub index_count: u32,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    /// Set by `VulkanData::load_mesh` for meshes with `MeshData::skin`.
+    pub skin: Option<VulkanSkin>,
+}
+
+impl VulkanMesh {
+    pub fn new(
+        vulkan_base: &mut VulkanBase,
+        mesh_data: &MeshData,
+        render_pass: vk::RenderPass,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<Self, String> {
+        let (transfer_queue_family, transfer_queue) = vulkan_base.get_transfer_queue();
+        let device = &vulkan_base.device;
+        let mut allocator = vulkan_base.allocator.lock().unwrap();
+        let allocator_rc = RefCell::new(&mut *allocator);
+
+        let vertex_sm_sg = {
+            let vertex_sm =
+                create_shader_module!(vulkan_base, "mesh.vert.spv", "mesh vertex shader")?;
+
+            guard(vertex_sm, |sm| {
+                log::warn!("mesh vertex shader scopeguard");
+                unsafe {
+                    device.destroy_shader_module(sm, None);
+                }
+            })
+        };
+
+        let fragment_sm_sg = {
+            let fragment_sm =
+                create_shader_module!(vulkan_base, "mesh.frag.spv", "mesh fragment shader")?;
+
+            guard(fragment_sm, |sm| {
+                log::warn!("mesh fragment shader scopeguard");
+                unsafe {
+                    device.destroy_shader_module(sm, None);
+                }
+            })
+        };
+
+        let vertex_mem_buffer_sg = {
+            let mem_buffer = vulkan_utils::create_gpu_buffer_init(
+                device,
+                *allocator_rc.borrow_mut(),
+                vulkan_base.debug_utils_loader.as_ref(),
+                transfer_queue_family,
+                transfer_queue,
+                vulkan_base.queue_family,
+                vulkan_base.queue,
+                mesh_data.get_vertices_slice(),
+                // skinning reads the vertices in a compute shader
+                match mesh_data.skin {
+                    Some(_) => {
+                        vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER
+                    }
+                    None => vk::BufferUsageFlags::VERTEX_BUFFER,
+                },
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                "mesh vertex buffer",
+            )?;
+
+            guard(mem_buffer, |mem_buffer| {
+                log::warn!("mesh vertex buffer scopeguard");
+                unsafe {
+                    device.destroy_buffer(mem_buffer.buffer, None);
+                }
+                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+            })
+        };
+
+        let index_mem_buffer_sg = {
+            let mem_buffer = vulkan_utils::create_gpu_buffer_init(
+                device,
+                *allocator_rc.borrow_mut(),
+                vulkan_base.debug_utils_loader.as_ref(),
+                transfer_queue_family,
+                transfer_queue,
+                vulkan_base.queue_family,
+                vulkan_base.queue,
+                mesh_data.get_indices_slice(),
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                vk::AccessFlags::INDEX_READ,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                "mesh index buffer",
+            )?;
+
+            guard(mem_buffer, |mem_buffer| {
+                log::warn!("mesh index buffer scopeguard");
+                unsafe {
+                    device.destroy_buffer(mem_buffer.buffer, None);
+                }
+                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+            })
+        };
+
+        let pipeline_layout_sg = {
+            let pipeline_layout = teapot_common::create_basic_pipeline_layout(
+                device,
+                vulkan_base.debug_utils_loader.as_ref(),
+            )?;
+
+            guard(pipeline_layout, |pipeline_layout| {
+                log::warn!("mesh pipeline layout scopeguard");
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout, None);
+                }
+            })
+        };
+
+        let pipeline = teapot_common::create_basic_pipeline(
+            device,
+            *vertex_sm_sg,
+            *fragment_sm_sg,
+            &teapot_common::PipelineConfig {
+                color_format: vulkan_base.target.surface_format.format,
+                depth_format: vulkan_base.depth_format,
+                pipeline_cache,
+                vertex_layout: teapot_common::VertexLayout::position_normal_uv(),
+                min_sample_shading: crate::MIN_SAMPLE_SHADING,
+                ..Default::default()
+            },
+            *pipeline_layout_sg,
+            render_pass,
+            vulkan_base.debug_utils_loader.as_ref(),
+        )?;
+
+        Ok(VulkanMesh {
+            vertex_shader_module: ScopeGuard::into_inner(vertex_sm_sg),
+            fragment_shader_module: ScopeGuard::into_inner(fragment_sm_sg),
+            vertex_mem_buffer: ScopeGuard::into_inner(vertex_mem_buffer_sg),
+            index_mem_buffer: ScopeGuard::into_inner(index_mem_buffer_sg),
+            index_count: mesh_data.get_index_count(),
+            pipeline_layout: ScopeGuard::into_inner(pipeline_layout_sg),
+            pipeline,
+            skin: None,
+        })
+    }
+
+    pub fn clean(self, vulkan_base: &mut VulkanBase) {
+        log::info!("cleaning mesh");
+
+        if let Some(skin) = self.skin {
+            skin.clean(vulkan_base);
+        }
+
+        unsafe {
+            let device = &vulkan_base.device;
+            let mut allocator = vulkan_base.allocator.lock().unwrap();
+
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+
+            device.destroy_buffer(self.vertex_mem_buffer.buffer, None);
+            let _ = allocator.free(self.vertex_mem_buffer.allocation);
+
+            device.destroy_buffer(self.index_mem_buffer.buffer, None);
+            let _ = allocator.free(self.index_mem_buffer.allocation);
+
+            device.destroy_shader_module(self.vertex_shader_module, None);
+            device.destroy_shader_module(self.fragment_shader_module, None);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use super::ComputePass;
+use crate::mesh::{SkinData, MESH_VERTEX_FLOAT_COUNT};
+use ash::vk;
+use cgmath::Matrix4;
+use scopeguard::{guard, ScopeGuard};
+use std::cell::RefCell;
+use vulkan_base::VulkanBase;
+
+// keep in sync with local_size_x in skinning.comp
+const SKINNING_LOCAL_SIZE: u32 = 64;
+// joint matrices are written with cmd_update_buffer, which is limited to 65536 bytes
+const MAX_JOINT_COUNT: u32 = 1024;
+const JOINT_MATRIX_SIZE: vk::DeviceSize = 64;
+
+/// Skins a mesh's vertices by joint matrices in a compute pass recorded before the frame's
+/// rendering. The mesh is then drawn from `output_mem_buffer` instead of its vertex buffer.
+pub struct VulkanSkin {
+    pub joints_mem_buffer: vulkan_utils::MemBuffer,
+    pub weights_mem_buffer: vulkan_utils::MemBuffer,
+    pub joint_matrices_mem_buffer: vulkan_utils::MemBuffer,
+    pub output_mem_buffer: vulkan_utils::MemBuffer,
+    pub compute_pass: ComputePass,
+    pub vertex_count: u32,
+    pub joint_count: u32,
+}
+
+impl VulkanSkin {
+    /// `vertex_buffer` needs `STORAGE_BUFFER` usage.
+    pub fn new(
+        vulkan_base: &mut VulkanBase,
+        vertex_buffer: vk::Buffer,
+        vertex_count: u32,
+        skin_data: &SkinData,
+    ) -> Result<Self, String> {
+        if skin_data.joint_count == 0 || skin_data.joint_count > MAX_JOINT_COUNT {
+            return Err(format!(
+                "skin joint count {} is not in [1, {}]",
+                skin_data.joint_count, MAX_JOINT_COUNT
+            ));
+        }
+
+        if skin_data
+            .joints
+            .iter()
+            .any(|&joint| joint >= skin_data.joint_count)
+        {
+            return Err(String::from("skin joint index out of range"));
+        }
+
+        let (transfer_queue_family, transfer_queue) = vulkan_base.get_transfer_queue();
+        let device = &vulkan_base.device;
+        let mut allocator = vulkan_base.allocator.lock().unwrap();
+        let allocator_rc = RefCell::new(&mut *allocator);
+
+        let joints_mem_buffer_sg = {
+            let mem_buffer = vulkan_utils::create_gpu_buffer_init(
+                device,
+                *allocator_rc.borrow_mut(),
+                vulkan_base.debug_utils_loader.as_ref(),
+                transfer_queue_family,
+                transfer_queue,
+                vulkan_base.queue_family,
+                vulkan_base.queue,
+                bytemuck::cast_slice(&skin_data.joints),
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                "skin joints buffer",
+            )?;
+
+            guard(mem_buffer, |mem_buffer| {
+                log::warn!("skin joints buffer scopeguard");
+                unsafe {
+                    device.destroy_buffer(mem_buffer.buffer, None);
+                }
+                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+            })
+        };
+
+        let weights_mem_buffer_sg = {
+            let mem_buffer = vulkan_utils::create_gpu_buffer_init(
+                device,
+                *allocator_rc.borrow_mut(),
+                vulkan_base.debug_utils_loader.as_ref(),
+                transfer_queue_family,
+                transfer_queue,
+                vulkan_base.queue_family,
+                vulkan_base.queue,
+                bytemuck::cast_slice(&skin_data.weights),
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                "skin weights buffer",
+            )?;
+
+            guard(mem_buffer, |mem_buffer| {
+                log::warn!("skin weights buffer scopeguard");
+                unsafe {
+                    device.destroy_buffer(mem_buffer.buffer, None);
+                }
+                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+            })
+        };
+
+        let joint_matrices_mem_buffer_sg = {
+            let mem_buffer = vulkan_utils::create_buffer(
+                device,
+                *allocator_rc.borrow_mut(),
+                vulkan_base.debug_utils_loader.as_ref(),
+                skin_data.joint_count as vk::DeviceSize * JOINT_MATRIX_SIZE,
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                gpu_allocator::MemoryLocation::GpuOnly,
+                &[vulkan_base.queue_family],
+                "skin joint matrices buffer",
+            )?;
+
+            guard(mem_buffer, |mem_buffer| {
+                log::warn!("skin joint matrices buffer scopeguard");
+                unsafe {
+                    device.destroy_buffer(mem_buffer.buffer, None);
+                }
+                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+            })
+        };
+
+        let output_mem_buffer_sg = {
+            let mem_buffer = vulkan_utils::create_buffer(
+                device,
+                *allocator_rc.borrow_mut(),
+                vulkan_base.debug_utils_loader.as_ref(),
+                (vertex_count as usize * MESH_VERTEX_FLOAT_COUNT * std::mem::size_of::<f32>())
+                    as vk::DeviceSize,
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+                gpu_allocator::MemoryLocation::GpuOnly,
+                &[vulkan_base.queue_family],
+                "skinned vertex buffer",
+            )?;
+
+            guard(mem_buffer, |mem_buffer| {
+                log::warn!("skinned vertex buffer scopeguard");
+                unsafe {
+                    device.destroy_buffer(mem_buffer.buffer, None);
+                }
+                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+            })
+        };
+
+        let shader_module =
+            create_shader_module!(vulkan_base, "skinning.comp.spv", "skinning shader")?;
+
+        let compute_pass = ComputePass::new(
+            device,
+            vulkan_base.debug_utils_loader.as_ref(),
+            shader_module,
+            &[
+                vertex_buffer,
+                joints_mem_buffer_sg.buffer,
+                weights_mem_buffer_sg.buffer,
+                joint_matrices_mem_buffer_sg.buffer,
+                output_mem_buffer_sg.buffer,
+            ],
+            std::mem::size_of::<u32>() as u32,
+            SKINNING_LOCAL_SIZE,
+            "skinning",
+        )?;
+
+        Ok(VulkanSkin {
+            joints_mem_buffer: ScopeGuard::into_inner(joints_mem_buffer_sg),
+            weights_mem_buffer: ScopeGuard::into_inner(weights_mem_buffer_sg),
+            joint_matrices_mem_buffer: ScopeGuard::into_inner(joint_matrices_mem_buffer_sg),
+            output_mem_buffer: ScopeGuard::into_inner(output_mem_buffer_sg),
+            compute_pass,
+            vertex_count,
+            joint_count: skin_data.joint_count,
+        })
+    }
+
+    /// Uploads `joint_matrices` and skins the vertices, must be recorded outside of a render
+    /// pass. The barriers order it after the previous frame's draw and before this one's.
+    pub fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        joint_matrices: &[Matrix4<f32>],
+    ) -> Result<(), String> {
+        if joint_matrices.len() != self.joint_count as usize {
+            return Err(format!(
+                "expected {} joint matrices, got {}",
+                self.joint_count,
+                joint_matrices.len()
+            ));
+        }
+
+        let joint_matrices = joint_matrices
+            .iter()
+            .map(|&matrix| cgmath::conv::array4(matrix))
+            .collect::<Vec<_>>();
+
+        unsafe {
+            // the previous frame may still read the joint matrices and the output
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+
+            device.cmd_update_buffer(
+                command_buffer,
+                self.joint_matrices_mem_buffer.buffer,
+                0,
+                bytemuck::cast_slice(&joint_matrices),
+            );
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .build()],
+                &[],
+                &[],
+            );
+        }
+
+        self.compute_pass.record(
+            device,
+            command_buffer,
+            self.vertex_count,
+            bytemuck::bytes_of(&self.vertex_count),
+        );
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
+                    .build()],
+                &[],
+                &[],
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn clean(self, vulkan_base: &mut VulkanBase) {
+        log::info!("cleaning skin");
+
+        self.compute_pass.clean(&vulkan_base.device);
+
+        unsafe {
+            let device = &vulkan_base.device;
+            let mut allocator = vulkan_base.allocator.lock().unwrap();
+
+            for mem_buffer in [
+                self.joints_mem_buffer,
+                self.weights_mem_buffer,
+                self.joint_matrices_mem_buffer,
+                self.output_mem_buffer,
+            ] {
+                device.destroy_buffer(mem_buffer.buffer, None);
+                let _ = allocator.free(mem_buffer.allocation);
+            }
+        }
+    }
+}
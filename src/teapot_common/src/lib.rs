@@ -0,0 +1,3 @@
+mod pipelines;
+
+pub use pipelines::*;
@@ -0,0 +1,1166 @@
+use ash::vk;
+
+pub fn create_descriptor_set_layout(
+    device: &ash::Device,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+) -> Result<vk::DescriptorSetLayout, String> {
+    log::info!("creating descriptor set layout");
+
+    let control_points_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .build();
+
+    let patch_data_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
+        .build();
+
+    let uniform_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
+        .build();
+
+    let bindings = [control_points_binding, patch_data_binding, uniform_binding];
+    let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&bindings)
+        .build();
+
+    let descriptor_set_layout = unsafe {
+        device
+            .create_descriptor_set_layout(&create_info, None)
+            .map_err(|_| String::from("failed to create descriptor set layout"))?
+    };
+
+    vulkan_utils::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        descriptor_set_layout,
+        "descriptor set layout",
+    );
+
+    log::info!("descriptor set layout created");
+
+    Ok(descriptor_set_layout)
+}
+
+pub fn create_pipeline_layout(
+    device: &ash::Device,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+) -> Result<vk::PipelineLayout, String> {
+    log::info!("creating pipeline layout");
+
+    // tessellation level
+    let tesc_push_const_range = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        offset: 0,
+        size: 4,
+    };
+
+    // patch count, needed to index per instance patch data
+    let tese_push_const_range = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        offset: 4,
+        size: 4,
+    };
+
+    let layouts = [descriptor_set_layout];
+    let ranges = [tesc_push_const_range, tese_push_const_range];
+    let create_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&ranges)
+        .build();
+
+    let pipeline_layout = unsafe {
+        device
+            .create_pipeline_layout(&create_info, None)
+            .map_err(|_| String::from("failed to create pipeline layout"))?
+    };
+
+    vulkan_utils::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        pipeline_layout,
+        "pipeline layout",
+    );
+
+    log::info!("pipeline layout created");
+
+    Ok(pipeline_layout)
+}
+
+pub struct PipelineShaders {
+    pub vertex: vk::ShaderModule,
+    pub tess_control: vk::ShaderModule,
+    pub tess_eval: vk::ShaderModule,
+    pub geometry: Option<vk::ShaderModule>,
+    pub fragment: vk::ShaderModule,
+}
+
+/// How the fragment color is combined with the color attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Overwrites the attachment.
+    Opaque,
+    /// Classic transparency, `src * src_alpha + dst * (1 - src_alpha)`.
+    AlphaBlend,
+    /// `src * src_alpha + dst`, for glows and particles.
+    Additive,
+}
+
+impl BlendMode {
+    pub fn color_blend_attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let builder = vk::PipelineColorBlendAttachmentState::builder().color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        );
+
+        match self {
+            BlendMode::Opaque => builder.blend_enable(false),
+            BlendMode::AlphaBlend => builder
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .alpha_blend_op(vk::BlendOp::ADD),
+            BlendMode::Additive => builder
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD),
+        }
+        .build()
+    }
+}
+
+/// One vertex shader input read from the vertex buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct VertexAttribute {
+    pub format: vk::Format,
+    /// Byte offset within a vertex.
+    pub offset: u32,
+}
+
+/// Layout of the single interleaved vertex buffer at binding 0. Attribute `i` is read at shader
+/// location `i`.
+#[derive(Clone, Debug, Default)]
+pub struct VertexLayout {
+    pub stride: u32,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+impl VertexLayout {
+    /// No vertex buffer, for shaders that pull their input from storage buffers like the teapot.
+    pub fn empty() -> Self {
+        VertexLayout::default()
+    }
+
+    /// Interleaved position (vec3), normal (vec3) and uv (vec2) floats.
+    pub fn position_normal_uv() -> Self {
+        let float_size = std::mem::size_of::<f32>() as u32;
+
+        VertexLayout {
+            stride: 8 * float_size,
+            attributes: vec![
+                VertexAttribute {
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: 0,
+                },
+                VertexAttribute {
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: 3 * float_size,
+                },
+                VertexAttribute {
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: 6 * float_size,
+                },
+            ],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty()
+    }
+
+    fn binding_descriptions(&self) -> Vec<vk::VertexInputBindingDescription> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: self.stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }]
+    }
+
+    fn attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.attributes
+            .iter()
+            .enumerate()
+            .map(
+                |(location, attribute)| vk::VertexInputAttributeDescription {
+                    location: location as u32,
+                    binding: 0,
+                    format: attribute.format,
+                    offset: attribute.offset,
+                },
+            )
+            .collect()
+    }
+}
+
+/// The parts of the teapot pipelines that differ between the teapot binaries.
+#[derive(Clone)]
+pub struct PipelineConfig {
+    /// `NONE` by default, the teapot patches are not closed.
+    pub cull_mode: vk::CullModeFlags,
+    /// `CLOCKWISE` by default, matching the teapot patches. Meshes exported counter-clockwise
+    /// need `COUNTER_CLOCKWISE`, and a viewport with negative height (Y flip) mirrors the
+    /// winding, so the value has to be swapped when one is used.
+    pub front_face: vk::FrontFace,
+    pub depth_test: bool,
+    /// Blended geometry usually also wants `depth_test` off, or drawn back to front.
+    pub blend_mode: BlendMode,
+    pub vertex_layout: VertexLayout,
+    /// Enables sample shading with this `minSampleShading`, clamped to [0, 1]. Needs the
+    /// `sample_rate_shading` device feature and only has an effect with more than one sample.
+    pub min_sample_shading: Option<f32>,
+    /// Adds `LINE_WIDTH` dynamic state so wireframe edges can be made thicker, the width then
+    /// has to be set with `vkCmdSetLineWidth` before drawing. Widths other than 1.0 need the
+    /// `wide_lines` device feature.
+    pub dynamic_line_width: bool,
+    /// Polygon mode of the wireframe pipeline, `LINE` by default or `POINT` to draw only the
+    /// vertices as dots. Both need the `fill_mode_non_solid` device feature. Points are one
+    /// pixel unless the last pre-rasterization stage writes `PointSize`, which tessellation and
+    /// geometry shaders may only do with the `shader_tessellation_and_geometry_point_size`
+    /// feature.
+    pub wireframe_polygon_mode: vk::PolygonMode,
+    // only used without a render pass
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub pipeline_cache: vk::PipelineCache,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::CLOCKWISE,
+            depth_test: true,
+            blend_mode: BlendMode::Opaque,
+            vertex_layout: VertexLayout::empty(),
+            min_sample_shading: None,
+            dynamic_line_width: false,
+            wireframe_polygon_mode: vk::PolygonMode::LINE,
+            color_format: vk::Format::UNDEFINED,
+            depth_format: vk::Format::UNDEFINED,
+            pipeline_cache: vk::PipelineCache::null(),
+        }
+    }
+}
+
+/// Creates the solid pipeline and its wireframe derivative. The wireframe pipeline copies the
+/// solid create info and only swaps the rasterization state, so both share the depth state that
+/// has to match the depth attachment of `create_render_pass`, or `config.depth_format` when
+/// `render_pass` is null.
+///
+/// Both pipelines are created by the same `vkCreateGraphicsPipelines` call, the wireframe
+/// pipeline refers to its base through `base_pipeline_index`. Use `create_wireframe_pipeline`
+/// to derive it from an already created solid pipeline instead.
+pub fn create_pipelines(
+    device: &ash::Device,
+    shaders: &PipelineShaders,
+    config: &PipelineConfig,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+) -> Result<(vk::Pipeline, vk::Pipeline), String> {
+    log::info!("creating pipelines");
+
+    let pipelines = create_teapot_pipelines(
+        device,
+        shaders,
+        config,
+        pipeline_layout,
+        render_pass,
+        vk::Pipeline::null(),
+    )?;
+
+    let solid_pipeline = pipelines[0];
+    let wireframe_pipeline = pipelines[1];
+
+    vulkan_utils::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        solid_pipeline,
+        "solid pipeline",
+    );
+
+    vulkan_utils::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        wireframe_pipeline,
+        "wireframe pipeline",
+    );
+
+    log::info!("pipelines created");
+
+    Ok((solid_pipeline, wireframe_pipeline))
+}
+
+/// Creates the wireframe pipeline on its own, as a derivative of `solid_pipeline` which has to
+/// come from `create_pipelines` with the same `shaders`, `config` and layout.
+pub fn create_wireframe_pipeline(
+    device: &ash::Device,
+    shaders: &PipelineShaders,
+    config: &PipelineConfig,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    solid_pipeline: vk::Pipeline,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+) -> Result<vk::Pipeline, String> {
+    log::info!("creating wireframe pipeline");
+
+    if solid_pipeline == vk::Pipeline::null() {
+        return Err(String::from(
+            "the wireframe pipeline needs a solid base pipeline",
+        ));
+    }
+
+    let pipelines = create_teapot_pipelines(
+        device,
+        shaders,
+        config,
+        pipeline_layout,
+        render_pass,
+        solid_pipeline,
+    )?;
+
+    let wireframe_pipeline = pipelines[0];
+
+    vulkan_utils::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        wireframe_pipeline,
+        "wireframe pipeline",
+    );
+
+    log::info!("wireframe pipeline created");
+
+    Ok(wireframe_pipeline)
+}
+
+/// Checks the derivative setup of `create_infos` as passed to one `vkCreateGraphicsPipelines`
+/// call. A `DERIVATIVE` pipeline names its base either by a `base_pipeline_index` pointing at an
+/// earlier create info of the same call, or by a non-null `base_pipeline_handle`, never both.
+/// The base needs `ALLOW_DERIVATIVES`.
+pub fn validate_pipeline_derivatives(
+    create_infos: &[vk::GraphicsPipelineCreateInfo],
+) -> Result<(), String> {
+    for (index, create_info) in create_infos.iter().enumerate() {
+        if !create_info
+            .flags
+            .contains(vk::PipelineCreateFlags::DERIVATIVE)
+        {
+            continue;
+        }
+
+        let has_base_index = create_info.base_pipeline_index >= 0;
+        let has_base_handle = create_info.base_pipeline_handle != vk::Pipeline::null();
+
+        if has_base_index == has_base_handle {
+            return Err(format!(
+                "derivative pipeline {} needs exactly one of base_pipeline_index or base_pipeline_handle",
+                index
+            ));
+        }
+
+        if has_base_index {
+            let base_index = create_info.base_pipeline_index as usize;
+
+            if base_index >= index {
+                return Err(format!(
+                    "derivative pipeline {} has base_pipeline_index {}, it must point at an earlier create info",
+                    index, base_index
+                ));
+            }
+
+            if !create_infos[base_index]
+                .flags
+                .contains(vk::PipelineCreateFlags::ALLOW_DERIVATIVES)
+            {
+                return Err(format!(
+                    "base pipeline {} of derivative pipeline {} does not allow derivatives",
+                    base_index, index
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Fixed function state shared by the teapot and basic pipelines. The create infos point into
+// the arrays, so the struct has to outlive the `vkCreateGraphicsPipelines` call.
+struct SharedPipelineState {
+    binding_descriptions: Vec<vk::VertexInputBindingDescription>,
+    attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
+    col_blend_attachment_states: [vk::PipelineColorBlendAttachmentState; 1],
+    dynamic_states: Vec<vk::DynamicState>,
+    viewports: [vk::Viewport; 1],
+    scissors: [vk::Rect2D; 1],
+    color_formats: [vk::Format; 1],
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    depth_test: bool,
+    depth_format: vk::Format,
+    min_sample_shading: Option<f32>,
+}
+
+impl SharedPipelineState {
+    fn new(config: &PipelineConfig, render_pass: vk::RenderPass) -> Result<Self, String> {
+        if render_pass == vk::RenderPass::null()
+            && config.depth_test
+            && config.depth_format == vk::Format::UNDEFINED
+        {
+            return Err(String::from(
+                "pipelines need a depth format for their depth-stencil state",
+            ));
+        }
+
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if config.dynamic_line_width {
+            dynamic_states.push(vk::DynamicState::LINE_WIDTH);
+        }
+
+        Ok(SharedPipelineState {
+            binding_descriptions: config.vertex_layout.binding_descriptions(),
+            attribute_descriptions: config.vertex_layout.attribute_descriptions(),
+            col_blend_attachment_states: [config.blend_mode.color_blend_attachment_state()],
+            dynamic_states,
+            viewports: [vk::Viewport {
+                ..Default::default()
+            }],
+            scissors: [vk::Rect2D {
+                ..Default::default()
+            }],
+            color_formats: [config.color_format],
+            cull_mode: config.cull_mode,
+            front_face: config.front_face,
+            depth_test: config.depth_test,
+            depth_format: config.depth_format,
+            min_sample_shading: config
+                .min_sample_shading
+                .map(|min_sample_shading| min_sample_shading.clamp(0.0, 1.0)),
+        })
+    }
+
+    fn vertex_input_state(&self) -> vk::PipelineVertexInputStateCreateInfo {
+        vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&self.binding_descriptions)
+            .vertex_attribute_descriptions(&self.attribute_descriptions)
+            .build()
+    }
+
+    fn rasterization_state(
+        &self,
+        polygon_mode: vk::PolygonMode,
+    ) -> vk::PipelineRasterizationStateCreateInfo {
+        vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .line_width(1.0f32)
+            .build()
+    }
+
+    fn color_blend_state(&self) -> vk::PipelineColorBlendStateCreateInfo {
+        vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&self.col_blend_attachment_states)
+            .build()
+    }
+
+    fn dynamic_state(&self) -> vk::PipelineDynamicStateCreateInfo {
+        vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&self.dynamic_states)
+            .build()
+    }
+
+    fn viewport_state(&self) -> vk::PipelineViewportStateCreateInfo {
+        vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(&self.viewports)
+            .scissors(&self.scissors)
+            .build()
+    }
+
+    fn multisample_state(&self) -> vk::PipelineMultisampleStateCreateInfo {
+        let mut multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        if let Some(min_sample_shading) = self.min_sample_shading {
+            multisample_state = multisample_state
+                .sample_shading_enable(true)
+                .min_sample_shading(min_sample_shading);
+        }
+
+        multisample_state.build()
+    }
+
+    fn depth_stencil_state(&self) -> vk::PipelineDepthStencilStateCreateInfo {
+        vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_test)
+            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .build()
+    }
+
+    // without a render pass the attachment formats are given at pipeline creation
+    fn rendering_create_info(&self) -> vk::PipelineRenderingCreateInfo {
+        vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(self.depth_format)
+            .build()
+    }
+}
+
+// Creates [solid, wireframe] for a null `base_pipeline`, otherwise only [wireframe] derived
+// from `base_pipeline`.
+fn create_teapot_pipelines(
+    device: &ash::Device,
+    shaders: &PipelineShaders,
+    config: &PipelineConfig,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    base_pipeline: vk::Pipeline,
+) -> Result<Vec<vk::Pipeline>, String> {
+    with_teapot_pipeline_create_infos(
+        shaders,
+        config,
+        pipeline_layout,
+        render_pass,
+        base_pipeline,
+        |create_infos| unsafe {
+            device
+                .create_graphics_pipelines(config.pipeline_cache, create_infos, None)
+                .map_err(|err| {
+                    vulkan_utils::destroy_partial_pipelines(
+                        device,
+                        err,
+                        "failed to create pipelines",
+                    )
+                })
+        },
+    )
+}
+
+// Passes the validated create infos of `create_teapot_pipelines` to `f`. They point to locals,
+// so they're only valid during the call.
+fn with_teapot_pipeline_create_infos<T>(
+    shaders: &PipelineShaders,
+    config: &PipelineConfig,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    base_pipeline: vk::Pipeline,
+    f: impl FnOnce(&[vk::GraphicsPipelineCreateInfo]) -> Result<T, String>,
+) -> Result<T, String> {
+    let shared_state = SharedPipelineState::new(config, render_pass)?;
+
+    let shader_entry_name = std::ffi::CString::new("main").unwrap();
+
+    let vs_state = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(shaders.vertex)
+        .name(&shader_entry_name)
+        .build();
+
+    let tc_state = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::TESSELLATION_CONTROL)
+        .module(shaders.tess_control)
+        .name(&shader_entry_name)
+        .build();
+
+    let te_state = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
+        .module(shaders.tess_eval)
+        .name(&shader_entry_name)
+        .build();
+
+    let fs_state = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(shaders.fragment)
+        .name(&shader_entry_name)
+        .build();
+
+    let ia_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::PATCH_LIST)
+        .build();
+
+    let raster_state = shared_state.rasterization_state(vk::PolygonMode::FILL);
+
+    let col_blend_state = shared_state.color_blend_state();
+    let dyn_state = shared_state.dynamic_state();
+    let viewport_state = shared_state.viewport_state();
+    let multisample_state = shared_state.multisample_state();
+    let depth_stencil_state = shared_state.depth_stencil_state();
+    let mut rendering_create_info = shared_state.rendering_create_info();
+
+    let tessellation_state = vk::PipelineTessellationStateCreateInfo::builder()
+        .patch_control_points(16)
+        .build();
+
+    let mut stages = vec![vs_state, tc_state, te_state];
+    if let Some(geometry_shader_module) = shaders.geometry {
+        stages.push(
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::GEOMETRY)
+                .module(geometry_shader_module)
+                .name(&shader_entry_name)
+                .build(),
+        );
+    }
+    stages.push(fs_state);
+
+    let vert_inp_state = shared_state.vertex_input_state();
+
+    let mut solid_pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .flags(vk::PipelineCreateFlags::ALLOW_DERIVATIVES)
+        .stages(&stages)
+        .input_assembly_state(&ia_state)
+        .rasterization_state(&raster_state)
+        .color_blend_state(&col_blend_state)
+        .dynamic_state(&dyn_state)
+        .viewport_state(&viewport_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .multisample_state(&multisample_state)
+        .tessellation_state(&tessellation_state)
+        .vertex_input_state(&vert_inp_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .base_pipeline_handle(vk::Pipeline::null())
+        .base_pipeline_index(-1);
+
+    if render_pass == vk::RenderPass::null() {
+        solid_pipeline_create_info =
+            solid_pipeline_create_info.push_next(&mut rendering_create_info);
+    }
+
+    let solid_pipeline_create_info = solid_pipeline_create_info.build();
+
+    let raster_state = shared_state.rasterization_state(config.wireframe_polygon_mode);
+
+    let mut wireframe_pipeline_create_info = solid_pipeline_create_info;
+    wireframe_pipeline_create_info.flags = vk::PipelineCreateFlags::DERIVATIVE;
+    wireframe_pipeline_create_info.p_rasterization_state = &raster_state;
+
+    let create_infos = if base_pipeline == vk::Pipeline::null() {
+        wireframe_pipeline_create_info.base_pipeline_index = 0;
+        vec![solid_pipeline_create_info, wireframe_pipeline_create_info]
+    } else {
+        wireframe_pipeline_create_info.base_pipeline_handle = base_pipeline;
+        vec![wireframe_pipeline_create_info]
+    };
+
+    validate_pipeline_derivatives(&create_infos)?;
+
+    f(&create_infos)
+}
+
+/// Pipeline layout of `create_basic_pipeline`, the mvp matrix is pushed to the vertex shader and
+/// no descriptor sets are used.
+pub fn create_basic_pipeline_layout(
+    device: &ash::Device,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+) -> Result<vk::PipelineLayout, String> {
+    log::info!("creating basic pipeline layout");
+
+    let ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX,
+        offset: 0,
+        size: std::mem::size_of::<[[f32; 4]; 4]>() as u32,
+    }];
+    let create_info = vk::PipelineLayoutCreateInfo::builder()
+        .push_constant_ranges(&ranges)
+        .build();
+
+    let pipeline_layout = unsafe {
+        device
+            .create_pipeline_layout(&create_info, None)
+            .map_err(|_| String::from("failed to create basic pipeline layout"))?
+    };
+
+    vulkan_utils::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        pipeline_layout,
+        "basic pipeline layout",
+    );
+
+    log::info!("basic pipeline layout created");
+
+    Ok(pipeline_layout)
+}
+
+/// Creates a `TRIANGLE_LIST` pipeline without tessellation stages for ordinary indexed meshes,
+/// with vertices described by `config.vertex_layout`, usually
+/// `VertexLayout::position_normal_uv`. Blend, depth and dynamic state match `create_pipelines`.
+pub fn create_basic_pipeline(
+    device: &ash::Device,
+    vertex_shader_module: vk::ShaderModule,
+    fragment_shader_module: vk::ShaderModule,
+    config: &PipelineConfig,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+) -> Result<vk::Pipeline, String> {
+    log::info!("creating basic pipeline");
+
+    let shared_state = SharedPipelineState::new(config, render_pass)?;
+
+    let shader_entry_name = std::ffi::CString::new("main").unwrap();
+
+    let stages = [
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertex_shader_module)
+            .name(&shader_entry_name)
+            .build(),
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragment_shader_module)
+            .name(&shader_entry_name)
+            .build(),
+    ];
+
+    let vert_inp_state = shared_state.vertex_input_state();
+
+    let ia_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .build();
+
+    let raster_state = shared_state.rasterization_state(vk::PolygonMode::FILL);
+
+    let col_blend_state = shared_state.color_blend_state();
+    let dyn_state = shared_state.dynamic_state();
+    let viewport_state = shared_state.viewport_state();
+    let multisample_state = shared_state.multisample_state();
+    let depth_stencil_state = shared_state.depth_stencil_state();
+    let mut rendering_create_info = shared_state.rendering_create_info();
+
+    let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vert_inp_state)
+        .input_assembly_state(&ia_state)
+        .rasterization_state(&raster_state)
+        .color_blend_state(&col_blend_state)
+        .dynamic_state(&dyn_state)
+        .viewport_state(&viewport_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0);
+
+    if render_pass == vk::RenderPass::null() {
+        create_info = create_info.push_next(&mut rendering_create_info);
+    }
+
+    let pipeline = unsafe {
+        device
+            .create_graphics_pipelines(config.pipeline_cache, &[create_info.build()], None)
+            .map_err(|err| {
+                vulkan_utils::destroy_partial_pipelines(
+                    device,
+                    err,
+                    "failed to create basic pipeline",
+                )
+            })?[0]
+    };
+
+    vulkan_utils::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        pipeline,
+        "basic pipeline",
+    );
+
+    log::info!("basic pipeline created");
+
+    Ok(pipeline)
+}
+
+/// Creates the single subpass render pass. Passing `vk::Format::UNDEFINED` as `depth_format`
+/// leaves out the depth attachment.
+pub fn create_render_pass(
+    device: &ash::Device,
+    surface_format: vk::Format,
+    depth_format: vk::Format,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+) -> Result<vk::RenderPass, String> {
+    log::info!("creating render pass");
+
+    let attachment_descriptions = render_pass_attachment_descriptions(surface_format, depth_format);
+
+    let col_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build();
+
+    let depth_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .build();
+
+    let references = [col_attachment_ref];
+
+    let mut subpass_descriptions = Vec::new();
+
+    let mut subpass_description = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&references);
+
+    if depth_format != vk::Format::UNDEFINED {
+        subpass_description = subpass_description.depth_stencil_attachment(&depth_attachment_ref);
+    }
+
+    subpass_descriptions.push(subpass_description.build());
+
+    let create_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachment_descriptions)
+        .subpasses(&subpass_descriptions);
+
+    let render_pass = unsafe {
+        device
+            .create_render_pass(&create_info, None)
+            .map_err(|_| String::from("failed to create render pass"))?
+    };
+
+    vulkan_utils::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        render_pass,
+        "render pass",
+    );
+
+    log::info!("render pass created");
+
+    Ok(render_pass)
+}
+
+// the color attachment, which has the swapchain images' format, then the optional depth one
+fn render_pass_attachment_descriptions(
+    surface_format: vk::Format,
+    depth_format: vk::Format,
+) -> Vec<vk::AttachmentDescription> {
+    let mut attachment_descriptions = Vec::new();
+
+    attachment_descriptions.push(
+        vk::AttachmentDescription::builder()
+            .format(surface_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .build(),
+    );
+
+    if depth_format != vk::Format::UNDEFINED {
+        attachment_descriptions.push(
+            vk::AttachmentDescription::builder()
+                .format(depth_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .build(),
+        );
+    }
+
+    attachment_descriptions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    #[test]
+    fn render_pass_color_attachment_has_the_swapchain_format() {
+        let swapchain_formats = [
+            vk::Format::B8G8R8A8_UNORM,
+            vk::Format::B8G8R8A8_SRGB,
+            vk::Format::R8G8B8A8_SRGB,
+        ];
+
+        for swapchain_format in swapchain_formats {
+            let attachment_descriptions =
+                render_pass_attachment_descriptions(swapchain_format, vk::Format::D32_SFLOAT);
+
+            assert_eq!(attachment_descriptions.len(), 2);
+            assert_eq!(attachment_descriptions[0].format, swapchain_format);
+            assert_eq!(
+                attachment_descriptions[0].final_layout,
+                vk::ImageLayout::PRESENT_SRC_KHR
+            );
+            assert_eq!(attachment_descriptions[1].format, vk::Format::D32_SFLOAT);
+        }
+    }
+
+    #[test]
+    fn render_pass_without_depth_format_has_only_a_color_attachment() {
+        let attachment_descriptions =
+            render_pass_attachment_descriptions(vk::Format::B8G8R8A8_SRGB, vk::Format::UNDEFINED);
+
+        assert_eq!(attachment_descriptions.len(), 1);
+        assert_eq!(attachment_descriptions[0].format, vk::Format::B8G8R8A8_SRGB);
+    }
+
+    fn test_shaders() -> PipelineShaders {
+        PipelineShaders {
+            vertex: vk::ShaderModule::null(),
+            tess_control: vk::ShaderModule::null(),
+            tess_eval: vk::ShaderModule::null(),
+            geometry: None,
+            fragment: vk::ShaderModule::null(),
+        }
+    }
+
+    // the depth attachment format given without a render pass, None with one
+    fn depth_attachment_format(create_info: &vk::GraphicsPipelineCreateInfo) -> Option<vk::Format> {
+        let rendering_create_info = create_info.p_next as *const vk::PipelineRenderingCreateInfo;
+
+        unsafe { rendering_create_info.as_ref() }.map(|info| info.depth_attachment_format)
+    }
+
+    #[test]
+    fn teapot_pipelines_have_depth_state_for_the_depth_format() {
+        let config = PipelineConfig {
+            color_format: vk::Format::B8G8R8A8_SRGB,
+            depth_format: vk::Format::D32_SFLOAT,
+            ..Default::default()
+        };
+
+        // the create infos point to state dropped after the closure
+        with_teapot_pipeline_create_infos(
+            &test_shaders(),
+            &config,
+            vk::PipelineLayout::null(),
+            vk::RenderPass::null(),
+            vk::Pipeline::null(),
+            |create_infos| {
+                assert_eq!(create_infos.len(), 2);
+                assert_eq!(
+                    create_infos[0].flags,
+                    vk::PipelineCreateFlags::ALLOW_DERIVATIVES
+                );
+                assert_eq!(create_infos[1].flags, vk::PipelineCreateFlags::DERIVATIVE);
+                assert_eq!(create_infos[1].base_pipeline_index, 0);
+
+                for create_info in create_infos {
+                    let depth_stencil_state = unsafe { create_info.p_depth_stencil_state.as_ref() }
+                        .expect("no depth-stencil state");
+
+                    assert_eq!(depth_stencil_state.depth_test_enable, vk::TRUE);
+                    assert_eq!(depth_stencil_state.depth_write_enable, vk::TRUE);
+                    assert_eq!(
+                        depth_attachment_format(create_info),
+                        Some(vk::Format::D32_SFLOAT)
+                    );
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn wireframe_pipeline_alone_is_derived_from_the_base_handle() {
+        let base_pipeline = vk::Pipeline::from_raw(1);
+
+        with_teapot_pipeline_create_infos(
+            &test_shaders(),
+            &PipelineConfig::default(),
+            vk::PipelineLayout::null(),
+            vk::RenderPass::from_raw(1),
+            base_pipeline,
+            |create_infos| {
+                assert_eq!(create_infos.len(), 1);
+                assert_eq!(create_infos[0].flags, vk::PipelineCreateFlags::DERIVATIVE);
+                assert_eq!(create_infos[0].base_pipeline_handle, base_pipeline);
+                assert_eq!(create_infos[0].base_pipeline_index, -1);
+                // the render pass has the formats
+                assert_eq!(depth_attachment_format(&create_infos[0]), None);
+                Ok(())
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn dynamic_rendering_without_depth_format_is_rejected() {
+        let config = PipelineConfig {
+            color_format: vk::Format::B8G8R8A8_SRGB,
+            ..Default::default()
+        };
+
+        assert!(SharedPipelineState::new(&config, vk::RenderPass::null()).is_err());
+        assert!(SharedPipelineState::new(&config, vk::RenderPass::from_raw(1)).is_ok());
+
+        let config = PipelineConfig {
+            depth_test: false,
+            ..config
+        };
+
+        assert!(SharedPipelineState::new(&config, vk::RenderPass::null()).is_ok());
+    }
+
+    fn pipeline_create_info(
+        flags: vk::PipelineCreateFlags,
+        base_pipeline_handle: vk::Pipeline,
+        base_pipeline_index: i32,
+    ) -> vk::GraphicsPipelineCreateInfo {
+        vk::GraphicsPipelineCreateInfo::builder()
+            .flags(flags)
+            .base_pipeline_handle(base_pipeline_handle)
+            .base_pipeline_index(base_pipeline_index)
+            .build()
+    }
+
+    #[test]
+    fn pipeline_derivatives_need_exactly_one_base() {
+        let base = pipeline_create_info(
+            vk::PipelineCreateFlags::ALLOW_DERIVATIVES,
+            vk::Pipeline::null(),
+            -1,
+        );
+        let derivative = |base_pipeline_handle, base_pipeline_index| {
+            pipeline_create_info(
+                vk::PipelineCreateFlags::DERIVATIVE,
+                base_pipeline_handle,
+                base_pipeline_index,
+            )
+        };
+        let base_pipeline = vk::Pipeline::from_raw(1);
+
+        assert!(
+            validate_pipeline_derivatives(&[base, derivative(vk::Pipeline::null(), 0)]).is_ok()
+        );
+        assert!(validate_pipeline_derivatives(&[derivative(base_pipeline, -1)]).is_ok());
+
+        assert!(validate_pipeline_derivatives(&[base, derivative(base_pipeline, 0)]).is_err());
+        assert!(
+            validate_pipeline_derivatives(&[base, derivative(vk::Pipeline::null(), -1)]).is_err()
+        );
+    }
+
+    #[test]
+    fn pipeline_derivative_base_index_must_point_back_to_an_allowing_pipeline() {
+        let derivative =
+            pipeline_create_info(vk::PipelineCreateFlags::DERIVATIVE, vk::Pipeline::null(), 1);
+        let base = pipeline_create_info(
+            vk::PipelineCreateFlags::ALLOW_DERIVATIVES,
+            vk::Pipeline::null(),
+            -1,
+        );
+        let not_allowing_base =
+            pipeline_create_info(vk::PipelineCreateFlags::empty(), vk::Pipeline::null(), -1);
+
+        // the base comes after the derivative
+        assert!(validate_pipeline_derivatives(&[derivative, base]).is_err());
+
+        let derivative =
+            pipeline_create_info(vk::PipelineCreateFlags::DERIVATIVE, vk::Pipeline::null(), 0);
+
+        assert!(validate_pipeline_derivatives(&[not_allowing_base, derivative]).is_err());
+    }
+
+    #[test]
+    fn rasterization_state_has_the_configured_cull_mode_and_front_face() {
+        let config = PipelineConfig {
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            ..Default::default()
+        };
+        let shared_state = SharedPipelineState::new(&config, vk::RenderPass::from_raw(1)).unwrap();
+
+        for polygon_mode in [vk::PolygonMode::FILL, vk::PolygonMode::LINE] {
+            let rasterization_state = shared_state.rasterization_state(polygon_mode);
+
+            assert_eq!(rasterization_state.cull_mode, vk::CullModeFlags::BACK);
+            assert_eq!(
+                rasterization_state.front_face,
+                vk::FrontFace::COUNTER_CLOCKWISE
+            );
+            assert_eq!(rasterization_state.polygon_mode, polygon_mode);
+        }
+    }
+
+    #[test]
+    fn rasterization_state_defaults() {
+        let shared_state =
+            SharedPipelineState::new(&PipelineConfig::default(), vk::RenderPass::from_raw(1))
+                .unwrap();
+
+        let rasterization_state = shared_state.rasterization_state(vk::PolygonMode::FILL);
+
+        assert_eq!(rasterization_state.cull_mode, vk::CullModeFlags::NONE);
+        assert_eq!(rasterization_state.front_face, vk::FrontFace::CLOCKWISE);
+    }
+
+    fn dynamic_states(shared_state: &SharedPipelineState) -> Vec<vk::DynamicState> {
+        let dynamic_state = shared_state.dynamic_state();
+
+        unsafe {
+            std::slice::from_raw_parts(
+                dynamic_state.p_dynamic_states,
+                dynamic_state.dynamic_state_count as usize,
+            )
+        }
+        .to_vec()
+    }
+
+    #[test]
+    fn line_width_is_dynamic_only_when_requested() {
+        let config = PipelineConfig {
+            dynamic_line_width: true,
+            ..Default::default()
+        };
+        let shared_state = SharedPipelineState::new(&config, vk::RenderPass::from_raw(1)).unwrap();
+
+        assert_eq!(
+            dynamic_states(&shared_state),
+            [
+                vk::DynamicState::VIEWPORT,
+                vk::DynamicState::SCISSOR,
+                vk::DynamicState::LINE_WIDTH
+            ]
+        );
+
+        let shared_state =
+            SharedPipelineState::new(&PipelineConfig::default(), vk::RenderPass::from_raw(1))
+                .unwrap();
+
+        assert!(!dynamic_states(&shared_state).contains(&vk::DynamicState::LINE_WIDTH));
+    }
+}
@@ -4,7 +4,7 @@ use std::path::Path;
 
 fn visit_dirs(
     dir: &Path,
-    cb: &dyn Fn(&std::path::PathBuf, shaderc::ShaderKind),
+    shaders: &mut Vec<(std::path::PathBuf, shaderc::ShaderKind)>,
 ) -> std::io::Result<()> {
     if dir.is_dir() {
         for entry in fs::read_dir(dir)? {
@@ -12,11 +12,11 @@ fn visit_dirs(
             let path = entry.path();
 
             if path.is_dir() {
-                visit_dirs(&path, cb)?;
+                visit_dirs(&path, shaders)?;
             } else {
                 let path_buf = entry.path();
                 if let Some(shader_kind) = get_shader_kind(&path_buf) {
-                    cb(&path_buf, shader_kind);
+                    shaders.push((path_buf, shader_kind));
                 }
             }
         }
@@ -36,35 +36,151 @@ fn get_shader_kind(path_buf: &std::path::PathBuf) -> Option<shaderc::ShaderKind>
         "frag" => Some(shaderc::ShaderKind::Fragment),
         "tese" => Some(shaderc::ShaderKind::TessEvaluation),
         "tesc" => Some(shaderc::ShaderKind::TessControl),
+        "comp" => Some(shaderc::ShaderKind::Compute),
         _ => None,
     }
 }
 
-fn compile_shader(path_buf: &std::path::PathBuf, shader_kind: shaderc::ShaderKind) {
-    let shader_str = fs::read_to_string(path_buf)
-        .expect(&format!("failed to read shader {:?} to string", path_buf));
+const FORCE_REBUILD_ENV_VAR: &str = "LYNX_FORCE_SHADER_REBUILD";
 
-    let compiler = shaderc::Compiler::new().expect("failed to create shader compilier");
+fn is_force_rebuild() -> bool {
+    std::env::var(FORCE_REBUILD_ENV_VAR).map_or(false, |value| value == "1")
+}
 
-    println!("compiling shader {:?}", path_buf);
+// the output is up to date when it is newer than every source it was built from
+fn is_up_to_date(sources: &[std::path::PathBuf], spv_path: &Path) -> bool {
+    if is_force_rebuild() {
+        return false;
+    }
 
-    let spv = compiler
-        .compile_into_spirv(
-            &shader_str,
-            shader_kind,
-            &path_buf.to_str().unwrap(),
-            "main",
-            None,
-        )
-        .expect(&format!("failed to compile shader {:?}", path_buf));
+    let spv_modified = match fs::metadata(spv_path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return false,
+    };
+
+    sources.iter().all(|source| {
+        match fs::metadata(source).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified <= spv_modified,
+            Err(_) => false,
+        }
+    })
+}
+
+const SHADER_DEBUG_ENV_VAR: &str = "LYNX_SHADER_DEBUG";
+
+// debug info for RenderDoc and friends, on for the debug profile unless overridden
+fn is_shader_debug() -> bool {
+    match std::env::var(SHADER_DEBUG_ENV_VAR).as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        Ok(value) => panic!(
+            "unknown {} value {:?}, expected 0 or 1",
+            SHADER_DEBUG_ENV_VAR, value
+        ),
+        Err(_) => std::env::var("PROFILE").map_or(false, |profile| profile == "debug"),
+    }
+}
+
+// the spv files don't record the settings they were built with, so they are written next to
+// them and e.g. switching between the debug and release profile rebuilds the shader
+fn is_settings_unchanged(settings_path: &Path, settings: &str) -> bool {
+    fs::read_to_string(settings_path).map_or(false, |previous| previous == settings)
+}
+
+const INCLUDE_DIR_ENV_VAR: &str = "LYNX_SHADER_INCLUDE_DIR";
+const DEFAULT_INCLUDE_DIR: &str = "shaders/include";
+
+fn get_include_dir() -> std::path::PathBuf {
+    std::env::var(INCLUDE_DIR_ENV_VAR).map_or(
+        std::path::PathBuf::from(DEFAULT_INCLUDE_DIR),
+        std::path::PathBuf::from,
+    )
+}
+
+// relative includes are looked up next to the including file first, then in the include dir
+fn resolve_include(
+    requested_source: &str,
+    include_type: shaderc::IncludeType,
+    requesting_source: &str,
+) -> Option<std::path::PathBuf> {
+    if matches!(include_type, shaderc::IncludeType::Relative) {
+        let include_path = Path::new(requesting_source)
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(requested_source);
+
+        if include_path.is_file() {
+            return Some(include_path);
+        }
+    }
+
+    let include_path = get_include_dir().join(requested_source);
+    if include_path.is_file() {
+        Some(include_path)
+    } else {
+        None
+    }
+}
+
+// all files reachable through #include, so edits to shared headers are noticed
+fn get_glsl_includes(path_buf: &std::path::PathBuf) -> Vec<std::path::PathBuf> {
+    let mut includes: Vec<std::path::PathBuf> = Vec::new();
+    let mut pending = vec![path_buf.clone()];
+
+    while let Some(source_path) = pending.pop() {
+        let source = match fs::read_to_string(&source_path) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+
+        for line in source.lines() {
+            let requested = match line.trim().strip_prefix("#include") {
+                Some(requested) => requested.trim(),
+                None => continue,
+            };
+
+            let (requested_source, include_type) =
+                if let Some(requested) = requested.strip_prefix('"') {
+                    (
+                        requested.trim_end_matches('"'),
+                        shaderc::IncludeType::Relative,
+                    )
+                } else if let Some(requested) = requested.strip_prefix('<') {
+                    (
+                        requested.trim_end_matches('>'),
+                        shaderc::IncludeType::Standard,
+                    )
+                } else {
+                    continue;
+                };
+
+            let requesting_source = source_path.to_str().unwrap();
+            if let Some(include_path) =
+                resolve_include(requested_source, include_type, requesting_source)
+            {
+                if !includes.contains(&include_path) {
+                    includes.push(include_path.clone());
+                    pending.push(include_path);
+                }
+            }
+        }
+    }
+
+    includes
+}
+
+fn compile_shader(
+    path_buf: &std::path::PathBuf,
+    shader_kind: shaderc::ShaderKind,
+    debug_info: bool,
+) {
+    println!("cargo:rerun-if-changed={}", path_buf.display());
 
     let mut file_name = path_buf
         .file_name()
         .expect("shader file should have a name")
         .to_os_string();
 
-    println!("cargo:rerun-if-changed={}", path_buf.display());
-
     file_name.push(".spv");
 
     let mut spv_path = path_buf
@@ -84,7 +200,64 @@ fn compile_shader(path_buf: &std::path::PathBuf, shader_kind: shaderc::ShaderKin
 
     spv_path.push(file_name);
 
+    let mut sources = vec![path_buf.clone()];
+    for include_path in get_glsl_includes(path_buf) {
+        println!("cargo:rerun-if-changed={}", include_path.display());
+        sources.push(include_path);
+    }
+
+    let settings_path = spv_path.with_extension("settings");
+    let settings = format!("debug_info: {}", debug_info);
+
+    if is_up_to_date(&sources, &spv_path) && is_settings_unchanged(&settings_path, &settings) {
+        println!("skipping up-to-date shader {:?}", path_buf);
+        return;
+    }
+
+    let shader_str = fs::read_to_string(path_buf)
+        .expect(&format!("failed to read shader {:?} to string", path_buf));
+
+    let compiler = shaderc::Compiler::new().expect("failed to create shader compilier");
+
+    let mut options =
+        shaderc::CompileOptions::new().expect("failed to create shader compile options");
+
+    if debug_info {
+        options.set_generate_debug_info();
+    }
+
+    options.set_include_callback(|requested_source, include_type, requesting_source, _| {
+        let include_path = resolve_include(requested_source, include_type, requesting_source)
+            .ok_or(format!(
+                "failed to resolve include {:?} from {:?}",
+                requested_source, requesting_source
+            ))?;
+
+        let content = fs::read_to_string(&include_path)
+            .map_err(|_| format!("failed to read include {:?}", include_path))?;
+
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: include_path.to_str().unwrap().to_string(),
+            content,
+        })
+    });
+
+    println!("compiling shader {:?}", path_buf);
+
+    // shaderc errors already carry file:line: message for every diagnostic
+    let spv = match compiler.compile_into_spirv(
+        &shader_str,
+        shader_kind,
+        &path_buf.to_str().unwrap(),
+        "main",
+        Some(&options),
+    ) {
+        Ok(spv) => spv,
+        Err(err) => panic!("failed to compile shader {:?}:\n{}", path_buf, err),
+    };
+
     fs::write(spv_path, spv.as_binary_u8()).expect("failed to write shader binary");
+    fs::write(settings_path, settings).expect("failed to write shader settings");
 }
 
 fn visit_dirs_slang(dir: &Path, cb: &dyn Fn(&str, &slang::GlobalSession)) -> std::io::Result<()> {
@@ -127,56 +300,106 @@ fn get_shader_kind_is_slang(path_buf: &std::path::PathBuf) -> bool {
     is_slang
 }
 
-fn compile_slang(dir: &str, global_session: &slang::GlobalSession) {
-    let search_path = std::ffi::CString::new(dir).unwrap();
-
-    let session_options = slang::CompilerOptions::default()
-        .optimization(slang::OptimizationLevel::High)
-        // .optimization(slang::OptimizationLevel::None)
-        .matrix_layout_row(true);
-
-    let target_desc = slang::TargetDesc::default()
-        .format(slang::CompileTarget::Spirv)
-        .profile(global_session.find_profile("glsl_450"));
+// direct `import name;` dependencies that live next to the shader
+fn get_slang_imports(path_buf: &Path) -> Vec<std::path::PathBuf> {
+    let source = match fs::read_to_string(path_buf) {
+        Ok(source) => source,
+        Err(_) => return Vec::new(),
+    };
 
-    let targets = [target_desc];
-    let search_paths = [search_path.as_ptr()];
+    let parent = path_buf.parent().unwrap_or(Path::new("."));
 
-    let session_desc = slang::SessionDesc::default()
-        .targets(&targets)
-        .search_paths(&search_paths)
-        .options(&session_options);
+    source
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("import "))
+        .map(|module| module.trim_end_matches(';').trim().replace('.', "/"))
+        .map(|module| parent.join(module).with_extension("slang"))
+        .filter(|import_path| import_path.is_file())
+        .collect()
+}
 
-    let session = global_session.create_session(&session_desc).unwrap();
-    let module = session.load_module(dir).unwrap();
-    let entry_point = module.find_entry_point_by_name("main").unwrap();
+// outputs are `<stem>.spv` for single entry point modules and `<stem>.<entry>.spv` otherwise
+fn get_slang_outputs(spv_dir: &Path, stem: &str) -> Vec<std::path::PathBuf> {
+    let entries = match fs::read_dir(spv_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
 
-    println!("compiling shader {:?}", dir);
-    let program = session
-        .create_composite_component_type(&[
-            module.downcast().clone(),
-            entry_point.downcast().clone(),
-        ])
-        .unwrap();
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            file_name == format!("{}.spv", stem)
+                || (file_name.starts_with(&format!("{}.", stem)) && file_name.ends_with(".spv"))
+        })
+        .collect()
+}
 
-    let linked_program = program.link().unwrap();
+const SLANG_OPT_ENV_VAR: &str = "LYNX_SLANG_OPT";
+const SLANG_MATRIX_ENV_VAR: &str = "LYNX_SLANG_MATRIX";
+
+#[derive(Clone, Copy)]
+struct SlangSettings {
+    debug_info: bool,
+    // High mangles the source mapping, off by default with debug info
+    optimize: bool,
+    // row matches the row by row transforms in TeapotData, column is for uploading matrices
+    // from a column-major math library as is
+    matrix_layout_row: bool,
+}
 
-    let reflection = linked_program.layout(0).unwrap();
+impl SlangSettings {
+    fn from_env(debug_info: bool) -> Self {
+        let optimize = match std::env::var(SLANG_OPT_ENV_VAR).as_deref() {
+            Err(_) => !debug_info,
+            Ok("high") => true,
+            Ok("none") => false,
+            Ok(value) => panic!(
+                "unknown {} value {:?}, expected none or high",
+                SLANG_OPT_ENV_VAR, value
+            ),
+        };
+
+        let matrix_layout_row = match std::env::var(SLANG_MATRIX_ENV_VAR).as_deref() {
+            Err(_) | Ok("row") => true,
+            Ok("column") => false,
+            Ok(value) => panic!(
+                "unknown {} value {:?}, expected row or column",
+                SLANG_MATRIX_ENV_VAR, value
+            ),
+        };
+
+        SlangSettings {
+            debug_info,
+            optimize,
+            matrix_layout_row,
+        }
+    }
 
-    let shader_bytecode = linked_program.entry_point_code(0, 0).unwrap();
+    fn describe(&self) -> String {
+        format!(
+            "debug_info: {}, optimize: {}, matrix_layout_row: {}",
+            self.debug_info, self.optimize, self.matrix_layout_row
+        )
+    }
+}
 
+fn compile_slang(dir: &str, global_session: &slang::GlobalSession, settings: &SlangSettings) {
     let path_buf = Path::new(dir);
 
-    let mut file_name = path_buf
-        .file_stem()
-        .expect("shader file should have a name")
-        .to_os_string();
-
     println!("cargo:rerun-if-changed={}", path_buf.display());
 
-    file_name.push(".spv");
+    let stem = path_buf
+        .file_stem()
+        .expect("shader file should have a name")
+        .to_str()
+        .expect("shader file name cannot be converted to &str");
 
-    let mut spv_path = path_buf
+    let spv_dir = path_buf
         .parent()
         .expect("failed to get shader file parent folder")
         .join("..")
@@ -186,27 +409,148 @@ fn compile_slang(dir: &str, global_session: &slang::GlobalSession) {
         .join("shaders")
         .join("slang");
 
-    std::fs::create_dir_all(spv_path.clone()).expect(&format!(
+    std::fs::create_dir_all(spv_dir.clone()).expect(&format!(
         "failed to create directory for shader {:?}",
         path_buf
     ));
 
-    spv_path.push(file_name);
+    let mut sources = vec![path_buf.to_path_buf()];
+    for import_path in get_slang_imports(path_buf) {
+        println!("cargo:rerun-if-changed={}", import_path.display());
+        sources.push(import_path);
+    }
+
+    let settings_path = spv_dir.join(format!("{}.settings", stem));
+
+    let outputs = get_slang_outputs(&spv_dir, stem);
+    if is_settings_unchanged(&settings_path, &settings.describe())
+        && !outputs.is_empty()
+        && outputs
+            .iter()
+            .all(|spv_path| is_up_to_date(&sources, spv_path))
+    {
+        println!("skipping up-to-date shader {:?}", dir);
+        return;
+    }
+
+    let search_path = std::ffi::CString::new(dir).unwrap();
+
+    let optimization = if settings.optimize {
+        slang::OptimizationLevel::High
+    } else {
+        slang::OptimizationLevel::None
+    };
 
-    // println!("{}", spv_path.display());
-    fs::write(spv_path, shader_bytecode.as_slice().to_vec())
-        .expect("failed to write shader binary");
+    let debug_information = if settings.debug_info {
+        slang::DebugInfoLevel::Standard
+    } else {
+        slang::DebugInfoLevel::None
+    };
+
+    let session_options = slang::CompilerOptions::default()
+        .optimization(optimization)
+        .debug_information(debug_information)
+        .matrix_layout_row(settings.matrix_layout_row)
+        .matrix_layout_column(!settings.matrix_layout_row);
+
+    let target_desc = slang::TargetDesc::default()
+        .format(slang::CompileTarget::Spirv)
+        .profile(global_session.find_profile("glsl_450"));
+
+    let targets = [target_desc];
+    let search_paths = [search_path.as_ptr()];
+
+    let session_desc = slang::SessionDesc::default()
+        .targets(&targets)
+        .search_paths(&search_paths)
+        .options(&session_options);
+
+    let session = global_session.create_session(&session_desc).unwrap();
+    // the slang error carries the diagnostics blob
+    let module = match session.load_module(dir) {
+        Ok(module) => module,
+        Err(err) => panic!("failed to load slang module {:?}:\n{:?}", dir, err),
+    };
+
+    // every [shader(...)] function of the module is an entry point
+    let entry_point_count = module.entry_point_count();
+    if entry_point_count == 0 {
+        panic!("slang module {:?} has no entry points", dir);
+    }
+
+    for i in 0..entry_point_count {
+        let entry_point = module
+            .entry_point_by_index(i)
+            .expect(&format!("failed to get entry point {} of {:?}", i, dir));
+
+        let entry_point_name = entry_point.function_reflection().name().to_string();
+
+        println!(
+            "compiling shader {:?} entry point {:?}",
+            dir, entry_point_name
+        );
+        let program = match session.create_composite_component_type(&[
+            module.downcast().clone(),
+            entry_point.downcast().clone(),
+        ]) {
+            Ok(program) => program,
+            Err(err) => panic!("failed to compose slang program {:?}:\n{:?}", dir, err),
+        };
+
+        let linked_program = match program.link() {
+            Ok(linked_program) => linked_program,
+            Err(err) => panic!("failed to link slang program {:?}:\n{:?}", dir, err),
+        };
+
+        let shader_bytecode = match linked_program.entry_point_code(0, 0) {
+            Ok(shader_bytecode) => shader_bytecode,
+            Err(err) => panic!("failed to generate spirv for {:?}:\n{:?}", dir, err),
+        };
+
+        let spv_path = if entry_point_count == 1 {
+            spv_dir.join(format!("{}.spv", stem))
+        } else {
+            spv_dir.join(format!("{}.{}.spv", stem, entry_point_name))
+        };
+
+        fs::write(spv_path, shader_bytecode.as_slice().to_vec())
+            .expect("failed to write shader binary");
+    }
+
+    fs::write(settings_path, settings.describe()).expect("failed to write shader settings");
 }
 
 fn main() -> Result<(), i32> {
+    println!("cargo:rerun-if-env-changed={}", FORCE_REBUILD_ENV_VAR);
+    println!("cargo:rerun-if-env-changed={}", INCLUDE_DIR_ENV_VAR);
+    println!("cargo:rerun-if-env-changed={}", SHADER_DEBUG_ENV_VAR);
+
+    let debug_info = is_shader_debug();
+    println!("cargo:rerun-if-env-changed={}", SLANG_OPT_ENV_VAR);
+    println!("cargo:rerun-if-env-changed={}", SLANG_MATRIX_ENV_VAR);
+
+    let slang_settings = SlangSettings::from_env(debug_info);
+
     let shaders_dir = Path::new("shaders/glsl");
 
-    if let Err(_) = visit_dirs(shaders_dir, &compile_shader) {
+    let mut shaders = Vec::new();
+    if let Err(_) = visit_dirs(shaders_dir, &mut shaders) {
         return Err(1);
     }
 
+    // every thread creates its own compiler, a failing shader panics with its path
+    // and the scope propagates the panic once all threads are done
+    std::thread::scope(|scope| {
+        for (path_buf, shader_kind) in &shaders {
+            scope.spawn(move || compile_shader(path_buf, *shader_kind, debug_info));
+        }
+    });
+
     let slang_dir = Path::new("shaders/slang");
-    if let Err(_) = visit_dirs_slang(slang_dir, &compile_slang) {
+    let compile = |dir: &str, global_session: &slang::GlobalSession| {
+        compile_slang(dir, global_session, &slang_settings)
+    };
+    if let Err(_) = visit_dirs_slang(slang_dir, &compile) {
         return Err(1);
     }
 
@@ -1,12 +1,13 @@
 use crate::draw_fns;
 use crate::vulkan_base::VulkanBase;
 use crate::VulkanData;
-use ash::vk;
+use ::vulkan_base::WindowBackend;
 use cgmath::{num_traits::ToPrimitive, perspective, Deg, Matrix4, Point3, Vector3};
 
-pub fn draw(
+pub fn draw<W: WindowBackend>(
     vulkan_data: &mut VulkanData,
-    vulkan_base: &VulkanBase,
+    vulkan_base: &mut VulkanBase,
+    window: &W,
     time_since_beginning_sec: f32,
 ) -> Result<(), String> {
     let get_image_index_result = draw_fns::get_image_index(vulkan_data, vulkan_base)?;
@@ -14,9 +15,8 @@ pub fn draw(
     let image_index = match get_image_index_result {
         draw_fns::GetImageIndexResult::Index(index) => index,
         draw_fns::GetImageIndexResult::ShouldRebuildSwapchain => {
-            println!("swapchain is suboptimal or out of date");
-            vulkan_data.should_resize = true;
-            return Ok(());
+            log::info!("swapchain is out of date");
+            return draw_fns::recreate_swapchain(vulkan_data, vulkan_base, window);
         }
     };
 
@@ -25,15 +25,6 @@ pub fn draw(
     let command_buffer = draw_fns::get_command_buffer(vulkan_data, vulkan_base)?;
     draw_fns::begin_command_buffer(vulkan_base, command_buffer)?;
 
-    draw_fns::begin_render_pass(
-        vulkan_data,
-        vulkan_base,
-        image_index as usize,
-        command_buffer,
-    );
-
-    draw_fns::set_viewport(vulkan_base, command_buffer);
-    draw_fns::set_scissor(vulkan_base, command_buffer);
     draw_fns::reset_descriptor_pool(vulkan_data, vulkan_base)?;
     let descriptor_set = draw_fns::allocate_descriptor_set(vulkan_data, vulkan_base)?;
     draw_fns::update_descriptor_set(vulkan_data, vulkan_base, descriptor_set);
@@ -75,55 +66,15 @@ pub fn draw(
     curr_uniform_buffer.allocation.mapped_slice_mut().unwrap()[..16 * 4]
         .copy_from_slice(mvp_data_bytes);
 
-    unsafe {
-        vulkan_base.device.cmd_push_constants(
-            command_buffer,
-            vulkan_data.pipeline_layout,
-            vk::ShaderStageFlags::TESSELLATION_CONTROL,
-            0,
-            bytemuck::cast_slice(&[vulkan_data.tesselation_level]),
-        );
-
-        vulkan_base.device.cmd_bind_descriptor_sets(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            vulkan_data.pipeline_layout,
-            0,
-            &[descriptor_set],
-            &[],
-        );
-
-        let curr_pipeline = match vulkan_data.is_wireframe_mode {
-            true => vulkan_data.wireframe_pipeline,
-            false => vulkan_data.solid_pipeline,
-        };
-
-        vulkan_base.device.cmd_bind_pipeline(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            curr_pipeline,
-        );
-
-        vulkan_base.device.cmd_bind_index_buffer(
-            command_buffer,
-            vulkan_data.patches_mem_buffer.buffer,
-            0,
-            vk::IndexType::UINT16,
-        );
-
-        vulkan_base.device.cmd_draw_indexed(
-            command_buffer,
-            vulkan_data.patch_point_count,
-            1,
-            0,
-            0,
-            0,
-        );
-    }
+    draw_fns::record_frame(
+        vulkan_data,
+        vulkan_base,
+        command_buffer,
+        image_index as usize,
+        descriptor_set,
+    );
 
     unsafe {
-        vulkan_base.device.cmd_end_render_pass(command_buffer);
-
         vulkan_base
             .device
             .end_command_buffer(command_buffer)
@@ -133,9 +84,8 @@ pub fn draw(
     draw_fns::submit(vulkan_data, vulkan_base, command_buffer)?;
 
     if !draw_fns::present(vulkan_data, vulkan_base, image_index)? {
-        println!("swapchain is suboptimal or out of date");
-        vulkan_data.should_resize = true;
-        return Ok(());
+        log::info!("swapchain is suboptimal or out of date");
+        return draw_fns::recreate_swapchain(vulkan_data, vulkan_base, window);
     }
 
     Ok(())
@@ -1,7 +1,7 @@
 use crate::vulkan_base::VulkanBase;
 use crate::vulkan_data::VulkanData;
-use crate::vulkan_utils;
 
+use ::vulkan_base::WindowBackend;
 use ash::vk;
 
 pub enum GetImageIndexResult {
@@ -13,7 +13,8 @@ pub fn get_image_index(
     vulkan_data: &VulkanData,
     vulkan_base: &VulkanBase,
 ) -> Result<GetImageIndexResult, String> {
-    let (index, is_suboptimal) = match unsafe {
+    // a suboptimal image is still rendered, present reports it once the semaphore is consumed
+    let index = match unsafe {
         vulkan_base.swapchain_loader.acquire_next_image(
             vulkan_base.swapchain,
             u64::MAX,
@@ -21,18 +22,35 @@ pub fn get_image_index(
             vk::Fence::null(),
         )
     } {
-        Ok((index, is_suboptimal)) => (index, is_suboptimal),
+        Ok((index, _)) => index,
         Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
             return Ok(GetImageIndexResult::ShouldRebuildSwapchain)
         }
         Err(_) => return Err(String::from("failed to acquire next image")),
     };
 
-    if is_suboptimal {
-        return Ok(GetImageIndexResult::ShouldRebuildSwapchain);
+    Ok(GetImageIndexResult::Index(index))
+}
+
+pub fn recreate_swapchain<W: WindowBackend>(
+    vulkan_data: &mut VulkanData,
+    vulkan_base: &mut VulkanBase,
+    window: &W,
+) -> Result<(), String> {
+    let (width, height) = window.inner_size();
+    if width == 0 || height == 0 {
+        // keep the request pending until the window is restored
+        vulkan_data.should_resize = true;
+        return Ok(());
     }
 
-    Ok(GetImageIndexResult::Index(index))
+    log::info!("recreating swapchain");
+
+    vulkan_data.should_resize = false;
+    vulkan_base.resize(window)?;
+    vulkan_data.resize(vulkan_base)?;
+
+    Ok(())
 }
 
 pub fn wait_resource_available(
@@ -193,12 +211,16 @@ pub fn begin_render_pass(
     }
 }
 
-pub fn set_viewport(vulkan_base: &VulkanBase, command_buffer: vk::CommandBuffer) {
+pub fn set_viewport(vulkan_base: &VulkanBase, command_buffer: vk::CommandBuffer, flip_y: bool) {
+    let width = vulkan_base.surface_extent.width as f32;
+    let height = vulkan_base.surface_extent.height as f32;
+
+    // a negative height flips the viewport, allowed since Vulkan 1.1
     let viewport = vk::Viewport {
         x: 0.0,
-        y: 0.0,
-        width: vulkan_base.surface_extent.width as f32,
-        height: vulkan_base.surface_extent.height as f32,
+        y: if flip_y { height } else { 0.0 },
+        width,
+        height: if flip_y { -height } else { height },
         min_depth: 0.0f32,
         max_depth: 1.0f32,
     };
@@ -268,8 +290,8 @@ pub fn allocate_descriptor_set(
 
     let set = descriptor_sets[0];
 
-    vulkan_utils::set_debug_utils_object_name2(
-        &vulkan_base.debug_utils_loader,
+    vulkan_utils::set_debug_utils_object_name(
+        Some(&vulkan_base.debug_utils_loader),
         vulkan_base.device.handle(),
         set,
         "descriptor set",
@@ -337,6 +359,69 @@ pub fn update_descriptor_set(
     }
 }
 
+/// Records the teapot draw into `command_buffer`, from beginning the render pass on
+/// `image_index`'s framebuffer to ending it. The dynamic viewport and scissor are set here.
+pub fn record_frame(
+    vulkan_data: &VulkanData,
+    vulkan_base: &VulkanBase,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    descriptor_set: vk::DescriptorSet,
+) {
+    begin_render_pass(vulkan_data, vulkan_base, image_index, command_buffer);
+
+    set_viewport(vulkan_base, command_buffer, vulkan_data.flip_viewport_y);
+    set_scissor(vulkan_base, command_buffer);
+
+    unsafe {
+        vulkan_base.device.cmd_push_constants(
+            command_buffer,
+            vulkan_data.pipeline_layout,
+            vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            0,
+            bytemuck::cast_slice(&[vulkan_data.tesselation_level]),
+        );
+
+        vulkan_base.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            vulkan_data.pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+
+        let curr_pipeline = match vulkan_data.is_wireframe_mode {
+            true => vulkan_data.wireframe_pipeline,
+            false => vulkan_data.solid_pipeline,
+        };
+
+        vulkan_base.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            curr_pipeline,
+        );
+
+        vulkan_base.device.cmd_bind_index_buffer(
+            command_buffer,
+            vulkan_data.patches_mem_buffer.buffer,
+            0,
+            vk::IndexType::UINT16,
+        );
+
+        vulkan_base.device.cmd_draw_indexed(
+            command_buffer,
+            vulkan_data.patch_point_count,
+            1,
+            0,
+            0,
+            0,
+        );
+
+        vulkan_base.device.cmd_end_render_pass(command_buffer);
+    }
+}
+
 pub fn submit(
     vulkan_data: &VulkanData,
     vulkan_base: &VulkanBase,
@@ -379,18 +464,17 @@ pub fn present(
         .image_indices(&indices)
         .build();
 
-    unsafe {
-        if let Err(err) = vulkan_base
+    let result = unsafe {
+        vulkan_base
             .swapchain_loader
             .queue_present(vulkan_base.queue, &present_info)
-        {
-            if err == vk::Result::SUBOPTIMAL_KHR || err == vk::Result::ERROR_OUT_OF_DATE_KHR {
-                return Ok(false);
-            } else {
-                return Err(String::from("failed to present"));
-            }
+    };
+
+    match result {
+        Ok(false) => Ok(true),
+        Ok(true) | Err(vk::Result::SUBOPTIMAL_KHR) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+            Ok(false)
         }
+        Err(_) => Err(String::from("failed to present")),
     }
-
-    Ok(true)
 }
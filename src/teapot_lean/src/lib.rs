@@ -5,14 +5,25 @@ mod teapot_lean_data;
 mod vulkan_base;
 mod vulkan_data;
 mod vulkan_data_fns;
-mod vulkan_utils;
 
-use vulkan_base::VulkanBase;
+use crate::vulkan_base::VulkanBase;
 use vulkan_data::VulkanData;
 
+// frames in flight, see VulkanData::new
 const CONCURRENT_RESOURCE_COUNT: u32 = 2;
+// procedural teapot copies to stress-test the instances buffer, rows x cols on a grid or a
+// ring of the given count, see TeapotData::grid and TeapotData::ring. 0 keeps one teapot.
+const GRID_INSTANCES: (u32, u32) = (0, 0);
+const RING_INSTANCE_COUNT: u32 = 0;
+// resize events closer together than this, e.g. while dragging the window border, are
+// coalesced into one swapchain recreation
+#[cfg(feature = "winit")]
+const RESIZE_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(100);
 
-pub fn main() {
+/// Returns an error when the window or Vulkan can't be initialized, e.g. without a suitable
+/// GPU. Once the event loop runs it never returns.
+#[cfg(feature = "winit")]
+pub fn main() -> Result<(), String> {
     // Window
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new()
@@ -20,34 +31,17 @@ pub fn main() {
         .with_inner_size(winit::dpi::LogicalSize::new(800.0, 600.0))
         .with_min_inner_size(winit::dpi::PhysicalSize::new(100.0, 100.0))
         .build(&event_loop)
-        .unwrap();
+        .map_err(|err| format!("failed to create window: {}", err))?;
 
-    // vulkan base
-    let device_extensions = vec![ash::extensions::khr::Swapchain::name()];
-    let instance_extensions = vulkan_data_fns::get_required_instance_extensions(&window).unwrap();
-
-    let mut vk_base = match VulkanBase::new(&window, &instance_extensions, &device_extensions) {
-        Ok(vk_base) => Some(vk_base),
-        Err(msg) => {
-            log::error!("{}", msg);
-            panic!("{}", msg);
-        }
-    };
-
-    // vulkan data
-    let mut vk_data = match VulkanData::new(vk_base.as_mut().unwrap()) {
-        Ok(vk_data) => Some(vk_data),
-        Err(msg) => {
-            log::error!("{}", msg);
-            let vk_base = vk_base.unwrap();
-            vk_base.clean();
-            panic!("{}", msg);
-        }
-    };
+    let (vk_base, vk_data) = init_vulkan(&window)?;
+    let mut vk_base = Some(vk_base);
+    let mut vk_data = Some(vk_data);
 
     // loop
     let mut app_exit = false;
     let start_time = std::time::Instant::now();
+    // latest requested size and when it was requested
+    let mut pending_resize: Option<(winit::dpi::PhysicalSize<u32>, std::time::Instant)> = None;
 
     event_loop.run(move |event, _, control_flow| {
         use winit::event::ElementState;
@@ -86,42 +80,21 @@ pub fn main() {
                 let vk_base_ref = vk_base.as_mut().unwrap();
                 let vk_data_ref = vk_data.as_mut().unwrap();
 
-                if vk_data_ref.should_resize {
-                    vk_data_ref.should_resize = false;
-
-                    log::info!("handling resize");
-
-                    if let Err(msg) = vk_base_ref.resize(&window) {
-                        log::error!("{}", msg);
-                        vulkan_data_fns::vulkan_clean(&mut vk_base, &mut vk_data);
-                        app_exit = true;
-                        *control_flow = ControlFlow::Exit;
-                        return;
-                    }
-
-                    if let Err(msg) = vk_data_ref.resize(&vk_base_ref) {
-                        log::error!("{}", msg);
-                        vulkan_data_fns::vulkan_clean(&mut vk_base, &mut vk_data);
-                        app_exit = true;
-                        *control_flow = ControlFlow::Exit;
-                        return;
+                // an out of date swapchain can't wait for the debounce
+                if let Some((size, requested_at)) = pending_resize {
+                    if vk_data_ref.should_resize || requested_at.elapsed() >= RESIZE_DEBOUNCE {
+                        log::info!("resizing to {:?}", size);
+                        pending_resize = None;
+                        vk_data_ref.should_resize = true;
                     }
                 }
 
-                if let Err(msg) = draw::draw(
-                    vk_data_ref,
-                    vk_base_ref,
-                    (std::time::Instant::now() - start_time).as_secs_f32(),
-                ) {
+                if let Err(msg) = draw_frame(vk_data_ref, vk_base_ref, &window, start_time) {
                     log::error!("{}", msg);
                     vulkan_data_fns::vulkan_clean(&mut vk_base, &mut vk_data);
                     app_exit = true;
                     *control_flow = ControlFlow::Exit;
-                    return;
                 }
-
-                vk_data_ref.curr_resource_index =
-                    (vk_data_ref.curr_resource_index + 1) % CONCURRENT_RESOURCE_COUNT;
             }
 
             Event::WindowEvent {
@@ -130,8 +103,7 @@ pub fn main() {
             } => {
                 log::info!("resize requested {:?}", physical_size);
 
-                let vk_data = vk_data.as_mut().unwrap();
-                vk_data.should_resize = true;
+                pending_resize = Some((physical_size, std::time::Instant::now()));
             }
 
             Event::WindowEvent {
@@ -151,6 +123,10 @@ pub fn main() {
                     let vk_data = vk_data.as_mut().unwrap();
                     vk_data.is_wireframe_mode = !vk_data.is_wireframe_mode;
                 }
+                VirtualKeyCode::Y => {
+                    let vk_data = vk_data.as_mut().unwrap();
+                    vk_data.flip_viewport_y = !vk_data.flip_viewport_y;
+                }
                 VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => {
                     let vk_data = vk_data.as_mut().unwrap();
                     vk_data.tesselation_level += 0.1f32;
@@ -168,3 +144,144 @@ pub fn main() {
         }
     });
 }
+
+/// Same as the winit `main` on an sdl2 window, used when only the `sdl2` feature is enabled.
+/// Unlike there resizes aren't debounced, every size change recreates the swapchain.
+#[cfg(all(feature = "sdl2", not(feature = "winit")))]
+pub fn main() -> Result<(), String> {
+    use sdl2::event::{Event, WindowEvent};
+    use sdl2::keyboard::Keycode;
+
+    // Window
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let mut window = video_subsystem
+        .window("Teapot", 800, 600)
+        .position_centered()
+        .resizable()
+        .vulkan()
+        .build()
+        .map_err(|err| format!("failed to create window: {}", err))?;
+    window
+        .set_minimum_size(100, 100)
+        .map_err(|err| format!("failed to set minimum window size: {}", err))?;
+    let mut event_pump = sdl_context.event_pump()?;
+
+    let (vk_base, vk_data) = init_vulkan(&window)?;
+    let mut vk_base = Some(vk_base);
+    let mut vk_data = Some(vk_data);
+
+    // loop
+    let start_time = std::time::Instant::now();
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => {
+                    log::info!("exit requested");
+                    vulkan_data_fns::vulkan_clean(&mut vk_base, &mut vk_data);
+                    return Ok(());
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(width, height),
+                    ..
+                } => {
+                    log::info!("resize requested {}x{}", width, height);
+                    vk_data.as_mut().unwrap().should_resize = true;
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    let vk_data = vk_data.as_mut().unwrap();
+                    match keycode {
+                        Keycode::Space => vk_data.is_wireframe_mode = !vk_data.is_wireframe_mode,
+                        Keycode::Y => vk_data.flip_viewport_y = !vk_data.flip_viewport_y,
+                        Keycode::Plus | Keycode::KpPlus => {
+                            vk_data.tesselation_level += 0.1f32;
+                            vk_data.tesselation_level = vk_data.tesselation_level.min(64.0);
+                        }
+                        Keycode::Minus | Keycode::KpMinus => {
+                            vk_data.tesselation_level -= 0.1f32;
+                            vk_data.tesselation_level = vk_data.tesselation_level.max(1.0);
+                        }
+                        _ => (),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // do nothing if window is minimized
+        let (width, height) = window.size();
+        if width == 0 && height == 0 {
+            continue;
+        }
+
+        if let Err(msg) = draw_frame(
+            vk_data.as_mut().unwrap(),
+            vk_base.as_mut().unwrap(),
+            &window,
+            start_time,
+        ) {
+            log::error!("{}", msg);
+            vulkan_data_fns::vulkan_clean(&mut vk_base, &mut vk_data);
+            return Err(msg);
+        }
+    }
+}
+
+/// Creates VulkanBase and VulkanData for the window, shared by the winit and sdl2 event loops.
+fn init_vulkan<W: ::vulkan_base::WindowBackend>(
+    window: &W,
+) -> Result<(VulkanBase, VulkanData), String> {
+    // vulkan base
+    let device_extensions = vec![ash::extensions::khr::Swapchain::name()];
+    let mut instance_extensions =
+        ::vulkan_base::get_required_instance_extensions(window).map_err(|err| err.to_string())?;
+    instance_extensions.push(ash::extensions::ext::DebugUtils::name());
+
+    let mut vk_base = match VulkanBase::new(window, &instance_extensions, &device_extensions) {
+        Ok(vk_base) => vk_base,
+        Err(msg) => {
+            log::error!("{}", msg);
+            return Err(msg);
+        }
+    };
+
+    // vulkan data
+    let vk_data = match VulkanData::new(&mut vk_base, CONCURRENT_RESOURCE_COUNT) {
+        Ok(vk_data) => vk_data,
+        Err(msg) => {
+            log::error!("{}", msg);
+            vk_base.clean();
+            return Err(msg);
+        }
+    };
+
+    Ok((vk_base, vk_data))
+}
+
+/// Recreates the swapchain when a resize is pending, then draws and advances to the next
+/// frame in flight.
+fn draw_frame<W: ::vulkan_base::WindowBackend>(
+    vk_data: &mut VulkanData,
+    vk_base: &mut VulkanBase,
+    window: &W,
+    start_time: std::time::Instant,
+) -> Result<(), String> {
+    if vk_data.should_resize {
+        log::info!("handling resize");
+        draw_fns::recreate_swapchain(vk_data, vk_base, window)?;
+    }
+
+    draw::draw(
+        vk_data,
+        vk_base,
+        window,
+        (std::time::Instant::now() - start_time).as_secs_f32(),
+    )?;
+
+    vk_data.curr_resource_index = (vk_data.curr_resource_index + 1) % vk_data.frames_in_flight;
+
+    Ok(())
+}
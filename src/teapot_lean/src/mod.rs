@@ -1,7 +1,5 @@
 // mod compatibility_check;
 // mod vulkan_base;
 // mod vulkan_data;
-// mod vulkan_utils;
 
 // pub use vulkan_base::*;
-// pub use vulkan_utils::*;
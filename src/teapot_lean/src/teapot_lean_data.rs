@@ -1,6 +1,9 @@
 pub struct TeapotData {
     control_points: Vec<f32>,
     patches: Vec<u16>,
+    /// One `PatchData` per patch, see tese.slang. The transforms are stored row by row, the
+    /// shaders are built with `matrix_layout_row(true)` and transform row vectors,
+    /// `mul(position, transform)`, so the translation is in the last row.
     instances: Vec<f32>,
 }
 
@@ -188,6 +191,64 @@ impl TeapotData {
         }
     }
     
+    /// `rows * cols` teapots on a grid in the xy plane, `spacing` apart and centered on the
+    /// origin. `rows` and `cols` are clamped to at least 1.
+    pub fn grid(rows: u32, cols: u32, spacing: f32) -> Self {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+        let center_x = (cols - 1) as f32 * 0.5;
+        let center_y = (rows - 1) as f32 * 0.5;
+
+        let mut transforms = Vec::with_capacity((rows * cols) as usize);
+        for row in 0..rows {
+            for col in 0..cols {
+                let x = (col as f32 - center_x) * spacing;
+                let y = (row as f32 - center_y) * spacing;
+                transforms.push(get_transform_z(0.0, x, y));
+            }
+        }
+
+        Self::new().repeat(&transforms)
+    }
+    
+    /// `count` teapots evenly spaced on a circle of `radius` around the z axis, each with the
+    /// spout pointing outward. `count` is clamped to at least 1.
+    pub fn ring(count: u32, radius: f32) -> Self {
+        let count = count.max(1);
+
+        let transforms = (0..count)
+            .map(|i| {
+                let ang_rad = i as f32 * std::f32::consts::TAU / count as f32;
+                get_transform_z(ang_rad, radius * ang_rad.cos(), radius * ang_rad.sin())
+            })
+            .collect::<Vec<_>>();
+
+        Self::new().repeat(&transforms)
+    }
+    
+    // draws the teapot once per transform. The draw stays a single indexed draw, the patches
+    // are repeated so SV_PrimitiveID indexes the patch data of the matching copy.
+    fn repeat(self, transforms: &[[f32; 16]]) -> Self {
+        let mut patches = Vec::with_capacity(self.patches.len() * transforms.len());
+        let mut instances = Vec::with_capacity(self.instances.len() * transforms.len());
+
+        for transform in transforms {
+            patches.extend_from_slice(&self.patches);
+
+            for patch_data in self.instances.chunks(PATCH_DATA_FLOAT_COUNT) {
+                let (patch_transform, color) = patch_data.split_at(16);
+                instances.extend_from_slice(&mul_transforms(patch_transform, transform));
+                instances.extend_from_slice(color);
+            }
+        }
+
+        Self {
+            control_points: self.control_points,
+            patches,
+            instances,
+        }
+    }
+    
     pub fn get_control_points_slice(&self) -> &[u8] {
         bytemuck::cast_slice(&self.control_points)
     }
@@ -205,6 +266,34 @@ impl TeapotData {
     }
 }
 
+// mat4 transform and vec4 color, see PatchData in tese.slang
+const PATCH_DATA_FLOAT_COUNT: usize = 20;
+
+// rotation around z by ang_rad followed by a translation, row by row
+fn get_transform_z(ang_rad: f32, x: f32, y: f32) -> [f32; 16] {
+    let (sin, cos) = ang_rad.sin_cos();
+
+    [
+        cos, sin, 0.0, 0.0, //
+        -sin, cos, 0.0, 0.0, //
+        0.0, 0.0, 1.0, 0.0, //
+        x, y, 0.0, 1.0, //
+    ]
+}
+
+// row vectors are transformed by a and then by b
+fn mul_transforms(a: &[f32], b: &[f32; 16]) -> [f32; 16] {
+    let mut result = [0.0f32; 16];
+
+    for row in 0..4 {
+        for col in 0..4 {
+            result[row * 4 + col] = (0..4).map(|k| a[row * 4 + k] * b[k * 4 + col]).sum();
+        }
+    }
+
+    result
+}
+
 fn push_rotation_z(v: &mut Vec<f32>, ang_rad: f32) {
     v.push(ang_rad.cos());
     v.push(-ang_rad.sin());
@@ -1,11 +1,11 @@
+use ::vulkan_base::WindowBackend;
 use ash::extensions::ext;
 use ash::extensions::khr;
 use ash::vk;
 use gpu_allocator::vulkan;
-use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use scopeguard::{guard, ScopeGuard};
 
-use crate::vulkan_utils::MemImage;
+use vulkan_utils::MemImage;
 
 pub fn compatibility_check<'a>(
     entry: &ash::Entry,
@@ -85,12 +85,17 @@ pub struct VulkanBase {
 }
 
 impl VulkanBase {
-    pub fn new<'a, 'b>(
-        window: &winit::window::Window,
+    pub fn new<'a, 'b, W: WindowBackend>(
+        window: &W,
         required_instance_extensions: &Vec<&'a std::ffi::CStr>,
         required_device_extensions: &Vec<&'b std::ffi::CStr>,
     ) -> Result<Self, String> {
+        #[cfg(feature = "linked")]
         let entry = ash::Entry::linked();
+        #[cfg(not(feature = "linked"))]
+        let entry = unsafe {
+            ash::Entry::load().map_err(|err| format!("Vulkan loader not found: {}", err))?
+        };
 
         match compatibility_check(&entry, required_instance_extensions) {
             Ok(_) => log::info!("compatibility check passed"),
@@ -149,30 +154,14 @@ impl VulkanBase {
             }
         };
 
-        let mut found_surface_format = false;
-        let mut surface_format = vk::SurfaceFormatKHR {
+        let preferred_formats = [vk::SurfaceFormatKHR {
             format: vk::Format::B8G8R8A8_UNORM,
             color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-        };
-        for f in &formats {
-            if f.format == vk::Format::B8G8R8A8_UNORM
-                && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            {
-                surface_format = vk::SurfaceFormatKHR {
-                    format: vk::Format::B8G8R8A8_UNORM,
-                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-                };
-                found_surface_format = true;
-            } else {
-                continue;
-            }
-        }
+        }];
 
-        if (found_surface_format) {
-            log::info!("found surface formats");
-        } else {
-            return Err(String::from("cannot find surface format"));
-        }
+        let surface_format = vulkan_utils::choose_surface_format(&formats, &preferred_formats)?;
+
+        log::info!("selected surface format: {:?}", surface_format);
 
         let mut present_mode = vk::PresentModeKHR::FIFO;
 
@@ -366,7 +355,17 @@ impl VulkanBase {
         })
     }
 
-    pub fn resize(&mut self, window: &winit::window::Window) -> Result<(), String> {
+    /// Recreates the swapchain, its image views and the depth buffer for the current window size.
+    /// The previous image views are destroyed, so callers must not keep using them (or any
+    /// framebuffer created from them) after this returns.
+    /// Does nothing while the window has a zero extent, e.g. when it is minimized.
+    pub fn resize<W: WindowBackend>(&mut self, window: &W) -> Result<(), String> {
+        let (width, height) = window.inner_size();
+        if width == 0 || height == 0 {
+            log::info!("window has zero extent, skipping resize");
+            return Ok(());
+        }
+
         let old_depth_buffer_mem_image = std::mem::take(&mut self.depth_buffer_mem_image);
         let resize_data = resize_internal(
             window,
@@ -398,16 +397,16 @@ impl VulkanBase {
         log::info!("cleaning vulkan base");
 
         unsafe {
-            self.device
-                .destroy_image(self.depth_buffer_mem_image.image, None);
             self.device
                 .destroy_image_view(self.depth_buffer_mem_image.view, None);
+            self.device
+                .destroy_image(self.depth_buffer_mem_image.image, None);
             let _ = self.allocator.free(self.depth_buffer_mem_image.allocation);
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, None);
             for &image_view in &self.swapchain_image_views {
                 self.device.destroy_image_view(image_view, None);
             }
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
             drop(self.allocator);
             self.device.destroy_device(None);
             self.surface_loader.destroy_surface(self.surface, None);
@@ -425,8 +424,8 @@ struct ResizeResult {
     depth_buffer_mem_image: MemImage,
 }
 
-fn resize_internal(
-    window: &winit::window::Window,
+fn resize_internal<W: WindowBackend>(
+    window: &W,
     device: &ash::Device,
     surface_loader: &ash::extensions::khr::Surface,
     swapchain_loader: &ash::extensions::khr::Swapchain,
@@ -452,23 +451,17 @@ fn resize_internal(
             .map_err(|_| String::from("failed to get physical device surface capabilities"))?
     };
 
-    let window_size = window.inner_size();
+    let (window_width, window_height) = window.inner_size();
     let mut surface_extent = vk::Extent2D::default();
 
     if surface_capabilities.current_extent.width == u32::MAX {
         surface_extent.width = std::cmp::max(
             surface_capabilities.min_image_extent.width,
-            std::cmp::min(
-                surface_capabilities.max_image_extent.width,
-                window_size.width,
-            ),
+            std::cmp::min(surface_capabilities.max_image_extent.width, window_width),
         );
         surface_extent.height = std::cmp::max(
             surface_capabilities.min_image_extent.height,
-            std::cmp::min(
-                surface_capabilities.max_image_extent.height,
-                window_size.height,
-            ),
+            std::cmp::min(surface_capabilities.max_image_extent.height, window_height),
         );
     } else {
         surface_extent = surface_capabilities.current_extent;
@@ -515,6 +508,8 @@ fn resize_internal(
             .map_err(|_| String::from("failed to get swapchain images"))?
     };
 
+    log::info!("created swapchain image count: {}", swapchain_images.len());
+
     if !old_swapchain_image_views.is_empty() {
         log::info!("destroying old swapchain image views");
         for &image_view in old_swapchain_image_views {
@@ -545,8 +540,8 @@ fn resize_internal(
     if let Some(mem_image) = old_depth_buffer_mem_image {
         log::info!("destroying old depth buffer");
         unsafe {
-            device.destroy_image(mem_image.image, None);
             device.destroy_image_view(mem_image.view, None);
+            device.destroy_image(mem_image.image, None);
         }
         let _ = allocator.free(mem_image.allocation);
     }
@@ -558,8 +553,8 @@ fn resize_internal(
         guard(depth_buffer_mem_image, |mem_image| {
             log::warn!("depth buffer mem image scopeguard");
             unsafe {
-                device.destroy_image(mem_image.image, None);
                 device.destroy_image_view(mem_image.view, None);
+                device.destroy_image(mem_image.image, None);
             }
             let _ = allocator.free(mem_image.allocation);
         })
@@ -1,11 +1,13 @@
 use crate::teapot_lean_data;
 use crate::vulkan_base::VulkanBase;
 use crate::vulkan_data_fns as vulkan;
-use crate::vulkan_utils;
-use crate::vulkan_utils::MemBuffer;
 use ash::vk;
 use scopeguard::{guard, ScopeGuard};
 use std::cell::RefCell;
+use vulkan_utils::MemBuffer;
+
+// distance between neighbouring procedural teapots, the teapot is about 6 units long
+const INSTANCE_SPACING: f32 = 8.0;
 
 pub struct VulkanData {
     pub vertex_shader_module: vk::ShaderModule,
@@ -31,13 +33,34 @@ pub struct VulkanData {
     pub descriptor_pools: Vec<vk::DescriptorPool>,
     pub available_command_buffers: Vec<Vec<vk::CommandBuffer>>,
     pub used_command_buffers: Vec<Vec<vk::CommandBuffer>>,
+    /// Number of frames the CPU may record ahead of the GPU, see `VulkanData::new`.
+    pub frames_in_flight: u32,
     pub curr_resource_index: u32,
     pub is_wireframe_mode: bool,
     pub tesselation_level: f32,
+    /// Flip the viewport vertically so +Y points up in clip space.
+    pub flip_viewport_y: bool,
 }
 
 impl VulkanData {
-    pub fn new(vulkan_base: &mut VulkanBase) -> Result<Self, String> {
+    /// `frames_in_flight` is how many frames the CPU may record while the GPU still works on
+    /// earlier ones, each with its own command pool, descriptor pool and uniform buffer. More
+    /// frames keep the GPU busier at the cost of input latency, 2 is the usual choice and 3
+    /// helps when CPU frame times vary.
+    pub fn new(vulkan_base: &mut VulkanBase, frames_in_flight: u32) -> Result<Self, String> {
+        debug_assert!(
+            frames_in_flight >= 1,
+            "at least one frame has to be in flight"
+        );
+
+        if frames_in_flight as usize > vulkan_base.swapchain_images.len() {
+            log::warn!(
+                "{} frames in flight but only {} swapchain images, extra frames wait on image acquisition",
+                frames_in_flight,
+                vulkan_base.swapchain_images.len()
+            );
+        }
+
         let device = &vulkan_base.device;
         let allocator_rc = RefCell::new(&mut vulkan_base.allocator);
 
@@ -45,7 +68,7 @@ impl VulkanData {
             let vertex_sm = vulkan_utils::create_shader_module(
                 &vulkan_base.device,
                 std::path::Path::new("shaders/slang/vert.spv"),
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
                 "vertex shader",
             )?;
 
@@ -61,7 +84,7 @@ impl VulkanData {
             let tese_sm = vulkan_utils::create_shader_module(
                 &vulkan_base.device,
                 std::path::Path::new("shaders/slang/tese.spv"),
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
                 "tessellation evaluation shader",
             )?;
 
@@ -77,7 +100,7 @@ impl VulkanData {
             let tesc_sm = vulkan_utils::create_shader_module(
                 &vulkan_base.device,
                 std::path::Path::new("shaders/slang/tesc.spv"),
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
                 "tessellation control shader",
             )?;
 
@@ -93,7 +116,7 @@ impl VulkanData {
             let fragment_sm = vulkan_utils::create_shader_module(
                 &vulkan_base.device,
                 std::path::Path::new("shaders/slang/frag.spv"),
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
                 "fragment shader",
             )?;
 
@@ -105,13 +128,24 @@ impl VulkanData {
             })
         };
 
-        let teapot_data = teapot_lean_data::TeapotData::new();
+        let teapot_data = match (crate::GRID_INSTANCES, crate::RING_INSTANCE_COUNT) {
+            ((rows, cols), _) if rows > 0 && cols > 0 => {
+                teapot_lean_data::TeapotData::grid(rows, cols, INSTANCE_SPACING)
+            }
+            (_, count) if count > 0 => teapot_lean_data::TeapotData::ring(
+                count,
+                INSTANCE_SPACING * count as f32 / std::f32::consts::TAU,
+            ),
+            _ => teapot_lean_data::TeapotData::new(),
+        };
 
         let control_points_mem_buffer_sg = {
             let control_points_mem_buffer = vulkan_utils::create_gpu_buffer_init(
                 &vulkan_base.device,
                 *allocator_rc.borrow_mut(),
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
+                vulkan_base.queue_family,
+                vulkan_base.queue,
                 vulkan_base.queue_family,
                 vulkan_base.queue,
                 teapot_data.get_control_points_slice(),
@@ -134,7 +168,9 @@ impl VulkanData {
             let patches_mem_buffer = vulkan_utils::create_gpu_buffer_init(
                 &vulkan_base.device,
                 *allocator_rc.borrow_mut(),
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
+                vulkan_base.queue_family,
+                vulkan_base.queue,
                 vulkan_base.queue_family,
                 vulkan_base.queue,
                 teapot_data.get_patches_slice(),
@@ -159,7 +195,9 @@ impl VulkanData {
             let instances_mem_buffer = vulkan_utils::create_gpu_buffer_init(
                 &vulkan_base.device,
                 *allocator_rc.borrow_mut(),
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
+                vulkan_base.queue_family,
+                vulkan_base.queue,
                 vulkan_base.queue_family,
                 vulkan_base.queue,
                 teapot_data.get_instances_slice(),
@@ -179,15 +217,16 @@ impl VulkanData {
         };
 
         let uniform_mem_buffers_sg = {
-            let mut mem_buffers = Vec::with_capacity(crate::CONCURRENT_RESOURCE_COUNT as usize);
-            for i in 0..crate::CONCURRENT_RESOURCE_COUNT {
+            let mut mem_buffers = Vec::with_capacity(frames_in_flight as usize);
+            for i in 0..frames_in_flight {
                 let mem_buffer = vulkan_utils::create_buffer(
                     &vulkan_base.device,
                     *allocator_rc.borrow_mut(),
-                    &vulkan_base.debug_utils_loader,
+                    Some(&vulkan_base.debug_utils_loader),
                     (16 * std::mem::size_of::<f32>()) as vk::DeviceSize,
                     vk::BufferUsageFlags::UNIFORM_BUFFER,
                     gpu_allocator::MemoryLocation::CpuToGpu,
+                    &[vulkan_base.queue_family],
                     &format!("uniform buffer {}", i),
                 )?;
 
@@ -209,7 +248,7 @@ impl VulkanData {
         let descriptor_set_layout_sg = {
             let descriptor_set_layout = vulkan::create_descriptor_set_layout(
                 &vulkan_base.device,
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
             )?;
 
             guard(descriptor_set_layout, |layout| {
@@ -224,7 +263,7 @@ impl VulkanData {
             let pipeline_layout = vulkan::create_pipeline_layout(
                 &vulkan_base.device,
                 *descriptor_set_layout_sg,
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
             )?;
 
             guard(pipeline_layout, |layout| {
@@ -240,7 +279,7 @@ impl VulkanData {
                 &vulkan_base.device,
                 vulkan_base.surface_format.format,
                 vulkan_base.depth_format,
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
             )?;
 
             guard(render_pass, |render_pass| {
@@ -303,7 +342,7 @@ impl VulkanData {
         let image_available_semaphore_sg = {
             let semaphore = vulkan_utils::create_semaphore(
                 &vulkan_base.device,
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
                 "image available semaphore",
             )?;
 
@@ -318,7 +357,7 @@ impl VulkanData {
         let rendering_finished_semaphore_sg = {
             let semaphore = vulkan_utils::create_semaphore(
                 &vulkan_base.device,
-                &vulkan_base.debug_utils_loader,
+                Some(&vulkan_base.debug_utils_loader),
                 "rendering finished semaphore",
             )?;
 
@@ -331,8 +370,11 @@ impl VulkanData {
         };
 
         let fences_sg = {
-            let fences =
-                vulkan::create_fences(&vulkan_base.device, &vulkan_base.debug_utils_loader)?;
+            let fences = vulkan::create_fences(
+                &vulkan_base.device,
+                frames_in_flight,
+                &vulkan_base.debug_utils_loader,
+            )?;
 
             guard(fences, |fences| {
                 log::warn!("fences scopeguard");
@@ -348,6 +390,7 @@ impl VulkanData {
             let command_pools = vulkan::create_command_pools(
                 &vulkan_base.device,
                 vulkan_base.queue_family,
+                frames_in_flight,
                 &vulkan_base.debug_utils_loader,
             )?;
 
@@ -364,6 +407,7 @@ impl VulkanData {
         let descriptor_pools_sg = {
             let descriptor_pools = vulkan::create_descriptor_pools(
                 &vulkan_base.device,
+                frames_in_flight,
                 &vulkan_base.debug_utils_loader,
             )?;
 
@@ -399,11 +443,13 @@ impl VulkanData {
             fences: ScopeGuard::into_inner(fences_sg),
             command_pools: ScopeGuard::into_inner(command_pools_sg),
             descriptor_pools: ScopeGuard::into_inner(descriptor_pools_sg),
-            available_command_buffers: vec![vec![]; crate::CONCURRENT_RESOURCE_COUNT as usize],
-            used_command_buffers: vec![vec![]; crate::CONCURRENT_RESOURCE_COUNT as usize],
+            available_command_buffers: vec![vec![]; frames_in_flight as usize],
+            used_command_buffers: vec![vec![]; frames_in_flight as usize],
+            frames_in_flight,
             curr_resource_index: 0,
             is_wireframe_mode: false,
             tesselation_level: 1.0,
+            flip_viewport_y: false,
         })
     }
 
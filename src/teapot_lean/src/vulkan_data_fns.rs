@@ -1,8 +1,8 @@
 use crate::vulkan_base;
 use crate::vulkan_data::VulkanData;
-use crate::vulkan_utils;
 use ash::vk;
-use raw_window_handle::HasRawDisplayHandle;
+
+pub use teapot_common::{create_descriptor_set_layout, create_pipeline_layout, create_render_pass};
 
 pub fn vulkan_clean(
     vulkan_base: &mut Option<vulkan_base::VulkanBase>,
@@ -19,120 +19,6 @@ pub fn vulkan_clean(
     vk_base.clean();
 }
 
-pub fn get_required_instance_extensions(
-    window: &winit::window::Window,
-) -> Result<Vec<&'static std::ffi::CStr>, String> {
-    log::info!("getting required instance extensions");
-
-    let mut instance_extensions =
-        match ash_window::enumerate_required_extensions(window.raw_display_handle()) {
-            Ok(extensions) => extensions
-                .to_vec()
-                .into_iter()
-                .map(|name| unsafe { std::ffi::CStr::from_ptr(name) })
-                .collect::<Vec<&'static std::ffi::CStr>>(),
-            Err(_) => {
-                return Err(String::from(
-                    "failed to enumerate required instance extensions",
-                ));
-            }
-        };
-
-    log::info!("required instance extensions: {:?}", instance_extensions);
-
-    instance_extensions.push(ash::extensions::ext::DebugUtils::name());
-
-    Ok(instance_extensions)
-}
-
-pub fn create_descriptor_set_layout(
-    device: &ash::Device,
-    debug_utils_loader: &ash::extensions::ext::DebugUtils,
-) -> Result<vk::DescriptorSetLayout, String> {
-    log::info!("creating descriptor set layout");
-
-    let control_points_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(0)
-        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::VERTEX)
-        .build();
-
-    let patch_data_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(1)
-        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
-        .build();
-
-    let uniform_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(2)
-        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
-        .build();
-
-    let bindings = [control_points_binding, patch_data_binding, uniform_binding];
-    let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
-        .bindings(&bindings)
-        .build();
-
-    let descriptor_set_layout = unsafe {
-        device
-            .create_descriptor_set_layout(&create_info, None)
-            .map_err(|_| String::from("failed to create descriptor set layout"))?
-    };
-
-    vulkan_utils::set_debug_utils_object_name2(
-        debug_utils_loader,
-        device.handle(),
-        descriptor_set_layout,
-        "descriptor set layout",
-    );
-
-    log::info!("descriptor set layout created");
-
-    Ok(descriptor_set_layout)
-}
-
-pub fn create_pipeline_layout(
-    device: &ash::Device,
-    descriptor_set_layout: vk::DescriptorSetLayout,
-    debug_utils_loader: &ash::extensions::ext::DebugUtils,
-) -> Result<vk::PipelineLayout, String> {
-    log::info!("creating pipeline layout");
-
-    let push_const_range = vk::PushConstantRange {
-        stage_flags: vk::ShaderStageFlags::TESSELLATION_CONTROL,
-        offset: 0,
-        size: 4,
-    };
-
-    let layouts = [descriptor_set_layout];
-    let ranges = [push_const_range];
-    let create_info = vk::PipelineLayoutCreateInfo::builder()
-        .set_layouts(&layouts)
-        .push_constant_ranges(&ranges)
-        .build();
-
-    let pipeline_layout = unsafe {
-        device
-            .create_pipeline_layout(&create_info, None)
-            .map_err(|_| String::from("failed to create pipeline layout"))?
-    };
-
-    vulkan_utils::set_debug_utils_object_name2(
-        debug_utils_loader,
-        device.handle(),
-        pipeline_layout,
-        "pipeline layout",
-    );
-
-    log::info!("pipeline layout created");
-
-    Ok(pipeline_layout)
-}
-
 pub fn create_pipelines(
     device: &ash::Device,
     vertex_shader_module: vk::ShaderModule,
@@ -143,233 +29,20 @@ pub fn create_pipelines(
     render_pass: vk::RenderPass,
     debug_utils_loader: &ash::extensions::ext::DebugUtils,
 ) -> Result<(vk::Pipeline, vk::Pipeline), String> {
-    log::info!("creating pipelines");
-
-    let shader_entry_name = std::ffi::CString::new("main").unwrap();
-
-    let vs_state = vk::PipelineShaderStageCreateInfo::builder()
-        .stage(vk::ShaderStageFlags::VERTEX)
-        .module(vertex_shader_module)
-        .name(&shader_entry_name)
-        .build();
-
-    let tc_state = vk::PipelineShaderStageCreateInfo::builder()
-        .stage(vk::ShaderStageFlags::TESSELLATION_CONTROL)
-        .module(tess_control_shader_module)
-        .name(&shader_entry_name)
-        .build();
-
-    let te_state = vk::PipelineShaderStageCreateInfo::builder()
-        .stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
-        .module(tess_eval_shader_module)
-        .name(&shader_entry_name)
-        .build();
-
-    let fs_state = vk::PipelineShaderStageCreateInfo::builder()
-        .stage(vk::ShaderStageFlags::FRAGMENT)
-        .module(fragment_shader_module)
-        .name(&shader_entry_name)
-        .build();
-
-    let ia_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
-        .topology(vk::PrimitiveTopology::PATCH_LIST)
-        .build();
-
-    let raster_state = vk::PipelineRasterizationStateCreateInfo::builder()
-        .polygon_mode(vk::PolygonMode::FILL)
-        .cull_mode(vk::CullModeFlags::NONE)
-        .front_face(vk::FrontFace::CLOCKWISE)
-        .line_width(1.0f32)
-        .build();
-
-    let col_blend_attachment_state = vk::PipelineColorBlendAttachmentState::builder()
-        .blend_enable(false)
-        .color_write_mask(
-            vk::ColorComponentFlags::R
-                | vk::ColorComponentFlags::G
-                | vk::ColorComponentFlags::B
-                | vk::ColorComponentFlags::A,
-        )
-        .build();
-
-    let attachments = [col_blend_attachment_state];
-    let col_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
-        .attachments(&attachments)
-        .build();
-
-    let states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-    let dyn_state = vk::PipelineDynamicStateCreateInfo::builder()
-        .dynamic_states(&states)
-        .build();
-
-    let viewports = [vk::Viewport {
-        ..Default::default()
-    }];
-    let scissors = [vk::Rect2D {
-        ..Default::default()
-    }];
-
-    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-        .viewports(&viewports)
-        .scissors(&scissors)
-        .build();
-
-    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
-        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-
-    let tessellation_state = vk::PipelineTessellationStateCreateInfo::builder()
-        .patch_control_points(16)
-        .build();
-
-    let stages = [vs_state, tc_state, te_state, fs_state];
-
-    let vert_inp_state = vk::PipelineVertexInputStateCreateInfo::builder().build();
-
-    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-        .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
-        .build();
-
-    let solid_pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
-        .flags(vk::PipelineCreateFlags::ALLOW_DERIVATIVES)
-        .stages(&stages)
-        .input_assembly_state(&ia_state)
-        .rasterization_state(&raster_state)
-        .color_blend_state(&col_blend_state)
-        .dynamic_state(&dyn_state)
-        .viewport_state(&viewport_state)
-        .layout(pipeline_layout)
-        .render_pass(render_pass)
-        .subpass(0)
-        .multisample_state(&multisample_state)
-        .tessellation_state(&tessellation_state)
-        .vertex_input_state(&vert_inp_state)
-        .depth_stencil_state(&depth_stencil_state)
-        .build();
-
-    let raster_state = vk::PipelineRasterizationStateCreateInfo::builder()
-        .polygon_mode(vk::PolygonMode::LINE)
-        .cull_mode(vk::CullModeFlags::NONE)
-        .front_face(vk::FrontFace::CLOCKWISE)
-        .line_width(1.0f32)
-        .build();
-
-    let mut wireframe_pipeline_create_info = solid_pipeline_create_info;
-    wireframe_pipeline_create_info.flags = vk::PipelineCreateFlags::DERIVATIVE;
-    wireframe_pipeline_create_info.p_rasterization_state = &raster_state;
-    wireframe_pipeline_create_info.base_pipeline_index = 0;
-
-    let pipelines = unsafe {
-        device
-            .create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &[solid_pipeline_create_info, wireframe_pipeline_create_info],
-                None,
-            )
-            .map_err(|_| String::from("failed to create pipelines"))?
-    };
-
-    let solid_pipeline = pipelines[0];
-    let wireframe_pipeline = pipelines[1];
-
-    vulkan_utils::set_debug_utils_object_name2(
-        debug_utils_loader,
-        device.handle(),
-        solid_pipeline,
-        "solid pipeline",
-    );
-
-    vulkan_utils::set_debug_utils_object_name2(
-        debug_utils_loader,
-        device.handle(),
-        wireframe_pipeline,
-        "wireframe pipeline",
-    );
-
-    log::info!("pipelines created");
-
-    Ok((solid_pipeline, wireframe_pipeline))
-}
-
-pub fn create_render_pass(
-    device: &ash::Device,
-    surface_format: vk::Format,
-    depth_format: vk::Format,
-    debug_utils_loader: &ash::extensions::ext::DebugUtils,
-) -> Result<vk::RenderPass, String> {
-    log::info!("creating render pass");
-
-    let mut attachment_descriptions = Vec::new();
-
-    attachment_descriptions.push(
-        vk::AttachmentDescription::builder()
-            .format(surface_format)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-            .build(),
-    );
-
-    attachment_descriptions.push(
-        vk::AttachmentDescription::builder()
-            .format(depth_format)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-            .build(),
-    );
-
-    let col_attachment_ref = vk::AttachmentReference::builder()
-        .attachment(0)
-        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-        .build();
-
-    let depth_attachment_ref = vk::AttachmentReference::builder()
-        .attachment(1)
-        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-        .build();
-
-    let references = [col_attachment_ref];
-
-    let mut subpass_descriptions = Vec::new();
-
-    subpass_descriptions.push(
-        vk::SubpassDescription::builder()
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-            .color_attachments(&references)
-            .depth_stencil_attachment(&depth_attachment_ref)
-            .build(),
-    );
-
-    let create_info = vk::RenderPassCreateInfo::builder()
-        .attachments(&attachment_descriptions)
-        .subpasses(&subpass_descriptions);
-
-    let render_pass = unsafe {
-        device
-            .create_render_pass(&create_info, None)
-            .map_err(|_| String::from("failed to create render pass"))?
-    };
-
-    vulkan_utils::set_debug_utils_object_name2(
-        debug_utils_loader,
-        device.handle(),
+    teapot_common::create_pipelines(
+        device,
+        &teapot_common::PipelineShaders {
+            vertex: vertex_shader_module,
+            tess_control: tess_control_shader_module,
+            tess_eval: tess_eval_shader_module,
+            geometry: None,
+            fragment: fragment_shader_module,
+        },
+        &teapot_common::PipelineConfig::default(),
+        pipeline_layout,
         render_pass,
-        "render pass",
-    );
-
-    log::info!("render pass created");
-
-    Ok(render_pass)
+        Some(debug_utils_loader),
+    )
 }
 
 pub fn create_framebuffers(
@@ -404,8 +77,8 @@ pub fn create_framebuffers(
 
         framebuffers.push(framebuffer);
 
-        vulkan_utils::set_debug_utils_object_name2(
-            debug_utils_loader,
+        vulkan_utils::set_debug_utils_object_name(
+            Some(debug_utils_loader),
             device.handle(),
             framebuffer,
             &format!("framebuffer {}", i),
@@ -418,6 +91,7 @@ pub fn create_framebuffers(
 pub fn create_command_pools(
     device: &ash::Device,
     queue_family: u32,
+    frame_count: u32,
     debug_utils_loader: &ash::extensions::ext::DebugUtils,
 ) -> Result<Vec<vk::CommandPool>, String> {
     log::info!("creating command pools");
@@ -426,9 +100,9 @@ pub fn create_command_pools(
         .flags(vk::CommandPoolCreateFlags::TRANSIENT)
         .queue_family_index(queue_family);
 
-    let mut command_pools = Vec::with_capacity(crate::CONCURRENT_RESOURCE_COUNT as usize);
+    let mut command_pools = Vec::with_capacity(frame_count as usize);
 
-    for i in 0..crate::CONCURRENT_RESOURCE_COUNT {
+    for i in 0..frame_count {
         let command_pool = unsafe {
             device
                 .create_command_pool(&create_info, None)
@@ -443,8 +117,8 @@ pub fn create_command_pools(
 
         command_pools.push(command_pool);
 
-        vulkan_utils::set_debug_utils_object_name2(
-            debug_utils_loader,
+        vulkan_utils::set_debug_utils_object_name(
+            Some(debug_utils_loader),
             device.handle(),
             command_pool,
             &format!("command pool {}", i),
@@ -458,6 +132,7 @@ pub fn create_command_pools(
 
 pub fn create_descriptor_pools(
     device: &ash::Device,
+    frame_count: u32,
     debug_utils_loader: &ash::extensions::ext::DebugUtils,
 ) -> Result<Vec<vk::DescriptorPool>, String> {
     log::info!("creating descriptor pools");
@@ -478,9 +153,9 @@ pub fn create_descriptor_pools(
         .pool_sizes(&sizes)
         .build();
 
-    let mut descriptor_pools = Vec::with_capacity(crate::CONCURRENT_RESOURCE_COUNT as usize);
+    let mut descriptor_pools = Vec::with_capacity(frame_count as usize);
 
-    for i in 0..crate::CONCURRENT_RESOURCE_COUNT {
+    for i in 0..frame_count {
         let pool = unsafe {
             device
                 .create_descriptor_pool(&create_info, None)
@@ -492,8 +167,8 @@ pub fn create_descriptor_pools(
                 })?
         };
 
-        vulkan_utils::set_debug_utils_object_name2(
-            debug_utils_loader,
+        vulkan_utils::set_debug_utils_object_name(
+            Some(debug_utils_loader),
             device.handle(),
             pool,
             &format!("descriptor pool {}", i),
@@ -509,6 +184,7 @@ pub fn create_descriptor_pools(
 
 pub fn create_fences(
     device: &ash::Device,
+    frame_count: u32,
     debug_utils_loader: &ash::extensions::ext::DebugUtils,
 ) -> Result<Vec<vk::Fence>, String> {
     log::info!("creating fences");
@@ -517,9 +193,9 @@ pub fn create_fences(
         .flags(vk::FenceCreateFlags::SIGNALED)
         .build();
 
-    let mut fences = Vec::with_capacity(crate::CONCURRENT_RESOURCE_COUNT as usize);
+    let mut fences = Vec::with_capacity(frame_count as usize);
 
-    for i in 0..crate::CONCURRENT_RESOURCE_COUNT {
+    for i in 0..frame_count {
         let fence = unsafe {
             device.create_fence(&create_info, None).map_err(|_| {
                 for &f in &fences {
@@ -532,8 +208,8 @@ pub fn create_fences(
 
         fences.push(fence);
 
-        vulkan_utils::set_debug_utils_object_name2(
-            debug_utils_loader,
+        vulkan_utils::set_debug_utils_object_name(
+            Some(debug_utils_loader),
             device.handle(),
             fence,
             &format!("fence {}", i),
@@ -0,0 +1,95 @@
+use ash::extensions::khr;
+use ash::vk;
+
+/// A single mip, single layer image layout transition recorded with `vkCmdPipelineBarrier2`.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageBarrier {
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub aspect_mask: vk::ImageAspectFlags,
+    pub src_stage: vk::PipelineStageFlags2,
+    pub src_access: vk::AccessFlags2,
+    pub dst_stage: vk::PipelineStageFlags2,
+    pub dst_access: vk::AccessFlags2,
+}
+
+impl ImageBarrier {
+    /// Discards the previous contents of a color image before it is rendered to.
+    pub fn undefined_to_color_attachment() -> Self {
+        ImageBarrier {
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            src_stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            src_access: vk::AccessFlags2::NONE,
+            dst_stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            dst_access: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+        }
+    }
+
+    /// Discards the previous contents of a depth image once the previous frame is done testing
+    /// against it. Needed before every use without a render pass, which would otherwise do the
+    /// transition through its attachment's `initial_layout`. The stencil aspect is included
+    /// for combined formats, a layout transition must cover both.
+    pub fn undefined_to_depth_attachment(depth_format: vk::Format) -> Self {
+        ImageBarrier {
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            aspect_mask: vulkan_utils::get_depth_aspect_mask(depth_format),
+            src_stage: vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            src_access: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_stage: vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS,
+            dst_access: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        }
+    }
+
+    /// Hands a rendered color image over to the presentation engine.
+    pub fn color_attachment_to_present() -> Self {
+        ImageBarrier {
+            old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            src_stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            src_access: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            dst_stage: vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+            dst_access: vk::AccessFlags2::NONE,
+        }
+    }
+}
+
+/// Records `barrier` for `image`. Needs the device created with
+/// `VulkanBaseConfig::enable_synchronization2`.
+pub fn cmd_image_barrier(
+    synchronization2_loader: &khr::Synchronization2,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    barrier: &ImageBarrier,
+) {
+    let image_barriers = [vk::ImageMemoryBarrier2::builder()
+        .src_stage_mask(barrier.src_stage)
+        .src_access_mask(barrier.src_access)
+        .dst_stage_mask(barrier.dst_stage)
+        .dst_access_mask(barrier.dst_access)
+        .old_layout(barrier.old_layout)
+        .new_layout(barrier.new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: barrier.aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .build()];
+
+    let dependency_info = vk::DependencyInfo::builder()
+        .image_memory_barriers(&image_barriers)
+        .build();
+
+    unsafe {
+        synchronization2_loader.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+    }
+}
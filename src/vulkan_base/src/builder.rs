@@ -0,0 +1,186 @@
+use ash::vk;
+
+use crate::{DevicePreference, VulkanBase, VulkanBaseConfig, VulkanError, WindowBackend};
+
+/// Chainable alternative to `VulkanBase::new`, unset options keep the `VulkanBaseConfig`
+/// defaults.
+#[derive(Clone, Default)]
+pub struct VulkanBaseBuilder<'a> {
+    instance_extensions: Vec<&'a std::ffi::CStr>,
+    device_extensions: Vec<&'a std::ffi::CStr>,
+    config: VulkanBaseConfig,
+}
+
+impl<'a> VulkanBaseBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn instance_extensions(mut self, extensions: &[&'a std::ffi::CStr]) -> Self {
+        self.instance_extensions.extend_from_slice(extensions);
+        self
+    }
+
+    /// Enabled only when supported, see `VulkanBaseConfig::optional_instance_extensions`.
+    pub fn optional_instance_extensions(mut self, extensions: &[&'static std::ffi::CStr]) -> Self {
+        self.config
+            .optional_instance_extensions
+            .extend_from_slice(extensions);
+        self
+    }
+
+    pub fn device_extensions(mut self, extensions: &[&'a std::ffi::CStr]) -> Self {
+        self.device_extensions.extend_from_slice(extensions);
+        self
+    }
+
+    /// Replaces every option set so far except the extensions.
+    pub fn config(mut self, config: VulkanBaseConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn required_features(mut self, features: vk::PhysicalDeviceFeatures) -> Self {
+        self.config.required_features = features;
+        self
+    }
+
+    pub fn optional_features(mut self, features: vk::PhysicalDeviceFeatures) -> Self {
+        self.config.optional_features = features;
+        self
+    }
+
+    pub fn validation(mut self, enable: bool) -> Self {
+        self.config.enable_validation = enable;
+        self
+    }
+
+    pub fn device_preference(mut self, preference: DevicePreference) -> Self {
+        self.config.device_preference = preference;
+        self
+    }
+
+    pub fn preferred_present_modes(mut self, present_modes: &[vk::PresentModeKHR]) -> Self {
+        self.config.preferred_present_modes = present_modes.to_vec();
+        self
+    }
+
+    pub fn prefer_srgb(mut self, prefer: bool) -> Self {
+        self.config.prefer_srgb = prefer;
+        self
+    }
+
+    pub fn debug_message_severity(
+        mut self,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    ) -> Self {
+        self.config.debug_message_severity = severity;
+        self
+    }
+
+    pub fn queue_flags(mut self, queue_flags: vk::QueueFlags) -> Self {
+        self.config.queue_flags = queue_flags;
+        self
+    }
+
+    pub fn enable_capture(mut self, enable: bool) -> Self {
+        self.config.enable_capture = enable;
+        self
+    }
+
+    pub fn enable_depth_readback(mut self, enable: bool) -> Self {
+        self.config.enable_depth_readback = enable;
+        self
+    }
+
+    pub fn extra_usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.config.extra_usage = usage;
+        self
+    }
+
+    /// Logs the memory heaps and every allocation and free, see `allocator_debug_settings` for
+    /// finer control.
+    pub fn log_allocations(mut self, enable: bool) -> Self {
+        self.config.allocator_debug_settings.log_memory_information = enable;
+        self.config.allocator_debug_settings.log_allocations = enable;
+        self.config.allocator_debug_settings.log_frees = enable;
+        self
+    }
+
+    pub fn allocator_debug_settings(
+        mut self,
+        debug_settings: gpu_allocator::AllocatorDebugSettings,
+    ) -> Self {
+        self.config.allocator_debug_settings = debug_settings;
+        self
+    }
+
+    pub fn enable_dynamic_rendering(mut self, enable: bool) -> Self {
+        self.config.enable_dynamic_rendering = enable;
+        self
+    }
+
+    pub fn enable_synchronization2(mut self, enable: bool) -> Self {
+        self.config.enable_synchronization2 = enable;
+        self
+    }
+
+    pub fn enable_timeline_semaphore(mut self, enable: bool) -> Self {
+        self.config.enable_timeline_semaphore = enable;
+        self
+    }
+
+    pub fn preferred_composite_alphas(
+        mut self,
+        composite_alphas: &[vk::CompositeAlphaFlagsKHR],
+    ) -> Self {
+        self.config.preferred_composite_alphas = composite_alphas.to_vec();
+        self
+    }
+
+    /// `version` is made with `vk::make_api_version`.
+    pub fn application(mut self, name: &str, version: u32) -> Self {
+        self.config.application_name = String::from(name);
+        self.config.application_version = version;
+        self
+    }
+
+    pub fn engine(mut self, name: &str, version: u32) -> Self {
+        self.config.engine_name = String::from(name);
+        self.config.engine_version = version;
+        self
+    }
+
+    pub fn needs_stencil(mut self, needs_stencil: bool) -> Self {
+        self.config.needs_stencil = needs_stencil;
+        self
+    }
+
+    pub fn desired_image_count(mut self, count: u32) -> Self {
+        self.config.desired_image_count = count;
+        self
+    }
+
+    /// See `VulkanBaseConfig::preferred_hdr_color_spaces`.
+    pub fn preferred_hdr_color_spaces(mut self, color_spaces: &[vk::ColorSpaceKHR]) -> Self {
+        self.config.preferred_hdr_color_spaces = color_spaces.to_vec();
+        self
+    }
+
+    pub fn build<W: WindowBackend>(self, window: &W) -> Result<VulkanBase, VulkanError> {
+        VulkanBase::create(
+            Some(window),
+            &self.instance_extensions,
+            &self.device_extensions,
+            &self.config,
+        )
+    }
+
+    pub fn build_headless(self) -> Result<VulkanBase, VulkanError> {
+        VulkanBase::new_headless(
+            &self.instance_extensions,
+            &self.device_extensions,
+            &self.config,
+        )
+    }
+}
@@ -0,0 +1,254 @@
+use ash::vk;
+use scopeguard::{guard, ScopeGuard};
+use std::cell::RefCell;
+
+use crate::VulkanError;
+
+/// Copies a swapchain image in `PRESENT_SRC_KHR` layout to a host visible buffer and writes it
+/// to `path` as an RGBA8 PNG. Waits for the queue to be idle.
+pub fn capture_swapchain_image(
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    queue_family: u32,
+    queue: vk::Queue,
+    image: vk::Image,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    path: &std::path::Path,
+) -> Result<(), VulkanError> {
+    log::info!("capturing swapchain image to {:?}", path);
+
+    let is_bgra = match format {
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => true,
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => false,
+        _ => {
+            return Err(VulkanError::Other(format!(
+                "capturing swapchain format {:?} is not supported",
+                format
+            )))
+        }
+    };
+
+    let allocator_rc = RefCell::new(allocator);
+
+    let size = extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4;
+
+    let staging_mem_buffer_sg = {
+        let staging_mem_buffer = vulkan_utils::create_buffer(
+            device,
+            *allocator_rc.borrow_mut(),
+            debug_utils_loader,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            gpu_allocator::MemoryLocation::GpuToCpu,
+            &[queue_family],
+            "capture buffer",
+        )?;
+
+        guard(staging_mem_buffer, |mem_buffer| {
+            log::warn!("capture buffer scopeguard");
+            unsafe {
+                device.destroy_buffer(mem_buffer.buffer, None);
+            }
+            let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+        })
+    };
+
+    let command_pool_sg = {
+        let create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue_family)
+            .build();
+
+        let command_pool = unsafe {
+            device
+                .create_command_pool(&create_info, None)
+                .map_err(|err| {
+                    VulkanError::Vulkan(String::from("failed to create capture command pool"), err)
+                })?
+        };
+
+        guard(command_pool, |command_pool| {
+            log::warn!("capture command pool scopeguard");
+            unsafe {
+                device.destroy_command_pool(command_pool, None);
+            }
+        })
+    };
+
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(*command_pool_sg)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1)
+        .build();
+
+    let command_buffer = unsafe {
+        device
+            .allocate_command_buffers(&allocate_info)
+            .map_err(|err| {
+                VulkanError::Vulkan(
+                    String::from("failed to allocate capture command buffer"),
+                    err,
+                )
+            })?[0]
+    };
+
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+        .build();
+
+    unsafe {
+        device
+            .begin_command_buffer(command_buffer, &begin_info)
+            .map_err(|err| {
+                VulkanError::Vulkan(String::from("failed to begin capture command buffer"), err)
+            })?;
+
+        let before_copy_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[before_copy_barrier],
+        );
+
+        // zero row length and image height keep the buffer rows tightly packed
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        };
+
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            staging_mem_buffer_sg.buffer,
+            &[region],
+        );
+
+        let after_copy_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        let buffer_barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(staging_mem_buffer_sg.buffer)
+            .offset(0)
+            .size(size)
+            .build();
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[buffer_barrier],
+            &[after_copy_barrier],
+        );
+
+        device.end_command_buffer(command_buffer).map_err(|err| {
+            VulkanError::Vulkan(String::from("failed to end capture command buffer"), err)
+        })?;
+
+        let cmd_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&cmd_buffers)
+            .build();
+
+        device
+            .queue_submit(queue, &[submit_info], vk::Fence::null())
+            .map_err(|err| {
+                VulkanError::from_result(String::from("failed to submit capture"), err)
+            })?;
+
+        device.queue_wait_idle(queue).map_err(|err| {
+            VulkanError::from_result(String::from("failed to wait idle queue"), err)
+        })?;
+    }
+
+    let mut pixels = match staging_mem_buffer_sg.allocation.mapped_slice() {
+        Some(mapped) => mapped[..size as usize].to_vec(),
+        None => {
+            return Err(VulkanError::Other(String::from(
+                "capture buffer is not host visible",
+            )))
+        }
+    };
+
+    if is_bgra {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    unsafe {
+        device.destroy_command_pool(ScopeGuard::into_inner(command_pool_sg), None);
+    }
+
+    let staging_mem_buffer = ScopeGuard::into_inner(staging_mem_buffer_sg);
+    unsafe {
+        device.destroy_buffer(staging_mem_buffer.buffer, None);
+    }
+    let _ = allocator_rc
+        .borrow_mut()
+        .free(staging_mem_buffer.allocation);
+
+    image::save_buffer(
+        path,
+        &pixels,
+        extent.width,
+        extent.height,
+        image::ColorType::Rgba8,
+    )
+    .map_err(|_| VulkanError::Other(format!("failed to write capture {:?}", path)))?;
+
+    log::info!("swapchain image captured");
+
+    Ok(())
+}
@@ -0,0 +1,265 @@
+use ash::vk;
+use scopeguard::{guard, ScopeGuard};
+use std::cell::RefCell;
+
+use crate::VulkanError;
+
+/// Copies the depth of pixel `x`, `y` of a depth image in `DEPTH_STENCIL_ATTACHMENT_OPTIMAL`
+/// layout to a host visible buffer and returns it normalized to 0..1. Waits for the queue to be
+/// idle, so the value is the one left by the last submitted frame.
+pub fn read_depth_pixel(
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    queue_family: u32,
+    queue: vk::Queue,
+    image: vk::Image,
+    format: vk::Format,
+    x: u32,
+    y: u32,
+) -> Result<f32, VulkanError> {
+    log::info!("reading depth pixel {}, {}", x, y);
+
+    // only the depth aspect is copied, the buffer layout of each format is fixed by the spec:
+    // 16 bit unorm, 24 bit unorm in the low bits of 32, or 32 bit float, never with stencil
+    let decode: fn([u8; 4]) -> f32 = match format {
+        vk::Format::D16_UNORM | vk::Format::D16_UNORM_S8_UINT => {
+            |bytes| u16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / u16::MAX as f32
+        }
+        vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D24_UNORM_S8_UINT => {
+            |bytes| (u32::from_ne_bytes(bytes) & 0x00ff_ffff) as f32 / 0x00ff_ffff as f32
+        }
+        vk::Format::D32_SFLOAT | vk::Format::D32_SFLOAT_S8_UINT => f32::from_ne_bytes,
+        _ => {
+            return Err(VulkanError::Other(format!(
+                "reading depth format {:?} is not supported",
+                format
+            )))
+        }
+    };
+
+    let allocator_rc = RefCell::new(allocator);
+
+    let size: vk::DeviceSize = 4;
+
+    let staging_mem_buffer_sg = {
+        let staging_mem_buffer = vulkan_utils::create_buffer(
+            device,
+            *allocator_rc.borrow_mut(),
+            debug_utils_loader,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            gpu_allocator::MemoryLocation::GpuToCpu,
+            &[queue_family],
+            "depth readback buffer",
+        )?;
+
+        guard(staging_mem_buffer, |mem_buffer| {
+            log::warn!("depth readback buffer scopeguard");
+            unsafe {
+                device.destroy_buffer(mem_buffer.buffer, None);
+            }
+            let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+        })
+    };
+
+    let command_pool_sg = {
+        let create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue_family)
+            .build();
+
+        let command_pool = unsafe {
+            device
+                .create_command_pool(&create_info, None)
+                .map_err(|err| {
+                    VulkanError::Vulkan(
+                        String::from("failed to create depth readback command pool"),
+                        err,
+                    )
+                })?
+        };
+
+        guard(command_pool, |command_pool| {
+            log::warn!("depth readback command pool scopeguard");
+            unsafe {
+                device.destroy_command_pool(command_pool, None);
+            }
+        })
+    };
+
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(*command_pool_sg)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1)
+        .build();
+
+    let command_buffer = unsafe {
+        device
+            .allocate_command_buffers(&allocate_info)
+            .map_err(|err| {
+                VulkanError::Vulkan(
+                    String::from("failed to allocate depth readback command buffer"),
+                    err,
+                )
+            })?[0]
+    };
+
+    // layout transitions of combined formats must cover the stencil aspect too
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vulkan_utils::get_depth_aspect_mask(format),
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+        .build();
+
+    unsafe {
+        device
+            .begin_command_buffer(command_buffer, &begin_info)
+            .map_err(|err| {
+                VulkanError::Vulkan(
+                    String::from("failed to begin depth readback command buffer"),
+                    err,
+                )
+            })?;
+
+        let before_copy_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[before_copy_barrier],
+        );
+
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+        };
+
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            staging_mem_buffer_sg.buffer,
+            &[region],
+        );
+
+        let after_copy_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        let buffer_barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(staging_mem_buffer_sg.buffer)
+            .offset(0)
+            .size(size)
+            .build();
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[buffer_barrier],
+            &[after_copy_barrier],
+        );
+
+        device.end_command_buffer(command_buffer).map_err(|err| {
+            VulkanError::Vulkan(
+                String::from("failed to end depth readback command buffer"),
+                err,
+            )
+        })?;
+
+        let cmd_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&cmd_buffers)
+            .build();
+
+        device
+            .queue_submit(queue, &[submit_info], vk::Fence::null())
+            .map_err(|err| {
+                VulkanError::from_result(String::from("failed to submit depth readback"), err)
+            })?;
+
+        device.queue_wait_idle(queue).map_err(|err| {
+            VulkanError::from_result(String::from("failed to wait idle queue"), err)
+        })?;
+    }
+
+    let bytes = match staging_mem_buffer_sg.allocation.mapped_slice() {
+        Some(mapped) => [mapped[0], mapped[1], mapped[2], mapped[3]],
+        None => {
+            return Err(VulkanError::Other(String::from(
+                "depth readback buffer is not host visible",
+            )))
+        }
+    };
+
+    unsafe {
+        device.destroy_command_pool(ScopeGuard::into_inner(command_pool_sg), None);
+    }
+
+    let staging_mem_buffer = ScopeGuard::into_inner(staging_mem_buffer_sg);
+    unsafe {
+        device.destroy_buffer(staging_mem_buffer.buffer, None);
+    }
+    let _ = allocator_rc
+        .borrow_mut()
+        .free(staging_mem_buffer.allocation);
+
+    let depth = decode(bytes);
+
+    log::info!("depth pixel read: {}", depth);
+
+    Ok(depth)
+}
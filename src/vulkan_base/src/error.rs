@@ -0,0 +1,81 @@
+use ash::vk;
+
+#[derive(Debug)]
+pub enum VulkanError {
+    /// Only without the `linked` feature, the Vulkan loader library couldn't be opened.
+    LoaderNotFound(ash::LoadingError),
+    InstanceCreation(vk::Result),
+    NoSuitableDevice,
+    InstanceExtensionUnsupported(String),
+    DeviceExtensionUnsupported(String),
+    ImageUsageUnsupported(vk::ImageUsageFlags),
+    Allocation(String, gpu_allocator::AllocationError),
+    Vulkan(String, vk::Result),
+    /// `VK_ERROR_DEVICE_LOST`, e.g. after a GPU hang, a driver reset or removal of the GPU.
+    /// Every device-level object is unusable, see `VulkanBase::recreate_device`.
+    DeviceLost(String),
+    Other(String),
+}
+
+impl VulkanError {
+    /// `Vulkan(msg, result)`, or `DeviceLost(msg)` for `ERROR_DEVICE_LOST` so callers can
+    /// tell it apart from errors a retry may fix.
+    pub fn from_result(msg: String, result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_DEVICE_LOST => {
+                log::error!(
+                    "{}: device lost, the GPU hung or its driver was reset, \
+                     device-level objects must be recreated",
+                    msg
+                );
+                VulkanError::DeviceLost(msg)
+            }
+            _ => VulkanError::Vulkan(msg, result),
+        }
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        matches!(self, VulkanError::DeviceLost(_))
+    }
+}
+
+impl std::fmt::Display for VulkanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VulkanError::LoaderNotFound(_) => write!(f, "Vulkan loader not found"),
+            VulkanError::InstanceCreation(_) => write!(f, "failed to create instance"),
+            VulkanError::NoSuitableDevice => write!(f, "failed to find suitable device"),
+            VulkanError::InstanceExtensionUnsupported(name) => {
+                write!(f, "instance extension {:?} is not supported", name)
+            }
+            VulkanError::DeviceExtensionUnsupported(name) => {
+                write!(f, "device extension {:?} is not supported", name)
+            }
+            VulkanError::ImageUsageUnsupported(usage) => {
+                write!(f, "swapchain image usage {:?} is not supported", usage)
+            }
+            VulkanError::Allocation(msg, _) => write!(f, "{}", msg),
+            VulkanError::Vulkan(msg, _) => write!(f, "{}", msg),
+            VulkanError::DeviceLost(msg) => write!(f, "{}: device lost", msg),
+            VulkanError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for VulkanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VulkanError::InstanceCreation(result) | VulkanError::Vulkan(_, result) => Some(result),
+            VulkanError::Allocation(_, err) => Some(err),
+            VulkanError::LoaderNotFound(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// helpers shared with vulkan_utils still report errors as strings
+impl From<String> for VulkanError {
+    fn from(msg: String) -> Self {
+        VulkanError::Other(msg)
+    }
+}
@@ -0,0 +1,253 @@
+use ash::vk;
+use scopeguard::{guard, ScopeGuard};
+
+use crate::{VulkanBase, VulkanError};
+
+/// Per frame in flight acquire semaphores and fences, and per swapchain image present
+/// semaphores.
+///
+/// `begin_frame` waits for the fence of the current frame before acquiring, and `end_frame`
+/// submits, presents and moves on to the next frame, so resources indexed by `frame_index`
+/// are never reused while the GPU still reads them.
+///
+/// The semaphore a present waits on is only free again once the image is acquired again,
+/// which the frame fences don't tell with fewer frames in flight than swapchain images. So
+/// `rendering_finished_semaphores` has one semaphore per image, indexed by the image index,
+/// and has to be recreated by `resize` with the swapchain.
+pub struct FrameSync {
+    pub image_available_semaphores: Vec<vk::Semaphore>,
+    pub rendering_finished_semaphores: Vec<vk::Semaphore>,
+    pub fences: Vec<vk::Fence>,
+    pub frame_index: u32,
+}
+
+impl FrameSync {
+    pub fn new(vulkan_base: &VulkanBase, frame_count: u32) -> Result<Self, VulkanError> {
+        log::info!("creating frame sync");
+
+        let device = &vulkan_base.device;
+
+        let mut frame_sync_sg = guard(
+            FrameSync {
+                image_available_semaphores: Vec::with_capacity(frame_count as usize),
+                rendering_finished_semaphores: Vec::new(),
+                fences: Vec::with_capacity(frame_count as usize),
+                frame_index: 0,
+            },
+            |frame_sync| {
+                log::warn!("frame sync scopeguard");
+                frame_sync.clean(device);
+            },
+        );
+
+        let fence_create_info = vk::FenceCreateInfo::builder()
+            .flags(vk::FenceCreateFlags::SIGNALED)
+            .build();
+
+        for i in 0..frame_count {
+            let image_available_semaphore = vulkan_utils::create_semaphore(
+                device,
+                vulkan_base.debug_utils_loader.as_ref(),
+                &format!("image available semaphore {}", i),
+            )?;
+            frame_sync_sg
+                .image_available_semaphores
+                .push(image_available_semaphore);
+
+            let fence = unsafe {
+                device
+                    .create_fence(&fence_create_info, None)
+                    .map_err(|err| {
+                        VulkanError::Vulkan(format!("failed to create fence {}", i), err)
+                    })?
+            };
+            frame_sync_sg.fences.push(fence);
+
+            vulkan_utils::set_debug_utils_object_name(
+                vulkan_base.debug_utils_loader.as_ref(),
+                device.handle(),
+                fence,
+                &format!("fence {}", i),
+            );
+        }
+
+        frame_sync_sg.rendering_finished_semaphores =
+            create_rendering_finished_semaphores(vulkan_base)?;
+
+        log::info!("frame sync created");
+
+        Ok(ScopeGuard::into_inner(frame_sync_sg))
+    }
+
+    /// Recreates the rendering finished semaphores for the images of the new swapchain, call
+    /// it after `VulkanBase::resize`. Waits for the device to be idle first, the old
+    /// semaphores may still be waited on.
+    pub fn resize(&mut self, vulkan_base: &VulkanBase) -> Result<(), VulkanError> {
+        unsafe {
+            vulkan_base.device.device_wait_idle().map_err(|err| {
+                VulkanError::from_result(String::from("failed to wait for device idle"), err)
+            })?;
+        }
+
+        let rendering_finished_semaphores = create_rendering_finished_semaphores(vulkan_base)?;
+
+        let old_semaphores = std::mem::replace(
+            &mut self.rendering_finished_semaphores,
+            rendering_finished_semaphores,
+        );
+        destroy_semaphores(&vulkan_base.device, old_semaphores);
+
+        Ok(())
+    }
+
+    /// Waits for the current frame and acquires a swapchain image. Returns `None` when the
+    /// swapchain is out of date and has to be recreated before rendering, or when
+    /// `vulkan_base` is paused.
+    pub fn begin_frame(&mut self, vulkan_base: &VulkanBase) -> Result<Option<u32>, VulkanError> {
+        if vulkan_base.target.paused {
+            return Ok(None);
+        }
+
+        let fence = self.fences[self.frame_index as usize];
+
+        unsafe {
+            vulkan_base
+                .device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .map_err(|err| {
+                    VulkanError::from_result(
+                        format!("failed to wait for frame fence {}", self.frame_index),
+                        err,
+                    )
+                })?;
+        }
+
+        let image_available_semaphore = self.image_available_semaphores[self.frame_index as usize];
+        let image_index = match vulkan_base
+            .target
+            .acquire_next_image(vulkan_base, image_available_semaphore)?
+        {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        // the fence is reset only after acquiring, so an early return never leaves it unsignaled
+        unsafe {
+            vulkan_base.device.reset_fences(&[fence]).map_err(|err| {
+                VulkanError::from_result(
+                    format!("failed to reset frame fence {}", self.frame_index),
+                    err,
+                )
+            })?;
+        }
+
+        Ok(Some(image_index))
+    }
+
+    /// Submits the frame's command buffer and presents the image. Returns `false` when the
+    /// swapchain is suboptimal or out of date and should be recreated.
+    pub fn end_frame(
+        &mut self,
+        vulkan_base: &VulkanBase,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+    ) -> Result<bool, VulkanError> {
+        self.submit(vulkan_base, command_buffer, image_index)?;
+        self.present(vulkan_base, image_index)
+    }
+
+    /// First half of `end_frame`, work recorded between `submit` and `present` still sees the
+    /// image before the presentation engine owns it.
+    pub fn submit(
+        &mut self,
+        vulkan_base: &VulkanBase,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+    ) -> Result<(), VulkanError> {
+        let frame_index = self.frame_index as usize;
+
+        let wait_semaphores = [self.image_available_semaphores[frame_index]];
+        let masks = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let cmd_buffers = [command_buffer];
+        let signal_semaphores = [self.rendering_finished_semaphores[image_index as usize]];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&masks)
+            .command_buffers(&cmd_buffers)
+            .signal_semaphores(&signal_semaphores)
+            .build();
+
+        unsafe {
+            vulkan_base
+                .device
+                .queue_submit(vulkan_base.queue, &[submit_info], self.fences[frame_index])
+                .map_err(|err| {
+                    VulkanError::from_result(
+                        String::from("failed to submit graphics command buffer"),
+                        err,
+                    )
+                })?
+        }
+
+        Ok(())
+    }
+
+    /// Second half of `end_frame`, presents the image and moves on to the next frame.
+    pub fn present(
+        &mut self,
+        vulkan_base: &VulkanBase,
+        image_index: u32,
+    ) -> Result<bool, VulkanError> {
+        let semaphore = self.rendering_finished_semaphores[image_index as usize];
+
+        self.frame_index = (self.frame_index + 1) % self.fences.len() as u32;
+
+        vulkan_base
+            .target
+            .present(vulkan_base, semaphore, image_index)
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        log::info!("cleaning frame sync");
+
+        destroy_semaphores(device, self.image_available_semaphores);
+        destroy_semaphores(device, self.rendering_finished_semaphores);
+
+        unsafe {
+            for fence in self.fences {
+                device.destroy_fence(fence, None);
+            }
+        }
+    }
+}
+
+// one per swapchain image, none while paused
+pub(crate) fn create_rendering_finished_semaphores(
+    vulkan_base: &VulkanBase,
+) -> Result<Vec<vk::Semaphore>, VulkanError> {
+    let device = &vulkan_base.device;
+
+    let mut semaphores_sg = guard(Vec::new(), |semaphores| {
+        log::warn!("rendering finished semaphores scopeguard");
+        destroy_semaphores(device, semaphores);
+    });
+
+    for i in 0..vulkan_base.image_count() {
+        let semaphore = vulkan_utils::create_semaphore(
+            device,
+            vulkan_base.debug_utils_loader.as_ref(),
+            &format!("rendering finished semaphore {}", i),
+        )?;
+        semaphores_sg.push(semaphore);
+    }
+
+    Ok(ScopeGuard::into_inner(semaphores_sg))
+}
+
+pub(crate) fn destroy_semaphores(device: &ash::Device, semaphores: Vec<vk::Semaphore>) {
+    unsafe {
+        for semaphore in semaphores {
+            device.destroy_semaphore(semaphore, None);
+        }
+    }
+}
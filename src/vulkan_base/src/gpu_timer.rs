@@ -0,0 +1,160 @@
+use ash::vk;
+
+use crate::{VulkanBase, VulkanError};
+
+/// Measures the GPU time between `begin` and `end` with a pair of timestamp queries per frame
+/// in flight.
+///
+/// Results are read when a frame index comes around again, after its fence was waited on, so
+/// `last_duration_ns` lags `frame_count` frames behind and never stalls.
+pub struct GpuTimer {
+    pub query_pool: vk::QueryPool,
+    timestamp_period: f32,
+    valid_bits_mask: u64,
+    written: Vec<bool>,
+    last_duration_ns: Option<u64>,
+}
+
+impl GpuTimer {
+    /// Fails when the queue family of `vulkan_base.queue` doesn't support timestamps.
+    pub fn new(vulkan_base: &VulkanBase, frame_count: u32) -> Result<Self, VulkanError> {
+        log::info!("creating gpu timer");
+
+        let queue_family_props = unsafe {
+            vulkan_base
+                .instance
+                .get_physical_device_queue_family_properties(vulkan_base.physical_device)
+        };
+
+        let valid_bits = queue_family_props[vulkan_base.queue_family as usize].timestamp_valid_bits;
+
+        if valid_bits == 0 {
+            return Err(VulkanError::Other(format!(
+                "queue family {} does not support timestamps",
+                vulkan_base.queue_family
+            )));
+        }
+
+        let valid_bits_mask = match valid_bits {
+            64 => u64::MAX,
+            bits => (1u64 << bits) - 1,
+        };
+
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2 * frame_count)
+            .build();
+
+        let query_pool = unsafe {
+            vulkan_base
+                .device
+                .create_query_pool(&create_info, None)
+                .map_err(|err| {
+                    VulkanError::Vulkan(String::from("failed to create timestamp query pool"), err)
+                })?
+        };
+
+        vulkan_utils::set_debug_utils_object_name(
+            vulkan_base.debug_utils_loader.as_ref(),
+            vulkan_base.device.handle(),
+            query_pool,
+            "timestamp query pool",
+        );
+
+        log::info!("gpu timer created");
+
+        Ok(GpuTimer {
+            query_pool,
+            timestamp_period: vulkan_base
+                .physical_device_properties
+                .limits
+                .timestamp_period,
+            valid_bits_mask,
+            written: vec![false; frame_count as usize],
+            last_duration_ns: None,
+        })
+    }
+
+    /// Reads the previous result of `frame_index` and records the start timestamp. Record it
+    /// outside a render pass, after the frame's fence was waited on.
+    pub fn begin(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: u32,
+    ) {
+        let first_query = 2 * frame_index;
+
+        if self.written[frame_index as usize] {
+            let mut timestamps = [0u64; 2];
+
+            match unsafe {
+                device.get_query_pool_results(
+                    self.query_pool,
+                    first_query,
+                    2,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            } {
+                Ok(_) => {
+                    let begin = timestamps[0] & self.valid_bits_mask;
+                    let end = timestamps[1] & self.valid_bits_mask;
+                    let ticks = end.wrapping_sub(begin) & self.valid_bits_mask;
+
+                    self.last_duration_ns =
+                        Some((ticks as f64 * self.timestamp_period as f64) as u64);
+                }
+                Err(vk::Result::NOT_READY) => (),
+                Err(err) => log::warn!("failed to get timestamp query results: {}", err),
+            }
+        }
+
+        unsafe {
+            device.cmd_reset_query_pool(command_buffer, self.query_pool, first_query, 2);
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                first_query,
+            );
+        }
+    }
+
+    /// Records the end timestamp of `frame_index`.
+    pub fn end(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: u32,
+    ) {
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                2 * frame_index + 1,
+            );
+        }
+
+        self.written[frame_index as usize] = true;
+    }
+
+    /// GPU time of the last frame whose results were read, `None` until the first one.
+    pub fn last_duration_ns(&self) -> Option<u64> {
+        self.last_duration_ns
+    }
+
+    pub fn last_duration_ms(&self) -> Option<f32> {
+        self.last_duration_ns
+            .map(|duration| duration as f32 / 1_000_000.0)
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        log::info!("cleaning gpu timer");
+
+        unsafe {
+            device.destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
@@ -1,47 +1,288 @@
+mod barrier;
+mod builder;
+mod capture;
+mod depth_readback;
+mod error;
+mod frame_sync;
+mod gpu_timer;
+mod pipeline_cache;
+mod render_target;
+mod timeline_sync;
 mod vulkan_base;
+mod window_backend;
 
 use vulkan_base::*;
 
+pub use barrier::{cmd_image_barrier, ImageBarrier};
+pub use builder::VulkanBaseBuilder;
+pub use error::VulkanError;
+pub use frame_sync::FrameSync;
+pub use gpu_timer::GpuTimer;
+pub use pipeline_cache::PipelineCache;
+pub use render_target::RenderTarget;
+pub use timeline_sync::TimelineSync;
+pub use vulkan_base::ValidationErrorCapture;
+pub use window_backend::{get_required_instance_extensions, WindowBackend};
+
 use ash::extensions::khr;
 use ash::vk;
 use scopeguard::{guard, ScopeGuard};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DevicePreference {
+    DiscreteFirst,
+    IntegratedFirst,
+    HighestMemory,
+    /// case-insensitive substring match against the device name
+    ByName(String),
+}
+
+/// A queue family of the physical device, see `VulkanBase::describe_queue_families`.
+#[derive(Clone, Debug)]
+pub struct QueueFamilyInfo {
+    pub index: u32,
+    pub queue_flags: vk::QueueFlags,
+    pub queue_count: u32,
+    /// Always false for a headless `VulkanBase`.
+    pub present_supported: bool,
+    /// 0 when the family doesn't support timestamps.
+    pub timestamp_valid_bits: u32,
+}
+
+#[derive(Clone)]
+pub struct VulkanBaseConfig {
+    pub enable_validation: bool,
+    pub required_features: vk::PhysicalDeviceFeatures,
+    /// Enabled in addition to `required_features` when the selected device supports them,
+    /// without affecting device selection. See `VulkanBase::enabled_features`.
+    pub optional_features: vk::PhysicalDeviceFeatures,
+    /// Overridden by the `LYNX_GPU_INDEX` and `LYNX_GPU_NAME` env vars, see
+    /// `get_physical_device`.
+    pub device_preference: DevicePreference,
+    /// Prefer an `*_SRGB` swapchain format so the hardware converts linear shader output to
+    /// sRGB on write. Clear colors and fragment outputs are then interpreted as linear values.
+    pub prefer_srgb: bool,
+    pub debug_message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    /// Capabilities the main queue family must have. Compute-only users can pass `COMPUTE`
+    /// so devices without a graphics queue are still accepted.
+    pub queue_flags: vk::QueueFlags,
+    /// Create the swapchain with `TRANSFER_SRC` usage so `VulkanBase::capture_frame` can read
+    /// swapchain images back.
+    pub enable_capture: bool,
+    /// Create the depth buffer with `TRANSFER_SRC` usage, and pick a depth format supporting
+    /// it, so `VulkanBase::read_depth_pixel` can read depth values back.
+    pub enable_depth_readback: bool,
+    /// Swapchain image usage added to `COLOR_ATTACHMENT`, e.g. `STORAGE` for compute
+    /// post-processing. Each flag must be supported by the surface.
+    pub extra_usage: vk::ImageUsageFlags,
+    /// Passed to the allocator as is, only leaks are logged by default. `LYNX_ALLOC_VERBOSE=1`
+    /// additionally logs the memory heaps and every allocation and free.
+    pub allocator_debug_settings: gpu_allocator::AllocatorDebugSettings,
+    /// Present modes to try in order, FIFO is used when none of them is supported. The default
+    /// favors latency: MAILBOX, then IMMEDIATE which tears, then FIFO_RELAXED. Put
+    /// FIFO_RELAXED first to save power, it waits for vblank like FIFO and only tears when a
+    /// frame misses it.
+    pub preferred_present_modes: Vec<vk::PresentModeKHR>,
+    /// Enable `VK_KHR_dynamic_rendering` so rendering can begin without a render pass and
+    /// framebuffers. Devices without the feature are skipped.
+    pub enable_dynamic_rendering: bool,
+    /// Enable `VK_KHR_synchronization2`, required by `cmd_image_barrier`. Devices without the
+    /// `synchronization2` feature are skipped.
+    pub enable_synchronization2: bool,
+    /// Enable the core 1.2 `timelineSemaphore` feature, required by `TimelineSync`. Devices
+    /// without it are skipped.
+    pub enable_timeline_semaphore: bool,
+    /// Composite alpha modes to try in order, creation fails when the surface supports none of
+    /// them. Put `PRE_MULTIPLIED` first for a transparent window.
+    pub preferred_composite_alphas: Vec<vk::CompositeAlphaFlagsKHR>,
+    /// Passed in `vk::ApplicationInfo`, drivers and tools like RenderDoc key profiles off it.
+    pub application_name: String,
+    /// Made with `vk::make_api_version`.
+    pub application_version: u32,
+    pub engine_name: String,
+    pub engine_version: u32,
+    /// Instance extensions enabled only when the loader supports them, a missing one is
+    /// logged, see `VulkanBase::enabled_instance_extensions`.
+    pub optional_instance_extensions: Vec<&'static std::ffi::CStr>,
+    /// Pick a combined depth/stencil format. Otherwise a depth-only format like `D32_SFLOAT`
+    /// is preferred, see `vulkan_utils::get_depth_aspect_mask` for the matching aspects.
+    pub needs_stencil: bool,
+    /// Swapchain images to request, clamped to what the surface supports. 2 lowers latency,
+    /// 3 or more smooths presentation, see `VulkanBase::image_count` for the created count.
+    pub desired_image_count: u32,
+    /// HDR color spaces to try in order, e.g. `HDR10_ST2084_EXT` or `EXTENDED_SRGB_LINEAR_EXT`.
+    /// Requests `VK_EXT_swapchain_colorspace` and falls back to the SDR format when neither the
+    /// extension nor any of the color spaces is supported, see `VulkanBase::is_hdr`.
+    pub preferred_hdr_color_spaces: Vec<vk::ColorSpaceKHR>,
+    /// Also reports the debug messenger's `ERROR` messages to the capture, see
+    /// `ValidationErrorCapture::install`.
+    pub validation_error_capture: Option<ValidationErrorCapture>,
+}
+
+impl Default for VulkanBaseConfig {
+    fn default() -> Self {
+        Self {
+            enable_validation: cfg!(debug_assertions),
+            required_features: vk::PhysicalDeviceFeatures::default(),
+            optional_features: vk::PhysicalDeviceFeatures::default(),
+            device_preference: DevicePreference::DiscreteFirst,
+            prefer_srgb: false,
+            debug_message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            queue_flags: vk::QueueFlags::GRAPHICS,
+            enable_capture: false,
+            enable_depth_readback: false,
+            extra_usage: vk::ImageUsageFlags::empty(),
+            allocator_debug_settings: gpu_allocator::AllocatorDebugSettings {
+                log_memory_information: false,
+                log_leaks_on_shutdown: true,
+                store_stack_traces: false,
+                log_allocations: false,
+                log_frees: false,
+                log_stack_traces: false,
+            },
+            preferred_present_modes: vec![
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::FIFO_RELAXED,
+            ],
+            enable_dynamic_rendering: false,
+            enable_synchronization2: false,
+            enable_timeline_semaphore: false,
+            // some Wayland and Android compositors don't support OPAQUE
+            preferred_composite_alphas: vec![
+                vk::CompositeAlphaFlagsKHR::OPAQUE,
+                vk::CompositeAlphaFlagsKHR::INHERIT,
+                vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+                vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+            ],
+            application_name: String::from("LynxVk"),
+            application_version: 0,
+            engine_name: String::from("LynxVk"),
+            engine_version: vk::make_api_version(0, 0, 1, 0),
+            optional_instance_extensions: vec![ash::extensions::ext::DebugUtils::name()],
+            needs_stencil: false,
+            desired_image_count: 3,
+            preferred_hdr_color_spaces: Vec::new(),
+            validation_error_capture: None,
+        }
+    }
+}
+
+impl VulkanBaseConfig {
+    fn extension_features(&self) -> ExtensionFeatures {
+        ExtensionFeatures {
+            dynamic_rendering: self.enable_dynamic_rendering,
+            synchronization2: self.enable_synchronization2,
+            timeline_semaphore: self.enable_timeline_semaphore,
+            // enabled by VulkanBase itself when the device supports it
+            swapchain_maintenance1: false,
+        }
+    }
+}
+
 pub struct VulkanBase {
     pub entry: ash::Entry,
     pub instance: ash::Instance,
     pub surface_loader: khr::Surface,
     pub swapchain_loader: khr::Swapchain,
-    pub debug_utils_loader: ash::extensions::ext::DebugUtils,
-    pub surface: vk::SurfaceKHR,
+    /// Required and available optional extensions the instance was created with.
+    pub enabled_instance_extensions: Vec<std::ffi::CString>,
+    /// `None` when `VK_EXT_debug_utils` is not among the instance extensions.
+    pub debug_utils_loader: Option<ash::extensions::ext::DebugUtils>,
+    /// Set when `VulkanBaseConfig::enable_dynamic_rendering` is.
+    pub dynamic_rendering_loader: Option<khr::DynamicRendering>,
+    /// Set when `VulkanBaseConfig::enable_synchronization2` is.
+    pub synchronization2_loader: Option<khr::Synchronization2>,
+    /// Set when `VK_EXT_surface_maintenance1` and `VK_EXT_swapchain_maintenance1` are both
+    /// enabled, i.e. when `set_present_mode` can avoid recreating the swapchain.
+    pub surface_capabilities2_loader: Option<khr::GetSurfaceCapabilities2>,
+    pub debug_messenger: vk::DebugUtilsMessengerEXT,
+    /// Whether `VK_LAYER_KHRONOS_validation` is enabled, i.e. `enable_validation` or the
+    /// `VK_LYNX_VALIDATION` env var requested it and it's installed.
+    pub validation_layer_enabled: bool,
+    // referenced by the debug messenger, dropped after it in clean
+    validation_error_capture: Option<ValidationErrorCapture>,
     pub physical_device: vk::PhysicalDevice,
     pub physical_device_properties: vk::PhysicalDeviceProperties,
-    pub surface_format: vk::SurfaceFormatKHR,
-    pub present_mode: vk::PresentModeKHR,
+    /// `VulkanBaseConfig::required_features` plus the supported optional features.
+    pub enabled_features: vk::PhysicalDeviceFeatures,
+    pub swapchain_image_usage: vk::ImageUsageFlags,
+    pub desired_image_count: u32,
     pub depth_format: vk::Format,
+    pub depth_image_usage: vk::ImageUsageFlags,
     pub queue_family: u32,
-    pub device: ash::Device,
+    pub transfer_queue_family: Option<u32>,
+    /// Shared with `vulkan_utils::OwnedMemBuffer` and `OwnedMemImage`.
+    pub device: std::sync::Arc<ash::Device>,
     pub queue: vk::Queue,
-    pub allocator: gpu_allocator::vulkan::Allocator,
-    pub surface_capabilities: vk::SurfaceCapabilitiesKHR,
-    pub surface_extent: vk::Extent2D,
-    pub swapchain: vk::SwapchainKHR,
-    pub swapchain_images: Vec<vk::Image>,
-    pub swapchain_image_views: Vec<vk::ImageView>,
-    pub depth_buffer_mem_image: vulkan_utils::MemImage,
+    pub transfer_queue: Option<vk::Queue>,
+    /// Dropped by `clean` before the device is destroyed, so every `OwnedMemBuffer` and
+    /// `OwnedMemImage` holding a clone has to be dropped first.
+    pub allocator: vulkan_utils::SharedAllocator,
+    /// Whether `VK_EXT_memory_budget` is enabled, see `memory_budget`.
+    pub memory_budget_supported: bool,
+    /// Device local, host visible memory types of a resizable BAR heap, 0 without one. Pass to
+    /// `vulkan_utils::create_buffer_with_memory_types` for small buffers written every frame.
+    pub rebar_memory_type_bits: u32,
+    /// Surface, swapchain and depth buffer of the window `VulkanBase` was created for, the
+    /// surface and swapchain are null when headless.
+    pub target: RenderTarget,
+    /// Live `RenderTarget`s, each has to be cleaned before `clean`.
+    render_target_count: usize,
 }
 
 impl VulkanBase {
-    pub fn new<'a, 'b>(
-        window: &winit::window::Window,
+    pub fn new<'a, W: WindowBackend>(
+        window: &W,
+        required_instance_extensions: &Vec<&'a std::ffi::CStr>,
+        required_device_extensions: &Vec<&'a std::ffi::CStr>,
+        config: &VulkanBaseConfig,
+    ) -> Result<Self, VulkanError> {
+        VulkanBaseBuilder::new()
+            .instance_extensions(required_instance_extensions)
+            .device_extensions(required_device_extensions)
+            .config(config.clone())
+            .build(window)
+    }
+
+    /// Creates a surface and swapchain for `window`, without a window the instance and device
+    /// are created headless, see `new_headless`.
+    fn create<'a, 'b, W: WindowBackend + ?Sized>(
+        window: Option<&W>,
         required_instance_extensions: &Vec<&'a std::ffi::CStr>,
         required_device_extensions: &Vec<&'b std::ffi::CStr>,
-    ) -> Result<Self, String> {
-        let entry = create_entry();
+        config: &VulkanBaseConfig,
+    ) -> Result<Self, VulkanError> {
+        let mut required_device_extensions =
+            get_device_extensions(required_device_extensions, config.extension_features());
+
+        let entry = create_entry()?;
         check_instance_version(&entry)?;
         check_required_instance_extensions(&entry, required_instance_extensions)?;
+        let mut optional_instance_extensions = config.optional_instance_extensions.clone();
+        if window.is_some() && !config.preferred_hdr_color_spaces.is_empty() {
+            optional_instance_extensions.push(vk::ExtSwapchainColorspaceFn::name());
+        }
+        let required_instance_extensions = &get_instance_extensions(
+            &entry,
+            required_instance_extensions,
+            &optional_instance_extensions,
+        );
+
+        let validation_layer_enabled =
+            is_validation_layer_enabled(&entry, config.enable_validation);
 
         let instance_sg = {
-            let instance = create_instance(&entry, required_instance_extensions)?;
+            let instance = create_instance(
+                &entry,
+                required_instance_extensions,
+                validation_layer_enabled,
+                &config.application_name,
+                config.application_version,
+                &config.engine_name,
+                config.engine_version,
+            )?;
             guard(instance, |instance| {
                 log::warn!("instance scopeguard");
                 unsafe {
@@ -50,11 +291,36 @@ impl VulkanBase {
             })
         };
 
-        let debug_utils_loader = create_debug_utils_loader(&entry, &instance_sg);
+        let debug_utils_loader =
+            create_debug_utils_loader(&entry, &instance_sg, required_instance_extensions);
+
+        let debug_messenger_sg = {
+            let debug_messenger = match &debug_utils_loader {
+                Some(debug_utils_loader) => create_debug_messenger(
+                    debug_utils_loader,
+                    config.debug_message_severity,
+                    config.validation_error_capture.as_ref(),
+                )?,
+                None => vk::DebugUtilsMessengerEXT::null(),
+            };
+            guard(debug_messenger, |debug_messenger| {
+                log::warn!("debug messenger scopeguard");
+                if let Some(debug_utils_loader) = &debug_utils_loader {
+                    unsafe {
+                        debug_utils_loader.destroy_debug_utils_messenger(debug_messenger, None);
+                    }
+                }
+            })
+        };
+
         let surface_loader = create_surface_loader(&entry, &instance_sg);
 
+        // headless instances keep a null surface, destroying it is a no-op
         let surface_sg = {
-            let surface = create_surface(&entry, &instance_sg, window)?;
+            let surface = match window {
+                Some(window) => create_surface(&entry, &instance_sg, window)?,
+                None => vk::SurfaceKHR::null(),
+            };
             guard(surface, |surface| {
                 log::warn!("surface scopeguard");
                 unsafe {
@@ -63,21 +329,105 @@ impl VulkanBase {
             })
         };
 
-        let physical_device = get_physical_device(&instance_sg, &required_device_extensions)?;
+        let physical_device = get_physical_device(
+            &instance_sg,
+            &required_device_extensions,
+            &config.required_features,
+            &config.device_preference,
+            config.queue_flags,
+            config.extension_features(),
+        )?;
         let physical_device_properties =
             get_physical_device_properties(&instance_sg, physical_device);
-        let surface_format = get_surface_format(physical_device, &surface_loader, *surface_sg)?;
-        let present_mode = get_present_mode(physical_device, &surface_loader, *surface_sg)?;
-        let queue_family =
-            get_queue_family(&instance_sg, physical_device, &surface_loader, *surface_sg)?;
-        let depth_format = get_depth_format(&instance_sg, physical_device)?;
+        let enabled_features = get_enabled_features(
+            &instance_sg,
+            physical_device,
+            &config.required_features,
+            &config.optional_features,
+        );
+        let rebar_memory_type_bits = get_rebar_memory_type_bits(&instance_sg, physical_device);
+        let memory_budget_supported = add_optional_device_extension(
+            &instance_sg,
+            physical_device,
+            &mut required_device_extensions,
+            vk::ExtMemoryBudgetFn::name(),
+        );
+        let present_mode_switching_supported = window.is_some()
+            && required_instance_extensions.contains(&vk::ExtSurfaceMaintenance1Fn::name())
+            && add_optional_device_extension(
+                &instance_sg,
+                physical_device,
+                &mut required_device_extensions,
+                vk::ExtSwapchainMaintenance1Fn::name(),
+            );
+        let (surface_format, present_mode, composite_alpha) = match window {
+            Some(_) => {
+                // HDR color spaces are only reported with VK_EXT_swapchain_colorspace
+                let hdr_color_spaces: &[vk::ColorSpaceKHR] = match required_instance_extensions
+                    .contains(&vk::ExtSwapchainColorspaceFn::name())
+                {
+                    true => &config.preferred_hdr_color_spaces,
+                    false => &[],
+                };
+                let surface_format = get_surface_format(
+                    physical_device,
+                    &surface_loader,
+                    *surface_sg,
+                    config.prefer_srgb,
+                    hdr_color_spaces,
+                )?;
+                let present_mode = get_present_mode(
+                    physical_device,
+                    &surface_loader,
+                    *surface_sg,
+                    &config.preferred_present_modes,
+                )?;
+                let composite_alpha = get_composite_alpha(
+                    &get_surface_capabilities(&surface_loader, physical_device, *surface_sg)?,
+                    &config.preferred_composite_alphas,
+                )?;
+                (surface_format, present_mode, composite_alpha)
+            }
+            None => (
+                vk::SurfaceFormatKHR::default(),
+                vk::PresentModeKHR::FIFO,
+                vk::CompositeAlphaFlagsKHR::OPAQUE,
+            ),
+        };
+        log_queue_family_infos(&get_queue_family_infos(
+            &instance_sg,
+            physical_device,
+            &surface_loader,
+            *surface_sg,
+        ));
+        let queue_family = find_graphics_queue(
+            &instance_sg,
+            physical_device,
+            &surface_loader,
+            *surface_sg,
+            config.queue_flags,
+            window.is_some(),
+        )?;
+        let transfer_queue_family = get_transfer_queue_family(&instance_sg, physical_device);
+        let depth_format = get_depth_format(
+            &instance_sg,
+            physical_device,
+            config.needs_stencil,
+            config.enable_depth_readback,
+        )?;
 
         let device_sg = {
             let device = create_logical_device(
                 &instance_sg,
                 physical_device,
                 queue_family,
+                transfer_queue_family,
                 &required_device_extensions,
+                &enabled_features,
+                ExtensionFeatures {
+                    swapchain_maintenance1: present_mode_switching_supported,
+                    ..config.extension_features()
+                },
             )?;
             guard(device, |device| {
                 log::warn!("device scopeguard");
@@ -88,146 +438,562 @@ impl VulkanBase {
         };
 
         let queue = get_queue(&device_sg, queue_family);
+        let transfer_queue = transfer_queue_family.map(|family| get_queue(&device_sg, family));
 
-        let mut allocator = create_allocator(&instance_sg, &device_sg, physical_device)?;
-
-        let swapchain_loader = create_swapchain_loader(&instance_sg, &device_sg);
-
-        let resize_data = resize_internal(
-            window,
+        let mut allocator = create_allocator(
+            &instance_sg,
             &device_sg,
-            &surface_loader,
-            &swapchain_loader,
             physical_device,
-            vk::SwapchainKHR::null(),
-            *surface_sg,
-            &surface_format,
-            present_mode,
-            &vec![],
-            depth_format,
-            &mut allocator,
-            None,
+            config.allocator_debug_settings,
         )?;
 
-        let swapchain_sg = {
-            guard(resize_data.swapchain, |swapchain| {
-                log::warn!("swapchain scopeguard");
-                unsafe {
-                    swapchain_loader.destroy_swapchain(swapchain, None);
-                }
-            })
+        let swapchain_loader = create_swapchain_loader(&instance_sg, &device_sg);
+        let dynamic_rendering_loader = match config.enable_dynamic_rendering {
+            true => Some(khr::DynamicRendering::new(&instance_sg, &device_sg)),
+            false => None,
+        };
+        let synchronization2_loader = match config.enable_synchronization2 {
+            true => Some(khr::Synchronization2::new(&instance_sg, &device_sg)),
+            false => None,
+        };
+        let surface_capabilities2_loader = match present_mode_switching_supported {
+            true => Some(khr::GetSurfaceCapabilities2::new(&entry, &instance_sg)),
+            false => None,
         };
 
-        let swapchain_image_views_sg = {
-            guard(resize_data.swapchain_image_views, |image_views| {
-                log::warn!("swapchain image views scopeguard");
-                for view in image_views {
-                    unsafe {
-                        device_sg.destroy_image_view(view, None);
-                    }
+        // headless instances have no swapchain or depth buffer for the usages to apply to
+        let (swapchain_image_usage, depth_image_usage) = match window {
+            Some(_) => {
+                let mut swapchain_image_usage =
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | config.extra_usage;
+                if config.enable_capture {
+                    swapchain_image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
                 }
-            })
+
+                let mut depth_image_usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+                if config.enable_depth_readback {
+                    depth_image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+                }
+
+                (swapchain_image_usage, depth_image_usage)
+            }
+            None => (vk::ImageUsageFlags::empty(), vk::ImageUsageFlags::empty()),
+        };
+
+        let resize_data = match window {
+            Some(window) => resize_internal(
+                window,
+                &device_sg,
+                &surface_loader,
+                surface_capabilities2_loader.as_ref(),
+                &swapchain_loader,
+                physical_device,
+                vk::SwapchainKHR::null(),
+                *surface_sg,
+                &surface_format,
+                present_mode,
+                composite_alpha,
+                swapchain_image_usage,
+                config.desired_image_count,
+                queue_family,
+                &vec![],
+                depth_format,
+                depth_image_usage,
+                &mut allocator,
+                None,
+            )?
+            .ok_or_else(|| VulkanError::Other(String::from("window has a zero size")))?,
+            None => ResizeResult::default(),
         };
 
         Ok(VulkanBase {
             entry,
             instance: ScopeGuard::into_inner(instance_sg),
-            surface: ScopeGuard::into_inner(surface_sg),
-            surface_loader,
+            enabled_instance_extensions: required_instance_extensions
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect(),
+            debug_messenger: ScopeGuard::into_inner(debug_messenger_sg),
+            validation_layer_enabled,
+            validation_error_capture: config.validation_error_capture.clone(),
             debug_utils_loader,
+            dynamic_rendering_loader,
+            synchronization2_loader,
+            surface_capabilities2_loader,
             physical_device,
             physical_device_properties,
-            surface_format,
-            present_mode,
+            enabled_features,
+            swapchain_image_usage,
+            desired_image_count: config.desired_image_count,
             depth_format,
+            depth_image_usage,
             queue_family,
+            transfer_queue_family,
             queue,
-            allocator,
-            surface_capabilities: resize_data.surface_capabilities,
-            surface_extent: resize_data.surface_extent,
-            swapchain: ScopeGuard::into_inner(swapchain_sg),
-            swapchain_images: resize_data.swapchain_images,
-            swapchain_image_views: ScopeGuard::into_inner(swapchain_image_views_sg),
+            transfer_queue,
+            allocator: std::sync::Arc::new(std::sync::Mutex::new(allocator)),
+            memory_budget_supported,
+            rebar_memory_type_bits,
+            target: RenderTarget {
+                surface: ScopeGuard::into_inner(surface_sg),
+                surface_format,
+                present_mode,
+                compatible_present_modes: resize_data.compatible_present_modes,
+                composite_alpha,
+                surface_capabilities: resize_data.surface_capabilities,
+                surface_extent: resize_data.surface_extent,
+                swapchain: resize_data.swapchain,
+                swapchain_images: resize_data.swapchain_images,
+                swapchain_image_views: resize_data.swapchain_image_views,
+                depth_buffer_mem_image: resize_data.depth_buffer_mem_image,
+                paused: false,
+            },
+            surface_loader,
             swapchain_loader,
-            device: ScopeGuard::into_inner(device_sg),
-            depth_buffer_mem_image: resize_data.depth_buffer_mem_image,
+            device: std::sync::Arc::new(ScopeGuard::into_inner(device_sg)),
+            render_target_count: 0,
         })
     }
 
-    pub fn resize(&mut self, window: &winit::window::Window) -> Result<(), String> {
-        let old_depth_buffer_mem_image = std::mem::take(&mut self.depth_buffer_mem_image);
-        let resize_data = resize_internal(
-            window,
-            &self.device,
+    /// Creates an instance and device without a surface, e.g. for CI or offscreen rendering.
+    /// Surface and swapchain handles are null and `swapchain_images` is empty, rendering targets
+    /// user-created images instead.
+    pub fn new_headless<'a, 'b>(
+        required_instance_extensions: &Vec<&'a std::ffi::CStr>,
+        required_device_extensions: &Vec<&'b std::ffi::CStr>,
+        config: &VulkanBaseConfig,
+    ) -> Result<Self, VulkanError> {
+        Self::create::<dyn WindowBackend>(
+            None,
+            required_instance_extensions,
+            required_device_extensions,
+            config,
+        )
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.target.surface == vk::SurfaceKHR::null()
+    }
+
+    /// Images in the current swapchain, which may differ from
+    /// `VulkanBaseConfig::desired_image_count`. 0 when headless or paused.
+    pub fn image_count(&self) -> u32 {
+        self.target.image_count()
+    }
+
+    /// Whether the swapchain uses an HDR color space from
+    /// `VulkanBaseConfig::preferred_hdr_color_spaces`, shaders then have to output values
+    /// encoded for `surface_format.color_space` instead of sRGB.
+    pub fn is_hdr(&self) -> bool {
+        self.target.surface_format.color_space != vk::ColorSpaceKHR::SRGB_NONLINEAR
+    }
+
+    /// Whether `name` was required or an available optional instance extension.
+    pub fn is_instance_extension_enabled(&self, name: &std::ffi::CStr) -> bool {
+        self.enabled_instance_extensions
+            .iter()
+            .any(|enabled| enabled.as_c_str() == name)
+    }
+
+    /// Every queue family of the physical device, not only the selected ones. Useful when a
+    /// dedicated compute or transfer family isn't picked up as expected.
+    pub fn describe_queue_families(&self) -> Vec<QueueFamilyInfo> {
+        get_queue_family_infos(
+            &self.instance,
+            self.physical_device,
             &self.surface_loader,
-            &self.swapchain_loader,
+            self.target.surface,
+        )
+    }
+
+    /// Returns the queue family and queue used for uploads. Falls back to the graphics queue
+    /// when the device has no dedicated transfer queue family.
+    pub fn get_transfer_queue(&self) -> (u32, vk::Queue) {
+        match (self.transfer_queue_family, self.transfer_queue) {
+            (Some(family), Some(queue)) => (family, queue),
+            _ => (self.queue_family, self.queue),
+        }
+    }
+
+    /// Rotation the swapchain images are presented with, see `pre_rotation_degrees`.
+    pub fn pre_transform(&self) -> vk::SurfaceTransformFlagsKHR {
+        self.target.surface_capabilities.current_transform
+    }
+
+    /// Clockwise rotation in degrees to apply to rendered content on rotated displays, e.g.
+    /// Android or tablets. `surface_extent` is then in the display's native orientation, with
+    /// width and height swapped for 90 and 270 degrees, so the projection should keep using the
+    /// window's aspect ratio. Vulkan clip space has Y pointing down, so a positive rotation
+    /// around Z applied after the projection, including any Y flip in it, turns the image
+    /// clockwise on screen.
+    pub fn pre_rotation_degrees(&self) -> f32 {
+        get_pre_rotation_degrees(self.pre_transform())
+    }
+
+    /// True when `pre_transform` rotates by 90 or 270 degrees.
+    pub fn is_pre_rotated(&self) -> bool {
+        is_rotated_transform(self.pre_transform())
+    }
+
+    pub fn resize<W: WindowBackend>(&mut self, window: &W) -> Result<(), VulkanError> {
+        if self.is_headless() {
+            return Err(VulkanError::Other(String::from(
+                "headless VulkanBase has no swapchain to resize",
+            )));
+        }
+
+        self.with_target(|target, vulkan_base| target.resize(vulkan_base, window))
+    }
+
+    /// Replaces the logical device after `VulkanError::DeviceLost`, keeping the instance,
+    /// surface and physical device. Pass the extensions and config `VulkanBase` was created
+    /// with.
+    ///
+    /// Everything created from the old device has to be destroyed before and rebuilt after:
+    /// command pools and buffers, pipelines and their layouts, descriptor pools and sets,
+    /// buffers and images with their allocations, `OwnedMemBuffer`s and `OwnedMemImage`s
+    /// included (the allocator is replaced too), `FrameSync`/`TimelineSync`, `GpuTimer` and pipeline caches. Windowed instances are left
+    /// `paused`, call `resize` to create the swapchain again.
+    pub fn recreate_device<'a>(
+        &mut self,
+        required_device_extensions: &Vec<&'a std::ffi::CStr>,
+        config: &VulkanBaseConfig,
+    ) -> Result<(), VulkanError> {
+        log::info!("recreating device");
+
+        // a lost device still accepts waits and destroy calls
+        unsafe {
+            let _ = self.device.device_wait_idle();
+        }
+
+        self.with_target(|target, vulkan_base| target.destroy_swapchain(vulkan_base));
+
+        let mut device_extensions =
+            get_device_extensions(required_device_extensions, config.extension_features());
+        if self.memory_budget_supported {
+            device_extensions.push(vk::ExtMemoryBudgetFn::name());
+        }
+        let present_mode_switching_supported = self.surface_capabilities2_loader.is_some();
+        if present_mode_switching_supported {
+            device_extensions.push(vk::ExtSwapchainMaintenance1Fn::name());
+        }
+
+        let device_sg = {
+            let device = create_logical_device(
+                &self.instance,
+                self.physical_device,
+                self.queue_family,
+                self.transfer_queue_family,
+                &device_extensions,
+                &self.enabled_features,
+                ExtensionFeatures {
+                    swapchain_maintenance1: present_mode_switching_supported,
+                    ..config.extension_features()
+                },
+            )?;
+            guard(device, |device| {
+                log::warn!("device scopeguard");
+                unsafe {
+                    device.destroy_device(None);
+                }
+            })
+        };
+
+        let allocator = create_allocator(
+            &self.instance,
+            &device_sg,
             self.physical_device,
-            self.swapchain,
-            self.surface,
-            &self.surface_format,
-            self.present_mode,
-            &self.swapchain_image_views,
-            self.depth_format,
-            &mut self.allocator,
-            Some(old_depth_buffer_mem_image),
+            config.allocator_debug_settings,
         )?;
 
-        self.surface_capabilities = resize_data.surface_capabilities;
-        self.surface_extent = resize_data.surface_extent;
-        self.swapchain = resize_data.swapchain;
-        self.swapchain_images = resize_data.swapchain_images;
-        self.swapchain_image_views = resize_data.swapchain_image_views;
-        self.depth_buffer_mem_image = resize_data.depth_buffer_mem_image;
+        // the old allocator returns its memory blocks to the old device, so it goes first
+        drop_allocator(std::mem::replace(
+            &mut self.allocator,
+            std::sync::Arc::new(std::sync::Mutex::new(allocator)),
+        ));
+        unsafe {
+            self.device.destroy_device(None);
+        }
+        self.device = std::sync::Arc::new(ScopeGuard::into_inner(device_sg));
+
+        self.queue = get_queue(&self.device, self.queue_family);
+        self.transfer_queue = self
+            .transfer_queue_family
+            .map(|family| get_queue(&self.device, family));
+        self.swapchain_loader = create_swapchain_loader(&self.instance, &self.device);
+        if self.dynamic_rendering_loader.is_some() {
+            self.dynamic_rendering_loader =
+                Some(khr::DynamicRendering::new(&self.instance, &self.device));
+        }
+        if self.synchronization2_loader.is_some() {
+            self.synchronization2_loader =
+                Some(khr::Synchronization2::new(&self.instance, &self.device));
+        }
+
+        log::info!("device recreated");
 
         Ok(())
     }
 
+    /// Present modes the surface supports, FIFO is always among them. Empty when headless.
+    pub fn supported_present_modes(&self) -> Result<Vec<vk::PresentModeKHR>, VulkanError> {
+        if self.is_headless() {
+            return Ok(Vec::new());
+        }
+
+        get_supported_present_modes(
+            self.physical_device,
+            &self.surface_loader,
+            self.target.surface,
+        )
+    }
+
+    /// Fails without changing anything when `present_mode` isn't supported, see
+    /// `supported_present_modes`.
+    pub fn set_present_mode<W: WindowBackend>(
+        &mut self,
+        present_mode: vk::PresentModeKHR,
+        window: &W,
+    ) -> Result<(), VulkanError> {
+        log::info!("setting present mode: {:?}", present_mode);
+
+        let modes = get_supported_present_modes(
+            self.physical_device,
+            &self.surface_loader,
+            self.target.surface,
+        )?;
+
+        if !modes.contains(&present_mode) {
+            return Err(VulkanError::Other(format!(
+                "present mode {:?} is not supported, supported modes: {:?}",
+                present_mode, modes
+            )));
+        }
+
+        if present_mode == self.target.present_mode {
+            return Ok(());
+        }
+
+        self.target.present_mode = present_mode;
+
+        // the next present switches the mode, see RenderTarget::present
+        if self.target.compatible_present_modes.contains(&present_mode) {
+            log::info!("switching present mode without recreating the swapchain");
+            return Ok(());
+        }
+
+        self.resize(window)
+    }
+
+    /// Budget and usage in bytes of every memory heap, as `(heap_index, budget, usage)`. The
+    /// budget is how much the process can allocate from the heap before running into trouble, it
+    /// shrinks when other applications use the GPU. Without `VK_EXT_memory_budget` the budget is
+    /// the heap size and the usage is reported as 0.
+    pub fn memory_budget(&self) -> Vec<(u32, vk::DeviceSize, vk::DeviceSize)> {
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties2::builder();
+        if self.memory_budget_supported {
+            memory_properties = memory_properties.push_next(&mut budget_properties);
+        }
+        let mut memory_properties = memory_properties.build();
+
+        unsafe {
+            self.instance.get_physical_device_memory_properties2(
+                self.physical_device,
+                &mut memory_properties,
+            );
+        }
+
+        let heaps = &memory_properties.memory_properties.memory_heaps
+            [..memory_properties.memory_properties.memory_heap_count as usize];
+
+        heaps
+            .iter()
+            .enumerate()
+            .map(|(index, heap)| match self.memory_budget_supported {
+                true => (
+                    index as u32,
+                    budget_properties.heap_budget[index],
+                    budget_properties.heap_usage[index],
+                ),
+                false => (index as u32, heap.size, 0),
+            })
+            .collect()
+    }
+
+    /// Returns the allocator's breakdown of used and reserved bytes and of every live allocation.
+    /// gpu-allocator 0.20 only exposes this report through its `Debug` implementation.
+    /// The usage of every heap against its `memory_budget` is appended.
+    pub fn memory_report(&self) -> String {
+        let mut report = format!("{:?}", *self.allocator.lock().unwrap());
+
+        for (heap_index, budget, usage) in self.memory_budget() {
+            report.push_str(&format!(
+                "\nheap {}: {} of {} MiB budget used",
+                heap_index,
+                usage / (1024 * 1024),
+                budget / (1024 * 1024)
+            ));
+        }
+
+        report
+    }
+
+    /// Writes swapchain image `image_index` to `path` as PNG. Call it after the frame was
+    /// submitted and before it is presented, while the image is still in `PRESENT_SRC_KHR`
+    /// layout and owned by the application.
+    pub fn capture_frame(
+        &mut self,
+        image_index: u32,
+        path: &std::path::Path,
+    ) -> Result<(), VulkanError> {
+        if self.is_headless() {
+            return Err(VulkanError::Other(String::from(
+                "headless VulkanBase has no swapchain images",
+            )));
+        }
+
+        self.with_target(|target, vulkan_base| target.capture_frame(vulkan_base, image_index, path))
+    }
+
+    /// Returns the depth of pixel `x`, `y` of the depth buffer, normalized to 0..1, e.g. for
+    /// picking. Call it between frames, outside of a render pass, with the depth buffer left in
+    /// `DEPTH_STENCIL_ATTACHMENT_OPTIMAL` and stored (`AttachmentStoreOp::STORE`). It is
+    /// synchronous: it waits for the queue to be idle, so it returns the depth of the last
+    /// submitted frame and stalls the CPU until the GPU is done with it.
+    pub fn read_depth_pixel(&mut self, x: u32, y: u32) -> Result<f32, VulkanError> {
+        if !self
+            .depth_image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(VulkanError::Other(String::from(
+                "depth buffer does not support TRANSFER_SRC, enable VulkanBaseConfig::enable_depth_readback",
+            )));
+        }
+
+        if self.target.paused {
+            return Err(VulkanError::Other(String::from(
+                "paused VulkanBase has no depth buffer",
+            )));
+        }
+
+        let extent = self.target.depth_buffer_mem_image.extent;
+        if x >= extent.width || y >= extent.height {
+            return Err(VulkanError::Other(format!(
+                "depth pixel {}, {} is outside of the {}x{} depth buffer",
+                x, y, extent.width, extent.height
+            )));
+        }
+
+        depth_readback::read_depth_pixel(
+            &self.device,
+            &mut self.allocator.lock().unwrap(),
+            self.debug_utils_loader.as_ref(),
+            self.queue_family,
+            self.queue,
+            self.target.depth_buffer_mem_image.image,
+            self.depth_format,
+            x,
+            y,
+        )
+    }
+
+    /// Number of `ERROR` messages the debug messenger received so far, across every
+    /// `VulkanBase` of the process. Needs `enable_validation` and a `debug_message_severity`
+    /// including `ERROR`, otherwise it stays 0. The validation layer checks object lifetimes,
+    /// so a count that grows during `clean` points at leaked or wrongly ordered destroys. Use a
+    /// `ValidationErrorCapture` to count the errors of a single `VulkanBase`.
+    pub fn validation_error_count() -> u32 {
+        get_validation_error_count()
+    }
+
+    // lends `target` out of `self` so its methods can take the `VulkanBase` mutably too
+    fn with_target<T>(&mut self, f: impl FnOnce(&mut RenderTarget, &mut Self) -> T) -> T {
+        let mut target = std::mem::take(&mut self.target);
+        let result = f(&mut target, self);
+        self.target = target;
+        result
+    }
+
+    /// Clean every `RenderTarget` created with `create_render_target` first, their surfaces and
+    /// swapchains need the instance and device.
     pub fn clean(mut self) {
         log::info!("cleaning vulkan base");
 
+        if self.render_target_count != 0 {
+            log::error!(
+                "{} render targets not cleaned before vulkan base",
+                self.render_target_count
+            );
+        }
+
+        let target = std::mem::take(&mut self.target);
+        target.destroy(&mut self);
+
+        drop_allocator(self.allocator);
+
         unsafe {
-            self.device
-                .destroy_image(self.depth_buffer_mem_image.image, None);
-            self.device
-                .destroy_image_view(self.depth_buffer_mem_image.view, None);
-            let _ = self.allocator.free(self.depth_buffer_mem_image.allocation);
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, None);
-            for &image_view in &self.swapchain_image_views {
-                self.device.destroy_image_view(image_view, None);
-            }
-            drop(self.allocator);
             self.device.destroy_device(None);
-            self.surface_loader.destroy_surface(self.surface, None);
+            if let Some(debug_utils_loader) = &self.debug_utils_loader {
+                debug_utils_loader.destroy_debug_utils_messenger(self.debug_messenger, None);
+            }
             self.instance.destroy_instance(None);
         }
+        drop(self.validation_error_capture);
+
+        let validation_error_count = get_validation_error_count();
+        if validation_error_count != 0 {
+            log::error!(
+                "{} validation errors were reported, see the log above",
+                validation_error_count
+            );
+        }
     }
 }
 
+// the allocator returns its memory blocks to the device it was created for, so it has to be
+// dropped before the device is destroyed
+fn drop_allocator(allocator: vulkan_utils::SharedAllocator) {
+    if let Err(allocator) = std::sync::Arc::try_unwrap(allocator) {
+        log::error!(
+            "{} allocator clones outlive the device, drop every OwnedMemBuffer and OwnedMemImage first",
+            std::sync::Arc::strong_count(&allocator) - 1
+        );
+    }
+}
+
+#[derive(Default)]
 struct ResizeResult {
     surface_capabilities: vk::SurfaceCapabilitiesKHR,
     surface_extent: vk::Extent2D,
     swapchain: vk::SwapchainKHR,
     swapchain_images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
+    compatible_present_modes: Vec<vk::PresentModeKHR>,
     depth_buffer_mem_image: vulkan_utils::MemImage,
 }
 
-fn resize_internal(
-    window: &winit::window::Window,
+fn resize_internal<W: WindowBackend + ?Sized>(
+    window: &W,
     device: &ash::Device,
     surface_loader: &ash::extensions::khr::Surface,
+    surface_capabilities2_loader: Option<&ash::extensions::khr::GetSurfaceCapabilities2>,
     swapchain_loader: &ash::extensions::khr::Swapchain,
     physical_device: vk::PhysicalDevice,
     old_swapchain: vk::SwapchainKHR,
     surface: vk::SurfaceKHR,
     surface_format: &vk::SurfaceFormatKHR,
     present_mode: vk::PresentModeKHR,
+    composite_alpha: vk::CompositeAlphaFlagsKHR,
+    image_usage: vk::ImageUsageFlags,
+    desired_image_count: u32,
+    queue_family: u32,
     old_swapchain_image_views: &Vec<vk::ImageView>,
     depth_format: vk::Format,
+    depth_image_usage: vk::ImageUsageFlags,
     allocator: &mut gpu_allocator::vulkan::Allocator,
     old_depth_buffer_mem_image: Option<vulkan_utils::MemImage>,
-) -> Result<ResizeResult, String> {
+) -> Result<Option<ResizeResult>, VulkanError> {
     log::info!("resizing VulkanBase");
 
     unsafe {
@@ -237,6 +1003,32 @@ fn resize_internal(
     let surface_capabilities = get_surface_capabilities(surface_loader, physical_device, surface)?;
     let surface_extent = get_surface_extent(window, &surface_capabilities);
 
+    // a minimized window can't have a swapchain, release the old one until it is restored
+    if surface_extent.width == 0 || surface_extent.height == 0 {
+        unsafe {
+            for &image_view in old_swapchain_image_views {
+                device.destroy_image_view(image_view, None);
+            }
+
+            if let Some(mem_image) = old_depth_buffer_mem_image {
+                device.destroy_image_view(mem_image.view, None);
+                device.destroy_image(mem_image.image, None);
+                let _ = allocator.free(mem_image.allocation);
+            }
+
+            swapchain_loader.destroy_swapchain(old_swapchain, None);
+        }
+
+        return Ok(None);
+    }
+
+    let compatible_present_modes = match surface_capabilities2_loader {
+        Some(loader) => {
+            get_compatible_present_modes(loader, physical_device, surface, present_mode)?
+        }
+        None => Vec::new(),
+    };
+
     let swapchain_sg = {
         let swapchain = create_swapchain(
             old_swapchain,
@@ -245,6 +1037,12 @@ fn resize_internal(
             surface_format,
             surface_extent,
             present_mode,
+            &compatible_present_modes,
+            composite_alpha,
+            image_usage,
+            desired_image_count,
+            // rendering and presenting share the queue
+            &[queue_family],
             swapchain_loader,
         )?;
         guard(swapchain, |swapchain| {
@@ -288,27 +1086,32 @@ fn resize_internal(
     if let Some(mem_image) = old_depth_buffer_mem_image {
         log::info!("destroying old depth buffer");
         unsafe {
-            device.destroy_image(mem_image.image, None);
             device.destroy_image_view(mem_image.view, None);
+            device.destroy_image(mem_image.image, None);
         }
         let _ = allocator.free(mem_image.allocation);
     }
 
     let depth_buffer_sg = {
-        let depth_buffer_mem_image =
-            create_depth_buffer(device, &surface_extent, depth_format, allocator)?;
+        let depth_buffer_mem_image = create_depth_buffer(
+            device,
+            &surface_extent,
+            depth_format,
+            depth_image_usage,
+            allocator,
+        )?;
 
         guard(depth_buffer_mem_image, |mem_image| {
             log::warn!("depth buffer mem image scopeguard");
             unsafe {
-                device.destroy_image(mem_image.image, None);
                 device.destroy_image_view(mem_image.view, None);
+                device.destroy_image(mem_image.image, None);
             }
             let _ = allocator.free(mem_image.allocation);
         })
     };
 
-    Ok(ResizeResult {
+    Ok(Some(ResizeResult {
         surface_capabilities,
         surface_extent,
         swapchain: ScopeGuard::into_inner(swapchain_sg),
@@ -317,6 +1120,7 @@ fn resize_internal(
             .into_iter()
             .map(|sg| ScopeGuard::into_inner(sg))
             .collect(),
+        compatible_present_modes,
         depth_buffer_mem_image: ScopeGuard::into_inner(depth_buffer_sg),
-    })
+    }))
 }
@@ -0,0 +1,141 @@
+use ash::vk;
+
+use crate::VulkanError;
+
+const PIPELINE_CACHE_FILE_NAME: &str = "pipeline_cache.bin";
+
+// VkPipelineCacheHeaderVersionOne: size, version, vendor id, device id, uuid
+const PIPELINE_CACHE_HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// A `vk::PipelineCache` persisted as `pipeline_cache.bin` in a user provided directory.
+///
+/// Cache data written by another driver or device is discarded on load.
+pub struct PipelineCache {
+    pub pipeline_cache: vk::PipelineCache,
+    pub path: std::path::PathBuf,
+}
+
+impl PipelineCache {
+    pub fn new(
+        device: &ash::Device,
+        physical_device_properties: &vk::PhysicalDeviceProperties,
+        debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+        dir: &std::path::Path,
+    ) -> Result<Self, VulkanError> {
+        log::info!("creating pipeline cache");
+
+        let path = dir.join(PIPELINE_CACHE_FILE_NAME);
+
+        let initial_data = match std::fs::read(&path) {
+            Ok(data) => {
+                if is_cache_data_compatible(&data, physical_device_properties) {
+                    log::info!("loaded pipeline cache {:?}", path);
+                    data
+                } else {
+                    log::warn!(
+                        "pipeline cache {:?} does not match the device, discarding",
+                        path
+                    );
+                    Vec::new()
+                }
+            }
+            Err(_) => {
+                log::info!("no pipeline cache at {:?}", path);
+                Vec::new()
+            }
+        };
+
+        let create_info = vk::PipelineCacheCreateInfo::builder()
+            .initial_data(&initial_data)
+            .build();
+
+        let pipeline_cache = unsafe {
+            device
+                .create_pipeline_cache(&create_info, None)
+                .map_err(|err| {
+                    VulkanError::Vulkan(String::from("failed to create pipeline cache"), err)
+                })?
+        };
+
+        vulkan_utils::set_debug_utils_object_name(
+            debug_utils_loader,
+            device.handle(),
+            pipeline_cache,
+            "pipeline cache",
+        );
+
+        log::info!("pipeline cache created");
+
+        Ok(PipelineCache {
+            pipeline_cache,
+            path,
+        })
+    }
+
+    pub fn save(&self, device: &ash::Device) -> Result<(), VulkanError> {
+        log::info!("saving pipeline cache {:?}", self.path);
+
+        let data = unsafe {
+            device
+                .get_pipeline_cache_data(self.pipeline_cache)
+                .map_err(|err| {
+                    VulkanError::Vulkan(String::from("failed to get pipeline cache data"), err)
+                })?
+        };
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|_| VulkanError::Other(format!("failed to create directory {:?}", dir)))?;
+        }
+
+        std::fs::write(&self.path, data).map_err(|_| {
+            VulkanError::Other(format!("failed to write pipeline cache {:?}", self.path))
+        })?;
+
+        log::info!("pipeline cache saved");
+
+        Ok(())
+    }
+
+    /// Writes the cache back to disk and destroys it.
+    pub fn clean(self, device: &ash::Device) {
+        if let Err(msg) = self.save(device) {
+            log::warn!("{}", msg);
+        }
+
+        unsafe {
+            device.destroy_pipeline_cache(self.pipeline_cache, None);
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn is_cache_data_compatible(
+    data: &[u8],
+    physical_device_properties: &vk::PhysicalDeviceProperties,
+) -> bool {
+    if data.len() < PIPELINE_CACHE_HEADER_SIZE {
+        return false;
+    }
+
+    let header_size = read_u32(data, 0) as usize;
+    let header_version = read_u32(data, 4);
+    let vendor_id = read_u32(data, 8);
+    let device_id = read_u32(data, 12);
+    let uuid = &data[16..PIPELINE_CACHE_HEADER_SIZE];
+
+    header_size >= PIPELINE_CACHE_HEADER_SIZE
+        && header_size <= data.len()
+        && header_version == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && vendor_id == physical_device_properties.vendor_id
+        && device_id == physical_device_properties.device_id
+        && uuid == physical_device_properties.pipeline_cache_uuid
+}
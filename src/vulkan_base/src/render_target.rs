@@ -0,0 +1,390 @@
+use ash::vk;
+use scopeguard::{guard, ScopeGuard};
+
+use crate::vulkan_base::*;
+use crate::{capture, resize_internal, VulkanBase, VulkanError, WindowBackend};
+
+/// Surface, swapchain and depth buffer of a window. `VulkanBase::target` is the one of the
+/// window `VulkanBase` was created for, `VulkanBase::create_render_target` adds one for
+/// another window, e.g. an inspector next to the main view. The device, queue and allocator
+/// are those of the `VulkanBase`.
+///
+/// Every target created with `create_render_target` has to be cleaned with `clean` before
+/// `VulkanBase::clean`.
+#[derive(Default)]
+pub struct RenderTarget {
+    /// Null when the `VulkanBase` is headless.
+    pub surface: vk::SurfaceKHR,
+    /// For targets from `create_render_target`, the format of `VulkanBase::target` when the
+    /// surface supports it, so the same pipelines can render to both.
+    pub surface_format: vk::SurfaceFormatKHR,
+    pub present_mode: vk::PresentModeKHR,
+    /// Present modes the current swapchain can switch between without being recreated. Empty
+    /// without `VulkanBase::surface_capabilities2_loader`.
+    pub compatible_present_modes: Vec<vk::PresentModeKHR>,
+    pub composite_alpha: vk::CompositeAlphaFlagsKHR,
+    pub surface_capabilities: vk::SurfaceCapabilitiesKHR,
+    pub surface_extent: vk::Extent2D,
+    /// Null, with no images, views or depth buffer, while `paused`.
+    pub swapchain: vk::SwapchainKHR,
+    pub swapchain_images: Vec<vk::Image>,
+    pub swapchain_image_views: Vec<vk::ImageView>,
+    pub depth_buffer_mem_image: vulkan_utils::MemImage,
+    /// Set by `resize` while the window has a zero size, e.g. when minimized. No image must be
+    /// acquired until a later `resize` recreates the swapchain.
+    pub paused: bool,
+}
+
+impl VulkanBase {
+    /// Creates a surface and swapchain for another window, sharing this `VulkanBase`'s device.
+    /// The swapchain uses the same image usage and image count, and the same format and
+    /// present mode when the surface supports them. Fails when the queue family can't present
+    /// to the window.
+    pub fn create_render_target<W: WindowBackend>(
+        &mut self,
+        window: &W,
+    ) -> Result<RenderTarget, VulkanError> {
+        if self.is_headless() {
+            return Err(VulkanError::Other(String::from(
+                "headless VulkanBase can't create render targets",
+            )));
+        }
+
+        log::info!("creating render target");
+
+        let surface_loader = &self.surface_loader;
+
+        let surface_sg = {
+            let surface = create_surface(&self.entry, &self.instance, window)?;
+            guard(surface, |surface| {
+                log::warn!("render target surface scopeguard");
+                unsafe {
+                    surface_loader.destroy_surface(surface, None);
+                }
+            })
+        };
+
+        let present_supported = unsafe {
+            surface_loader
+                .get_physical_device_surface_support(
+                    self.physical_device,
+                    self.queue_family,
+                    *surface_sg,
+                )
+                .map_err(|err| {
+                    VulkanError::Vulkan(
+                        String::from("failed to get physical device surface_support"),
+                        err,
+                    )
+                })?
+        };
+
+        if !present_supported {
+            return Err(VulkanError::Other(format!(
+                "queue family {} can't present to the render target's surface",
+                self.queue_family
+            )));
+        }
+
+        let formats = unsafe {
+            surface_loader
+                .get_physical_device_surface_formats(self.physical_device, *surface_sg)
+                .map_err(|err| {
+                    VulkanError::Vulkan(
+                        String::from("failed to get physical device surface formats"),
+                        err,
+                    )
+                })?
+        };
+        let surface_format =
+            vulkan_utils::choose_surface_format(&formats, &[self.target.surface_format])?;
+        if surface_format != self.target.surface_format {
+            log::warn!(
+                "render target surface format {:?} differs from {:?}",
+                surface_format,
+                self.target.surface_format
+            );
+        }
+
+        let present_modes =
+            get_supported_present_modes(self.physical_device, surface_loader, *surface_sg)?;
+        // FIFO is always supported
+        let present_mode = match present_modes.contains(&self.target.present_mode) {
+            true => self.target.present_mode,
+            false => vk::PresentModeKHR::FIFO,
+        };
+
+        let composite_alpha = get_composite_alpha(
+            &get_surface_capabilities(surface_loader, self.physical_device, *surface_sg)?,
+            &[
+                self.target.composite_alpha,
+                vk::CompositeAlphaFlagsKHR::OPAQUE,
+                vk::CompositeAlphaFlagsKHR::INHERIT,
+                vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+                vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+            ],
+        )?;
+
+        let resize_data = resize_internal(
+            window,
+            &self.device,
+            surface_loader,
+            self.surface_capabilities2_loader.as_ref(),
+            &self.swapchain_loader,
+            self.physical_device,
+            vk::SwapchainKHR::null(),
+            *surface_sg,
+            &surface_format,
+            present_mode,
+            composite_alpha,
+            self.swapchain_image_usage,
+            self.desired_image_count,
+            self.queue_family,
+            &Vec::new(),
+            self.depth_format,
+            self.depth_image_usage,
+            &mut self.allocator.lock().unwrap(),
+            None,
+        )?
+        .ok_or_else(|| VulkanError::Other(String::from("window has a zero size")))?;
+
+        self.render_target_count += 1;
+
+        log::info!("render target created");
+
+        Ok(RenderTarget {
+            surface: ScopeGuard::into_inner(surface_sg),
+            surface_format,
+            present_mode,
+            compatible_present_modes: resize_data.compatible_present_modes,
+            composite_alpha,
+            surface_capabilities: resize_data.surface_capabilities,
+            surface_extent: resize_data.surface_extent,
+            swapchain: resize_data.swapchain,
+            swapchain_images: resize_data.swapchain_images,
+            swapchain_image_views: resize_data.swapchain_image_views,
+            depth_buffer_mem_image: resize_data.depth_buffer_mem_image,
+            paused: false,
+        })
+    }
+}
+
+impl RenderTarget {
+    pub fn image_count(&self) -> u32 {
+        self.swapchain_images.len() as u32
+    }
+
+    pub fn resize<W: WindowBackend + ?Sized>(
+        &mut self,
+        vulkan_base: &mut VulkanBase,
+        window: &W,
+    ) -> Result<(), VulkanError> {
+        let old_depth_buffer_mem_image = std::mem::take(&mut self.depth_buffer_mem_image);
+        let resize_data = resize_internal(
+            window,
+            &vulkan_base.device,
+            &vulkan_base.surface_loader,
+            vulkan_base.surface_capabilities2_loader.as_ref(),
+            &vulkan_base.swapchain_loader,
+            vulkan_base.physical_device,
+            self.swapchain,
+            self.surface,
+            &self.surface_format,
+            self.present_mode,
+            self.composite_alpha,
+            vulkan_base.swapchain_image_usage,
+            vulkan_base.desired_image_count,
+            vulkan_base.queue_family,
+            &self.swapchain_image_views,
+            vulkan_base.depth_format,
+            vulkan_base.depth_image_usage,
+            &mut vulkan_base.allocator.lock().unwrap(),
+            Some(old_depth_buffer_mem_image),
+        )?;
+
+        let resize_data = match resize_data {
+            Some(resize_data) => resize_data,
+            None => {
+                log::info!("window has a zero size, pausing");
+                self.swapchain = vk::SwapchainKHR::null();
+                self.swapchain_images = Vec::new();
+                self.swapchain_image_views = Vec::new();
+                self.compatible_present_modes = Vec::new();
+                self.paused = true;
+                return Ok(());
+            }
+        };
+
+        self.paused = false;
+        self.surface_capabilities = resize_data.surface_capabilities;
+        self.surface_extent = resize_data.surface_extent;
+        self.swapchain = resize_data.swapchain;
+        self.swapchain_images = resize_data.swapchain_images;
+        self.swapchain_image_views = resize_data.swapchain_image_views;
+        self.compatible_present_modes = resize_data.compatible_present_modes;
+        self.depth_buffer_mem_image = resize_data.depth_buffer_mem_image;
+
+        Ok(())
+    }
+
+    /// Returns `None` when the swapchain is out of date or the target is paused, `resize` it
+    /// before rendering.
+    pub fn acquire_next_image(
+        &self,
+        vulkan_base: &VulkanBase,
+        image_available_semaphore: vk::Semaphore,
+    ) -> Result<Option<u32>, VulkanError> {
+        if self.paused {
+            return Ok(None);
+        }
+
+        // a suboptimal image is still rendered, present reports it once the semaphore is consumed
+        match unsafe {
+            vulkan_base.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                image_available_semaphore,
+                vk::Fence::null(),
+            )
+        } {
+            Ok((index, _)) => Ok(Some(index)),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(None),
+            Err(err) => Err(VulkanError::from_result(
+                String::from("failed to acquire next image"),
+                err,
+            )),
+        }
+    }
+
+    /// Presents on the `VulkanBase` queue. Returns `false` when the swapchain is suboptimal or
+    /// out of date and should be resized.
+    pub fn present(
+        &self,
+        vulkan_base: &VulkanBase,
+        wait_semaphore: vk::Semaphore,
+        image_index: u32,
+    ) -> Result<bool, VulkanError> {
+        let semaphores = [wait_semaphore];
+        let swapchains = [self.swapchain];
+        let indices = [image_index];
+        let present_modes = [self.present_mode];
+        let mut present_mode_info =
+            vk::SwapchainPresentModeInfoEXT::builder().present_modes(&present_modes);
+        let mut present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(&semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&indices);
+
+        // switches the swapchain to `set_present_mode`'s mode without recreating it
+        if !self.compatible_present_modes.is_empty() {
+            present_info = present_info.push_next(&mut present_mode_info);
+        }
+
+        let present_info = present_info.build();
+
+        match unsafe {
+            vulkan_base
+                .swapchain_loader
+                .queue_present(vulkan_base.queue, &present_info)
+        } {
+            Ok(false) => Ok(true),
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(false),
+            Err(err) => Err(VulkanError::from_result(
+                String::from("failed to present"),
+                err,
+            )),
+        }
+    }
+
+    /// Writes swapchain image `image_index` to `path` as PNG, see `VulkanBase::capture_frame`.
+    pub fn capture_frame(
+        &self,
+        vulkan_base: &mut VulkanBase,
+        image_index: u32,
+        path: &std::path::Path,
+    ) -> Result<(), VulkanError> {
+        if !vulkan_base
+            .swapchain_image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(VulkanError::Other(String::from(
+                "swapchain images do not support TRANSFER_SRC, enable VulkanBaseConfig::enable_capture",
+            )));
+        }
+
+        if self.paused {
+            return Err(VulkanError::Other(String::from(
+                "paused render target has no swapchain images",
+            )));
+        }
+
+        if image_index >= self.image_count() {
+            return Err(VulkanError::Other(format!(
+                "swapchain image {} is outside of the {} swapchain images",
+                image_index,
+                self.image_count()
+            )));
+        }
+
+        capture::capture_swapchain_image(
+            &vulkan_base.device,
+            &mut vulkan_base.allocator.lock().unwrap(),
+            vulkan_base.debug_utils_loader.as_ref(),
+            vulkan_base.queue_family,
+            vulkan_base.queue,
+            self.swapchain_images[image_index as usize],
+            self.surface_format.format,
+            self.surface_extent,
+            path,
+        )
+    }
+
+    /// The device must be idle, or at least done with this target's images.
+    pub fn clean(self, vulkan_base: &mut VulkanBase) {
+        log::info!("cleaning render target");
+
+        self.destroy(vulkan_base);
+
+        vulkan_base.render_target_count -= 1;
+    }
+
+    /// Destroys the swapchain and its depth buffer, leaving a windowed target paused until the
+    /// next `resize`.
+    pub(crate) fn destroy_swapchain(&mut self, vulkan_base: &mut VulkanBase) {
+        let depth_buffer_mem_image = std::mem::take(&mut self.depth_buffer_mem_image);
+
+        unsafe {
+            let device = &vulkan_base.device;
+
+            device.destroy_image_view(depth_buffer_mem_image.view, None);
+            device.destroy_image(depth_buffer_mem_image.image, None);
+            let _ = vulkan_base
+                .allocator
+                .lock()
+                .unwrap()
+                .free(depth_buffer_mem_image.allocation);
+            for &image_view in &self.swapchain_image_views {
+                device.destroy_image_view(image_view, None);
+            }
+            vulkan_base
+                .swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+        }
+
+        self.swapchain = vk::SwapchainKHR::null();
+        self.swapchain_images = Vec::new();
+        self.swapchain_image_views = Vec::new();
+        self.compatible_present_modes = Vec::new();
+        self.paused = self.surface != vk::SurfaceKHR::null();
+    }
+
+    pub(crate) fn destroy(mut self, vulkan_base: &mut VulkanBase) {
+        self.destroy_swapchain(vulkan_base);
+
+        unsafe {
+            vulkan_base
+                .surface_loader
+                .destroy_surface(self.surface, None);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use ash::vk;
+use scopeguard::{guard, ScopeGuard};
+
+use crate::frame_sync::{create_rendering_finished_semaphores, destroy_semaphores};
+use crate::{VulkanBase, VulkanError};
+
+/// Timeline semaphore based alternative to `FrameSync`, needs the device created with
+/// `VulkanBaseConfig::enable_timeline_semaphore`.
+///
+/// Every `submit` signals `timeline_semaphore` with the next value and `begin_frame` waits
+/// until the submission `frame_count` frames back has finished, so resources indexed by
+/// `frame_index` are never reused while the GPU still reads them. Other submissions, e.g.
+/// async compute or transfers, can wait on or signal values of the same semaphore. Acquire
+/// and present still use binary semaphores, the swapchain does not accept timeline ones. As
+/// with `FrameSync`, the present semaphores are per swapchain image and recreated by `resize`.
+pub struct TimelineSync {
+    pub image_available_semaphores: Vec<vk::Semaphore>,
+    pub rendering_finished_semaphores: Vec<vk::Semaphore>,
+    pub timeline_semaphore: vk::Semaphore,
+    /// Value signaled by the last `submit`, 0 before the first one.
+    pub timeline_value: u64,
+    pub frame_index: u32,
+}
+
+impl TimelineSync {
+    pub fn new(vulkan_base: &VulkanBase, frame_count: u32) -> Result<Self, VulkanError> {
+        log::info!("creating timeline sync");
+
+        let device = &vulkan_base.device;
+
+        let mut timeline_sync_sg = guard(
+            TimelineSync {
+                image_available_semaphores: Vec::with_capacity(frame_count as usize),
+                rendering_finished_semaphores: Vec::new(),
+                timeline_semaphore: vk::Semaphore::null(),
+                timeline_value: 0,
+                frame_index: 0,
+            },
+            |timeline_sync| {
+                log::warn!("timeline sync scopeguard");
+                timeline_sync.clean(device);
+            },
+        );
+
+        for i in 0..frame_count {
+            let image_available_semaphore = vulkan_utils::create_semaphore(
+                device,
+                vulkan_base.debug_utils_loader.as_ref(),
+                &format!("image available semaphore {}", i),
+            )?;
+            timeline_sync_sg
+                .image_available_semaphores
+                .push(image_available_semaphore);
+        }
+
+        timeline_sync_sg.rendering_finished_semaphores =
+            create_rendering_finished_semaphores(vulkan_base)?;
+
+        let mut semaphore_type_create_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0)
+            .build();
+
+        let create_info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut semaphore_type_create_info)
+            .build();
+
+        timeline_sync_sg.timeline_semaphore = unsafe {
+            device.create_semaphore(&create_info, None).map_err(|err| {
+                VulkanError::Vulkan(String::from("failed to create timeline semaphore"), err)
+            })?
+        };
+
+        vulkan_utils::set_debug_utils_object_name(
+            vulkan_base.debug_utils_loader.as_ref(),
+            device.handle(),
+            timeline_sync_sg.timeline_semaphore,
+            "timeline semaphore",
+        );
+
+        log::info!("timeline sync created");
+
+        Ok(ScopeGuard::into_inner(timeline_sync_sg))
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.image_available_semaphores.len() as u32
+    }
+
+    /// See `FrameSync::resize`.
+    pub fn resize(&mut self, vulkan_base: &VulkanBase) -> Result<(), VulkanError> {
+        unsafe {
+            vulkan_base.device.device_wait_idle().map_err(|err| {
+                VulkanError::from_result(String::from("failed to wait for device idle"), err)
+            })?;
+        }
+
+        let rendering_finished_semaphores = create_rendering_finished_semaphores(vulkan_base)?;
+
+        let old_semaphores = std::mem::replace(
+            &mut self.rendering_finished_semaphores,
+            rendering_finished_semaphores,
+        );
+        destroy_semaphores(&vulkan_base.device, old_semaphores);
+
+        Ok(())
+    }
+
+    /// Blocks until the GPU has signaled `value` on `timeline_semaphore`.
+    pub fn wait(&self, vulkan_base: &VulkanBase, value: u64) -> Result<(), VulkanError> {
+        let semaphores = [self.timeline_semaphore];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values)
+            .build();
+
+        unsafe {
+            vulkan_base
+                .device
+                .wait_semaphores(&wait_info, u64::MAX)
+                .map_err(|err| {
+                    VulkanError::from_result(
+                        format!("failed to wait for timeline value {}", value),
+                        err,
+                    )
+                })
+        }
+    }
+
+    /// Waits for the submission that last used the current frame's resources and acquires a
+    /// swapchain image. Returns `None` when the swapchain is out of date and has to be
+    /// recreated before rendering, or when `vulkan_base` is paused.
+    pub fn begin_frame(&mut self, vulkan_base: &VulkanBase) -> Result<Option<u32>, VulkanError> {
+        if vulkan_base.target.paused {
+            return Ok(None);
+        }
+
+        // the next submit signals timeline_value + 1, it reuses the resources of the submit
+        // frame_count values before it
+        let frame_count = self.frame_count() as u64;
+        if self.timeline_value >= frame_count {
+            self.wait(vulkan_base, self.timeline_value + 1 - frame_count)?;
+        }
+
+        let image_available_semaphore = self.image_available_semaphores[self.frame_index as usize];
+        vulkan_base
+            .target
+            .acquire_next_image(vulkan_base, image_available_semaphore)
+    }
+
+    /// Submits the frame's command buffer and presents the image. Returns `false` when the
+    /// swapchain is suboptimal or out of date and should be recreated.
+    pub fn end_frame(
+        &mut self,
+        vulkan_base: &VulkanBase,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+    ) -> Result<bool, VulkanError> {
+        self.submit(vulkan_base, command_buffer, image_index)?;
+        self.present(vulkan_base, image_index)
+    }
+
+    /// First half of `end_frame`, signals `timeline_value + 1` once the command buffer is done.
+    pub fn submit(
+        &mut self,
+        vulkan_base: &VulkanBase,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+    ) -> Result<(), VulkanError> {
+        let frame_index = self.frame_index as usize;
+        let signal_value = self.timeline_value + 1;
+
+        let wait_semaphores = [self.image_available_semaphores[frame_index]];
+        let masks = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let cmd_buffers = [command_buffer];
+        let signal_semaphores = [
+            self.timeline_semaphore,
+            self.rendering_finished_semaphores[image_index as usize],
+        ];
+
+        // values for binary semaphores are ignored
+        let wait_values = [0];
+        let signal_values = [signal_value, 0];
+        let mut timeline_submit_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values)
+            .build();
+
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&masks)
+            .command_buffers(&cmd_buffers)
+            .signal_semaphores(&signal_semaphores)
+            .push_next(&mut timeline_submit_info)
+            .build();
+
+        unsafe {
+            vulkan_base
+                .device
+                .queue_submit(vulkan_base.queue, &[submit_info], vk::Fence::null())
+                .map_err(|err| {
+                    VulkanError::from_result(
+                        String::from("failed to submit graphics command buffer"),
+                        err,
+                    )
+                })?
+        }
+
+        self.timeline_value = signal_value;
+
+        Ok(())
+    }
+
+    /// Second half of `end_frame`, presents the image and moves on to the next frame.
+    pub fn present(
+        &mut self,
+        vulkan_base: &VulkanBase,
+        image_index: u32,
+    ) -> Result<bool, VulkanError> {
+        let semaphore = self.rendering_finished_semaphores[image_index as usize];
+
+        self.frame_index = (self.frame_index + 1) % self.frame_count();
+
+        vulkan_base
+            .target
+            .present(vulkan_base, semaphore, image_index)
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        log::info!("cleaning timeline sync");
+
+        destroy_semaphores(device, self.image_available_semaphores);
+        destroy_semaphores(device, self.rendering_finished_semaphores);
+
+        unsafe {
+            device.destroy_semaphore(self.timeline_semaphore, None);
+        }
+    }
+}
@@ -2,19 +2,43 @@ use ash::extensions::ext;
 use ash::extensions::khr;
 use ash::vk;
 use gpu_allocator::vulkan;
-use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 
-pub fn create_entry() -> ash::Entry {
-    log::info!("creating entry");
+use crate::{QueueFamilyInfo, VulkanError, WindowBackend};
+
+#[cfg(feature = "linked")]
+pub fn create_entry() -> Result<ash::Entry, VulkanError> {
+    log::info!("creating linked entry");
 
     let entry = ash::Entry::linked();
 
     log::info!("entry created");
 
-    entry
+    Ok(entry)
+}
+
+#[cfg(not(feature = "linked"))]
+pub fn create_entry() -> Result<ash::Entry, VulkanError> {
+    log::info!("loading Vulkan loader");
+
+    let entry = unsafe { ash::Entry::load().map_err(VulkanError::LoaderNotFound)? };
+
+    log::info!("entry created");
+
+    Ok(entry)
 }
 
-pub fn check_instance_version(entry: &ash::Entry) -> Result<(), String> {
+/// Device features that are enabled through extension feature structs at device creation.
+#[derive(Clone, Copy, Default)]
+pub struct ExtensionFeatures {
+    pub dynamic_rendering: bool,
+    pub synchronization2: bool,
+    pub timeline_semaphore: bool,
+    pub swapchain_maintenance1: bool,
+}
+
+pub fn check_instance_version(entry: &ash::Entry) -> Result<(), VulkanError> {
     log::info!("checking instance version");
 
     let api_version = match entry.try_enumerate_instance_version() {
@@ -22,8 +46,11 @@ pub fn check_instance_version(entry: &ash::Entry) -> Result<(), String> {
             Some(version) => version,
             None => vk::make_api_version(0, 1, 0, 0),
         },
-        Err(_) => {
-            return Err(String::from("failed to enumerate instance version"));
+        Err(err) => {
+            return Err(VulkanError::Vulkan(
+                String::from("failed to enumerate instance version"),
+                err,
+            ));
         }
     };
 
@@ -35,9 +62,9 @@ pub fn check_instance_version(entry: &ash::Entry) -> Result<(), String> {
     );
 
     if vk::api_version_major(api_version) < 1 && vk::api_version_minor(api_version) < 2 {
-        return Err(String::from(
+        return Err(VulkanError::Other(String::from(
             "minimum supported vulkan api version is 1.2.0",
-        ));
+        )));
     }
 
     Ok(())
@@ -46,7 +73,7 @@ pub fn check_instance_version(entry: &ash::Entry) -> Result<(), String> {
 pub fn check_required_instance_extensions<'a>(
     entry: &ash::Entry,
     required_instance_extensions: &Vec<&'a std::ffi::CStr>,
-) -> Result<(), String> {
+) -> Result<(), VulkanError> {
     log::info!(
         "checking required instance extensions: {:?}",
         required_instance_extensions
@@ -54,9 +81,10 @@ pub fn check_required_instance_extensions<'a>(
 
     let supported_instance_extensions = match entry.enumerate_instance_extension_properties(None) {
         Ok(props) => props,
-        Err(_) => {
-            return Err(String::from(
-                "failed to enumerate instance extension properties",
+        Err(err) => {
+            return Err(VulkanError::Vulkan(
+                String::from("failed to enumerate instance extension properties"),
+                err,
             ));
         }
     };
@@ -69,9 +97,8 @@ pub fn check_required_instance_extensions<'a>(
 
     for &extension_name in required_instance_extensions {
         if !supported_instance_extensions_set.contains(extension_name) {
-            return Err(format!(
-                "instance extension {:?} is not supported",
-                extension_name
+            return Err(VulkanError::InstanceExtensionUnsupported(
+                extension_name.to_string_lossy().into_owned(),
             ));
         }
     }
@@ -81,10 +108,136 @@ pub fn check_required_instance_extensions<'a>(
     Ok(())
 }
 
+fn is_instance_extension_available(entry: &ash::Entry, extension_name: &std::ffi::CStr) -> bool {
+    let extensions = match entry.enumerate_instance_extension_properties(None) {
+        Ok(extensions) => extensions,
+        Err(_) => {
+            log::warn!("failed to enumerate instance extension properties");
+            return false;
+        }
+    };
+
+    extensions.iter().any(|extension| {
+        extension_name == unsafe { std::ffi::CStr::from_ptr(extension.extension_name.as_ptr()) }
+    })
+}
+
+/// Adds the available `optional_instance_extensions` and `VK_KHR_portability_enumeration` when
+/// the loader has it, so portability subset implementations like MoltenVK are enumerated.
+pub fn get_instance_extensions<'a>(
+    entry: &ash::Entry,
+    required_instance_extensions: &Vec<&'a std::ffi::CStr>,
+    optional_instance_extensions: &[&'a std::ffi::CStr],
+) -> Vec<&'a std::ffi::CStr> {
+    let mut instance_extensions = required_instance_extensions.clone();
+
+    for &name in optional_instance_extensions {
+        if instance_extensions.contains(&name) {
+            continue;
+        }
+
+        if is_instance_extension_available(entry, name) {
+            log::info!("enabling optional {:?}", name);
+            instance_extensions.push(name);
+        } else {
+            log::info!("optional {:?} not available, skipping", name);
+        }
+    }
+
+    let portability_enumeration_name = vk::KhrPortabilityEnumerationFn::name();
+    if !instance_extensions.contains(&portability_enumeration_name)
+        && is_instance_extension_available(entry, portability_enumeration_name)
+    {
+        log::info!("enabling {:?}", portability_enumeration_name);
+        instance_extensions.push(portability_enumeration_name);
+    }
+
+    // lets swapchains switch present modes without being recreated, see set_present_mode
+    if instance_extensions.contains(&khr::Surface::name()) {
+        let surface_maintenance1_names = [
+            khr::GetSurfaceCapabilities2::name(),
+            vk::ExtSurfaceMaintenance1Fn::name(),
+        ];
+
+        if surface_maintenance1_names
+            .iter()
+            .all(|&name| is_instance_extension_available(entry, name))
+        {
+            for name in surface_maintenance1_names {
+                if !instance_extensions.contains(&name) {
+                    log::info!("enabling {:?}", name);
+                    instance_extensions.push(name);
+                }
+            }
+        }
+    }
+
+    instance_extensions
+}
+
+const VALIDATION_LAYER_NAME: &str = "VK_LAYER_KHRONOS_validation";
+const VALIDATION_ENV_VAR: &str = "VK_LYNX_VALIDATION";
+
+fn is_validation_requested(enable_validation: bool) -> bool {
+    match std::env::var(VALIDATION_ENV_VAR) {
+        Ok(value) => match value.to_lowercase().as_str() {
+            "1" | "true" | "on" => true,
+            "0" | "false" | "off" => false,
+            _ => {
+                log::warn!(
+                    "unrecognized {} value {:?}, ignoring",
+                    VALIDATION_ENV_VAR,
+                    value
+                );
+                enable_validation
+            }
+        },
+        Err(_) => enable_validation,
+    }
+}
+
+fn is_instance_layer_available(entry: &ash::Entry, layer_name: &std::ffi::CStr) -> bool {
+    let layers = match entry.enumerate_instance_layer_properties() {
+        Ok(layers) => layers,
+        Err(_) => {
+            log::warn!("failed to enumerate instance layer properties");
+            return false;
+        }
+    };
+
+    layers
+        .iter()
+        .any(|layer| unsafe { std::ffi::CStr::from_ptr(layer.layer_name.as_ptr()) } == layer_name)
+}
+
+/// Whether validation is requested, see `VALIDATION_ENV_VAR`, and the validation layer is
+/// installed.
+pub fn is_validation_layer_enabled(entry: &ash::Entry, enable_validation: bool) -> bool {
+    if !is_validation_requested(enable_validation) {
+        return false;
+    }
+
+    let validation_layer_name = std::ffi::CString::new(VALIDATION_LAYER_NAME).unwrap();
+    if !is_instance_layer_available(entry, &validation_layer_name) {
+        log::warn!(
+            "{} requested but not available, continuing without validation",
+            VALIDATION_LAYER_NAME
+        );
+        return false;
+    }
+
+    true
+}
+
 pub fn create_instance<'a>(
     entry: &ash::Entry,
     instance_extensions: &Vec<&'a std::ffi::CStr>,
-) -> Result<ash::Instance, String> {
+    enable_validation_layer: bool,
+    application_name: &str,
+    application_version: u32,
+    engine_name: &str,
+    engine_version: u32,
+) -> Result<ash::Instance, VulkanError> {
     log::info!("creating instance");
 
     let extension_names_raw = instance_extensions
@@ -92,19 +245,53 @@ pub fn create_instance<'a>(
         .map(|ext| ext.as_ptr())
         .collect::<Vec<_>>();
 
+    let validation_layer_name = std::ffi::CString::new(VALIDATION_LAYER_NAME).unwrap();
+    let mut layer_names_raw = Vec::new();
+
+    if enable_validation_layer {
+        log::info!("enabling {}", VALIDATION_LAYER_NAME);
+        layer_names_raw.push(validation_layer_name.as_ptr());
+    }
+
+    let application_name = std::ffi::CString::new(application_name).map_err(|_| {
+        VulkanError::Other(format!("invalid application name {:?}", application_name))
+    })?;
+    let engine_name = std::ffi::CString::new(engine_name)
+        .map_err(|_| VulkanError::Other(format!("invalid engine name {:?}", engine_name)))?;
+
+    log::info!(
+        "application: {:?} {}, engine: {:?} {}",
+        application_name,
+        application_version,
+        engine_name,
+        engine_version
+    );
+
     let app_info = vk::ApplicationInfo::builder()
+        .application_name(&application_name)
+        .application_version(application_version)
+        .engine_name(&engine_name)
+        .engine_version(engine_version)
         .api_version(vk::make_api_version(0, 1, 2, 0))
         .build();
 
+    let flags = if instance_extensions.contains(&vk::KhrPortabilityEnumerationFn::name()) {
+        vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+    } else {
+        vk::InstanceCreateFlags::empty()
+    };
+
     let create_info = vk::InstanceCreateInfo::builder()
+        .flags(flags)
         .enabled_extension_names(&extension_names_raw)
+        .enabled_layer_names(&layer_names_raw)
         .application_info(&app_info)
         .build();
 
     let instance = unsafe {
         entry
             .create_instance(&create_info, None)
-            .map_err(|_| String::from("failed to create instance"))?
+            .map_err(VulkanError::InstanceCreation)?
     };
 
     log::info!("instance created");
@@ -112,12 +299,152 @@ pub fn create_instance<'a>(
     Ok(instance)
 }
 
-pub fn create_debug_utils_loader(entry: &ash::Entry, instance: &ash::Instance) -> ext::DebugUtils {
+// None when the instance was created without VK_EXT_debug_utils
+pub fn create_debug_utils_loader<'a>(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    instance_extensions: &Vec<&'a std::ffi::CStr>,
+) -> Option<ext::DebugUtils> {
+    if !instance_extensions.contains(&ext::DebugUtils::name()) {
+        log::info!(
+            "{:?} not enabled, skipping debug messenger and object names",
+            ext::DebugUtils::name()
+        );
+        return None;
+    }
+
     let debug_utils_loader = ext::DebugUtils::new(&entry, &instance);
 
     log::info!("debug utils loader created");
 
-    debug_utils_loader
+    Some(debug_utils_loader)
+}
+
+/// `validation_error_capture` must outlive the messenger, `VulkanBase` keeps a clone of it
+/// until its instance is destroyed.
+pub fn create_debug_messenger(
+    debug_utils_loader: &ext::DebugUtils,
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    validation_error_capture: Option<&ValidationErrorCapture>,
+) -> Result<vk::DebugUtilsMessengerEXT, VulkanError> {
+    log::info!("creating debug messenger");
+
+    let p_user_data = match validation_error_capture {
+        Some(capture) => Arc::as_ptr(&capture.error_count) as *mut std::os::raw::c_void,
+        None => std::ptr::null_mut(),
+    };
+
+    let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(message_severity)
+        .message_type(
+            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        )
+        .pfn_user_callback(Some(vulkan_debug_callback))
+        .user_data(p_user_data)
+        .build();
+
+    let debug_messenger = unsafe {
+        debug_utils_loader
+            .create_debug_utils_messenger(&create_info, None)
+            .map_err(|err| {
+                VulkanError::Vulkan(String::from("failed to create debug messenger"), err)
+            })?
+    };
+
+    log::info!("debug messenger created");
+
+    Ok(debug_messenger)
+}
+
+// every debug messenger reports to the same callback, so the count is shared by all instances
+static VALIDATION_ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
+
+pub fn get_validation_error_count() -> u32 {
+    VALIDATION_ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+/// Counts the `ERROR` messages of the debug messengers it's installed in, unlike
+/// `VulkanBase::validation_error_count` only those of their `VulkanBase`s, so tests running in
+/// parallel don't see each other's errors.
+#[derive(Clone, Default)]
+pub struct ValidationErrorCapture {
+    error_count: Arc<AtomicU32>,
+}
+
+impl ValidationErrorCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables validation and `VK_EXT_debug_utils` in `config` with a debug messenger reporting
+    /// `ERROR` messages to this capture. The validation layer is still skipped when it's not
+    /// installed, see `VulkanBase::validation_layer_enabled`.
+    pub fn install(&self, config: &mut crate::VulkanBaseConfig) {
+        config.enable_validation = true;
+        config.debug_message_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+        if !config
+            .optional_instance_extensions
+            .contains(&ext::DebugUtils::name())
+        {
+            config
+                .optional_instance_extensions
+                .push(ext::DebugUtils::name());
+        }
+        config.validation_error_capture = Some(self.clone());
+    }
+
+    pub fn error_count(&self) -> u32 {
+        self.error_count.load(Ordering::Relaxed)
+    }
+}
+
+unsafe extern "system" fn vulkan_debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut std::os::raw::c_void,
+) -> vk::Bool32 {
+    if p_callback_data.is_null() {
+        return vk::FALSE;
+    }
+
+    let callback_data = &*p_callback_data;
+
+    let message_id_name = if callback_data.p_message_id_name.is_null() {
+        std::borrow::Cow::from("")
+    } else {
+        std::ffi::CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy()
+    };
+
+    let message = if callback_data.p_message.is_null() {
+        std::borrow::Cow::from("")
+    } else {
+        std::ffi::CStr::from_ptr(callback_data.p_message).to_string_lossy()
+    };
+
+    match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => {
+            log::trace!("{:?} [{}]: {}", message_type, message_id_name, message)
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
+            log::info!("{:?} [{}]: {}", message_type, message_id_name, message)
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+            log::warn!("{:?} [{}]: {}", message_type, message_id_name, message)
+        }
+        _ => {
+            VALIDATION_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+            // the error count of a ValidationErrorCapture, see create_debug_messenger
+            if !p_user_data.is_null() {
+                (*(p_user_data as *const AtomicU32)).fetch_add(1, Ordering::Relaxed);
+            }
+            log::error!("{:?} [{}]: {}", message_type, message_id_name, message)
+        }
+    }
+
+    vk::FALSE
 }
 
 pub fn create_surface_loader(entry: &ash::Entry, instance: &ash::Instance) -> khr::Surface {
@@ -128,11 +455,11 @@ pub fn create_surface_loader(entry: &ash::Entry, instance: &ash::Instance) -> kh
     surface_loader
 }
 
-pub fn create_surface(
+pub fn create_surface<W: WindowBackend + ?Sized>(
     entry: &ash::Entry,
     instance: &ash::Instance,
-    window: &winit::window::Window,
-) -> Result<vk::SurfaceKHR, String> {
+    window: &W,
+) -> Result<vk::SurfaceKHR, VulkanError> {
     log::info!("creating surface");
 
     let surface = unsafe {
@@ -143,7 +470,7 @@ pub fn create_surface(
             window.raw_window_handle(),
             None,
         )
-        .map_err(|_| String::from("failed to create surface"))?
+        .map_err(|err| VulkanError::Vulkan(String::from("failed to create surface"), err))?
     };
 
     log::info!("surface created");
@@ -155,8 +482,11 @@ fn check_device_suitability(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     required_extensions: &Vec<&std::ffi::CStr>,
+    required_features: &vk::PhysicalDeviceFeatures,
+    queue_flags: vk::QueueFlags,
+    extension_features: ExtensionFeatures,
     properties: &vk::PhysicalDeviceProperties,
-) -> Result<(), String> {
+) -> Result<(), VulkanError> {
     // api version
     log::info!("checking api version");
     log::info!(
@@ -169,42 +499,272 @@ fn check_device_suitability(
     if vk::api_version_major(properties.api_version) < 1
         && vk::api_version_minor(properties.api_version) < 2
     {
-        return Err(String::from(
+        return Err(VulkanError::Other(String::from(
             "the device does not support API version 1.2.0",
-        ));
+        )));
     }
 
     // features
     log::info!("checking supported features");
     let features = unsafe { instance.get_physical_device_features(physical_device) };
 
-    // TODO pass as parameter
-    if features.tessellation_shader == 0 {
-        return Err(String::from(
-            "the device does not support tesselation shader",
-        ));
+    let is_portability_subset = is_portability_subset_device(instance, physical_device);
+
+    let missing_features = get_missing_features(required_features, &features);
+    if !missing_features.is_empty() {
+        // MoltenVK only exposes tessellation on some Apple GPUs
+        let note = match is_portability_subset {
+            true => " (portability subset device, tessellation support varies)",
+            false => "",
+        };
+
+        return Err(VulkanError::Other(format!(
+            "the device does not support features: {}{}",
+            missing_features.join(", "),
+            note
+        )));
     }
 
-    log::info!("tesselation shader supported");
+    if is_portability_subset {
+        check_portability_subset_features(instance, physical_device, required_features);
+    }
 
-    if features.fill_mode_non_solid == 0 {
-        return Err(String::from(
-            "the device does not support fill mode non solid",
-        ));
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+    let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::default();
+    let mut timeline_semaphore_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut dynamic_rendering_features)
+        .push_next(&mut synchronization2_features)
+        .push_next(&mut timeline_semaphore_features)
+        .build();
+
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    if extension_features.dynamic_rendering
+        && dynamic_rendering_features.dynamic_rendering == vk::FALSE
+    {
+        return Err(VulkanError::Other(String::from(
+            "the device does not support dynamic rendering",
+        )));
+    }
+
+    if extension_features.synchronization2
+        && synchronization2_features.synchronization2 == vk::FALSE
+    {
+        return Err(VulkanError::Other(String::from(
+            "the device does not support synchronization2",
+        )));
+    }
+
+    if extension_features.timeline_semaphore
+        && timeline_semaphore_features.timeline_semaphore == vk::FALSE
+    {
+        return Err(VulkanError::Other(String::from(
+            "the device does not support timeline semaphores",
+        )));
+    }
+
+    log::info!("all features are supported");
+
+    // queues
+    log::info!("checking queue capabilities");
+    let queue_family_props =
+        unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+    if !queue_family_props
+        .iter()
+        .any(|p| p.queue_count > 0 && p.queue_flags.contains(queue_flags))
+    {
+        return Err(VulkanError::Other(format!(
+            "the device has no queue family supporting {:?}",
+            queue_flags
+        )));
     }
 
-    log::info!("fill mode non solid supported");
+    log::info!("queue capabilities are supported");
 
     check_required_device_extensions(instance, physical_device, required_extensions)?;
 
     Ok(())
 }
 
+// calls `$macro!($args, ...)` with every `vk::PhysicalDeviceFeatures` field appended
+macro_rules! with_feature_names {
+    ($macro:ident!($($args:expr),+)) => {
+        $macro!(
+            $($args),+,
+            robust_buffer_access,
+            full_draw_index_uint32,
+            image_cube_array,
+            independent_blend,
+            geometry_shader,
+            tessellation_shader,
+            sample_rate_shading,
+            dual_src_blend,
+            logic_op,
+            multi_draw_indirect,
+            draw_indirect_first_instance,
+            depth_clamp,
+            depth_bias_clamp,
+            fill_mode_non_solid,
+            depth_bounds,
+            wide_lines,
+            large_points,
+            alpha_to_one,
+            multi_viewport,
+            sampler_anisotropy,
+            texture_compression_etc2,
+            texture_compression_astc_ldr,
+            texture_compression_bc,
+            occlusion_query_precise,
+            pipeline_statistics_query,
+            vertex_pipeline_stores_and_atomics,
+            fragment_stores_and_atomics,
+            shader_tessellation_and_geometry_point_size,
+            shader_image_gather_extended,
+            shader_storage_image_extended_formats,
+            shader_storage_image_multisample,
+            shader_storage_image_read_without_format,
+            shader_storage_image_write_without_format,
+            shader_uniform_buffer_array_dynamic_indexing,
+            shader_sampled_image_array_dynamic_indexing,
+            shader_storage_buffer_array_dynamic_indexing,
+            shader_storage_image_array_dynamic_indexing,
+            shader_clip_distance,
+            shader_cull_distance,
+            shader_float64,
+            shader_int64,
+            shader_int16,
+            shader_resource_residency,
+            shader_resource_min_lod,
+            sparse_binding,
+            sparse_residency_buffer,
+            sparse_residency_image2_d,
+            sparse_residency_image3_d,
+            sparse_residency2_samples,
+            sparse_residency4_samples,
+            sparse_residency8_samples,
+            sparse_residency16_samples,
+            sparse_residency_aliased,
+            variable_multisample_rate,
+            inherited_queries,
+        )
+    };
+}
+
+macro_rules! collect_missing_features {
+    ($required:expr, $supported:expr, $($feature:ident),+ $(,)?) => {{
+        let mut missing = Vec::new();
+        $(
+            if $required.$feature != 0 && $supported.$feature == 0 {
+                missing.push(stringify!($feature));
+            }
+        )+
+        missing
+    }};
+}
+
+// enables each optional feature of `$enabled` that is supported, returns the unsupported ones
+macro_rules! add_supported_features {
+    ($enabled:expr, $optional:expr, $supported:expr, $($feature:ident),+ $(,)?) => {{
+        let mut unsupported = Vec::new();
+        $(
+            if $optional.$feature != 0 && $enabled.$feature == 0 {
+                match $supported.$feature != 0 {
+                    true => $enabled.$feature = vk::TRUE,
+                    false => unsupported.push(stringify!($feature)),
+                }
+            }
+        )+
+        unsupported
+    }};
+}
+
+fn get_missing_features(
+    required: &vk::PhysicalDeviceFeatures,
+    supported: &vk::PhysicalDeviceFeatures,
+) -> Vec<&'static str> {
+    with_feature_names!(collect_missing_features!(required, supported))
+}
+
+/// `required` plus the features of `optional` the device supports, as passed to
+/// `create_logical_device`.
+pub fn get_enabled_features(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    required: &vk::PhysicalDeviceFeatures,
+    optional: &vk::PhysicalDeviceFeatures,
+) -> vk::PhysicalDeviceFeatures {
+    let supported = unsafe { instance.get_physical_device_features(physical_device) };
+
+    let mut enabled = *required;
+    let unsupported = with_feature_names!(add_supported_features!(enabled, optional, supported));
+    if !unsupported.is_empty() {
+        log::info!(
+            "optional features not supported: {}",
+            unsupported.join(", ")
+        );
+    }
+
+    enabled
+}
+
+fn is_portability_subset_device(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    let extensions =
+        match unsafe { instance.enumerate_device_extension_properties(physical_device) } {
+            Ok(extensions) => extensions,
+            Err(_) => {
+                log::warn!("failed to enumerate device extension properties");
+                return false;
+            }
+        };
+
+    extensions.iter().any(|extension| {
+        vk::KhrPortabilitySubsetFn::name()
+            == unsafe { std::ffi::CStr::from_ptr(extension.extension_name.as_ptr()) }
+    })
+}
+
+/// Portability subset restrictions don't make a device unsuitable, they are only reported.
+fn check_portability_subset_features(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    required_features: &vk::PhysicalDeviceFeatures,
+) {
+    log::info!("the device is a portability subset implementation");
+
+    let mut portability_subset_features = vk::PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut portability_subset_features)
+        .build();
+
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    if required_features.tessellation_shader == vk::TRUE {
+        if portability_subset_features.tessellation_isolines == vk::FALSE {
+            log::warn!("the device does not support tessellation isolines");
+        }
+
+        if portability_subset_features.tessellation_point_mode == vk::FALSE {
+            log::warn!("the device does not support tessellation point mode");
+        }
+    }
+
+    if required_features.fill_mode_non_solid == vk::TRUE
+        && portability_subset_features.point_polygons == vk::FALSE
+    {
+        log::warn!("the device does not support point polygons");
+    }
+}
+
 fn check_required_device_extensions(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     required_extensions: &Vec<&std::ffi::CStr>,
-) -> Result<(), String> {
+) -> Result<(), VulkanError> {
     log::info!(
         "checking required device extensions: {:?}",
         required_extensions
@@ -213,9 +773,10 @@ fn check_required_device_extensions(
     let supported_device_extensions =
         match unsafe { instance.enumerate_device_extension_properties(physical_device) } {
             Ok(props) => props,
-            Err(_) => {
-                return Err(String::from(
-                    "failed to enumerate instance extension properies",
+            Err(err) => {
+                return Err(VulkanError::Vulkan(
+                    String::from("failed to enumerate instance extension properies"),
+                    err,
                 ));
             }
         };
@@ -228,9 +789,8 @@ fn check_required_device_extensions(
 
     for extension_name in required_extensions {
         if !supported_device_extensions_set.contains(extension_name) {
-            return Err(format!(
-                "device extension {:?} is not supported",
-                extension_name
+            return Err(VulkanError::DeviceExtensionUnsupported(
+                extension_name.to_string_lossy().into_owned(),
             ));
         }
     }
@@ -240,15 +800,61 @@ fn check_required_device_extensions(
     Ok(())
 }
 
+/// Appends `extension` to `device_extensions` when `physical_device` supports it. Returns
+/// whether it does.
+pub fn add_optional_device_extension<'a>(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device_extensions: &mut Vec<&'a std::ffi::CStr>,
+    extension: &'a std::ffi::CStr,
+) -> bool {
+    let supported_device_extensions =
+        match unsafe { instance.enumerate_device_extension_properties(physical_device) } {
+            Ok(props) => props,
+            Err(_) => return false,
+        };
+
+    let is_supported = supported_device_extensions.iter().any(
+        |props| unsafe { std::ffi::CStr::from_ptr(props.extension_name.as_ptr()) } == extension,
+    );
+
+    if is_supported && !device_extensions.contains(&extension) {
+        device_extensions.push(extension);
+    }
+
+    log::info!(
+        "optional device extension {:?} supported: {}",
+        extension,
+        is_supported
+    );
+
+    is_supported
+}
+
+const GPU_INDEX_ENV_VAR: &str = "LYNX_GPU_INDEX";
+const GPU_NAME_ENV_VAR: &str = "LYNX_GPU_NAME";
+
+/// Picks the best ranked suitable device. `LYNX_GPU_INDEX` (enumeration index) or
+/// `LYNX_GPU_NAME` (case-insensitive substring of the device name) override the ranking when
+/// they name a suitable device, the index wins when both are set.
 pub fn get_physical_device<'a>(
     instance: &ash::Instance,
     required_device_extensions: &Vec<&'a std::ffi::CStr>,
-) -> Result<vk::PhysicalDevice, String> {
+    required_features: &vk::PhysicalDeviceFeatures,
+    device_preference: &crate::DevicePreference,
+    queue_flags: vk::QueueFlags,
+    extension_features: ExtensionFeatures,
+) -> Result<vk::PhysicalDevice, VulkanError> {
     log::info!("enumerating physical devices");
 
     let devices = match unsafe { instance.enumerate_physical_devices() } {
         Ok(devices) => devices,
-        Err(_) => return Err(String::from("failed to enumerate physical devices")),
+        Err(err) => {
+            return Err(VulkanError::Vulkan(
+                String::from("failed to enumerate physical devices"),
+                err,
+            ))
+        }
     };
 
     log::info!("available physical devices: ");
@@ -258,7 +864,9 @@ pub fn get_physical_device<'a>(
         log::info!("{:?}", device_name);
     }
 
-    for physical_device in devices {
+    let mut candidates = Vec::with_capacity(devices.len());
+
+    for (index, physical_device) in devices.into_iter().enumerate() {
         let properties = unsafe { instance.get_physical_device_properties(physical_device) };
         let device_name = unsafe { std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) };
 
@@ -268,18 +876,206 @@ pub fn get_physical_device<'a>(
             instance,
             physical_device,
             required_device_extensions,
+            required_features,
+            queue_flags,
+            extension_features,
             &properties,
         ) {
             log::warn!("{:?}: {}", device_name, msg);
             continue;
         }
 
-        log::info!("selected physical device {:?}", device_name);
+        let score =
+            score_physical_device(instance, physical_device, &properties, device_preference);
+
+        candidates.push((
+            physical_device,
+            index,
+            device_name.to_string_lossy().into_owned(),
+            score,
+        ));
+    }
+
+    // stable sort keeps enumeration order between devices with equal scores
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.3));
+
+    log::info!("ranked physical devices ({:?}):", device_preference);
+    for (i, (_, _, device_name, score)) in candidates.iter().enumerate() {
+        log::info!("{}: {:?} score {}", i, device_name, score);
+    }
+
+    if let Some(position) = get_env_override(&candidates) {
+        let candidate = candidates.remove(position);
+        candidates.insert(0, candidate);
+    }
+
+    match candidates.first() {
+        Some((physical_device, _, device_name, _)) => {
+            log::info!("selected physical device {:?}", device_name);
+            Ok(*physical_device)
+        }
+        None => Err(VulkanError::NoSuitableDevice),
+    }
+}
+
+// position in `candidates` of the device named by the env vars, if it is among them
+fn get_env_override(candidates: &[(vk::PhysicalDevice, usize, String, u64)]) -> Option<usize> {
+    if let Ok(value) = std::env::var(GPU_INDEX_ENV_VAR) {
+        let index = match value.trim().parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => {
+                log::warn!("{}={:?} ignored, not an index", GPU_INDEX_ENV_VAR, value);
+                return None;
+            }
+        };
+
+        let position = candidates
+            .iter()
+            .position(|(_, candidate_index, _, _)| *candidate_index == index);
+
+        match position {
+            Some(_) => log::info!("{}={} honored", GPU_INDEX_ENV_VAR, index),
+            None => log::warn!(
+                "{}={} ignored, no suitable device has that index",
+                GPU_INDEX_ENV_VAR,
+                index
+            ),
+        }
+
+        return position;
+    }
+
+    if let Ok(name) = std::env::var(GPU_NAME_ENV_VAR) {
+        let name_lowercase = name.to_lowercase();
+
+        let position = candidates.iter().position(|(_, _, device_name, _)| {
+            device_name.to_lowercase().contains(&name_lowercase)
+        });
+
+        match position {
+            Some(_) => log::info!("{}={:?} honored", GPU_NAME_ENV_VAR, name),
+            None => log::warn!(
+                "{}={:?} ignored, no suitable device matches it",
+                GPU_NAME_ENV_VAR,
+                name
+            ),
+        }
+
+        return position;
+    }
+
+    None
+}
+
+fn score_device_type(
+    device_type: vk::PhysicalDeviceType,
+    preferred: vk::PhysicalDeviceType,
+) -> u64 {
+    if device_type == preferred {
+        return 4;
+    }
+
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU | vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    }
+}
+
+fn get_device_local_memory_size(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> vk::DeviceSize {
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+    memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum()
+}
+
+// the legacy BAR window is 256 MiB, a larger host visible device local heap means resizable BAR
+const REBAR_MIN_HEAP_SIZE: vk::DeviceSize = 256 * 1024 * 1024;
+
+/// Memory types that are device local and host visible and coherent in a heap larger than
+/// the legacy BAR window, 0 without resizable BAR.
+pub fn get_rebar_memory_type_bits(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> u32 {
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
 
-        return Ok(physical_device);
+    let rebar_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL
+        | vk::MemoryPropertyFlags::HOST_VISIBLE
+        | vk::MemoryPropertyFlags::HOST_COHERENT;
+
+    let memory_type_bits = memory_properties.memory_types
+        [..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .filter(|(_, memory_type)| {
+            memory_type.property_flags.contains(rebar_flags)
+                && memory_properties.memory_heaps[memory_type.heap_index as usize].size
+                    > REBAR_MIN_HEAP_SIZE
+        })
+        .fold(0, |bits, (index, _)| bits | (1 << index));
+
+    if memory_type_bits != 0 {
+        log::info!(
+            "resizable BAR memory types: {:#b}, used for per-frame uniforms",
+            memory_type_bits
+        );
+    } else {
+        log::info!("no resizable BAR memory, per-frame uniforms use CpuToGpu memory");
     }
 
-    Err(String::from("failed to find suitable device"))
+    memory_type_bits
+}
+
+fn score_physical_device(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    properties: &vk::PhysicalDeviceProperties,
+    device_preference: &crate::DevicePreference,
+) -> u64 {
+    // memory in MiB is used as a tie breaker between devices of the same type
+    let memory_mib = get_device_local_memory_size(instance, physical_device) / (1024 * 1024);
+    let memory_score = memory_mib.min(u32::MAX as u64);
+
+    match device_preference {
+        crate::DevicePreference::DiscreteFirst => {
+            (score_device_type(properties.device_type, vk::PhysicalDeviceType::DISCRETE_GPU) << 32)
+                + memory_score
+        }
+        crate::DevicePreference::IntegratedFirst => {
+            (score_device_type(
+                properties.device_type,
+                vk::PhysicalDeviceType::INTEGRATED_GPU,
+            ) << 32)
+                + memory_score
+        }
+        crate::DevicePreference::HighestMemory => memory_score,
+        crate::DevicePreference::ByName(name) => {
+            let device_name = unsafe { std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) }
+                .to_string_lossy()
+                .to_lowercase();
+
+            let name_score = if device_name.contains(&name.to_lowercase()) {
+                8
+            } else {
+                0
+            };
+
+            ((name_score
+                + score_device_type(properties.device_type, vk::PhysicalDeviceType::DISCRETE_GPU))
+                << 32)
+                + memory_score
+        }
+    }
 }
 
 pub fn get_physical_device_properties(
@@ -293,163 +1089,334 @@ pub fn get_surface_format(
     physical_device: vk::PhysicalDevice,
     surface_loader: &khr::Surface,
     surface: vk::SurfaceKHR,
-) -> Result<vk::SurfaceFormatKHR, String> {
+    prefer_srgb: bool,
+    hdr_color_spaces: &[vk::ColorSpaceKHR],
+) -> Result<vk::SurfaceFormatKHR, VulkanError> {
     log::info!("getting surface format");
 
     let formats = match unsafe {
         surface_loader.get_physical_device_surface_formats(physical_device, surface)
     } {
         Ok(formats) => formats,
-        Err(_) => {
-            return Err(String::from(
-                "failed to get physical device surface formats",
+        Err(err) => {
+            return Err(VulkanError::Vulkan(
+                String::from("failed to get physical device surface formats"),
+                err,
             ));
         }
     };
 
-    for f in &formats {
-        if f.format == vk::Format::B8G8R8A8_UNORM
-            && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        {
-            let surface_format = vk::SurfaceFormatKHR {
-                format: vk::Format::B8G8R8A8_UNORM,
-                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-            };
-
-            log::info!("selected surface format: {:?}", surface_format);
-
+    for &color_space in hdr_color_spaces {
+        if let Some(&surface_format) = formats.iter().find(|f| f.color_space == color_space) {
+            log::info!("selected HDR surface format: {:?}", surface_format);
             return Ok(surface_format);
         }
     }
 
-    log::info!("selected first surface format: {:?}", formats[0]);
+    if !hdr_color_spaces.is_empty() {
+        log::warn!(
+            "none of the HDR color spaces {:?} is supported, falling back to SDR",
+            hdr_color_spaces
+        );
+    }
+
+    let unorm_format = vk::SurfaceFormatKHR {
+        format: vk::Format::B8G8R8A8_UNORM,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    };
+
+    let preferred_formats = if prefer_srgb {
+        vec![
+            vk::SurfaceFormatKHR {
+                format: vk::Format::B8G8R8A8_SRGB,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+            vk::SurfaceFormatKHR {
+                format: vk::Format::R8G8B8A8_SRGB,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+            unorm_format,
+        ]
+    } else {
+        vec![unorm_format]
+    };
+
+    let surface_format = vulkan_utils::choose_surface_format(&formats, &preferred_formats)?;
 
-    Ok(formats[0])
+    log::info!("selected surface format: {:?}", surface_format);
+
+    Ok(surface_format)
 }
 
-pub fn get_present_mode(
+pub fn get_supported_present_modes(
     physical_device: vk::PhysicalDevice,
     surface_loader: &khr::Surface,
     surface: vk::SurfaceKHR,
-) -> Result<vk::PresentModeKHR, String> {
-    log::info!("getting present mode");
-
+) -> Result<Vec<vk::PresentModeKHR>, VulkanError> {
     let modes = match unsafe {
         surface_loader.get_physical_device_surface_present_modes(physical_device, surface)
     } {
         Ok(formats) => formats,
-        Err(_) => {
-            return Err(String::from(
-                "failed to get physical device surface present modes",
+        Err(err) => {
+            return Err(VulkanError::Vulkan(
+                String::from("failed to get physical device surface present modes"),
+                err,
             ));
         }
     };
 
     if modes.is_empty() {
-        return Err(String::from(
+        return Err(VulkanError::Other(String::from(
             "failed to get physical device surface present modes",
-        ));
+        )));
     }
 
-    if modes.contains(&vk::PresentModeKHR::MAILBOX) {
-        let present_mode = vk::PresentModeKHR::MAILBOX;
+    Ok(modes)
+}
 
-        log::info!("selected present mode: {:?}", present_mode);
+pub fn get_present_mode(
+    physical_device: vk::PhysicalDevice,
+    surface_loader: &khr::Surface,
+    surface: vk::SurfaceKHR,
+    preferred_present_modes: &[vk::PresentModeKHR],
+) -> Result<vk::PresentModeKHR, VulkanError> {
+    log::info!("getting present mode");
 
-        return Ok(present_mode);
-    }
+    let modes = get_supported_present_modes(physical_device, surface_loader, surface)?;
 
-    if modes.contains(&vk::PresentModeKHR::IMMEDIATE) {
-        let present_mode = vk::PresentModeKHR::IMMEDIATE;
+    // FIFO is always supported
+    let present_mode = preferred_present_modes
+        .iter()
+        .copied()
+        .find(|mode| modes.contains(mode))
+        .unwrap_or(vk::PresentModeKHR::FIFO);
 
-        log::info!("selected present mode: {:?}", present_mode);
+    log::info!("selected present mode: {:?}", present_mode);
 
-        return Ok(present_mode);
-    }
+    Ok(present_mode)
+}
 
-    let present_mode = vk::PresentModeKHR::FIFO;
+pub fn get_composite_alpha(
+    surface_capabilities: &vk::SurfaceCapabilitiesKHR,
+    preferred_composite_alphas: &[vk::CompositeAlphaFlagsKHR],
+) -> Result<vk::CompositeAlphaFlagsKHR, VulkanError> {
+    log::info!("getting composite alpha");
 
-    log::info!("selected present mode: {:?}", present_mode);
+    let composite_alpha = preferred_composite_alphas
+        .iter()
+        .copied()
+        .find(|&alpha| {
+            surface_capabilities
+                .supported_composite_alpha
+                .contains(alpha)
+        })
+        .ok_or_else(|| {
+            VulkanError::Other(format!(
+                "none of the composite alpha modes {:?} is supported, supported modes: {:?}",
+                preferred_composite_alphas, surface_capabilities.supported_composite_alpha
+            ))
+        })?;
 
-    Ok(present_mode)
+    log::info!("selected composite alpha: {:?}", composite_alpha);
+
+    Ok(composite_alpha)
+}
+
+/// Passing a null `surface` reports no present support.
+pub fn get_queue_family_infos(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    surface_loader: &khr::Surface,
+    surface: vk::SurfaceKHR,
+) -> Vec<QueueFamilyInfo> {
+    let props = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+    props
+        .iter()
+        .enumerate()
+        .map(|(ind, p)| {
+            let present_supported = surface != vk::SurfaceKHR::null()
+                && unsafe {
+                    surface_loader
+                        .get_physical_device_surface_support(physical_device, ind as u32, surface)
+                        .unwrap_or(false)
+                };
+
+            QueueFamilyInfo {
+                index: ind as u32,
+                queue_flags: p.queue_flags,
+                queue_count: p.queue_count,
+                present_supported,
+                timestamp_valid_bits: p.timestamp_valid_bits,
+            }
+        })
+        .collect()
 }
 
-pub fn get_queue_family(
+pub fn log_queue_family_infos(queue_family_infos: &[QueueFamilyInfo]) {
+    log::info!("queue families:");
+    for info in queue_family_infos {
+        log::info!(
+            "{}: {:?}, {} queues, present: {}, timestamp bits: {}",
+            info.index,
+            info.queue_flags,
+            info.queue_count,
+            info.present_supported,
+            info.timestamp_valid_bits
+        );
+    }
+}
+
+/// First family with `queue_flags`, which may be `COMPUTE` for compute-only devices. With
+/// `require_present` the family must also be able to present to `surface`, otherwise the surface
+/// isn't queried and may be null, e.g. for off-screen rendering.
+pub fn find_graphics_queue(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     surface_loader: &khr::Surface,
     surface: vk::SurfaceKHR,
-) -> Result<u32, String> {
-    log::info!("getting queue family");
+    queue_flags: vk::QueueFlags,
+    require_present: bool,
+) -> Result<u32, VulkanError> {
+    log::info!(
+        "getting {:?} queue family, present required: {}",
+        queue_flags,
+        require_present
+    );
 
     let props = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
 
     for (ind, p) in props.iter().enumerate() {
-        if p.queue_count > 0 && p.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-            let present_supported = match unsafe {
-                surface_loader.get_physical_device_surface_support(
-                    physical_device,
-                    ind as u32,
-                    surface,
-                )
-            } {
-                Ok(result) => result,
-                Err(_) => {
-                    return Err(String::from(
-                        "failed to get physical device surface_support",
-                    ));
-                }
+        if p.queue_count == 0 || !p.queue_flags.contains(queue_flags) {
+            continue;
+        }
+
+        if require_present {
+            let present_supported = unsafe {
+                surface_loader
+                    .get_physical_device_surface_support(physical_device, ind as u32, surface)
+                    .map_err(|err| {
+                        VulkanError::Vulkan(
+                            String::from("failed to get physical device surface_support"),
+                            err,
+                        )
+                    })?
             };
 
-            if present_supported {
-                log::info!("selected queue family: {}", ind);
-                return Ok(ind as u32);
+            if !present_supported {
+                continue;
             }
         }
+
+        log::info!("selected queue family: {}", ind);
+        return Ok(ind as u32);
+    }
+
+    match require_present {
+        true => Err(VulkanError::Other(format!(
+            "failed to find {:?} queue with present support",
+            queue_flags
+        ))),
+        false => Err(VulkanError::Other(format!(
+            "failed to find {:?} queue",
+            queue_flags
+        ))),
     }
+}
+
+pub fn get_transfer_queue_family(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Option<u32> {
+    log::info!("getting transfer queue family");
+
+    let props = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+    let is_dedicated_transfer = |p: &vk::QueueFamilyProperties| {
+        p.queue_count > 0
+            && p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+            && !p.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+    };
+
+    // prefer a transfer-only family (usually backed by a DMA engine) over an async compute one
+    let transfer_queue_family = props
+        .iter()
+        .position(|p| is_dedicated_transfer(p) && !p.queue_flags.contains(vk::QueueFlags::COMPUTE))
+        .or_else(|| props.iter().position(is_dedicated_transfer))
+        .map(|ind| ind as u32);
 
-    Err(String::from(
-        "failed to find graphics queue with present support",
-    ))
+    match transfer_queue_family {
+        Some(ind) => log::info!("selected transfer queue family: {}", ind),
+        None => log::info!("no dedicated transfer queue family, using graphics queue"),
+    }
+
+    transfer_queue_family
 }
 
 pub fn get_depth_format(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
-) -> Result<vk::Format, String> {
-    log::info!("getting depth format");
+    needs_stencil: bool,
+    needs_transfer_src: bool,
+) -> Result<vk::Format, VulkanError> {
+    log::info!(
+        "getting depth format, needs stencil: {}, needs transfer src: {}",
+        needs_stencil,
+        needs_transfer_src
+    );
 
-    let format_candidates = [
+    let mut required_features = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT;
+    if needs_transfer_src {
+        required_features |= vk::FormatFeatureFlags::TRANSFER_SRC;
+    }
+
+    let depth_only_candidates = [vk::Format::D32_SFLOAT, vk::Format::D16_UNORM];
+
+    let depth_stencil_candidates = [
         vk::Format::D16_UNORM_S8_UINT,
         vk::Format::D24_UNORM_S8_UINT,
         vk::Format::D32_SFLOAT_S8_UINT,
     ];
 
-    for &format in &format_candidates {
+    // a combined format still works without stencil, only slower and bigger
+    let format_candidates = if needs_stencil {
+        depth_stencil_candidates.to_vec()
+    } else {
+        [&depth_only_candidates[..], &depth_stencil_candidates[..]].concat()
+    };
+
+    for format in format_candidates {
         let props =
             unsafe { instance.get_physical_device_format_properties(physical_device, format) };
 
-        if props
-            .optimal_tiling_features
-            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
-        {
+        if props.optimal_tiling_features.contains(required_features) {
             log::info!("selected depth format: {:?}", format);
             return Ok(format);
         }
     }
 
-    Err(String::from("failed to find depth format"))
+    Err(VulkanError::Other(String::from(
+        "failed to find depth format",
+    )))
 }
 
 pub fn create_logical_device<'a>(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     queue_family: u32,
+    transfer_queue_family: Option<u32>,
     device_extensions: &Vec<&'a std::ffi::CStr>,
-) -> Result<ash::Device, String> {
+    features: &vk::PhysicalDeviceFeatures,
+    extension_features: ExtensionFeatures,
+) -> Result<ash::Device, VulkanError> {
     log::info!("creating logical devices");
 
-    let queue_indices = [queue_family];
+    let mut queue_indices = vec![queue_family];
+    if let Some(family) = transfer_queue_family {
+        if family != queue_family {
+            queue_indices.push(family);
+        }
+    }
 
     let mut queue_priorities = Vec::new();
     for _ in &queue_indices {
@@ -466,26 +1433,62 @@ pub fn create_logical_device<'a>(
         queue_create_infos.push(info.build());
     }
 
-    // TODO pass features parameter
-    let features = vk::PhysicalDeviceFeatures::builder()
-        .tessellation_shader(true)
-        .fill_mode_non_solid(true)
-        .build();
-
-    let device_extensions_raw = device_extensions
+    let mut device_extensions_raw = device_extensions
         .iter()
         .map(|&s| s.as_ptr())
         .collect::<Vec<*const std::os::raw::c_char>>();
 
-    let create_info = vk::DeviceCreateInfo::builder()
+    // the spec requires enabling the extension whenever the device exposes it
+    let portability_subset_name = vk::KhrPortabilitySubsetFn::name();
+    if !device_extensions.contains(&portability_subset_name)
+        && is_portability_subset_device(instance, physical_device)
+    {
+        log::info!("enabling {:?}", portability_subset_name);
+        device_extensions_raw.push(portability_subset_name.as_ptr());
+    }
+
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::builder()
+        .dynamic_rendering(true)
+        .build();
+
+    let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::builder()
+        .synchronization2(true)
+        .build();
+
+    let mut timeline_semaphore_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder()
+        .timeline_semaphore(true)
+        .build();
+
+    let mut swapchain_maintenance1_features =
+        vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT::builder()
+            .swapchain_maintenance1(true)
+            .build();
+
+    let mut create_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_create_infos)
         .enabled_extension_names(&device_extensions_raw)
-        .enabled_features(&features);
+        .enabled_features(features);
+
+    if extension_features.dynamic_rendering {
+        create_info = create_info.push_next(&mut dynamic_rendering_features);
+    }
+
+    if extension_features.synchronization2 {
+        create_info = create_info.push_next(&mut synchronization2_features);
+    }
+
+    if extension_features.timeline_semaphore {
+        create_info = create_info.push_next(&mut timeline_semaphore_features);
+    }
+
+    if extension_features.swapchain_maintenance1 {
+        create_info = create_info.push_next(&mut swapchain_maintenance1_features);
+    }
 
     let device = unsafe {
         instance
             .create_device(physical_device, &create_info, None)
-            .map_err(|_| String::from("failed to create device"))?
+            .map_err(|err| VulkanError::Vulkan(String::from("failed to create device"), err))?
     };
 
     log::info!("logical device created");
@@ -493,6 +1496,27 @@ pub fn create_logical_device<'a>(
     return Ok(device);
 }
 
+pub fn get_device_extensions<'a>(
+    required_device_extensions: &Vec<&'a std::ffi::CStr>,
+    extension_features: ExtensionFeatures,
+) -> Vec<&'a std::ffi::CStr> {
+    let mut device_extensions = required_device_extensions.clone();
+
+    if extension_features.dynamic_rendering
+        && !device_extensions.contains(&khr::DynamicRendering::name())
+    {
+        device_extensions.push(khr::DynamicRendering::name());
+    }
+
+    if extension_features.synchronization2
+        && !device_extensions.contains(&khr::Synchronization2::name())
+    {
+        device_extensions.push(khr::Synchronization2::name());
+    }
+
+    device_extensions
+}
+
 pub fn get_queue(device: &ash::Device, queue_family: u32) -> vk::Queue {
     let queue = unsafe { device.get_device_queue(queue_family, 0) };
 
@@ -501,19 +1525,40 @@ pub fn get_queue(device: &ash::Device, queue_family: u32) -> vk::Queue {
     queue
 }
 
+const ALLOC_VERBOSE_ENV_VAR: &str = "LYNX_ALLOC_VERBOSE";
+
+fn is_alloc_verbose_requested() -> bool {
+    match std::env::var(ALLOC_VERBOSE_ENV_VAR) {
+        Ok(value) => match value.to_lowercase().as_str() {
+            "1" | "true" | "on" => true,
+            "0" | "false" | "off" => false,
+            _ => {
+                log::warn!(
+                    "unrecognized {} value {:?}, ignoring",
+                    ALLOC_VERBOSE_ENV_VAR,
+                    value
+                );
+                false
+            }
+        },
+        Err(_) => false,
+    }
+}
+
 pub fn create_allocator(
     instance: &ash::Instance,
     device: &ash::Device,
     physical_device: vk::PhysicalDevice,
-) -> Result<vulkan::Allocator, String> {
-    let debug_settings = gpu_allocator::AllocatorDebugSettings {
-        log_memory_information: true,
-        log_leaks_on_shutdown: true,
-        store_stack_traces: false,
-        log_allocations: true,
-        log_frees: true,
-        log_stack_traces: false,
-    };
+    debug_settings: gpu_allocator::AllocatorDebugSettings,
+) -> Result<vulkan::Allocator, VulkanError> {
+    let mut debug_settings = debug_settings;
+
+    if is_alloc_verbose_requested() {
+        log::info!("{} set, logging every allocation", ALLOC_VERBOSE_ENV_VAR);
+        debug_settings.log_memory_information = true;
+        debug_settings.log_allocations = true;
+        debug_settings.log_frees = true;
+    }
 
     let create_info = &vulkan::AllocatorCreateDesc {
         instance: instance.clone(),
@@ -524,7 +1569,7 @@ pub fn create_allocator(
     };
 
     let allocator = vulkan::Allocator::new(&create_info)
-        .map_err(|_| String::from("failed to create allocator"))?;
+        .map_err(|err| VulkanError::Allocation(String::from("failed to create allocator"), err))?;
 
     log::info!("allocator created");
 
@@ -543,13 +1588,18 @@ pub fn get_surface_capabilities(
     surface_loader: &khr::Surface,
     physical_device: vk::PhysicalDevice,
     surface: vk::SurfaceKHR,
-) -> Result<vk::SurfaceCapabilitiesKHR, String> {
+) -> Result<vk::SurfaceCapabilitiesKHR, VulkanError> {
     log::info!("getting surface capabilities");
 
     let surface_capabilities = unsafe {
         surface_loader
             .get_physical_device_surface_capabilities(physical_device, surface)
-            .map_err(|_| String::from("failed to get physical device surface capabilities"))?
+            .map_err(|err| {
+                VulkanError::Vulkan(
+                    String::from("failed to get physical device surface capabilities"),
+                    err,
+                )
+            })?
     };
 
     log::info!("surface capabilities got");
@@ -557,38 +1607,151 @@ pub fn get_surface_capabilities(
     Ok(surface_capabilities)
 }
 
-pub fn get_surface_extent(
-    window: &winit::window::Window,
+/// Present modes a swapchain created with `present_mode` can switch to at present time without
+/// being recreated, `present_mode` included. Needs `VK_EXT_surface_maintenance1`.
+pub fn get_compatible_present_modes(
+    surface_capabilities2_loader: &khr::GetSurfaceCapabilities2,
+    physical_device: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+    present_mode: vk::PresentModeKHR,
+) -> Result<Vec<vk::PresentModeKHR>, VulkanError> {
+    log::info!("getting present modes compatible with {:?}", present_mode);
+
+    let mut surface_present_mode = vk::SurfacePresentModeEXT::builder().present_mode(present_mode);
+    let surface_info = vk::PhysicalDeviceSurfaceInfo2KHR::builder()
+        .surface(surface)
+        .push_next(&mut surface_present_mode);
+
+    // ash's wrapper doesn't take a pNext chain for the output
+    let get_capabilities = |capabilities: &mut vk::SurfaceCapabilities2KHR| unsafe {
+        (surface_capabilities2_loader
+            .fp()
+            .get_physical_device_surface_capabilities2_khr)(
+            physical_device,
+            &*surface_info,
+            capabilities,
+        )
+        .result()
+        .map_err(|err| {
+            VulkanError::Vulkan(String::from("failed to get compatible present modes"), err)
+        })
+    };
+
+    let mut compatibility = vk::SurfacePresentModeCompatibilityEXT::default();
+    get_capabilities(
+        &mut vk::SurfaceCapabilities2KHR::builder()
+            .push_next(&mut compatibility)
+            .build(),
+    )?;
+
+    let mut present_modes =
+        vec![vk::PresentModeKHR::default(); compatibility.present_mode_count as usize];
+    let mut compatibility =
+        vk::SurfacePresentModeCompatibilityEXT::builder().present_modes(&mut present_modes);
+    get_capabilities(
+        &mut vk::SurfaceCapabilities2KHR::builder()
+            .push_next(&mut compatibility)
+            .build(),
+    )?;
+    let present_mode_count = compatibility.present_mode_count as usize;
+
+    present_modes.truncate(present_mode_count);
+
+    log::info!("compatible present modes: {:?}", present_modes);
+
+    Ok(present_modes)
+}
+
+pub fn is_rotated_transform(transform: vk::SurfaceTransformFlagsKHR) -> bool {
+    transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_90
+            | vk::SurfaceTransformFlagsKHR::ROTATE_270
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_90
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_270,
+    )
+}
+
+/// Clockwise rotation in degrees the presentation engine expects the content to be rendered
+/// with for `transform`. Mirroring is not included.
+pub fn get_pre_rotation_degrees(transform: vk::SurfaceTransformFlagsKHR) -> f32 {
+    if transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_90
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_90,
+    ) {
+        90.0
+    } else if transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_180
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_180,
+    ) {
+        180.0
+    } else if transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_270
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_270,
+    ) {
+        270.0
+    } else {
+        0.0
+    }
+}
+
+/// The swapchain is created with `current_transform` as pre-transform, so for a 90 or 270
+/// degree rotation its images are in the display's native orientation and width and height
+/// are swapped relative to the window.
+pub fn get_surface_extent<W: WindowBackend + ?Sized>(
+    window: &W,
     surface_capabilities: &vk::SurfaceCapabilitiesKHR,
 ) -> vk::Extent2D {
-    let window_size = window.inner_size();
+    let rotated = is_rotated_transform(surface_capabilities.current_transform);
+
+    let (mut window_width, mut window_height) = window.inner_size();
+    if rotated {
+        std::mem::swap(&mut window_width, &mut window_height);
+    }
 
     let mut surface_extent = vk::Extent2D::default();
 
     if surface_capabilities.current_extent.width == u32::MAX {
         surface_extent.width = std::cmp::max(
             surface_capabilities.min_image_extent.width,
-            std::cmp::min(
-                surface_capabilities.max_image_extent.width,
-                window_size.width,
-            ),
+            std::cmp::min(surface_capabilities.max_image_extent.width, window_width),
         );
         surface_extent.height = std::cmp::max(
             surface_capabilities.min_image_extent.height,
-            std::cmp::min(
-                surface_capabilities.max_image_extent.height,
-                window_size.height,
-            ),
+            std::cmp::min(surface_capabilities.max_image_extent.height, window_height),
         );
     } else {
         surface_extent = surface_capabilities.current_extent;
+
+        if rotated {
+            std::mem::swap(&mut surface_extent.width, &mut surface_extent.height);
+        }
     }
 
-    log::info!("surface extent got: {:?}", surface_extent);
+    log::info!(
+        "surface extent got: {:?}, transform: {:?}",
+        surface_extent,
+        surface_capabilities.current_transform
+    );
 
     surface_extent
 }
 
+fn check_image_usage_support(
+    image_usage: vk::ImageUsageFlags,
+    surface_capabilities: &vk::SurfaceCapabilitiesKHR,
+) -> Result<(), VulkanError> {
+    for bit in 0..u32::BITS {
+        let flag = vk::ImageUsageFlags::from_raw(1 << bit);
+
+        if image_usage.contains(flag) && !surface_capabilities.supported_usage_flags.contains(flag)
+        {
+            return Err(VulkanError::ImageUsageUnsupported(flag));
+        }
+    }
+
+    Ok(())
+}
+
 pub fn create_swapchain(
     old_swapchain: vk::SwapchainKHR,
     surface: vk::SurfaceKHR,
@@ -596,38 +1759,59 @@ pub fn create_swapchain(
     surface_format: &vk::SurfaceFormatKHR,
     surface_extent: vk::Extent2D,
     present_mode: vk::PresentModeKHR,
+    compatible_present_modes: &[vk::PresentModeKHR],
+    composite_alpha: vk::CompositeAlphaFlagsKHR,
+    image_usage: vk::ImageUsageFlags,
+    desired_image_count: u32,
+    queue_families: &[u32],
     swapchain_loader: &khr::Swapchain,
-) -> Result<vk::SwapchainKHR, String> {
+) -> Result<vk::SwapchainKHR, VulkanError> {
     log::info!("creating swapchain");
 
-    let mut image_count = std::cmp::max(surface_capabilities.min_image_count, 3);
+    check_image_usage_support(image_usage, surface_capabilities)?;
+
+    let mut image_count = std::cmp::max(surface_capabilities.min_image_count, desired_image_count);
 
+    // 0 means there is no limit
     if surface_capabilities.max_image_count != 0 {
         image_count = std::cmp::min(image_count, surface_capabilities.max_image_count);
     }
 
     log::info!("requested swapchain image count: {}", image_count);
 
-    let create_info = vk::SwapchainCreateInfoKHR::builder()
+    let (sharing_mode, queue_family_indices) = vulkan_utils::get_sharing_mode(queue_families);
+
+    log::info!("swapchain image sharing mode: {:?}", sharing_mode);
+
+    let mut present_modes_info =
+        vk::SwapchainPresentModesCreateInfoEXT::builder().present_modes(compatible_present_modes);
+
+    let mut create_info = vk::SwapchainCreateInfoKHR::builder()
         .surface(surface)
         .min_image_count(image_count)
         .image_format(surface_format.format)
         .image_color_space(surface_format.color_space)
         .image_extent(surface_extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-        .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .image_usage(image_usage)
+        .image_sharing_mode(sharing_mode)
+        .queue_family_indices(&queue_family_indices)
         .pre_transform(surface_capabilities.current_transform)
-        .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+        .composite_alpha(composite_alpha)
         .present_mode(present_mode)
         .clipped(true)
-        .old_swapchain(old_swapchain)
-        .build();
+        .old_swapchain(old_swapchain);
+
+    if !compatible_present_modes.is_empty() {
+        create_info = create_info.push_next(&mut present_modes_info);
+    }
+
+    let create_info = create_info.build();
 
     let swapchain = unsafe {
         swapchain_loader
             .create_swapchain(&create_info, None)
-            .map_err(|_| String::from("failed to create swapchain"))?
+            .map_err(|err| VulkanError::Vulkan(String::from("failed to create swapchain"), err))?
     };
 
     if old_swapchain != vk::SwapchainKHR::null() {
@@ -644,13 +1828,15 @@ pub fn create_swapchain(
 pub fn get_swapchain_images(
     swapchain_loader: &khr::Swapchain,
     swapchain: vk::SwapchainKHR,
-) -> Result<Vec<vk::Image>, String> {
+) -> Result<Vec<vk::Image>, VulkanError> {
     log::info!("getting swapchain images");
 
     let swapchain_images = unsafe {
         swapchain_loader
             .get_swapchain_images(swapchain)
-            .map_err(|_| String::from("failed to get swapchain images"))?
+            .map_err(|err| {
+                VulkanError::Vulkan(String::from("failed to get swapchain images"), err)
+            })?
     };
 
     log::info!("swapchain images got");
@@ -663,7 +1849,7 @@ pub fn create_swapchain_image_views(
     device: &ash::Device,
     swapchain_images: &Vec<vk::Image>,
     surface_format: &vk::SurfaceFormatKHR,
-) -> Result<Vec<vk::ImageView>, String> {
+) -> Result<Vec<vk::ImageView>, VulkanError> {
     log::info!("creating swapchain images views");
 
     let mut swapchain_image_views = Vec::with_capacity(swapchain_images.len());
@@ -689,10 +1875,12 @@ pub fn create_swapchain_image_views(
             .build();
 
         let view = unsafe {
-            device.create_image_view(&create_info, None).map_err(|_| {
-                clear_image_views(device, &swapchain_image_views);
-                format!("failed to create image view {}", i)
-            })?
+            device
+                .create_image_view(&create_info, None)
+                .map_err(|err| {
+                    clear_image_views(device, &swapchain_image_views);
+                    VulkanError::Vulkan(format!("failed to create image view {}", i), err)
+                })?
         };
 
         swapchain_image_views.push(view);
@@ -715,8 +1903,9 @@ pub fn create_depth_buffer(
     device: &ash::Device,
     surface_extent: &vk::Extent2D,
     depth_format: vk::Format,
+    image_usage: vk::ImageUsageFlags,
     allocator: &mut gpu_allocator::vulkan::Allocator,
-) -> Result<vulkan_utils::MemImage, String> {
+) -> Result<vulkan_utils::MemImage, VulkanError> {
     // image
     log::info!("creating depth buffer image");
 
@@ -735,7 +1924,7 @@ pub fn create_depth_buffer(
             .array_layers(1)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+            .usage(image_usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
             .build();
@@ -743,7 +1932,9 @@ pub fn create_depth_buffer(
         let image = unsafe {
             device
                 .create_image(&image_create_info, None)
-                .map_err(|_| format!("failed to create depth buffer image"))?
+                .map_err(|err| {
+                    VulkanError::Vulkan(String::from("failed to create depth buffer image"), err)
+                })?
         };
 
         scopeguard::guard(image, |image| {
@@ -769,9 +1960,12 @@ pub fn create_depth_buffer(
             linear: false,
         };
 
-        let allocation = allocator
-            .allocate(&allocation_create_desc)
-            .map_err(|_| format!("failed to allocate depth buffer image memory"))?;
+        let allocation = allocator.allocate(&allocation_create_desc).map_err(|err| {
+            VulkanError::Allocation(
+                String::from("failed to allocate depth buffer image memory"),
+                err,
+            )
+        })?;
 
         scopeguard::guard(allocation, |allocation| {
             log::warn!("depth buffer image allocation scopeguard");
@@ -787,7 +1981,12 @@ pub fn create_depth_buffer(
     unsafe {
         device
             .bind_image_memory(*image_sg, allocation_sg.memory(), allocation_sg.offset())
-            .map_err(|_| format!("failed to bind depth buffer image memory"))?
+            .map_err(|err| {
+                VulkanError::Vulkan(
+                    String::from("failed to bind depth buffer image memory"),
+                    err,
+                )
+            })?
     };
 
     log::info!("depth buffer image memory bound");
@@ -807,7 +2006,7 @@ pub fn create_depth_buffer(
                 a: vk::ComponentSwizzle::A,
             })
             .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                aspect_mask: vulkan_utils::get_depth_aspect_mask(depth_format),
                 base_mip_level: 0,
                 level_count: 1,
                 base_array_layer: 0,
@@ -818,7 +2017,12 @@ pub fn create_depth_buffer(
         let view = unsafe {
             device
                 .create_image_view(&view_create_info, None)
-                .map_err(|_| format!("failed to create depth buffer image view"))?
+                .map_err(|err| {
+                    VulkanError::Vulkan(
+                        String::from("failed to create depth buffer image view"),
+                        err,
+                    )
+                })?
         };
 
         scopeguard::guard(view, |view| {
@@ -0,0 +1,69 @@
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+
+use crate::VulkanError;
+
+/// A window VulkanBase can create a surface for. Implemented for `winit::window::Window`
+/// (feature `winit`, enabled by default) and `sdl2::video::Window` (feature `sdl2`).
+pub trait WindowBackend {
+    fn raw_display_handle(&self) -> RawDisplayHandle;
+    fn raw_window_handle(&self) -> RawWindowHandle;
+    /// Drawable size in pixels.
+    fn inner_size(&self) -> (u32, u32);
+}
+
+#[cfg(feature = "winit")]
+impl WindowBackend for winit::window::Window {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        raw_window_handle::HasRawDisplayHandle::raw_display_handle(self)
+    }
+
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        raw_window_handle::HasRawWindowHandle::raw_window_handle(self)
+    }
+
+    fn inner_size(&self) -> (u32, u32) {
+        let size = winit::window::Window::inner_size(self);
+        (size.width, size.height)
+    }
+}
+
+#[cfg(feature = "sdl2")]
+impl WindowBackend for sdl2::video::Window {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        raw_window_handle::HasRawDisplayHandle::raw_display_handle(self)
+    }
+
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        raw_window_handle::HasRawWindowHandle::raw_window_handle(self)
+    }
+
+    fn inner_size(&self) -> (u32, u32) {
+        self.vulkan_drawable_size()
+    }
+}
+
+/// Instance extensions needed to create a surface for `window`. `VK_EXT_debug_utils` is in the
+/// default `VulkanBaseConfig::optional_instance_extensions`.
+pub fn get_required_instance_extensions<W: WindowBackend>(
+    window: &W,
+) -> Result<Vec<&'static std::ffi::CStr>, VulkanError> {
+    log::info!("getting required instance extensions");
+
+    let instance_extensions =
+        match ash_window::enumerate_required_extensions(window.raw_display_handle()) {
+            Ok(extensions) => extensions
+                .iter()
+                .map(|&name| unsafe { std::ffi::CStr::from_ptr(name) })
+                .collect::<Vec<&'static std::ffi::CStr>>(),
+            Err(err) => {
+                return Err(VulkanError::Vulkan(
+                    String::from("failed to enumerate required instance extensions"),
+                    err,
+                ));
+            }
+        };
+
+    log::info!("required instance extensions: {:?}", instance_extensions);
+
+    Ok(instance_extensions)
+}
@@ -0,0 +1,242 @@
+//! Checks that `VulkanBase` and the helpers built on it leave no validation errors behind, e.g.
+//! leaked objects or wrongly ordered destroys. Needs a Vulkan driver and the validation layer:
+//!
+//! ```sh
+//! cargo test -p vulkan_base --features icd-tests
+//! ```
+#![cfg(feature = "icd-tests")]
+
+use ash::vk;
+use vulkan_base::{ValidationErrorCapture, VulkanBase, VulkanBaseConfig, VulkanError};
+use vulkan_utils::MemImage;
+
+/// A headless `VulkanBase` with validation reporting to `capture`, `None` when there's no
+/// driver, device or validation layer to test with.
+fn create_headless_base(capture: &ValidationErrorCapture) -> Option<VulkanBase> {
+    let mut config = VulkanBaseConfig::default();
+    capture.install(&mut config);
+
+    let vulkan_base = match VulkanBase::new_headless(&vec![], &vec![], &config) {
+        Ok(vulkan_base) => vulkan_base,
+        Err(
+            err @ (VulkanError::LoaderNotFound(_)
+            | VulkanError::InstanceCreation(vk::Result::ERROR_INCOMPATIBLE_DRIVER)
+            | VulkanError::NoSuitableDevice),
+        ) => {
+            eprintln!("skipped, no Vulkan device: {}", err);
+            return None;
+        }
+        Err(err) => panic!("failed to create headless vulkan base: {}", err),
+    };
+
+    if !vulkan_base.validation_layer_enabled {
+        eprintln!("skipped, the validation layer is not installed");
+        vulkan_base.clean();
+        return None;
+    }
+
+    Some(vulkan_base)
+}
+
+#[test]
+fn headless_base_cleans_without_validation_errors() {
+    let capture = ValidationErrorCapture::new();
+    let Some(vulkan_base) = create_headless_base(&capture) else {
+        return;
+    };
+
+    vulkan_base.clean();
+
+    assert_eq!(capture.error_count(), 0);
+}
+
+/// The depth buffer of a windowed base, which a headless one doesn't create.
+fn create_depth_buffer(vulkan_base: &mut VulkanBase, extent: vk::Extent2D) -> MemImage {
+    let device = &vulkan_base.device;
+
+    let image_create_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(vulkan_base.depth_format)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .build();
+    let image = unsafe { device.create_image(&image_create_info, None) }.unwrap();
+
+    let allocation = vulkan_base
+        .allocator
+        .lock()
+        .unwrap()
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "test depth buffer",
+            requirements: unsafe { device.get_image_memory_requirements(image) },
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+            linear: false,
+        })
+        .unwrap();
+    unsafe { device.bind_image_memory(image, allocation.memory(), allocation.offset()) }.unwrap();
+
+    let view_create_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(vulkan_base.depth_format)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vulkan_utils::get_depth_aspect_mask(vulkan_base.depth_format),
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .build();
+    let view = unsafe { device.create_image_view(&view_create_info, None) }.unwrap();
+
+    MemImage {
+        image,
+        view,
+        extent: image_create_info.extent,
+        allocation,
+    }
+}
+
+// clean used to destroy the depth image before its view
+#[test]
+fn clean_destroys_the_depth_buffer_without_validation_errors() {
+    let capture = ValidationErrorCapture::new();
+    let Some(mut vulkan_base) = create_headless_base(&capture) else {
+        return;
+    };
+
+    vulkan_base.target.depth_buffer_mem_image = create_depth_buffer(
+        &mut vulkan_base,
+        vk::Extent2D {
+            width: 64,
+            height: 64,
+        },
+    );
+    vulkan_base.clean();
+
+    assert_eq!(capture.error_count(), 0);
+}
+
+// OpEntryPoint GLCompute "main", local size 1 1 1, returning right away
+#[rustfmt::skip]
+const EMPTY_COMPUTE_SHADER: &[u32] = &[
+    0x07230203, 0x00010000, 0, 5, 0,
+    0x00020011, 1,                                  // OpCapability Shader
+    0x0003000e, 0, 1,                               // OpMemoryModel Logical GLSL450
+    0x0005000f, 5, 3, 0x6e69616d, 0,                // OpEntryPoint GLCompute %3 "main"
+    0x00060010, 3, 17, 1, 1, 1,                     // OpExecutionMode %3 LocalSize 1 1 1
+    0x00020013, 1,                                  // %1 = OpTypeVoid
+    0x00030021, 2, 1,                               // %2 = OpTypeFunction %1
+    0x00050036, 1, 3, 0, 2,                         // %3 = OpFunction %1 None %2
+    0x000200f8, 4,                                  // %4 = OpLabel
+    0x000100fd,                                     // OpReturn
+    0x00010038,                                     // OpFunctionEnd
+];
+
+// create_compute_pipelines and create_graphics_pipelines return the pipelines created before
+// a failure, which destroy_partial_pipelines must not leak
+#[test]
+fn destroy_partial_pipelines_leaks_no_pipeline() {
+    let capture = ValidationErrorCapture::new();
+    let Some(vulkan_base) = create_headless_base(&capture) else {
+        return;
+    };
+    let device = &vulkan_base.device;
+
+    let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
+        .code(EMPTY_COMPUTE_SHADER)
+        .build();
+    let shader_module =
+        unsafe { device.create_shader_module(&shader_module_create_info, None) }.unwrap();
+    let pipeline_layout =
+        unsafe { device.create_pipeline_layout(&vk::PipelineLayoutCreateInfo::default(), None) }
+            .unwrap();
+
+    let shader_entry_name = std::ffi::CString::new("main").unwrap();
+    let create_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(shader_module)
+                .name(&shader_entry_name)
+                .build(),
+        )
+        .layout(pipeline_layout)
+        .build();
+    let pipelines = unsafe {
+        device.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[create_info, create_info],
+            None,
+        )
+    }
+    .unwrap();
+
+    // as if creating a pipeline between the two failed
+    let message = vulkan_utils::destroy_partial_pipelines(
+        device,
+        (
+            vec![pipelines[0], vk::Pipeline::null(), pipelines[1]],
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY,
+        ),
+        "test pipelines: failed to create",
+    );
+    assert_eq!(
+        message,
+        format!(
+            "test pipelines: failed to create: {}",
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+        )
+    );
+
+    unsafe {
+        device.destroy_pipeline_layout(pipeline_layout, None);
+        device.destroy_shader_module(shader_module, None);
+    }
+    vulkan_base.clean();
+
+    assert_eq!(capture.error_count(), 0);
+}
+
+// the buffer holds a clone of the allocator, clean must only see the last one once it's dropped
+#[test]
+fn owned_mem_buffer_frees_before_clean_without_validation_errors() {
+    let capture = ValidationErrorCapture::new();
+    let Some(vulkan_base) = create_headless_base(&capture) else {
+        return;
+    };
+
+    let mem_buffer = vulkan_utils::create_buffer(
+        &vulkan_base.device,
+        &mut vulkan_base.allocator.lock().unwrap(),
+        vulkan_base.debug_utils_loader.as_ref(),
+        256,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+        &[vulkan_base.queue_family],
+        "test buffer",
+    )
+    .unwrap();
+    let owned_mem_buffer = vulkan_utils::OwnedMemBuffer::new(
+        vulkan_base.device.clone(),
+        vulkan_base.allocator.clone(),
+        mem_buffer,
+    );
+    assert_eq!(std::sync::Arc::strong_count(&vulkan_base.allocator), 2);
+
+    drop(owned_mem_buffer);
+    assert_eq!(std::sync::Arc::strong_count(&vulkan_base.allocator), 1);
+    vulkan_base.clean();
+
+    assert_eq!(capture.error_count(), 0);
+}
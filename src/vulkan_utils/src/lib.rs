@@ -1,3 +1,16 @@
+//! Vulkan helpers shared by `vulkan_base`, `teapot_common` and both teapot variants: buffer and
+//! image allocations (`MemBuffer`, `MemImage`, their owning `OwnedMemBuffer` and
+//! `OwnedMemImage`), buffer creation and upload (`create_buffer`, `create_gpu_buffer_init`,
+//! `upload_buffers`, `StagingPool`), shader modules, textures and debug utils object names and
+//! labels.
+//!
+//! Functions taking a `debug_utils_loader` skip naming when it is `None`, and report errors as
+//! `String`s prefixed with the object name.
+
+mod staging_pool;
+mod texture;
 mod vulkan_utils;
 
+pub use staging_pool::*;
+pub use texture::*;
 pub use vulkan_utils::*;
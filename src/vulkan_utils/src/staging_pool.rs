@@ -0,0 +1,478 @@
+use ash::vk;
+use std::collections::VecDeque;
+
+use crate::MemBuffer;
+
+// keeps every range usable as a buffer to image copy source, whose offset must be a multiple
+// of the texel size and of 4
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+
+fn align_up(offset: vk::DeviceSize) -> vk::DeviceSize {
+    (offset + STAGING_ALIGNMENT - 1) & !(STAGING_ALIGNMENT - 1)
+}
+
+// a part of the ring written by one upload, free again once `fence` signals
+struct StagingRange {
+    begin: vk::DeviceSize,
+    end: vk::DeviceSize,
+    // null until `mark_submitted`
+    fence: vk::Fence,
+}
+
+// the used part of a ring, in allocation order so it starts at the front and ends at the back.
+// Kept apart from the buffer, the fences are only compared and passed to `recycle`'s callback.
+struct StagingRing {
+    capacity: vk::DeviceSize,
+    ranges: VecDeque<StagingRange>,
+}
+
+impl StagingRing {
+    fn new(capacity: vk::DeviceSize) -> Self {
+        StagingRing {
+            capacity,
+            ranges: VecDeque::new(),
+        }
+    }
+
+    // the offset of `size` free bytes, used until the fence given to `mark_submitted` signals
+    fn allocate(&mut self, size: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let offset = self.find_offset(size)?;
+
+        self.ranges.push_back(StagingRange {
+            begin: offset,
+            end: offset + size,
+            fence: vk::Fence::null(),
+        });
+
+        Some(offset)
+    }
+
+    fn mark_submitted(&mut self, fence: vk::Fence) {
+        for range in self.ranges.iter_mut().rev() {
+            if range.fence != vk::Fence::null() {
+                break;
+            }
+            range.fence = fence;
+        }
+    }
+
+    // frees the ranges up to the first one whose fence hasn't signaled
+    fn recycle(
+        &mut self,
+        mut is_fence_signaled: impl FnMut(vk::Fence) -> Result<bool, String>,
+    ) -> Result<(), String> {
+        while let Some(range) = self.ranges.front() {
+            if range.fence == vk::Fence::null() || !is_fence_signaled(range.fence)? {
+                break;
+            }
+            self.ranges.pop_front();
+        }
+
+        Ok(())
+    }
+
+    fn find_offset(&self, size: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let (front, back) = match (self.ranges.front(), self.ranges.back()) {
+            (Some(front), Some(back)) => (front.begin, align_up(back.end)),
+            _ => return (size <= self.capacity).then_some(0),
+        };
+
+        if back > front {
+            // the used part is contiguous, the free space is after it and before it
+            if back + size <= self.capacity {
+                Some(back)
+            } else if size <= front {
+                Some(0)
+            } else {
+                None
+            }
+        } else if back + size <= front {
+            // the used part wraps around, the free space is between its back and front. Both
+            // are equal when the ring is full.
+            Some(back)
+        } else {
+            None
+        }
+    }
+
+    // at least double the capacity, and enough for `size`
+    fn grown_capacity(&self, size: vk::DeviceSize) -> vk::DeviceSize {
+        (self.capacity * 2).max(size).next_power_of_two()
+    }
+
+    // empties the ring for a new buffer of `capacity` bytes. Returns the fence of the old
+    // buffer's last range, null if not submitted yet, or `None` if the old one is unused. The
+    // ranges are freed in order, so the old buffer is done once its last range is.
+    fn reset(&mut self, capacity: vk::DeviceSize) -> Option<vk::Fence> {
+        let last_fence = self.ranges.back().map(|range| range.fence);
+
+        self.capacity = capacity;
+        self.ranges.clear();
+
+        last_fence
+    }
+}
+
+// a ring replaced by a bigger one while copies from it were still pending or in flight
+struct RetiredBuffer {
+    mem_buffer: MemBuffer,
+    // the fence of its last range, null until `mark_submitted`
+    fence: vk::Fence,
+}
+
+/// A persistently mapped host visible ring buffer that uploads are staged through, instead of
+/// allocating a staging buffer per upload like `create_gpu_buffer_init`.
+///
+/// `upload` only records the copy. Once the command buffers are submitted, `mark_submitted`
+/// tags the ranges written since the last call with the submission's fence, and later uploads
+/// reuse those ranges after it signals. The ring doubles when an upload doesn't fit next to
+/// the ranges still in use, the old one is freed once its copies are done.
+///
+/// Fences passed to `mark_submitted` must stay valid until the pool is cleaned. A fence reset
+/// and reused for a later submission only delays the recycling of its ranges.
+pub struct StagingPool {
+    mem_buffer: MemBuffer,
+    ring: StagingRing,
+    retired_buffers: Vec<RetiredBuffer>,
+    object_name: String,
+}
+
+impl StagingPool {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut gpu_allocator::vulkan::Allocator,
+        debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+        capacity: vk::DeviceSize,
+        object_name: &str,
+    ) -> Result<Self, String> {
+        let capacity = align_up(capacity.max(1));
+
+        let mem_buffer = crate::create_buffer(
+            device,
+            allocator,
+            debug_utils_loader,
+            capacity,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            gpu_allocator::MemoryLocation::CpuToGpu,
+            &[],
+            object_name,
+        )?;
+
+        Ok(StagingPool {
+            mem_buffer,
+            ring: StagingRing::new(capacity),
+            retired_buffers: Vec::new(),
+            object_name: String::from(object_name),
+        })
+    }
+
+    pub fn capacity(&self) -> vk::DeviceSize {
+        self.ring.capacity
+    }
+
+    /// The current ring, replaced when it grows.
+    pub fn buffer(&self) -> vk::Buffer {
+        self.mem_buffer.buffer
+    }
+
+    /// Copies `data` to the ring and records its copy to `dst_buffer` at `dst_offset` into
+    /// `command_buffer`, which must be recording. The caller makes the copy visible to the
+    /// destination's users with a barrier, and calls `mark_submitted` once `command_buffer` is
+    /// submitted.
+    pub fn upload(
+        &mut self,
+        device: &ash::Device,
+        allocator: &mut gpu_allocator::vulkan::Allocator,
+        debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+        data: &[u8],
+        dst_buffer: vk::Buffer,
+        dst_offset: vk::DeviceSize,
+        command_buffer: vk::CommandBuffer,
+    ) -> Result<(), String> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.recycle(device, allocator)?;
+
+        let size = data.len() as vk::DeviceSize;
+        let offset = match self.ring.allocate(size) {
+            Some(offset) => offset,
+            None => {
+                self.grow(device, allocator, debug_utils_loader, size)?;
+                // the new ring is empty and fits `size`
+                self.ring.allocate(size).unwrap()
+            }
+        };
+
+        let mapped = match self.mem_buffer.allocation.mapped_slice_mut() {
+            Some(mapped) => mapped,
+            None => return Err(format!("{}: memory is not host visible", self.object_name)),
+        };
+        mapped[offset as usize..(offset + size) as usize].copy_from_slice(data);
+
+        let buffer_copy = vk::BufferCopy {
+            src_offset: offset,
+            dst_offset,
+            size,
+        };
+
+        unsafe {
+            device.cmd_copy_buffer(
+                command_buffer,
+                self.mem_buffer.buffer,
+                dst_buffer,
+                &[buffer_copy],
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Tags the uploads recorded since the last call with `fence`, the fence of the submission
+    /// of their command buffers.
+    pub fn mark_submitted(&mut self, fence: vk::Fence) {
+        self.ring.mark_submitted(fence);
+
+        for retired_buffer in &mut self.retired_buffers {
+            if retired_buffer.fence == vk::Fence::null() {
+                retired_buffer.fence = fence;
+            }
+        }
+    }
+
+    /// Frees the ranges and retired rings whose fences have signaled. `upload` calls it first.
+    pub fn recycle(
+        &mut self,
+        device: &ash::Device,
+        allocator: &mut gpu_allocator::vulkan::Allocator,
+    ) -> Result<(), String> {
+        let object_name = &self.object_name;
+        self.ring
+            .recycle(|fence| is_fence_signaled(device, fence, object_name))?;
+
+        let mut i = 0;
+        while i < self.retired_buffers.len() {
+            if is_fence_signaled(device, self.retired_buffers[i].fence, object_name)? {
+                let retired_buffer = self.retired_buffers.swap_remove(i);
+                unsafe {
+                    device.destroy_buffer(retired_buffer.mem_buffer.buffer, None);
+                }
+                let _ = allocator.free(retired_buffer.mem_buffer.allocation);
+            } else {
+                i += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The device must be idle, or at least done with the submitted uploads.
+    pub fn clean(self, device: &ash::Device, allocator: &mut gpu_allocator::vulkan::Allocator) {
+        let mem_buffers = self
+            .retired_buffers
+            .into_iter()
+            .map(|retired_buffer| retired_buffer.mem_buffer)
+            .chain(std::iter::once(self.mem_buffer));
+
+        for mem_buffer in mem_buffers {
+            unsafe {
+                device.destroy_buffer(mem_buffer.buffer, None);
+            }
+            let _ = allocator.free(mem_buffer.allocation);
+        }
+    }
+
+    fn grow(
+        &mut self,
+        device: &ash::Device,
+        allocator: &mut gpu_allocator::vulkan::Allocator,
+        debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+        size: vk::DeviceSize,
+    ) -> Result<(), String> {
+        let capacity = self.ring.grown_capacity(size);
+
+        log::info!(
+            "{}: growing from {} to {} bytes",
+            self.object_name,
+            self.ring.capacity,
+            capacity
+        );
+
+        let mem_buffer = crate::create_buffer(
+            device,
+            allocator,
+            debug_utils_loader,
+            capacity,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            gpu_allocator::MemoryLocation::CpuToGpu,
+            &[],
+            &self.object_name,
+        )?;
+
+        let old_mem_buffer = std::mem::replace(&mut self.mem_buffer, mem_buffer);
+
+        match self.ring.reset(capacity) {
+            Some(fence) => self.retired_buffers.push(RetiredBuffer {
+                mem_buffer: old_mem_buffer,
+                fence,
+            }),
+            None => {
+                unsafe {
+                    device.destroy_buffer(old_mem_buffer.buffer, None);
+                }
+                let _ = allocator.free(old_mem_buffer.allocation);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// a range or ring not submitted yet is still being recorded
+fn is_fence_signaled(
+    device: &ash::Device,
+    fence: vk::Fence,
+    object_name: &str,
+) -> Result<bool, String> {
+    if fence == vk::Fence::null() {
+        return Ok(false);
+    }
+
+    unsafe {
+        device
+            .get_fence_status(fence)
+            .map_err(|_| format!("{}: failed to get fence status", object_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn fence(raw: u64) -> vk::Fence {
+        vk::Fence::from_raw(raw)
+    }
+
+    fn recycle(ring: &mut StagingRing, signaled_fences: &[vk::Fence]) {
+        ring.recycle(|fence| Ok(signaled_fences.contains(&fence)))
+            .unwrap();
+    }
+
+    #[test]
+    fn sequential_uploads_reuse_the_ring_once_recycled() {
+        let mut ring = StagingRing::new(64);
+
+        assert_eq!(ring.allocate(48), Some(0));
+        ring.mark_submitted(fence(1));
+
+        // still in flight
+        recycle(&mut ring, &[]);
+        assert_eq!(ring.allocate(48), None);
+
+        recycle(&mut ring, &[fence(1)]);
+        assert_eq!(ring.allocate(48), Some(0));
+    }
+
+    #[test]
+    fn unsubmitted_ranges_are_not_recycled() {
+        let mut ring = StagingRing::new(64);
+
+        assert_eq!(ring.allocate(16), Some(0));
+        ring.mark_submitted(fence(1));
+        assert_eq!(ring.allocate(16), Some(16));
+
+        recycle(&mut ring, &[fence(1)]);
+
+        assert_eq!(ring.ranges.len(), 1);
+        assert_eq!(ring.ranges[0].fence, vk::Fence::null());
+    }
+
+    #[test]
+    fn ranges_are_recycled_in_order() {
+        let mut ring = StagingRing::new(64);
+
+        assert_eq!(ring.allocate(16), Some(0));
+        ring.mark_submitted(fence(1));
+        assert_eq!(ring.allocate(16), Some(16));
+        ring.mark_submitted(fence(2));
+
+        // the second range is done but stays behind the first one
+        recycle(&mut ring, &[fence(2)]);
+        assert_eq!(ring.ranges.len(), 2);
+
+        recycle(&mut ring, &[fence(1), fence(2)]);
+        assert!(ring.ranges.is_empty());
+    }
+
+    #[test]
+    fn offsets_are_aligned() {
+        let mut ring = StagingRing::new(64);
+
+        assert_eq!(ring.allocate(3), Some(0));
+        assert_eq!(ring.allocate(5), Some(STAGING_ALIGNMENT));
+        assert_eq!(ring.allocate(1), Some(2 * STAGING_ALIGNMENT));
+    }
+
+    #[test]
+    fn allocations_wrap_around() {
+        let mut ring = StagingRing::new(64);
+
+        assert_eq!(ring.allocate(16), Some(0));
+        ring.mark_submitted(fence(1));
+        assert_eq!(ring.allocate(32), Some(16));
+        ring.mark_submitted(fence(2));
+
+        recycle(&mut ring, &[fence(1)]);
+
+        // [16, 48) is used, 16 bytes are free after it and 16 before it
+        assert_eq!(ring.allocate(24), None);
+        assert_eq!(ring.allocate(16), Some(48));
+        ring.mark_submitted(fence(3));
+        assert_eq!(ring.allocate(16), Some(0));
+        ring.mark_submitted(fence(4));
+
+        // the back has caught up with the front
+        assert_eq!(ring.allocate(1), None);
+
+        recycle(&mut ring, &[fence(2)]);
+
+        // [48, 64) and [0, 16) are used, the free space is between them
+        assert_eq!(ring.allocate(48), None);
+        assert_eq!(ring.allocate(32), Some(16));
+    }
+
+    #[test]
+    fn growing_doubles_and_fits_the_allocation() {
+        let ring = StagingRing::new(64);
+
+        assert_eq!(ring.grown_capacity(16), 128);
+        assert_eq!(ring.grown_capacity(128), 128);
+        assert_eq!(ring.grown_capacity(300), 512);
+    }
+
+    #[test]
+    fn growing_retires_the_old_ring() {
+        let mut ring = StagingRing::new(64);
+
+        assert_eq!(ring.allocate(16), Some(0));
+        ring.mark_submitted(fence(1));
+        assert_eq!(ring.allocate(32), Some(16));
+        assert_eq!(ring.allocate(32), None);
+
+        // the last range of the old ring isn't submitted yet
+        let capacity = ring.grown_capacity(32);
+        assert_eq!(ring.reset(capacity), Some(vk::Fence::null()));
+
+        assert_eq!(ring.capacity, 128);
+        assert_eq!(ring.allocate(32), Some(0));
+        assert_eq!(ring.allocate(96), Some(32));
+        ring.mark_submitted(fence(2));
+        assert_eq!(ring.reset(256), Some(fence(2)));
+
+        // nothing to retire
+        assert_eq!(ring.reset(512), None);
+        assert_eq!(ring.allocate(512), Some(0));
+    }
+}
@@ -0,0 +1,701 @@
+use ash::vk;
+use std::cell::RefCell;
+
+use crate::{MemBuffer, MemImage};
+
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// A sampled image and the sampler it's read with.
+pub struct Texture {
+    pub mem_image: MemImage,
+    pub sampler: vk::Sampler,
+}
+
+impl Texture {
+    pub fn clean(self, device: &ash::Device, allocator: &mut gpu_allocator::vulkan::Allocator) {
+        unsafe {
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_image_view(self.mem_image.view, None);
+            device.destroy_image(self.mem_image.image, None);
+        }
+        let _ = allocator.free(self.mem_image.allocation);
+    }
+}
+
+/// Loads an image file as an RGBA8 sRGB texture with a full mip chain, in
+/// `SHADER_READ_ONLY_OPTIMAL` layout.
+///
+/// `max_anisotropy` enables anisotropic filtering, pass `limits.max_sampler_anisotropy` when the
+/// device was created with the `sampler_anisotropy` feature and `None` otherwise.
+pub fn create_texture_from_path(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    queue_family: u32,
+    queue: vk::Queue,
+    max_anisotropy: Option<f32>,
+    path: &std::path::Path,
+) -> Result<Texture, String> {
+    let object_name = format!("texture {}", path.display());
+
+    log::info!("{}: loading", object_name);
+
+    let pixels = image::open(path)
+        .map_err(|_| format!("{}: failed to load", object_name))?
+        .to_rgba8();
+
+    let (width, height) = pixels.dimensions();
+    let pixels = pixels.into_raw();
+
+    let mip_levels = mip_level_count(width, height);
+
+    log::info!(
+        "{}: loaded {}x{}, {} mip levels",
+        object_name,
+        width,
+        height,
+        mip_levels
+    );
+
+    let allocator_rc = RefCell::new(allocator);
+
+    // staging buffer
+    let mut staging_mem_buffer_sg = {
+        let staging_mem_buffer = crate::create_buffer(
+            device,
+            *allocator_rc.borrow_mut(),
+            debug_utils_loader,
+            pixels.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            gpu_allocator::MemoryLocation::CpuToGpu,
+            &[queue_family],
+            &format!("{} staging", object_name),
+        )?;
+
+        scopeguard::guard(staging_mem_buffer, |mem_buffer| {
+            log::warn!("{} staging scopeguard", object_name);
+            unsafe {
+                device.destroy_buffer(mem_buffer.buffer, None);
+            }
+            let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+        })
+    };
+
+    staging_mem_buffer_sg.allocation.mapped_slice_mut().unwrap()[..pixels.len()]
+        .copy_from_slice(&pixels);
+
+    // image
+    let mem_image_sg = {
+        let mem_image = create_mem_image(
+            device,
+            *allocator_rc.borrow_mut(),
+            debug_utils_loader,
+            vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_levels,
+            TEXTURE_FORMAT,
+            vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::SAMPLED,
+            &object_name,
+        )?;
+
+        scopeguard::guard(mem_image, |mem_image| {
+            log::warn!("{} scopeguard", object_name);
+            unsafe {
+                device.destroy_image_view(mem_image.view, None);
+                device.destroy_image(mem_image.image, None);
+            }
+            let _ = allocator_rc.borrow_mut().free(mem_image.allocation);
+        })
+    };
+
+    // command pool
+    let command_pool_sg = {
+        let command_pool = crate::create_command_pool(device, queue_family, &object_name)?;
+        scopeguard::guard(command_pool, |command_pool| {
+            log::warn!("{} command pool scopeguard", object_name);
+            unsafe {
+                device.destroy_command_pool(command_pool, None);
+            }
+        })
+    };
+
+    copy_buffer_to_image(
+        instance,
+        physical_device,
+        device,
+        queue,
+        *command_pool_sg,
+        &staging_mem_buffer_sg,
+        &mem_image_sg,
+        mip_levels,
+        &object_name,
+    )?;
+
+    // sampler
+    let sampler = create_sampler(device, debug_utils_loader, max_anisotropy, &object_name)?;
+
+    // clear temporary objects
+    log::info!("{}: destroying temporary objects", object_name);
+
+    let staging_mem_buffer = scopeguard::ScopeGuard::into_inner(staging_mem_buffer_sg);
+
+    unsafe {
+        device.destroy_buffer(staging_mem_buffer.buffer, None);
+        let _ = allocator_rc
+            .borrow_mut()
+            .free(staging_mem_buffer.allocation);
+        device.destroy_command_pool(scopeguard::ScopeGuard::into_inner(command_pool_sg), None);
+    }
+
+    Ok(Texture {
+        mem_image: scopeguard::ScopeGuard::into_inner(mem_image_sg),
+        sampler,
+    })
+}
+
+fn create_mem_image(
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    extent: vk::Extent3D,
+    mip_levels: u32,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    object_name: &str,
+) -> Result<MemImage, String> {
+    // image
+    log::info!("{}: creating image", object_name);
+
+    let image_sg = {
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(extent)
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe {
+            device
+                .create_image(&image_create_info, None)
+                .map_err(|_| format!("{}: failed to create image", object_name))?
+        };
+
+        scopeguard::guard(image, |image| {
+            log::warn!("{} image scopeguard", object_name);
+            unsafe {
+                device.destroy_image(image, None);
+            }
+        })
+    };
+
+    log::info!("{}: image created", object_name);
+
+    // allocation
+    log::info!("{}: allocating memory", object_name);
+
+    let memory_requirements = unsafe { device.get_image_memory_requirements(*image_sg) };
+
+    let allocation_create_desc = gpu_allocator::vulkan::AllocationCreateDesc {
+        name: object_name,
+        requirements: memory_requirements,
+        location: gpu_allocator::MemoryLocation::GpuOnly,
+        linear: false,
+    };
+
+    let allocation_sg = {
+        let allocation = allocator
+            .allocate(&allocation_create_desc)
+            .map_err(|_| format!("{}: failed to allocate memory", object_name))?;
+
+        scopeguard::guard(allocation, |allocation| {
+            log::warn!("{} allocation scopeguard", object_name);
+            let _ = allocator.free(allocation);
+        })
+    };
+
+    log::info!("{}: memory allocated", object_name);
+
+    // binding
+    log::info!("{}: binding memory", object_name);
+
+    unsafe {
+        device
+            .bind_image_memory(*image_sg, allocation_sg.memory(), allocation_sg.offset())
+            .map_err(|_| format!("{}: failed to bind memory", object_name))?
+    };
+
+    log::info!("{}: memory bound", object_name);
+
+    // view
+    log::info!("{}: creating image view", object_name);
+
+    let view_create_info = vk::ImageViewCreateInfo::builder()
+        .image(*image_sg)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .build();
+
+    let view = unsafe {
+        device
+            .create_image_view(&view_create_info, None)
+            .map_err(|_| format!("{}: failed to create image view", object_name))?
+    };
+
+    log::info!("{}: image view created", object_name);
+
+    crate::set_debug_utils_object_name(debug_utils_loader, device.handle(), *image_sg, object_name);
+
+    crate::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        view,
+        &format!("{} view", object_name),
+    );
+
+    Ok(MemImage {
+        image: scopeguard::ScopeGuard::into_inner(image_sg),
+        view,
+        extent,
+        allocation: scopeguard::ScopeGuard::into_inner(allocation_sg),
+    })
+}
+
+/// Copies the buffer to the first mip level and fills the rest with `generate_mipmaps`.
+fn copy_buffer_to_image(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &ash::Device,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    src_mem_buffer: &MemBuffer,
+    dst_mem_image: &MemImage,
+    mip_levels: u32,
+    object_name: &str,
+) -> Result<(), String> {
+    log::info!("{}: copying buffer to image", object_name);
+
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: mip_levels,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+
+    let before_copy_barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(dst_mem_image.image)
+        .subresource_range(subresource_range)
+        .build();
+
+    let region = vk::BufferImageCopy {
+        buffer_offset: 0,
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        image_extent: dst_mem_image.extent,
+    };
+
+    crate::one_time_submit(device, command_pool, queue, object_name, |command_buffer| {
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[before_copy_barrier],
+            );
+
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                src_mem_buffer.buffer,
+                dst_mem_image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+        }
+
+        generate_mipmaps(
+            instance,
+            physical_device,
+            device,
+            command_buffer,
+            dst_mem_image.image,
+            TEXTURE_FORMAT,
+            vk::Extent2D {
+                width: dst_mem_image.extent.width,
+                height: dst_mem_image.extent.height,
+            },
+            mip_levels,
+        )
+    })?;
+
+    log::info!("{}: buffer to image copied", object_name);
+
+    Ok(())
+}
+
+/// Number of levels in a full mip chain down to 1x1.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Blit regions halving each level into the next, one per level after the first.
+pub fn mip_blit_regions(extent: vk::Extent2D, mip_levels: u32) -> Vec<vk::ImageBlit> {
+    let mut regions = Vec::with_capacity(mip_levels.saturating_sub(1) as usize);
+
+    let mut src_width = extent.width as i32;
+    let mut src_height = extent.height as i32;
+
+    for level in 1..mip_levels {
+        let dst_width = (src_width / 2).max(1);
+        let dst_height = (src_height / 2).max(1);
+
+        regions.push(vk::ImageBlit {
+            src_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level - 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            src_offsets: [
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: src_width,
+                    y: src_height,
+                    z: 1,
+                },
+            ],
+            dst_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            dst_offsets: [
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: dst_width,
+                    y: dst_height,
+                    z: 1,
+                },
+            ],
+        });
+
+        src_width = dst_width;
+        src_height = dst_height;
+    }
+
+    regions
+}
+
+/// Records the blits filling mip levels `1..mip_levels` from level 0.
+///
+/// Every level has to be in `TRANSFER_DST_OPTIMAL` with level 0 written. Each source level
+/// moves to `TRANSFER_SRC_OPTIMAL` for its blit and then to `SHADER_READ_ONLY_OPTIMAL`; the last
+/// level is never a blit source and goes straight from `TRANSFER_DST_OPTIMAL`.
+pub fn generate_mipmaps(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    mip_levels: u32,
+) -> Result<(), String> {
+    if mip_levels == 0 {
+        return Err(String::from(
+            "mipmap generation needs at least one mip level",
+        ));
+    }
+
+    let format_properties =
+        unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+
+    if mip_levels > 1
+        && !format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+    {
+        return Err(format!(
+            "format {:?} does not support linear blitting for mipmap generation",
+            format
+        ));
+    }
+
+    let level_barrier = |level: u32,
+                         src_access_mask: vk::AccessFlags,
+                         dst_access_mask: vk::AccessFlags,
+                         old_layout: vk::ImageLayout,
+                         new_layout: vk::ImageLayout| {
+        vk::ImageMemoryBarrier::builder()
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: level,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build()
+    };
+
+    for region in mip_blit_regions(extent, mip_levels) {
+        let src_level = region.src_subresource.mip_level;
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[level_barrier(
+                    src_level,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::TRANSFER_READ,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                )],
+            );
+
+            device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+                vk::Filter::LINEAR,
+            );
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[level_barrier(
+                    src_level,
+                    vk::AccessFlags::TRANSFER_READ,
+                    vk::AccessFlags::SHADER_READ,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )],
+            );
+        }
+    }
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[level_barrier(
+                mip_levels - 1,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )],
+        );
+    }
+
+    Ok(())
+}
+
+/// Linear, repeating sampler covering every mip level.
+pub fn create_sampler(
+    device: &ash::Device,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    max_anisotropy: Option<f32>,
+    object_name: &str,
+) -> Result<vk::Sampler, String> {
+    log::info!("{}: creating sampler", object_name);
+
+    let create_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::REPEAT)
+        .address_mode_v(vk::SamplerAddressMode::REPEAT)
+        .address_mode_w(vk::SamplerAddressMode::REPEAT)
+        .anisotropy_enable(max_anisotropy.is_some())
+        .max_anisotropy(max_anisotropy.unwrap_or(1.0f32))
+        .min_lod(0.0f32)
+        .max_lod(vk::LOD_CLAMP_NONE)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .build();
+
+    let sampler = unsafe {
+        device
+            .create_sampler(&create_info, None)
+            .map_err(|_| format!("{}: failed to create sampler", object_name))?
+    };
+
+    crate::set_debug_utils_object_name(
+        debug_utils_loader,
+        device.handle(),
+        sampler,
+        &format!("{} sampler", object_name),
+    );
+
+    log::info!("{}: sampler created", object_name);
+
+    Ok(sampler)
+}
+
+pub fn combined_image_sampler_binding(
+    binding: u32,
+    stage_flags: vk::ShaderStageFlags,
+) -> vk::DescriptorSetLayoutBinding {
+    vk::DescriptorSetLayoutBinding::builder()
+        .binding(binding)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(stage_flags)
+        .build()
+}
+
+pub fn write_combined_image_sampler(
+    device: &ash::Device,
+    descriptor_set: vk::DescriptorSet,
+    binding: u32,
+    texture: &Texture,
+) {
+    let image_infos = [vk::DescriptorImageInfo {
+        sampler: texture.sampler,
+        image_view: texture.mem_image.view,
+        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }];
+
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(binding)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(&image_infos)
+        .build();
+
+    unsafe {
+        device.update_descriptor_sets(&[write], &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dst_extent(region: &vk::ImageBlit) -> (i32, i32) {
+        (region.dst_offsets[1].x, region.dst_offsets[1].y)
+    }
+
+    #[test]
+    fn mip_blit_regions_blits_every_level_after_the_first() {
+        let extent = vk::Extent2D {
+            width: 8,
+            height: 8,
+        };
+        let mip_levels = mip_level_count(extent.width, extent.height);
+
+        let regions = mip_blit_regions(extent, mip_levels);
+
+        assert_eq!(mip_levels, 4);
+        assert_eq!(regions.len(), mip_levels as usize - 1);
+        for (i, region) in regions.iter().enumerate() {
+            assert_eq!(region.src_subresource.mip_level, i as u32);
+            assert_eq!(region.dst_subresource.mip_level, i as u32 + 1);
+        }
+        assert_eq!(dst_extent(regions.last().unwrap()), (1, 1));
+    }
+
+    #[test]
+    fn mip_blit_regions_clamps_the_shorter_side_to_1() {
+        let extent = vk::Extent2D {
+            width: 16,
+            height: 4,
+        };
+        let mip_levels = mip_level_count(extent.width, extent.height);
+
+        let regions = mip_blit_regions(extent, mip_levels);
+
+        assert_eq!(regions.len(), mip_levels as usize - 1);
+        let dst_extents = Vec::from_iter(regions.iter().map(dst_extent));
+        assert_eq!(dst_extents, [(8, 2), (4, 1), (2, 1), (1, 1)]);
+        // each level is blitted from the previous one's extent
+        for pair in regions.windows(2) {
+            assert_eq!(pair[1].src_offsets[1], pair[0].dst_offsets[1]);
+        }
+    }
+
+    #[test]
+    fn mip_blit_regions_of_a_1xn_image() {
+        let extent = vk::Extent2D {
+            width: 1,
+            height: 5,
+        };
+        let mip_levels = mip_level_count(extent.width, extent.height);
+
+        let regions = mip_blit_regions(extent, mip_levels);
+
+        assert_eq!(mip_levels, 3);
+        assert_eq!(regions.len(), 2);
+        let dst_extents = Vec::from_iter(regions.iter().map(dst_extent));
+        assert_eq!(dst_extents, [(1, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn mip_blit_regions_without_mips() {
+        let extent = vk::Extent2D {
+            width: 4,
+            height: 4,
+        };
+
+        assert!(mip_blit_regions(extent, 1).is_empty());
+        assert!(mip_blit_regions(extent, 0).is_empty());
+    }
+}
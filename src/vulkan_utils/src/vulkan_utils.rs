@@ -3,11 +3,14 @@ use ash::vk;
 use std::cell::RefCell;
 use std::io::Read;
 
+/// A buffer and its memory, both freed by the caller. See `OwnedMemBuffer` to free on drop.
 pub struct MemBuffer {
     pub buffer: ash::vk::Buffer,
     pub allocation: gpu_allocator::vulkan::Allocation,
 }
 
+/// An image, its view and its memory, all freed by the caller. The default of null handles is
+/// a placeholder, e.g. for an image moved out with `std::mem::take`.
 pub struct MemImage {
     pub image: ash::vk::Image,
     pub view: ash::vk::ImageView,
@@ -26,12 +29,160 @@ impl Default for MemImage {
     }
 }
 
-pub fn set_debug_utils_object_name2<T: vk::Handle>(
-    debug_utils_loader: &ext::DebugUtils,
+/// Source data and destination parameters of a buffer created by `upload_buffers`.
+pub struct UploadRequest<'a> {
+    pub data: &'a [u8],
+    pub usage: vk::BufferUsageFlags,
+    pub access_mask: vk::AccessFlags,
+    pub stage_flags: vk::PipelineStageFlags,
+    pub object_name: &'a str,
+}
+
+/// The allocator `VulkanBase` shares with `OwnedMemBuffer` and `OwnedMemImage`. The last clone
+/// has to be dropped before the device is destroyed.
+pub type SharedAllocator = std::sync::Arc<std::sync::Mutex<gpu_allocator::vulkan::Allocator>>;
+
+/// Owns a `MemBuffer` and destroys it on drop.
+///
+/// `into_inner` hands the buffer back for manual cleanup.
+pub struct OwnedMemBuffer {
+    device: std::sync::Arc<ash::Device>,
+    allocator: SharedAllocator,
+    mem_buffer: Option<MemBuffer>,
+}
+
+impl OwnedMemBuffer {
+    pub fn new(
+        device: std::sync::Arc<ash::Device>,
+        allocator: SharedAllocator,
+        mem_buffer: MemBuffer,
+    ) -> Self {
+        Self {
+            device,
+            allocator,
+            mem_buffer: Some(mem_buffer),
+        }
+    }
+
+    pub fn into_inner(mut self) -> MemBuffer {
+        self.mem_buffer.take().unwrap()
+    }
+}
+
+impl std::ops::Deref for OwnedMemBuffer {
+    type Target = MemBuffer;
+
+    fn deref(&self) -> &MemBuffer {
+        self.mem_buffer.as_ref().unwrap()
+    }
+}
+
+// e.g. for `allocation.mapped_slice_mut`
+impl std::ops::DerefMut for OwnedMemBuffer {
+    fn deref_mut(&mut self) -> &mut MemBuffer {
+        self.mem_buffer.as_mut().unwrap()
+    }
+}
+
+impl Drop for OwnedMemBuffer {
+    fn drop(&mut self) {
+        if let Some(mem_buffer) = self.mem_buffer.take() {
+            unsafe {
+                self.device.destroy_buffer(mem_buffer.buffer, None);
+            }
+            if let Ok(mut allocator) = self.allocator.lock() {
+                let _ = allocator.free(mem_buffer.allocation);
+            }
+        }
+    }
+}
+
+/// Owns a `MemImage` and destroys it on drop, view first.
+///
+/// `into_inner` hands the image back for manual cleanup.
+pub struct OwnedMemImage {
+    device: std::sync::Arc<ash::Device>,
+    allocator: SharedAllocator,
+    mem_image: Option<MemImage>,
+}
+
+impl OwnedMemImage {
+    pub fn new(
+        device: std::sync::Arc<ash::Device>,
+        allocator: SharedAllocator,
+        mem_image: MemImage,
+    ) -> Self {
+        Self {
+            device,
+            allocator,
+            mem_image: Some(mem_image),
+        }
+    }
+
+    pub fn into_inner(mut self) -> MemImage {
+        self.mem_image.take().unwrap()
+    }
+}
+
+impl std::ops::Deref for OwnedMemImage {
+    type Target = MemImage;
+
+    fn deref(&self) -> &MemImage {
+        self.mem_image.as_ref().unwrap()
+    }
+}
+
+// e.g. for `allocation.mapped_slice_mut`
+impl std::ops::DerefMut for OwnedMemImage {
+    fn deref_mut(&mut self) -> &mut MemImage {
+        self.mem_image.as_mut().unwrap()
+    }
+}
+
+impl Drop for OwnedMemImage {
+    fn drop(&mut self) {
+        if let Some(mem_image) = self.mem_image.take() {
+            unsafe {
+                self.device.destroy_image_view(mem_image.view, None);
+                self.device.destroy_image(mem_image.image, None);
+            }
+            if let Ok(mut allocator) = self.allocator.lock() {
+                let _ = allocator.free(mem_image.allocation);
+            }
+        }
+    }
+}
+
+/// Error path of `create_graphics_pipelines` and `create_compute_pipelines`: destroys the
+/// pipelines that were created before the failure and returns `message` with the result appended.
+pub fn destroy_partial_pipelines(
+    device: &ash::Device,
+    (pipelines, result): (Vec<vk::Pipeline>, vk::Result),
+    message: &str,
+) -> String {
+    for pipeline in pipelines {
+        if pipeline != vk::Pipeline::null() {
+            unsafe {
+                device.destroy_pipeline(pipeline, None);
+            }
+        }
+    }
+
+    format!("{}: {}", message, result)
+}
+
+/// Does nothing when `VK_EXT_debug_utils` is not enabled, i.e. `debug_utils_loader` is `None`.
+pub fn set_debug_utils_object_name<T: vk::Handle>(
+    debug_utils_loader: Option<&ext::DebugUtils>,
     device: vk::Device,
     object_handle: T,
     object_name: &str,
 ) {
+    let debug_utils_loader = match debug_utils_loader {
+        Some(debug_utils_loader) => debug_utils_loader,
+        None => return,
+    };
+
     let name_cstr = std::ffi::CString::new(object_name).expect("wrong string parameter");
 
     let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
@@ -42,10 +193,86 @@ pub fn set_debug_utils_object_name2<T: vk::Handle>(
     let _ = unsafe { debug_utils_loader.debug_utils_set_object_name(device, &name_info) };
 }
 
+/// Opens a named region shown by RenderDoc and Nsight, closed by `cmd_end_debug_label`. Does
+/// nothing without `VK_EXT_debug_utils`.
+pub fn cmd_begin_debug_label(
+    debug_utils_loader: Option<&ext::DebugUtils>,
+    command_buffer: vk::CommandBuffer,
+    name: &str,
+    color: [f32; 4],
+) {
+    if let Some(debug_utils_loader) = debug_utils_loader {
+        let name_cstr = std::ffi::CString::new(name).expect("wrong string parameter");
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&name_cstr)
+            .color(color);
+
+        unsafe { debug_utils_loader.cmd_begin_debug_utils_label(command_buffer, &label) };
+    }
+}
+
+pub fn cmd_end_debug_label(
+    debug_utils_loader: Option<&ext::DebugUtils>,
+    command_buffer: vk::CommandBuffer,
+) {
+    if let Some(debug_utils_loader) = debug_utils_loader {
+        unsafe { debug_utils_loader.cmd_end_debug_utils_label(command_buffer) };
+    }
+}
+
+/// Queue counterpart of `cmd_begin_debug_label`, labels every submission until
+/// `queue_end_debug_label`.
+pub fn queue_begin_debug_label(
+    debug_utils_loader: Option<&ext::DebugUtils>,
+    queue: vk::Queue,
+    name: &str,
+    color: [f32; 4],
+) {
+    if let Some(debug_utils_loader) = debug_utils_loader {
+        let name_cstr = std::ffi::CString::new(name).expect("wrong string parameter");
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&name_cstr)
+            .color(color);
+
+        unsafe { debug_utils_loader.queue_begin_debug_utils_label(queue, &label) };
+    }
+}
+
+pub fn queue_end_debug_label(debug_utils_loader: Option<&ext::DebugUtils>, queue: vk::Queue) {
+    if let Some(debug_utils_loader) = debug_utils_loader {
+        unsafe { debug_utils_loader.queue_end_debug_utils_label(queue) };
+    }
+}
+
+pub fn choose_surface_format(
+    available_formats: &[vk::SurfaceFormatKHR],
+    preferred_formats: &[vk::SurfaceFormatKHR],
+) -> Result<vk::SurfaceFormatKHR, String> {
+    if available_formats.is_empty() {
+        return Err(String::from("no surface formats available"));
+    }
+
+    // a single UNDEFINED entry means the surface has no preferred format
+    if available_formats.len() == 1 && available_formats[0].format == vk::Format::UNDEFINED {
+        if let Some(&preferred) = preferred_formats.first() {
+            return Ok(preferred);
+        }
+    }
+
+    for preferred in preferred_formats {
+        if available_formats.contains(preferred) {
+            return Ok(*preferred);
+        }
+    }
+
+    Ok(available_formats[0])
+}
+
+/// Loads SPIR-V from `path`, see `create_shader_module_from_bytes`.
 pub fn create_shader_module(
     device: &ash::Device,
     path: &std::path::Path,
-    debug_utils_loader: &ash::extensions::ext::DebugUtils,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
     object_name: &str,
 ) -> Result<vk::ShaderModule, String> {
     log::info!("{}: creating", object_name);
@@ -59,21 +286,46 @@ pub fn create_shader_module(
         return Err(format!("failed to read file {:?}", path));
     }
 
-    let spirv_u32 = match ash::util::read_spv(&mut std::io::Cursor::new(&spirv_u8)) {
+    create_shader_module_from_bytes(device, &spirv_u8, debug_utils_loader, object_name)
+}
+
+const SPIRV_MAGIC_NUMBER: u32 = 0x07230203;
+
+pub fn create_shader_module_from_bytes(
+    device: &ash::Device,
+    spirv: &[u8],
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    object_name: &str,
+) -> Result<vk::ShaderModule, String> {
+    if !spirv.len().is_multiple_of(4) {
+        return Err(format!(
+            "{}: spirv size {} is not a multiple of 4",
+            object_name,
+            spirv.len()
+        ));
+    }
+
+    // read_spv copies into a u32 buffer, so unaligned input from include_bytes! is fine,
+    // and it swaps the words when the module was written with the other endianness
+    let spirv_u32 = match ash::util::read_spv(&mut std::io::Cursor::new(spirv)) {
         Ok(buf) => buf,
-        Err(_) => return Err(format!("failed to read spirv {:?}", path)),
+        Err(_) => return Err(format!("{}: failed to read spirv", object_name)),
     };
 
+    if spirv_u32.first() != Some(&SPIRV_MAGIC_NUMBER) {
+        return Err(format!("{}: invalid spirv magic number", object_name));
+    }
+
     let create_info = vk::ShaderModuleCreateInfo::builder()
         .code(&spirv_u32)
         .build();
 
     let shader_module = match unsafe { device.create_shader_module(&create_info, None) } {
         Ok(module) => module,
-        Err(_) => return Err(format!("failed to create shader module {:?}", path)),
+        Err(_) => return Err(format!("{}: failed to create shader module", object_name)),
     };
 
-    set_debug_utils_object_name2(
+    set_debug_utils_object_name(
         debug_utils_loader,
         device.handle(),
         shader_module,
@@ -85,31 +337,154 @@ pub fn create_shader_module(
     Ok(shader_module)
 }
 
+pub fn create_compute_pipeline(
+    device: &ash::Device,
+    shader_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    object_name: &str,
+) -> Result<vk::Pipeline, String> {
+    log::info!("{}: creating", object_name);
+
+    let shader_entry_name = std::ffi::CString::new("main").unwrap();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(&shader_entry_name)
+        .build();
+
+    let create_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(pipeline_layout)
+        .build();
+
+    let pipeline = unsafe {
+        device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+            .map_err(|err| {
+                destroy_partial_pipelines(
+                    device,
+                    err,
+                    &format!("{}: failed to create", object_name),
+                )
+            })?[0]
+    };
+
+    set_debug_utils_object_name(debug_utils_loader, device.handle(), pipeline, object_name);
+
+    log::info!("{}: created", object_name);
+
+    Ok(pipeline)
+}
+
+/// Work groups needed to cover `element_count` invocations with `local_size` invocations per
+/// group, the shader must skip the ones past the end.
+pub fn get_dispatch_group_count(element_count: u32, local_size: u32) -> u32 {
+    element_count.div_ceil(local_size)
+}
+
+/// Image aspects of a depth format, `DEPTH | STENCIL` for the combined `*_S8_UINT` formats.
+pub fn get_depth_aspect_mask(depth_format: vk::Format) -> vk::ImageAspectFlags {
+    match depth_format {
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+/// A gpu only buffer filled with `init_data`, see `upload_buffers`. Pass `queue_family` and `queue`
+/// as the destination too when the buffer is used where it was uploaded.
 pub fn create_gpu_buffer_init(
     device: &ash::Device,
     allocator: &mut gpu_allocator::vulkan::Allocator,
-    debug_utils_loader: &ash::extensions::ext::DebugUtils,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
     queue_family: u32,
     queue: vk::Queue,
+    dst_queue_family: u32,
+    dst_queue: vk::Queue,
     init_data: &[u8],
     buffer_usage: vk::BufferUsageFlags,
     buffer_access_mask: vk::AccessFlags,
     buffer_stage_flags: vk::PipelineStageFlags,
     object_name: &str,
 ) -> Result<MemBuffer, String> {
+    let mut mem_buffers = upload_buffers(
+        device,
+        allocator,
+        debug_utils_loader,
+        queue_family,
+        queue,
+        dst_queue_family,
+        dst_queue,
+        &[UploadRequest {
+            data: init_data,
+            usage: buffer_usage,
+            access_mask: buffer_access_mask,
+            stage_flags: buffer_stage_flags,
+            object_name,
+        }],
+    )?;
+
+    Ok(mem_buffers.pop().unwrap())
+}
+
+const UPLOAD_LABEL_COLOR: [f32; 4] = [0.2, 0.6, 1.0, 1.0];
+
+/// Creates one gpu buffer per request and fills them through a single staging buffer, all
+/// copies are recorded in one command buffer and the queue is waited on once.
+pub fn upload_buffers(
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    queue_family: u32,
+    queue: vk::Queue,
+    dst_queue_family: u32,
+    dst_queue: vk::Queue,
+    requests: &[UploadRequest],
+) -> Result<Vec<MemBuffer>, String> {
+    if requests.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if let Some(request) = requests.iter().find(|request| request.data.is_empty()) {
+        return Err(format!("{}: no data to upload", request.object_name));
+    }
+
     let allocator_rc = RefCell::new(allocator);
 
+    let object_name = if requests.len() == 1 {
+        String::from(requests[0].object_name)
+    } else {
+        String::from("upload batch")
+    };
+
     // staging buffer
-    log::info!("{}: creating with data", object_name);
+    log::info!(
+        "{}: creating {} buffers with data",
+        object_name,
+        requests.len()
+    );
+
+    let mut offsets = Vec::with_capacity(requests.len());
+    let mut staging_size = 0;
+    for request in requests {
+        offsets.push(staging_size);
+        staging_size += request.data.len() as vk::DeviceSize;
+    }
 
     let mut staging_mem_buffer_sg = {
         let staging_mem_buffer = create_buffer(
             device,
             *allocator_rc.borrow_mut(),
             debug_utils_loader,
-            init_data.len() as vk::DeviceSize,
+            staging_size,
             vk::BufferUsageFlags::TRANSFER_SRC,
             gpu_allocator::MemoryLocation::CpuToGpu,
+            &[queue_family],
             &format!("{} staging", object_name),
         )?;
 
@@ -125,33 +500,47 @@ pub fn create_gpu_buffer_init(
     // copy data to staging memory
     log::info!("{} staging: copying data to mapped memory", object_name);
 
-    staging_mem_buffer_sg.allocation.mapped_slice_mut().unwrap()[..init_data.len()]
-        .copy_from_slice(init_data);
+    {
+        let mapped = staging_mem_buffer_sg.allocation.mapped_slice_mut().unwrap();
+        for (request, &offset) in requests.iter().zip(&offsets) {
+            let offset = offset as usize;
+            mapped[offset..offset + request.data.len()].copy_from_slice(request.data);
+        }
+    }
+
+    // gpu buffers
+    let mut gpu_mem_buffers_sg = scopeguard::guard(
+        Vec::with_capacity(requests.len()),
+        |mem_buffers: Vec<MemBuffer>| {
+            log::warn!("{} gpu buffers scopeguard", object_name);
+            for mem_buffer in mem_buffers {
+                unsafe {
+                    device.destroy_buffer(mem_buffer.buffer, None);
+                }
+                let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
+            }
+        },
+    );
 
-    // gpu buffer
-    let gpu_mem_buffer_sg = {
+    for request in requests {
         let gpu_mem_buffer = create_buffer(
             device,
             *allocator_rc.borrow_mut(),
             debug_utils_loader,
-            init_data.len() as vk::DeviceSize,
-            buffer_usage | vk::BufferUsageFlags::TRANSFER_DST,
+            request.data.len() as vk::DeviceSize,
+            request.usage | vk::BufferUsageFlags::TRANSFER_DST,
             gpu_allocator::MemoryLocation::GpuOnly,
-            object_name,
+            // exclusive, ownership is transferred to dst_queue_family after the copy
+            &[queue_family],
+            request.object_name,
         )?;
 
-        scopeguard::guard(gpu_mem_buffer, |mem_buffer| {
-            log::warn!("{} scopeguard", object_name);
-            unsafe {
-                device.destroy_buffer(mem_buffer.buffer, None);
-            }
-            let _ = allocator_rc.borrow_mut().free(mem_buffer.allocation);
-        })
-    };
+        gpu_mem_buffers_sg.push(gpu_mem_buffer);
+    }
 
     // command pool
     let command_pool_sg = {
-        let command_pool = create_command_pool(device, queue_family, object_name)?;
+        let command_pool = create_command_pool(device, queue_family, &object_name)?;
         scopeguard::guard(command_pool, |command_pool| {
             log::warn!("{} command pool scopeguard", object_name);
             unsafe {
@@ -160,22 +549,61 @@ pub fn create_gpu_buffer_init(
         })
     };
 
-    // command buffer
-    let command_buffer = allocate_command_buffer(device, *command_pool_sg, object_name)?;
-    // no need to free explicitly, it will be freed implicitly on command pool destruction
-
     // copy staging memory to gpu memory
-    copy_buffer(
+    queue_begin_debug_label(
+        debug_utils_loader,
+        queue,
+        &format!("{} upload", object_name),
+        UPLOAD_LABEL_COLOR,
+    );
+
+    let copy_result = copy_buffers(
         device,
         queue,
-        command_buffer,
+        *command_pool_sg,
         staging_mem_buffer_sg.buffer,
-        gpu_mem_buffer_sg.buffer,
-        buffer_access_mask,
-        buffer_stage_flags,
-        init_data.len() as vk::DeviceSize,
-        object_name,
-    )?;
+        &gpu_mem_buffers_sg,
+        &offsets,
+        requests,
+        queue_family,
+        dst_queue_family,
+        &object_name,
+    );
+
+    queue_end_debug_label(debug_utils_loader, queue);
+
+    copy_result?;
+
+    // acquire ownership on the destination queue family
+    if queue_family != dst_queue_family {
+        let dst_command_pool_sg = {
+            let command_pool = create_command_pool(device, dst_queue_family, &object_name)?;
+            scopeguard::guard(command_pool, |command_pool| {
+                log::warn!("{} acquire command pool scopeguard", object_name);
+                unsafe {
+                    device.destroy_command_pool(command_pool, None);
+                }
+            })
+        };
+
+        acquire_buffers(
+            device,
+            dst_queue,
+            *dst_command_pool_sg,
+            &gpu_mem_buffers_sg,
+            requests,
+            queue_family,
+            dst_queue_family,
+            &object_name,
+        )?;
+
+        unsafe {
+            device.destroy_command_pool(
+                scopeguard::ScopeGuard::into_inner(dst_command_pool_sg),
+                None,
+            );
+        }
+    }
 
     // clear temporary objects
     log::info!("{}: destroying temporary objects", object_name);
@@ -190,27 +618,76 @@ pub fn create_gpu_buffer_init(
         device.destroy_command_pool(scopeguard::ScopeGuard::into_inner(command_pool_sg), None);
     }
 
-    let gpu_mem_buffer = scopeguard::ScopeGuard::into_inner(gpu_mem_buffer_sg);
+    let gpu_mem_buffers = scopeguard::ScopeGuard::into_inner(gpu_mem_buffers_sg);
 
-    Ok(gpu_mem_buffer)
+    Ok(gpu_mem_buffers)
 }
 
+/// Sharing mode and queue family index list for a resource used by `queue_families`.
+/// Duplicates are ignored, a single family or none gives `EXCLUSIVE` with an empty list.
+///
+/// `CONCURRENT` saves the ownership transfer barriers but may disable optimizations like
+/// framebuffer compression on some drivers, so prefer `EXCLUSIVE` with explicit transfers for
+/// resources accessed every frame.
+pub fn get_sharing_mode(queue_families: &[u32]) -> (vk::SharingMode, Vec<u32>) {
+    let mut queue_family_indices = queue_families.to_vec();
+    queue_family_indices.sort_unstable();
+    queue_family_indices.dedup();
+
+    if queue_family_indices.len() > 1 {
+        (vk::SharingMode::CONCURRENT, queue_family_indices)
+    } else {
+        (vk::SharingMode::EXCLUSIVE, Vec::new())
+    }
+}
+
+/// `queue_families` are the families using the buffer, see `get_sharing_mode`.
 pub fn create_buffer(
     device: &ash::Device,
     allocator: &mut gpu_allocator::vulkan::Allocator,
-    debug_utils_loader: &ash::extensions::ext::DebugUtils,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
+    size: vk::DeviceSize,
+    buffer_usage: vk::BufferUsageFlags,
+    memory_location: gpu_allocator::MemoryLocation,
+    queue_families: &[u32],
+    object_name: &str,
+) -> Result<MemBuffer, String> {
+    create_buffer_with_memory_types(
+        device,
+        allocator,
+        debug_utils_loader,
+        size,
+        buffer_usage,
+        memory_location,
+        u32::MAX,
+        queue_families,
+        object_name,
+    )
+}
+
+/// Like `create_buffer`, but only allocates from the memory types in `memory_type_bits`.
+/// When none of them can hold the buffer, e.g. for 0, `memory_location` picks from all of them.
+pub fn create_buffer_with_memory_types(
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
     size: vk::DeviceSize,
     buffer_usage: vk::BufferUsageFlags,
     memory_location: gpu_allocator::MemoryLocation,
+    memory_type_bits: u32,
+    queue_families: &[u32],
     object_name: &str,
 ) -> Result<MemBuffer, String> {
     // buffer
     log::info!("{}: creating", object_name);
 
+    let (sharing_mode, queue_family_indices) = get_sharing_mode(queue_families);
+
     let buffer_create_info = vk::BufferCreateInfo::builder()
         .size(size)
         .usage(buffer_usage)
-        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        .sharing_mode(sharing_mode)
+        .queue_family_indices(&queue_family_indices);
 
     let buffer_sg = {
         let buffer = unsafe {
@@ -232,7 +709,25 @@ pub fn create_buffer(
     // allocation
     log::info!("{}: allocating memory", object_name);
 
-    let memory_requirements = unsafe { device.get_buffer_memory_requirements(*buffer_sg) };
+    let mut memory_requirements = unsafe { device.get_buffer_memory_requirements(*buffer_sg) };
+
+    if memory_type_bits != u32::MAX {
+        let allowed_memory_type_bits = memory_requirements.memory_type_bits & memory_type_bits;
+        if allowed_memory_type_bits != 0 {
+            log::info!(
+                "{}: restricted to memory types {:#b}",
+                object_name,
+                allowed_memory_type_bits
+            );
+            memory_requirements.memory_type_bits = allowed_memory_type_bits;
+        } else {
+            log::info!(
+                "{}: no requested memory type fits, falling back to {:?}",
+                object_name,
+                memory_location
+            );
+        }
+    }
 
     let allocation_create_desc = gpu_allocator::vulkan::AllocationCreateDesc {
         name: object_name,
@@ -265,15 +760,15 @@ pub fn create_buffer(
 
     log::info!("{}: memory bound", object_name);
 
-    crate::set_debug_utils_object_name2(
+    crate::set_debug_utils_object_name(
         debug_utils_loader,
         device.handle(),
         *buffer_sg,
         object_name,
     );
 
-    crate::set_debug_utils_object_name2(
-        &debug_utils_loader,
+    crate::set_debug_utils_object_name(
+        debug_utils_loader,
         device.handle(),
         unsafe { allocation_sg.memory() },
         &format!("{} memory", object_name),
@@ -285,7 +780,7 @@ pub fn create_buffer(
     })
 }
 
-fn create_command_pool(
+pub(crate) fn create_command_pool(
     device: &ash::Device,
     queue_family: u32,
     object_name: &str,
@@ -332,18 +827,176 @@ fn allocate_command_buffer(
     Ok(command_buffers[0])
 }
 
-fn copy_buffer(
+fn copy_buffers(
     device: &ash::Device,
     queue: vk::Queue,
-    command_buffer: vk::CommandBuffer,
+    command_pool: vk::CommandPool,
     src_buffer: vk::Buffer,
-    dst_buffer: vk::Buffer,
-    buffer_access_mask: vk::AccessFlags,
-    buffer_stage_flags: vk::PipelineStageFlags,
-    size: vk::DeviceSize,
+    dst_mem_buffers: &[MemBuffer],
+    src_offsets: &[vk::DeviceSize],
+    requests: &[UploadRequest],
+    src_queue_family: u32,
+    dst_queue_family: u32,
     object_name: &str,
 ) -> Result<(), String> {
-    log::info!("{}: copying buffer to buffer", object_name);
+    log::info!("{}: copying buffer to buffers", object_name);
+
+    let mut after_copy_barriers = Vec::with_capacity(requests.len());
+    let mut dst_stage_flags = vk::PipelineStageFlags::empty();
+
+    for (mem_buffer, request) in dst_mem_buffers.iter().zip(requests) {
+        // with different queue families the barrier is a release operation, the matching
+        // acquire is recorded on the destination queue by acquire_buffers
+        let (dst_access_mask, src_family_index, dst_family_index) =
+            if src_queue_family == dst_queue_family {
+                dst_stage_flags |= request.stage_flags;
+                (
+                    request.access_mask,
+                    vk::QUEUE_FAMILY_IGNORED,
+                    vk::QUEUE_FAMILY_IGNORED,
+                )
+            } else {
+                dst_stage_flags |= vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+                (vk::AccessFlags::empty(), src_queue_family, dst_queue_family)
+            };
+
+        after_copy_barriers.push(
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(src_family_index)
+                .dst_queue_family_index(dst_family_index)
+                .buffer(mem_buffer.buffer)
+                .offset(0)
+                .size(request.data.len() as vk::DeviceSize)
+                .build(),
+        );
+    }
+
+    one_time_submit(device, command_pool, queue, object_name, |command_buffer| {
+        unsafe {
+            for ((mem_buffer, &src_offset), request) in
+                dst_mem_buffers.iter().zip(src_offsets).zip(requests)
+            {
+                let buffer_copy = vk::BufferCopy {
+                    src_offset,
+                    dst_offset: 0,
+                    size: request.data.len() as vk::DeviceSize,
+                };
+
+                device.cmd_copy_buffer(
+                    command_buffer,
+                    src_buffer,
+                    mem_buffer.buffer,
+                    &[buffer_copy],
+                );
+            }
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                dst_stage_flags,
+                vk::DependencyFlags::empty(),
+                &[],
+                &after_copy_barriers,
+                &[],
+            );
+        }
+
+        Ok(())
+    })?;
+
+    log::info!("{}: buffer to buffers copied", object_name);
+
+    Ok(())
+}
+
+fn acquire_buffers(
+    device: &ash::Device,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    mem_buffers: &[MemBuffer],
+    requests: &[UploadRequest],
+    src_queue_family: u32,
+    dst_queue_family: u32,
+    object_name: &str,
+) -> Result<(), String> {
+    log::info!(
+        "{}: acquiring buffers ownership from queue family {} to {}",
+        object_name,
+        src_queue_family,
+        dst_queue_family
+    );
+
+    let mut acquire_barriers = Vec::with_capacity(requests.len());
+    let mut dst_stage_flags = vk::PipelineStageFlags::empty();
+
+    for (mem_buffer, request) in mem_buffers.iter().zip(requests) {
+        dst_stage_flags |= request.stage_flags;
+
+        acquire_barriers.push(
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(request.access_mask)
+                .src_queue_family_index(src_queue_family)
+                .dst_queue_family_index(dst_queue_family)
+                .buffer(mem_buffer.buffer)
+                .offset(0)
+                .size(request.data.len() as vk::DeviceSize)
+                .build(),
+        );
+    }
+
+    one_time_submit(device, command_pool, queue, object_name, |command_buffer| {
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                dst_stage_flags,
+                vk::DependencyFlags::empty(),
+                &[],
+                &acquire_barriers,
+                &[],
+            );
+        }
+
+        Ok(())
+    })?;
+
+    log::info!("{}: buffers ownership acquired", object_name);
+
+    Ok(())
+}
+
+/// Records a primary command buffer allocated from `command_pool`, submits it to `queue` and
+/// waits for it on a fence before freeing it, also when recording fails.
+pub fn one_time_submit<F>(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    object_name: &str,
+    record: F,
+) -> Result<(), String>
+where
+    F: FnOnce(vk::CommandBuffer) -> Result<(), String>,
+{
+    let command_buffer_sg = {
+        let command_buffer = allocate_command_buffer(device, command_pool, object_name)?;
+        scopeguard::guard(command_buffer, |command_buffer| unsafe {
+            device.free_command_buffers(command_pool, &[command_buffer]);
+        })
+    };
+
+    let fence_sg = {
+        let fence = unsafe {
+            device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .map_err(|_| format!("{}: failed to create fence", object_name))?
+        };
+        scopeguard::guard(fence, |fence| unsafe {
+            device.destroy_fence(fence, None);
+        })
+    };
 
     let begin_info = vk::CommandBufferBeginInfo::builder()
         .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
@@ -351,63 +1004,37 @@ fn copy_buffer(
 
     unsafe {
         device
-            .begin_command_buffer(command_buffer, &begin_info)
-            .map_err(|_| format!("{}: failed to begin copy command buffer", object_name))?;
-
-        let buffer_copy = vk::BufferCopy {
-            src_offset: 0,
-            dst_offset: 0,
-            size,
-        };
-
-        device.cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, &[buffer_copy]);
-
-        let after_copy_barrier = vk::BufferMemoryBarrier::builder()
-            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-            .dst_access_mask(buffer_access_mask)
-            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .buffer(dst_buffer)
-            .offset(0)
-            .size(size)
-            .build();
+            .begin_command_buffer(*command_buffer_sg, &begin_info)
+            .map_err(|_| format!("{}: failed to begin command buffer", object_name))?;
+    }
 
-        device.cmd_pipeline_barrier(
-            command_buffer,
-            vk::PipelineStageFlags::TRANSFER,
-            buffer_stage_flags,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[after_copy_barrier],
-            &[],
-        );
+    record(*command_buffer_sg)?;
 
+    unsafe {
         device
-            .end_command_buffer(command_buffer)
-            .map_err(|_| format!("{}: failed to end copy command buffer", object_name))?;
+            .end_command_buffer(*command_buffer_sg)
+            .map_err(|_| format!("{}: failed to end command buffer", object_name))?;
 
-        let cmd_buffers = [command_buffer];
+        let cmd_buffers = [*command_buffer_sg];
         let submit_info = vk::SubmitInfo::builder()
             .command_buffers(&cmd_buffers)
             .build();
 
         device
-            .queue_submit(queue, &[submit_info], vk::Fence::null())
-            .map_err(|_| format!("{}: failed to submit copy", object_name))?;
+            .queue_submit(queue, &[submit_info], *fence_sg)
+            .map_err(|_| format!("{}: failed to submit command buffer", object_name))?;
 
         device
-            .queue_wait_idle(queue)
-            .map_err(|_| format!("{}: failed to wait idle queue", object_name))?;
-
-        log::info!("{}: buffer to buffer copied", object_name);
-    };
+            .wait_for_fences(&[*fence_sg], true, u64::MAX)
+            .map_err(|_| format!("{}: failed to wait for fence", object_name))?;
+    }
 
     Ok(())
 }
 
 pub fn create_semaphore(
     device: &ash::Device,
-    debug_utils_loader: &ash::extensions::ext::DebugUtils,
+    debug_utils_loader: Option<&ash::extensions::ext::DebugUtils>,
     object_name: &str,
 ) -> Result<vk::Semaphore, String> {
     log::info!("{}: creating", object_name);
@@ -420,14 +1047,63 @@ pub fn create_semaphore(
             .map_err(|_| format!("failed to create {}", object_name))?
     };
 
-    crate::set_debug_utils_object_name2(
-        debug_utils_loader,
-        device.handle(),
-        semaphore,
-        object_name,
-    );
+    crate::set_debug_utils_object_name(debug_utils_loader, device.handle(), semaphore, object_name);
 
     log::info!("{}: created", object_name);
 
     Ok(semaphore)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface_format(format: vk::Format) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        }
+    }
+
+    #[test]
+    fn choose_surface_format_prefers_the_first_available_preference() {
+        let available = [
+            surface_format(vk::Format::R8G8B8A8_UNORM),
+            surface_format(vk::Format::B8G8R8A8_SRGB),
+            surface_format(vk::Format::B8G8R8A8_UNORM),
+        ];
+        let preferred = [
+            surface_format(vk::Format::R8G8B8A8_SRGB),
+            surface_format(vk::Format::B8G8R8A8_UNORM),
+            surface_format(vk::Format::B8G8R8A8_SRGB),
+        ];
+
+        let chosen = choose_surface_format(&available, &preferred).unwrap();
+
+        assert_eq!(chosen, surface_format(vk::Format::B8G8R8A8_UNORM));
+    }
+
+    #[test]
+    fn choose_surface_format_falls_back_to_the_first_available() {
+        let available = [
+            surface_format(vk::Format::R8G8B8A8_UNORM),
+            surface_format(vk::Format::A2B10G10R10_UNORM_PACK32),
+        ];
+        // same format, different color space
+        let preferred = [vk::SurfaceFormatKHR {
+            format: vk::Format::R8G8B8A8_UNORM,
+            color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        }];
+
+        let chosen = choose_surface_format(&available, &preferred).unwrap();
+
+        assert_eq!(chosen, available[0]);
+    }
+
+    #[test]
+    fn choose_surface_format_fails_without_available_formats() {
+        let preferred = [surface_format(vk::Format::B8G8R8A8_UNORM)];
+
+        assert!(choose_surface_format(&[], &preferred).is_err());
+    }
+}